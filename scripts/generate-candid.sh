@@ -0,0 +1,13 @@
+#!/usr/bin/env bash
+# Regenerates the .did files from the Rust endpoint definitions.
+# Requires: cargo install candid-extractor
+set -euo pipefail
+
+cd "$(dirname "$0")/.."
+
+for canister in officex-canisters-backend factory-canister; do
+    cargo build --release --target wasm32-unknown-unknown --package "$canister"
+    wasm="target/wasm32-unknown-unknown/release/${canister//-/_}.wasm"
+    candid-extractor "$wasm" > "src/$canister/$canister.did"
+    echo "Wrote src/$canister/$canister.did"
+done
@@ -0,0 +1,139 @@
+// admin.rs
+
+use candid::{CandidType, Principal};
+use ic_cdk::api::management_canister::main::{deposit_cycles, CanisterIdRecord};
+use ic_cdk::caller;
+use ic_cdk_macros::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::heartbeats::ReceivedHeartbeat;
+use crate::{is_admin, DriveCanisterId, STATE};
+
+const MAX_DRIVE_PAGE_SIZE: u32 = 500;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct DriveSummary {
+    index: u64,
+    canister_id: DriveCanisterId,
+    owner: Option<Principal>,
+    username: Option<String>,
+    wasm_hash: Option<String>,
+    paid: bool,
+    last_heartbeat: Option<ReceivedHeartbeat>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct DrivePage {
+    drives: Vec<DriveSummary>,
+    total: u64,
+}
+
+fn is_controller() -> Result<(), String> {
+    if ic_cdk::api::is_controller(&caller()) {
+        Ok(())
+    } else {
+        Err("Caller is not a controller".to_string())
+    }
+}
+
+/// Rejects principals an admin has blocklisted.
+pub fn check_not_blocked(principal: Principal) -> Result<(), String> {
+    if STATE.with(|state| state.borrow().blocklist.contains(&principal)) {
+        return Err("Principal is blocked".to_string());
+    }
+    Ok(())
+}
+
+/// Grants admin rights. Only controllers manage the admin list, so admins
+/// cannot promote others.
+#[update(guard = "is_controller")]
+fn add_admin(principal: Principal) {
+    STATE.with(|state| state.borrow_mut().admins.insert(principal));
+}
+
+#[update(guard = "is_controller")]
+fn remove_admin(principal: Principal) {
+    STATE.with(|state| state.borrow_mut().admins.remove(&principal));
+}
+
+/// Admins besides the controllers, who are always admins.
+#[query(guard = "is_admin")]
+fn list_admins() -> Vec<Principal> {
+    STATE.with(|state| state.borrow().admins.iter().copied().collect())
+}
+
+/// Every drive the factory created, in creation order.
+#[query(guard = "is_admin")]
+fn list_all_drives(offset: u64, limit: u32) -> DrivePage {
+    STATE.with(|state| {
+        let state = state.borrow();
+        let owners: HashMap<DriveCanisterId, Principal> = state
+            .user_drive_directory
+            .iter()
+            .map(|(owner, id)| (*id, *owner))
+            .chain(state.team_drives.iter().map(|(id, owner)| (*id, *owner)))
+            .collect();
+        let usernames: HashMap<DriveCanisterId, &String> =
+            state.usernames.iter().map(|(name, id)| (*id, name)).collect();
+
+        let mut indexed: Vec<(&u64, &DriveCanisterId)> = state.drives_directory.iter().collect();
+        indexed.sort_by_key(|(index, _)| **index);
+        let drives = indexed
+            .into_iter()
+            .skip(offset as usize)
+            .take(limit.min(MAX_DRIVE_PAGE_SIZE) as usize)
+            .map(|(index, id)| DriveSummary {
+                index: *index,
+                canister_id: *id,
+                owner: owners.get(id).copied(),
+                username: usernames.get(id).map(|name| name.to_string()),
+                wasm_hash: state.drive_wasm_records.get(id).map(|record| record.wasm_hash.clone()),
+                paid: state.drive_payments.contains_key(id),
+                last_heartbeat: state.drive_heartbeats.get(id).cloned(),
+            })
+            .collect();
+        DrivePage { drives, total: state.drives_directory.len() as u64 }
+    })
+}
+
+/// Stops or resumes `create_drive` for everyone, e.g. during an incident.
+#[update(guard = "is_admin")]
+fn set_creation_paused(paused: bool) {
+    STATE.with(|state| state.borrow_mut().creation_paused = paused);
+}
+
+#[query]
+fn is_creation_paused() -> bool {
+    STATE.with(|state| state.borrow().creation_paused)
+}
+
+/// Sends `amount` cycles from the factory to one of its drives.
+#[update(guard = "is_admin")]
+async fn top_up_drive(canister_id: Principal, amount: u128) -> Result<(), String> {
+    let known = STATE.with(|state| state.borrow().drives_directory.values().any(|id| *id == canister_id));
+    if !known {
+        return Err("Unknown drive canister".to_string());
+    }
+    deposit_cycles(CanisterIdRecord { canister_id }, amount)
+        .await
+        .map_err(|e| format!("Failed to deposit cycles: {:?}", e))?;
+    ic_cdk::println!("Topped up drive {} with {} cycles", canister_id, amount);
+    Ok(())
+}
+
+/// Blocks a principal from creating drives. Existing drives keep working.
+#[update(guard = "is_admin")]
+fn block_principal(principal: Principal) {
+    STATE.with(|state| state.borrow_mut().blocklist.insert(principal));
+}
+
+#[update(guard = "is_admin")]
+fn unblock_principal(principal: Principal) {
+    STATE.with(|state| state.borrow_mut().blocklist.remove(&principal));
+}
+
+#[query(guard = "is_admin")]
+fn list_blocked_principals() -> Vec<Principal> {
+    STATE.with(|state| state.borrow().blocklist.iter().copied().collect())
+}
@@ -0,0 +1,30 @@
+// creation_lock.rs
+
+use candid::Principal;
+
+use crate::STATE;
+
+/// Marks a drive creation as in flight for one caller. Held across the awaits
+/// in `create_drive` and released on drop, which ic-cdk also runs when a
+/// later callback traps, so a failed creation never leaves the caller locked.
+pub struct CreationLock {
+    caller: Principal,
+}
+
+impl CreationLock {
+    /// Fails if `caller` already has a creation in flight.
+    pub fn acquire(caller: Principal) -> Result<Self, String> {
+        STATE.with(|state| {
+            if !state.borrow_mut().pending_creations.insert(caller) {
+                return Err("Drive creation is already in progress".to_string());
+            }
+            Ok(Self { caller })
+        })
+    }
+}
+
+impl Drop for CreationLock {
+    fn drop(&mut self) {
+        STATE.with(|state| state.borrow_mut().pending_creations.remove(&self.caller));
+    }
+}
@@ -0,0 +1,119 @@
+// creation_settings.rs
+
+use candid::{CandidType, Nat, Principal};
+use ic_cdk::api::call::call_with_payment128;
+use ic_cdk::api::management_canister::main::{create_canister, CanisterSettings, CreateCanisterArgument};
+use ic_cdk_macros::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{is_admin, DriveCanisterId, STATE};
+
+// Cycles minting canister, which can place new canisters on a chosen subnet
+const CMC_CANISTER_ID: &str = "rkp4c-7iaaa-aaaaa-aaaca-cai";
+// Creation fee plus enough left over for the drive to install and run
+const MIN_INITIAL_CYCLES: u128 = 500_000_000_000;
+const DEFAULT_INITIAL_CYCLES: u128 = 1_000_000_000_000;
+
+/// Parameters every new drive canister is created with.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct DriveCreationSettings {
+    pub initial_cycles: u128,
+    // Percent of an execution core reserved for the drive, 0-100
+    pub compute_allocation: Option<u64>,
+    // Bytes of memory reserved for the drive
+    pub memory_allocation: Option<u64>,
+    // Seconds of idle burn the drive keeps in reserve before freezing
+    pub freezing_threshold: Option<u64>,
+    // Subnet to place drives on; the factory's own subnet when unset
+    pub subnet: Option<Principal>,
+}
+
+impl Default for DriveCreationSettings {
+    fn default() -> Self {
+        Self {
+            initial_cycles: DEFAULT_INITIAL_CYCLES,
+            compute_allocation: None,
+            memory_allocation: None,
+            freezing_threshold: None,
+            subnet: None,
+        }
+    }
+}
+
+impl DriveCreationSettings {
+    fn validate(&self) -> Result<(), String> {
+        if self.initial_cycles < MIN_INITIAL_CYCLES {
+            return Err(format!("Drives need at least {} initial cycles", MIN_INITIAL_CYCLES));
+        }
+        if self.compute_allocation.is_some_and(|allocation| allocation > 100) {
+            return Err("Compute allocation is a percentage and cannot exceed 100".to_string());
+        }
+        Ok(())
+    }
+
+    fn canister_settings(&self, controllers: Vec<Principal>) -> CanisterSettings {
+        CanisterSettings {
+            controllers: Some(controllers),
+            compute_allocation: self.compute_allocation.map(Nat::from),
+            memory_allocation: self.memory_allocation.map(Nat::from),
+            freezing_threshold: self.freezing_threshold.map(Nat::from),
+            reserved_cycles_limit: None,
+        }
+    }
+}
+
+#[derive(CandidType)]
+enum SubnetSelection {
+    Subnet { subnet: Principal },
+}
+
+#[derive(CandidType)]
+struct CmcCreateCanisterArg {
+    settings: Option<CanisterSettings>,
+    subnet_selection: Option<SubnetSelection>,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+enum CmcCreateCanisterError {
+    Refunded { refund_amount: Nat, create_error: String },
+}
+
+/// Creates an empty canister for a drive using the current creation settings,
+/// through the cycles minting canister when a subnet is pinned.
+pub async fn create_drive_canister(controllers: Vec<Principal>) -> Result<DriveCanisterId, String> {
+    let settings = STATE.with(|state| state.borrow().drive_creation_settings.clone());
+    let canister_settings = settings.canister_settings(controllers);
+
+    let Some(subnet) = settings.subnet else {
+        let (record,) = create_canister(
+            CreateCanisterArgument { settings: Some(canister_settings) },
+            settings.initial_cycles,
+        )
+        .await
+        .map_err(|e| format!("Failed to create canister: {:?}", e))?;
+        return Ok(record.canister_id);
+    };
+
+    let cmc = Principal::from_text(CMC_CANISTER_ID).expect("Invalid cycles minting canister id");
+    let arg = CmcCreateCanisterArg {
+        settings: Some(canister_settings),
+        subnet_selection: Some(SubnetSelection::Subnet { subnet }),
+    };
+    let (result,): (Result<Principal, CmcCreateCanisterError>,) =
+        call_with_payment128(cmc, "create_canister", (arg,), settings.initial_cycles)
+            .await
+            .map_err(|(code, message)| format!("Failed to create canister: {:?} {}", code, message))?;
+    result.map_err(|e| format!("Failed to create canister on subnet {}: {:?}", subnet, e))
+}
+
+#[update(guard = "is_admin")]
+fn set_drive_creation_settings(settings: DriveCreationSettings) -> Result<(), String> {
+    settings.validate()?;
+    STATE.with(|state| state.borrow_mut().drive_creation_settings = settings);
+    Ok(())
+}
+
+#[query(guard = "is_admin")]
+fn get_drive_creation_settings() -> DriveCreationSettings {
+    STATE.with(|state| state.borrow().drive_creation_settings.clone())
+}
@@ -0,0 +1,72 @@
+// deletion.rs
+
+use candid::{Principal, Reserved};
+use ic_cdk::api::management_canister::main::{delete_canister, stop_canister, CanisterIdRecord};
+use ic_cdk::caller;
+use ic_cdk_macros::*;
+
+use crate::directory::remove_public_profile;
+use crate::usernames::release_drive_usernames;
+use crate::{is_admin, DriveCanisterId, STATE};
+
+/// Pulls the drive's cycles back into the factory. Failure here is not fatal:
+/// the drive is still deleted, its remaining cycles are simply burned.
+async fn reclaim_drive_cycles(canister_id: DriveCanisterId) -> u128 {
+    let result: Result<(Result<u128, Reserved>,), _> = ic_cdk::call(canister_id, "reclaim_cycles", ()).await;
+    match result {
+        Ok((Ok(amount),)) => amount,
+        Ok((Err(_),)) => {
+            ic_cdk::println!("Drive {} refused to release its cycles", canister_id);
+            0
+        }
+        Err((code, message)) => {
+            ic_cdk::println!("Failed to reclaim cycles from {}: {:?} {}", canister_id, code, message);
+            0
+        }
+    }
+}
+
+pub async fn delete_drive_canister(canister_id: DriveCanisterId) -> Result<u128, String> {
+    let reclaimed = reclaim_drive_cycles(canister_id).await;
+
+    stop_canister(CanisterIdRecord { canister_id })
+        .await
+        .map_err(|e| format!("Failed to stop canister: {:?}", e))?;
+    delete_canister(CanisterIdRecord { canister_id })
+        .await
+        .map_err(|e| format!("Failed to delete canister: {:?}", e))?;
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.user_drive_directory.retain(|_, id| *id != canister_id);
+        state.drives_directory.retain(|_, id| *id != canister_id);
+        state.drive_wasm_records.remove(&canister_id);
+        state.drive_heartbeats.remove(&canister_id);
+        state.team_drives.remove(&canister_id);
+    });
+    release_drive_usernames(canister_id);
+    remove_public_profile(canister_id);
+
+    ic_cdk::println!("Deleted drive {} and reclaimed {} cycles", canister_id, reclaimed);
+    Ok(reclaimed)
+}
+
+/// Deletes the caller's drive and returns the number of cycles reclaimed.
+/// Drive indexes are not reused, so `get_total_drives` keeps counting it.
+#[update]
+async fn delete_drive() -> Result<u128, String> {
+    let caller: Principal = caller();
+    let canister_id = STATE
+        .with(|state| state.borrow().user_drive_directory.get(&caller).copied())
+        .ok_or_else(|| "User has no drive".to_string())?;
+    delete_drive_canister(canister_id).await
+}
+
+#[update(guard = "is_admin")]
+async fn admin_delete_drive(canister_id: Principal) -> Result<u128, String> {
+    let known = STATE.with(|state| state.borrow().drives_directory.values().any(|id| *id == canister_id));
+    if !known {
+        return Err("Unknown drive canister".to_string());
+    }
+    delete_drive_canister(canister_id).await
+}
@@ -0,0 +1,138 @@
+// directory.rs
+//
+// Opt-in directory of public profiles for discovery across the network.
+// Drives list themselves here when their owner asks them to, along with the
+// folders they have published; drives that never opt in are not listed.
+
+use candid::{CandidType, Principal};
+use ic_cdk::caller;
+use ic_cdk_macros::*;
+use serde::{Deserialize, Serialize};
+
+use crate::usernames::username_key;
+use crate::{DriveCanisterId, STATE};
+
+const MAX_DISPLAY_NAME_CHARS: usize = 64;
+const MAX_BIO_CHARS: usize = 500;
+const MAX_AVATAR_URL_BYTES: usize = 512;
+const MAX_LISTED_FOLDERS: usize = 100;
+const MAX_SEARCH_RESULTS: usize = 50;
+const MAX_QUERY_CHARS: usize = 64;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ListedFolder {
+    pub folder_id: String,
+    pub full_folder_path: String,
+}
+
+/// What a drive sends when it lists itself.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct PublicProfileListing {
+    pub display_name: String,
+    pub bio: String,
+    pub avatar_url: Option<String>,
+    pub published_folders: Vec<ListedFolder>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ListedProfile {
+    pub listing: PublicProfileListing,
+    pub updated_at_ns: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct PublicProfile {
+    pub username: String,
+    pub drive_canister_id: DriveCanisterId,
+    pub listing: PublicProfileListing,
+    pub updated_at_ns: u64,
+}
+
+fn validate_listing(listing: &PublicProfileListing) -> Result<(), String> {
+    if listing.display_name.chars().count() > MAX_DISPLAY_NAME_CHARS {
+        return Err(format!("Display name is longer than {} characters", MAX_DISPLAY_NAME_CHARS));
+    }
+    if listing.bio.chars().count() > MAX_BIO_CHARS {
+        return Err(format!("Bio is longer than {} characters", MAX_BIO_CHARS));
+    }
+    if listing.avatar_url.as_ref().is_some_and(|url| url.len() > MAX_AVATAR_URL_BYTES) {
+        return Err(format!("Avatar URL is longer than {} bytes", MAX_AVATAR_URL_BYTES));
+    }
+    if listing.published_folders.len() > MAX_LISTED_FOLDERS {
+        return Err(format!("At most {} folders can be listed", MAX_LISTED_FOLDERS));
+    }
+    Ok(())
+}
+
+pub fn remove_public_profile(drive_canister_id: DriveCanisterId) {
+    STATE.with(|state| state.borrow_mut().public_profiles.remove(&drive_canister_id));
+}
+
+/// Called by a drive to list or refresh its public profile.
+#[update]
+fn set_public_profile(listing: PublicProfileListing) -> Result<(), String> {
+    let drive_canister_id: Principal = caller();
+    validate_listing(&listing)?;
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if !state.drives_directory.values().any(|id| *id == drive_canister_id) {
+            return Err("Caller is not a registered drive".to_string());
+        }
+        state
+            .public_profiles
+            .insert(drive_canister_id, ListedProfile { listing, updated_at_ns: ic_cdk::api::time() });
+        Ok(())
+    })
+}
+
+/// Called by a drive to take its profile out of the directory.
+#[update]
+fn clear_public_profile() -> Result<(), String> {
+    remove_public_profile(caller());
+    Ok(())
+}
+
+fn public_profile(username: &str, drive_canister_id: DriveCanisterId, listed: &ListedProfile) -> PublicProfile {
+    PublicProfile {
+        username: username.to_string(),
+        drive_canister_id,
+        listing: listed.listing.clone(),
+        updated_at_ns: listed.updated_at_ns,
+    }
+}
+
+#[query]
+fn get_public_profile(username: String) -> Option<PublicProfile> {
+    let key = username_key(&username);
+    STATE.with(|state| {
+        let state = state.borrow();
+        let drive_canister_id = *state.usernames.get(&key)?;
+        let listed = state.public_profiles.get(&drive_canister_id)?;
+        Some(public_profile(&key, drive_canister_id, listed))
+    })
+}
+
+/// Listed profiles whose username or display name contains `query`,
+/// ignoring case, ordered by username. Returns at most 50.
+#[query]
+fn search_users(query: String) -> Vec<PublicProfile> {
+    let query: String = query.trim().to_lowercase().chars().take(MAX_QUERY_CHARS).collect();
+    if query.is_empty() {
+        return Vec::new();
+    }
+    STATE.with(|state| {
+        let state = state.borrow();
+        let mut matches: Vec<PublicProfile> = state
+            .usernames
+            .iter()
+            .filter_map(|(username, drive_canister_id)| {
+                let listed = state.public_profiles.get(drive_canister_id)?;
+                let matched = username.contains(&query) || listed.listing.display_name.to_lowercase().contains(&query);
+                matched.then(|| public_profile(username, *drive_canister_id, listed))
+            })
+            .collect();
+        matches.sort_by(|a, b| a.username.cmp(&b.username));
+        matches.truncate(MAX_SEARCH_RESULTS);
+        matches
+    })
+}
@@ -0,0 +1,67 @@
+// heartbeats.rs
+
+use candid::{CandidType, Principal};
+use ic_cdk::caller;
+use ic_cdk_macros::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{is_admin, DriveCanisterId, STATE};
+
+/// Self-reported drive status, as sent by the drive's heartbeat timer.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct DriveHeartbeat {
+    pub cycle_balance: u128,
+    pub file_count: u64,
+    pub folder_count: u64,
+    pub stable_memory_bytes: u64,
+    pub wasm_version: String,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ReceivedHeartbeat {
+    pub report: DriveHeartbeat,
+    pub received_at_ns: u64,
+}
+
+/// Called periodically by every drive. Only drives this factory created
+/// (including standby drives) are recorded.
+#[update]
+fn report_drive_heartbeat(report: DriveHeartbeat) -> Result<(), String> {
+    let drive_canister_id: DriveCanisterId = caller();
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let known = state.drives_directory.values().any(|id| *id == drive_canister_id)
+            || state.standby_drives.contains(&drive_canister_id);
+        if !known {
+            return Err("Caller is not a registered drive".to_string());
+        }
+        state.drive_heartbeats.insert(
+            drive_canister_id,
+            ReceivedHeartbeat { report, received_at_ns: ic_cdk::api::time() },
+        );
+        Ok(())
+    })
+}
+
+#[query(guard = "is_admin")]
+fn get_drive_heartbeat(canister_id: Principal) -> Option<ReceivedHeartbeat> {
+    STATE.with(|state| state.borrow().drive_heartbeats.get(&canister_id).cloned())
+}
+
+/// Drives that have not checked in for `max_age_seconds`, oldest first.
+/// Drives that never reported are listed with no timestamp.
+#[query(guard = "is_admin")]
+fn list_silent_drives(max_age_seconds: u64) -> Vec<(DriveCanisterId, Option<u64>)> {
+    let cutoff_ns = ic_cdk::api::time().saturating_sub(max_age_seconds.saturating_mul(1_000_000_000));
+    STATE.with(|state| {
+        let state = state.borrow();
+        let mut silent: Vec<(DriveCanisterId, Option<u64>)> = state
+            .drives_directory
+            .values()
+            .map(|id| (*id, state.drive_heartbeats.get(id).map(|beat| beat.received_at_ns)))
+            .filter(|(_, received_at_ns)| received_at_ns.is_none_or(|at| at < cutoff_ns))
+            .collect();
+        silent.sort_by_key(|(_, received_at_ns)| *received_at_ns);
+        silent
+    })
+}
@@ -1,33 +1,91 @@
 use candid::{CandidType, Encode, Principal};
-use ic_cdk::api::management_canister::main::{
-    create_canister, install_code, CanisterInstallMode, CreateCanisterArgument, InstallCodeArgument,
-};
+use ic_cdk::api::management_canister::main::{install_code, CanisterInstallMode, InstallCodeArgument};
 use ic_cdk::caller;
 use ic_cdk_macros::*;
 use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet, VecDeque};
 
 use regex::Regex;
 
+mod admin;
+mod creation_lock;
+mod creation_settings;
+mod deletion;
+mod directory;
+mod heartbeats;
+mod ownership;
+mod payments;
+mod persistence;
+mod pool;
+mod rollouts;
+mod schema;
+mod team_drives;
+mod upgrades;
+mod usernames;
+mod wasm_store;
+
+use admin::check_not_blocked;
+use creation_lock::CreationLock;
+use creation_settings::{create_drive_canister, DriveCreationSettings};
+use directory::ListedProfile;
+use heartbeats::ReceivedHeartbeat;
+use payments::{collect_payment, record_drive_payment, refund_payment, Payment, PaymentToken, TokenPrice};
+use pool::{claim_standby_drive, start_standby_pool_refill};
+use rollouts::StagedRollout;
+use team_drives::TeamMember;
+use upgrades::{record_installed_wasm, wasm_hash, DriveWasmRecord};
+use usernames::UsernameReservation;
+use wasm_store::current_drive_wasm;
+
 type DriveCanisterId = Principal;
 
+/// Drive WASM bundled at build time, used until an admin commits an uploaded version.
 const DRIVE_WASM: &[u8] =
     include_bytes!("../../../target/wasm32-unknown-unknown/release/officex_canisters_backend.wasm");
 
-#[derive(CandidType, Serialize, Deserialize, Clone)]
-struct CanisterSettings {
-    controllers: Option<Vec<Principal>>,
-    compute_allocation: Option<u8>,
-    memory_allocation: Option<u64>,
-    freezing_threshold: Option<u64>,
-}
-
 #[derive(CandidType, Serialize, Deserialize)]
 struct State {
     drives_counter: u64,
     user_drive_directory: HashMap<Principal, DriveCanisterId>,
     drives_directory: HashMap<u64, DriveCanisterId>,
+    drive_wasm_records: HashMap<DriveCanisterId, DriveWasmRecord>,
+    drive_prices: HashMap<PaymentToken, TokenPrice>,
+    drive_payments: HashMap<DriveCanisterId, Payment>,
+    // Refunds the ledger rejected, kept until an admin retries them
+    failed_refunds: Vec<Payment>,
+    // Installed drives still owned by the factory, waiting for a user
+    standby_drives: VecDeque<DriveCanisterId>,
+    standby_pool_size: u32,
+    #[serde(skip)]
+    standby_pool_refilling: bool,
+    // Callers with a create_drive call in flight. In-flight markers are not
+    // saved across upgrades, since the calls they guard cannot resume
+    #[serde(skip)]
+    pending_creations: HashSet<Principal>,
+    // Lowercased username to drive, unique across all drives
+    usernames: HashMap<String, DriveCanisterId>,
+    // Usernames held by creations still in flight
+    #[serde(skip)]
+    reserved_usernames: HashSet<String>,
+    // Admins in addition to the controllers
+    admins: HashSet<Principal>,
+    creation_paused: bool,
+    blocklist: HashSet<Principal>,
+    drive_creation_settings: DriveCreationSettings,
+    // Most recent staged rollout, kept after it finishes for inspection
+    rollout: Option<StagedRollout>,
+    // Latest check-in from each drive
+    #[serde(default)]
+    drive_heartbeats: HashMap<DriveCanisterId, ReceivedHeartbeat>,
+    // Team drives and their owners; these are kept out of the one-per-user directory
+    #[serde(default)]
+    team_drives: HashMap<DriveCanisterId, Principal>,
+    // Drives listed in the public profile directory
+    #[serde(default)]
+    public_profiles: HashMap<DriveCanisterId, ListedProfile>,
+    // Layout version of the fields above; see `schema.rs`
+    schema_version: u32,
 }
 
 impl State {
@@ -36,6 +94,25 @@ impl State {
             drives_counter: 0,
             user_drive_directory: HashMap::new(),
             drives_directory: HashMap::new(),
+            drive_wasm_records: HashMap::new(),
+            drive_prices: HashMap::new(),
+            drive_payments: HashMap::new(),
+            failed_refunds: Vec::new(),
+            standby_drives: VecDeque::new(),
+            standby_pool_size: 0,
+            standby_pool_refilling: false,
+            pending_creations: HashSet::new(),
+            usernames: HashMap::new(),
+            reserved_usernames: HashSet::new(),
+            admins: HashSet::new(),
+            creation_paused: false,
+            blocklist: HashSet::new(),
+            drive_creation_settings: DriveCreationSettings::default(),
+            rollout: None,
+            drive_heartbeats: HashMap::new(),
+            team_drives: HashMap::new(),
+            public_profiles: HashMap::new(),
+            schema_version: schema::SCHEMA_VERSION,
         }
     }
 }
@@ -44,79 +121,165 @@ thread_local! {
     static STATE: RefCell<State> = RefCell::new(State::new());
 }
 
-#[update]
-async fn create_drive(username: String) -> Result<String, String> {
-    let caller: Principal = caller();
-    if caller == Principal::anonymous() {
-        return Err("Free users can only use local drives".to_string());
+#[init]
+fn init() {
+    start_standby_pool_refill();
+}
+
+#[pre_upgrade]
+fn pre_upgrade() {
+    persistence::save_heap_state();
+}
+
+#[post_upgrade]
+fn post_upgrade() {
+    persistence::restore_heap_state();
+    // Timers do not survive an upgrade
+    start_standby_pool_refill();
+    rollouts::resume_staged_rollout();
+}
+
+/// Controllers, plus anyone a controller added with `add_admin`.
+fn is_admin() -> Result<(), String> {
+    let caller = caller();
+    if ic_cdk::api::is_controller(&caller) || STATE.with(|state| state.borrow().admins.contains(&caller)) {
+        Ok(())
+    } else {
+        Err("Caller is not an admin".to_string())
     }
+}
 
+/// Creates a canister controlled by `controllers` and installs the current
+/// drive WASM on it with `(init_owner, username, members)` as the init
+/// argument. Passing `members` makes it a team drive.
+async fn install_new_drive(
+    init_owner: Principal,
+    username: &str,
+    controllers: Vec<Principal>,
+    members: Option<&[TeamMember]>,
+) -> Result<DriveCanisterId, String> {
+    let drive_canister_id = create_drive_canister(controllers).await.inspect_err(|e| {
+        ic_cdk::println!("{}", e);
+    })?;
 
-    let sanitized_username = sanitize_username(&username);
-    if !is_valid_username(&sanitized_username) {
-        return Err("Invalid username format".to_string());
+    let arg = Encode!(&init_owner, &username, &members).unwrap();
+    ic_cdk::println!("Encoded arguments: {:?}", arg);
+
+    let wasm_module = current_drive_wasm();
+    let installed_hash = wasm_hash(&wasm_module);
+
+    let install_code_arg = InstallCodeArgument {
+        mode: CanisterInstallMode::Install,
+        canister_id: drive_canister_id,
+        wasm_module,
+        arg,
+    };
+
+    ic_cdk::println!("Installing code with mode: {:?}", install_code_arg.mode);
+
+    match install_code(install_code_arg).await {
+        Ok(()) => {
+            ic_cdk::println!("Code installed successfully");
+            record_installed_wasm(drive_canister_id, installed_hash);
+            Ok(drive_canister_id)
+        }
+        Err(e) => {
+            ic_cdk::println!("Failed to install code: {:?}", e);
+            Err(format!("Failed to install code: {:?}", e))
+        }
     }
+}
 
-    // Check if the user already has a drive
-    if STATE.with(|state| state.borrow().user_drive_directory.contains_key(&caller)) {
-        return Err("User already has a drive".to_string());
+/// Adds a drive that now belongs to `owner` to the directories. Team drives
+/// are listed apart from the owner's personal drive.
+fn register_drive(owner: Principal, drive_canister_id: DriveCanisterId, team: bool) {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.drives_counter += 1;
+        let drive_index = state.drives_counter;
+        state.drives_directory.insert(drive_index, drive_canister_id);
+        if team {
+            state.team_drives.insert(drive_canister_id, owner);
+        } else {
+            state.user_drive_directory.insert(owner, drive_canister_id);
+        }
+    });
+}
+
+/// Checks shared by every drive creation endpoint.
+fn check_can_create_drive(caller: Principal) -> Result<(), String> {
+    if caller == Principal::anonymous() {
+        return Err("Free users can only use local drives".to_string());
     }
+    check_not_blocked(caller)?;
+    if STATE.with(|state| state.borrow().creation_paused) {
+        return Err("Drive creation is paused".to_string());
+    }
+    Ok(())
+}
+
+/// Takes payment, then sets up a drive for `owner` from the standby pool or
+/// from scratch, refunding the payment if that fails.
+async fn provision_drive(
+    owner: Principal,
+    sanitized_username: &str,
+    payment_token: Option<PaymentToken>,
+    members: Option<&[TeamMember]>,
+) -> Result<DriveCanisterId, String> {
+    let username_reservation = UsernameReservation::acquire(sanitized_username)?;
+    let payment = collect_payment(owner, payment_token).await?;
 
-    ic_cdk::println!("Creating drive for owner: {} with username: {}", caller, sanitized_username);
+    ic_cdk::println!("Creating drive for owner: {} with username: {}", owner, sanitized_username);
 
-    let create_canister_arg = CreateCanisterArgument {
-        settings: Some(ic_cdk::api::management_canister::main::CanisterSettings {
-            controllers: Some(vec![ic_cdk::id(), caller]),
-            compute_allocation: None,
-            memory_allocation: None,
-            freezing_threshold: None,
-            reserved_cycles_limit: None,
-        }),
+    // A standby drive from the pool skips canister creation entirely
+    let provisioned = match claim_standby_drive(owner, sanitized_username, members).await {
+        Some(drive_canister_id) => Ok(drive_canister_id),
+        None => install_new_drive(owner, sanitized_username, vec![ic_cdk::id(), owner], members).await,
     };
 
-    let cycles_to_use = 1_000_000_000_000u128; // Adjust as needed
-
-    match create_canister(create_canister_arg, cycles_to_use).await {
-        Ok((canister_id_record,)) => {
-            let drive_canister_id: DriveCanisterId = canister_id_record.canister_id;
-
-            let arg = Encode!(&caller, &sanitized_username).unwrap();
-            ic_cdk::println!("Encoded arguments: {:?}", arg);
-
-            let install_code_arg = InstallCodeArgument {
-                mode: CanisterInstallMode::Install,
-                canister_id: drive_canister_id,
-                wasm_module: DRIVE_WASM.to_vec(),
-                arg,
-            };
-
-            ic_cdk::println!("Installing code with mode: {:?}", install_code_arg.mode);
-
-            match install_code(install_code_arg).await {
-                Ok(()) => {
-                    ic_cdk::println!("Code installed successfully");
-                    STATE.with(|state| {
-                        let mut state = state.borrow_mut();
-                        state.drives_counter += 1;
-                        let drive_index = state.drives_counter;
-                        state.drives_directory.insert(drive_index, drive_canister_id);
-                        state.user_drive_directory.insert(caller, drive_canister_id);
-                    });
-                    Ok(drive_canister_id.to_string())
-                }
-                Err(e) => {
-                    ic_cdk::println!("Failed to install code: {:?}", e);
-                    Err(format!("Failed to install code: {:?}", e))
-                }
+    match provisioned {
+        Ok(drive_canister_id) => {
+            register_drive(owner, drive_canister_id, members.is_some());
+            username_reservation.register(drive_canister_id);
+            if let Some(payment) = payment {
+                record_drive_payment(drive_canister_id, payment);
             }
+            Ok(drive_canister_id)
         }
         Err(e) => {
-            ic_cdk::println!("Failed to create canister: {:?}", e);
-            Err(format!("Failed to create canister: {:?}", e))
+            if let Some(payment) = payment {
+                refund_payment(payment).await;
+            }
+            Err(e)
         }
     }
 }
 
+/// Creates a drive for the caller. When drive prices are set, the caller must
+/// first deposit the price in `payment_token` to `get_deposit_account`; the
+/// payment is refunded if the drive cannot be provisioned. Calling again once
+/// the drive exists returns it instead of creating another.
+#[update]
+async fn create_drive(username: String, payment_token: Option<PaymentToken>) -> Result<String, String> {
+    let caller: Principal = caller();
+    check_can_create_drive(caller)?;
+
+    let sanitized_username = sanitize_username(&username);
+    if !is_valid_username(&sanitized_username) {
+        return Err("Invalid username format".to_string());
+    }
+
+    // A repeated signup (e.g. a double click) is turned away while the first
+    // is in flight, and gets the same drive back once it exists
+    let _lock = CreationLock::acquire(caller)?;
+    if let Some(existing) = STATE.with(|state| state.borrow().user_drive_directory.get(&caller).copied()) {
+        return Ok(existing.to_string());
+    }
+
+    provision_drive(caller, &sanitized_username, payment_token, None)
+        .await
+        .map(|drive_canister_id| drive_canister_id.to_string())
+}
 
 fn sanitize_username(username: &str) -> String {
     let re = Regex::new(r#"[/\\@:;'"`]"#).unwrap();
@@ -166,3 +329,18 @@ fn get_canister_balance() -> u64 {
     ic_cdk::println!("Canister balance: {}", balance);
     balance
 }
+
+#[cfg(test)]
+mod logic_tests;
+
+// Types used in endpoint signatures must be in scope here for export_candid!
+use admin::DrivePage;
+use directory::{PublicProfile, PublicProfileListing};
+use heartbeats::DriveHeartbeat;
+use payments::Account;
+use pool::StandbyPoolInfo;
+use rollouts::{DriveVersion, VersionGroup};
+use upgrades::UpgradeReport;
+use wasm_store::DriveWasmVersion;
+
+ic_cdk::export_candid!();
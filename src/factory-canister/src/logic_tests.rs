@@ -0,0 +1,53 @@
+// logic_tests.rs
+
+use candid::Principal;
+use serde_cbor::Value as CborValue;
+
+use crate::payments::overpayment_refund;
+use crate::persistence::decode_heap_state;
+use crate::schema::SCHEMA_VERSION;
+use crate::State;
+
+#[test]
+fn test_decode_heap_state() {
+    // Factories upgraded from a build that never saved state have nothing stored
+    assert!(decode_heap_state(&[]).is_none());
+
+    // State as saved before `schema_version` and the later fields existed
+    let user = Principal::from_slice(&[7; 29]);
+    let drive = Principal::from_slice(&[9; 10]);
+    let mut state = State::new();
+    state.drives_counter = 1;
+    state.user_drive_directory.insert(user, drive);
+    state.drives_directory.insert(0, drive);
+    let saved: CborValue = serde_cbor::from_slice(&serde_cbor::to_vec(&state).unwrap()).unwrap();
+    let CborValue::Map(mut fields) = saved else {
+        panic!("State is not saved as a map");
+    };
+    for name in ["schema_version", "rollout", "drive_heartbeats", "team_drives", "public_profiles"] {
+        fields.remove(&CborValue::Text(name.to_string()));
+    }
+    let bytes = serde_cbor::to_vec(&CborValue::Map(fields)).unwrap();
+
+    let restored = decode_heap_state(&bytes).expect("Saved state was not restored");
+    assert_eq!(restored.drives_counter, 1);
+    assert_eq!(restored.user_drive_directory.get(&user), Some(&drive));
+    assert!(restored.rollout.is_none());
+    assert!(restored.drive_heartbeats.is_empty());
+    assert!(restored.team_drives.is_empty());
+    assert!(restored.public_profiles.is_empty());
+    assert_eq!(restored.schema_version, SCHEMA_VERSION);
+}
+
+#[test]
+fn test_overpayment_refund() {
+    let (price, fee) = (100_000_000, 10_000);
+    // Exactly the price and the collecting fee
+    assert_eq!(overpayment_refund(price + fee, price, fee), None);
+    // Extra that only covers the fee of returning it
+    assert_eq!(overpayment_refund(price + 2 * fee, price, fee), None);
+    assert_eq!(overpayment_refund(price + 2 * fee + 1, price, fee), Some(1));
+    assert_eq!(overpayment_refund(3 * price, price, fee), Some(2 * price - 2 * fee));
+    // Short deposits are rejected before collecting, but never underflow here
+    assert_eq!(overpayment_refund(price, price, fee), None);
+}
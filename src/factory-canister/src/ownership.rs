@@ -0,0 +1,61 @@
+// ownership.rs
+
+use candid::Principal;
+use ic_cdk::api::management_canister::main::{update_settings, CanisterSettings, UpdateSettingsArgument};
+use ic_cdk::caller;
+use ic_cdk_macros::*;
+
+use crate::STATE;
+
+/// Called by a drive canister when its ownership transfer is accepted. Swaps
+/// the old owner for the new one in the drive's controllers and re-keys the
+/// user directory. Team drives move between owners without that limit, since
+/// a user may own any number of them.
+#[update]
+async fn notify_drive_owner_changed(new_owner: Principal) -> Result<(), String> {
+    let drive_canister_id = caller();
+
+    let (previous_owner, team) = STATE.with(|state| {
+        let state = state.borrow();
+        if let Some(owner) = state.team_drives.get(&drive_canister_id) {
+            return Ok((*owner, true));
+        }
+        if state.user_drive_directory.contains_key(&new_owner) {
+            return Err("New owner already has a drive".to_string());
+        }
+        state
+            .user_drive_directory
+            .iter()
+            .find(|(_, id)| **id == drive_canister_id)
+            .map(|(owner, _)| (*owner, false))
+            .ok_or_else(|| "Caller is not a registered drive".to_string())
+    })?;
+
+    update_settings(UpdateSettingsArgument {
+        canister_id: drive_canister_id,
+        settings: CanisterSettings {
+            controllers: Some(vec![ic_cdk::id(), new_owner]),
+            ..Default::default()
+        },
+    })
+    .await
+    .map_err(|e| format!("Failed to update drive controllers: {:?}", e))?;
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if team {
+            state.team_drives.insert(drive_canister_id, new_owner);
+        } else {
+            state.user_drive_directory.remove(&previous_owner);
+            state.user_drive_directory.insert(new_owner, drive_canister_id);
+        }
+    });
+
+    ic_cdk::println!(
+        "Drive {} ownership moved from {} to {}",
+        drive_canister_id,
+        previous_owner,
+        new_owner
+    );
+    Ok(())
+}
@@ -0,0 +1,285 @@
+// payments.rs
+
+use candid::{CandidType, Nat, Principal};
+use ic_cdk::caller;
+use ic_cdk_macros::*;
+use serde::{Deserialize, Serialize};
+use serde_bytes::ByteBuf;
+
+use crate::{is_admin, DriveCanisterId, STATE};
+
+// Tags sweeps and refunds on the ledger so they can be traced back to signups
+const PAYMENT_MEMO: &[u8] = b"officex-drive";
+const REFUND_MEMO: &[u8] = b"officex-refund";
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum PaymentToken {
+    Icp,
+    CkBTC,
+}
+
+/// Price of one drive in the token's smallest unit (e8s for ICP, satoshis
+/// for ckBTC), payable on `ledger`.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct TokenPrice {
+    pub token: PaymentToken,
+    pub ledger: Principal,
+    pub amount: u64,
+}
+
+/// A deposit swept into the factory's main account for one drive.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Payment {
+    pub payer: Principal,
+    pub token: PaymentToken,
+    pub ledger: Principal,
+    pub amount: u64,
+    pub block_index: u64,
+    pub paid_at_ns: u64,
+}
+
+// ICRC-1 ledger interface, shared by the ICP and ckBTC ledgers
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub(crate) struct Account {
+    owner: Principal,
+    subaccount: Option<ByteBuf>,
+}
+
+#[derive(CandidType)]
+struct TransferArg {
+    from_subaccount: Option<ByteBuf>,
+    to: Account,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<ByteBuf>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+enum TransferError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    TemporarilyUnavailable,
+    Duplicate { duplicate_of: Nat },
+    GenericError { error_code: Nat, message: String },
+}
+
+fn nat_to_u64(value: &Nat) -> Result<u64, String> {
+    u64::try_from(value.0.clone()).map_err(|_| format!("Ledger amount {} does not fit in 64 bits", value))
+}
+
+/// Subaccount of the factory that `payer` deposits into, following the usual
+/// convention of a length-prefixed principal padded to 32 bytes.
+fn deposit_subaccount(payer: Principal) -> ByteBuf {
+    let bytes = payer.as_slice();
+    let mut subaccount = vec![0u8; 32];
+    subaccount[0] = bytes.len() as u8;
+    subaccount[1..1 + bytes.len()].copy_from_slice(bytes);
+    ByteBuf::from(subaccount)
+}
+
+fn deposit_account(payer: Principal) -> Account {
+    Account {
+        owner: ic_cdk::id(),
+        subaccount: Some(deposit_subaccount(payer)),
+    }
+}
+
+async fn ledger_fee(ledger: Principal) -> Result<u64, String> {
+    let (fee,): (Nat,) = ic_cdk::call(ledger, "icrc1_fee", ())
+        .await
+        .map_err(|(code, message)| format!("Failed to fetch ledger fee: {:?} {}", code, message))?;
+    nat_to_u64(&fee)
+}
+
+async fn ledger_balance(ledger: Principal, account: Account) -> Result<u64, String> {
+    let (balance,): (Nat,) = ic_cdk::call(ledger, "icrc1_balance_of", (account,))
+        .await
+        .map_err(|(code, message)| format!("Failed to fetch deposit balance: {:?} {}", code, message))?;
+    nat_to_u64(&balance)
+}
+
+async fn ledger_transfer(ledger: Principal, arg: TransferArg) -> Result<u64, String> {
+    let (result,): (Result<Nat, TransferError>,) = ic_cdk::call(ledger, "icrc1_transfer", (arg,))
+        .await
+        .map_err(|(code, message)| format!("Failed to call ledger: {:?} {}", code, message))?;
+    match result {
+        Ok(block_index) => nat_to_u64(&block_index),
+        Err(e) => Err(format!("Ledger rejected transfer: {:?}", e)),
+    }
+}
+
+/// Takes payment for a new drive from the caller's deposit subaccount and
+/// returns anything deposited beyond the price to the payer's default
+/// account. Returns `None` when no prices are configured and drives are free.
+pub async fn collect_payment(payer: Principal, token: Option<PaymentToken>) -> Result<Option<Payment>, String> {
+    let price = STATE.with(|state| {
+        let state = state.borrow();
+        if state.drive_prices.is_empty() {
+            return Ok(None);
+        }
+        let token = token.ok_or_else(|| "Drive creation requires payment, see get_pricing".to_string())?;
+        state
+            .drive_prices
+            .get(&token)
+            .cloned()
+            .map(Some)
+            .ok_or_else(|| format!("{:?} is not accepted as payment", token))
+    })?;
+    let Some(price) = price else {
+        return Ok(None);
+    };
+
+    let fee = ledger_fee(price.ledger).await?;
+    let balance = ledger_balance(price.ledger, deposit_account(payer)).await?;
+    if balance < price.amount.saturating_add(fee) {
+        return Err(format!(
+            "Insufficient deposit: found {}, need {} plus the {} ledger fee",
+            balance, price.amount, fee
+        ));
+    }
+
+    let amount = price.amount;
+    let block_index = ledger_transfer(
+        price.ledger,
+        TransferArg {
+            from_subaccount: Some(deposit_subaccount(payer)),
+            to: Account { owner: ic_cdk::id(), subaccount: None },
+            amount: Nat::from(amount),
+            fee: Some(Nat::from(fee)),
+            memo: Some(ByteBuf::from(PAYMENT_MEMO.to_vec())),
+            created_at_time: None,
+        },
+    )
+    .await?;
+
+    ic_cdk::println!("Collected {} {:?} from {} at block {}", amount, price.token, payer, block_index);
+
+    // An overpayment too small to cover the fee, or whose return fails, stays
+    // in the deposit subaccount and counts toward the payer's next drive
+    if let Some(refund) = overpayment_refund(balance, amount, fee) {
+        let returned = ledger_transfer(
+            price.ledger,
+            TransferArg {
+                from_subaccount: Some(deposit_subaccount(payer)),
+                to: Account { owner: payer, subaccount: None },
+                amount: Nat::from(refund),
+                fee: Some(Nat::from(fee)),
+                memo: Some(ByteBuf::from(REFUND_MEMO.to_vec())),
+                created_at_time: None,
+            },
+        )
+        .await;
+        match returned {
+            Ok(block_index) => {
+                ic_cdk::println!("Returned {} overpaid {:?} to {} at block {}", refund, price.token, payer, block_index);
+            }
+            Err(e) => ic_cdk::println!("Failed to return overpayment to {}: {}", payer, e),
+        }
+    }
+    Ok(Some(Payment {
+        payer,
+        token: price.token,
+        ledger: price.ledger,
+        amount,
+        block_index,
+        paid_at_ns: ic_cdk::api::time(),
+    }))
+}
+
+/// What goes back to a payer whose deposit of `balance` covered `price` and
+/// the fee of collecting it: the rest, less the fee of returning it. `None`
+/// when nothing would be left to return.
+pub(crate) fn overpayment_refund(balance: u64, price: u64, fee: u64) -> Option<u64> {
+    let overpaid = balance.checked_sub(price)?.checked_sub(fee)?;
+    overpaid.checked_sub(fee).filter(|refund| *refund > 0)
+}
+
+async fn send_refund(payment: &Payment) -> Result<u64, String> {
+    let fee = ledger_fee(payment.ledger).await?;
+    if payment.amount <= fee {
+        return Err("Payment does not cover the refund fee".to_string());
+    }
+    ledger_transfer(
+        payment.ledger,
+        TransferArg {
+            from_subaccount: None,
+            to: Account { owner: payment.payer, subaccount: None },
+            amount: Nat::from(payment.amount - fee),
+            fee: Some(Nat::from(fee)),
+            memo: Some(ByteBuf::from(REFUND_MEMO.to_vec())),
+            created_at_time: None,
+        },
+    )
+    .await
+}
+
+/// Returns a payment to the payer's default account after a failed install.
+/// Refunds that fail are kept for an admin to retry.
+pub async fn refund_payment(payment: Payment) {
+    match send_refund(&payment).await {
+        Ok(block_index) => {
+            ic_cdk::println!("Refunded {:?} payment to {} at block {}", payment.token, payment.payer, block_index);
+        }
+        Err(e) => {
+            ic_cdk::println!("Failed to refund {}: {}", payment.payer, e);
+            STATE.with(|state| state.borrow_mut().failed_refunds.push(payment));
+        }
+    }
+}
+
+pub fn record_drive_payment(drive: DriveCanisterId, payment: Payment) {
+    STATE.with(|state| state.borrow_mut().drive_payments.insert(drive, payment));
+}
+
+#[query]
+fn get_pricing() -> Vec<TokenPrice> {
+    STATE.with(|state| state.borrow().drive_prices.values().cloned().collect())
+}
+
+/// Account the caller sends tokens to before calling `create_drive`. The
+/// same account is used for every accepted token, each on its own ledger.
+#[query]
+fn get_deposit_account() -> Account {
+    deposit_account(caller())
+}
+
+/// Sets the drive price in `token`, or stops accepting it when `amount` is
+/// omitted. With no prices set, drive creation is free.
+#[update(guard = "is_admin")]
+fn set_drive_price(token: PaymentToken, ledger: Principal, amount: Option<u64>) {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        match amount {
+            Some(amount) => {
+                state.drive_prices.insert(token, TokenPrice { token, ledger, amount });
+            }
+            None => {
+                state.drive_prices.remove(&token);
+            }
+        }
+    });
+}
+
+#[query(guard = "is_admin")]
+fn list_failed_refunds() -> Vec<Payment> {
+    STATE.with(|state| state.borrow().failed_refunds.clone())
+}
+
+/// Retries every failed refund once; those that fail again stay queued.
+#[update(guard = "is_admin")]
+async fn retry_failed_refunds() -> u32 {
+    let pending = STATE.with(|state| std::mem::take(&mut state.borrow_mut().failed_refunds));
+    let mut refunded = 0;
+    for payment in pending {
+        match send_refund(&payment).await {
+            Ok(_) => refunded += 1,
+            Err(_) => STATE.with(|state| state.borrow_mut().failed_refunds.push(payment)),
+        }
+    }
+    refunded
+}
@@ -0,0 +1,54 @@
+// persistence.rs
+
+use ic_stable_structures::memory_manager::MemoryId;
+use ic_stable_structures::StableCell;
+use std::cell::RefCell;
+
+use crate::schema;
+use crate::wasm_store::{Memory, MEMORY_MANAGER};
+use crate::{State, STATE};
+
+// Ids 0-3 belong to the drive WASM store
+const HEAP_STATE_MEMORY_ID: MemoryId = MemoryId::new(4);
+
+thread_local! {
+    // Serialized factory State, written in pre_upgrade
+    static HEAP_STATE: RefCell<StableCell<Vec<u8>, Memory>> = RefCell::new(
+        StableCell::init(MEMORY_MANAGER.with(|m| m.borrow().get(HEAP_STATE_MEMORY_ID)), Vec::new())
+            .expect("Failed to initialize factory state cell")
+    );
+}
+
+pub fn save_heap_state() {
+    let bytes = STATE.with(|state| serde_cbor::to_vec(&*state.borrow()))
+        .expect("Failed to serialize factory state");
+    HEAP_STATE.with(|cell| cell.borrow_mut().set(bytes))
+        .expect("Failed to write factory state to stable memory");
+}
+
+/// Decodes state saved by `save_heap_state`, migrating it to the current
+/// schema. Returns `None` when nothing was saved.
+pub(crate) fn decode_heap_state(bytes: &[u8]) -> Option<State> {
+    if bytes.is_empty() {
+        return None;
+    }
+    let stored: serde_cbor::Value = serde_cbor::from_slice(bytes)
+        .expect("Failed to deserialize factory state");
+    let restored = serde_cbor::value::from_value(schema::migrate(stored))
+        .expect("Failed to decode migrated factory state");
+    Some(restored)
+}
+
+/// Restores the State saved by the previous version. Factories upgraded from
+/// a build that never saved any state start from an empty directory.
+pub fn restore_heap_state() {
+    let bytes = HEAP_STATE.with(|cell| cell.borrow().get().clone());
+    let Some(restored) = decode_heap_state(&bytes) else {
+        ic_cdk::println!("No saved factory state found, starting empty");
+        return;
+    };
+    STATE.with(|state| *state.borrow_mut() = restored);
+    // Free the copy held by the cell; it is rewritten on the next upgrade
+    HEAP_STATE.with(|cell| cell.borrow_mut().set(Vec::new()))
+        .expect("Failed to clear stored factory state");
+}
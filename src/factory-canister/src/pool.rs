@@ -0,0 +1,152 @@
+// pool.rs
+
+use candid::{CandidType, Principal};
+use ic_cdk::api::management_canister::main::{update_settings, CanisterSettings, UpdateSettingsArgument};
+use ic_cdk_macros::*;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::deletion::delete_drive_canister;
+use crate::team_drives::TeamMember;
+use crate::upgrades::{upgrade_drive_canister, wasm_hash};
+use crate::wasm_store::current_drive_wasm;
+use crate::{install_new_drive, is_admin, DriveCanisterId, STATE};
+
+const POOL_REFILL_INTERVAL: Duration = Duration::from_secs(5 * 60);
+const STANDBY_USERNAME: &str = "Standby";
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct StandbyPoolInfo {
+    target_size: u32,
+    standby_drives: Vec<DriveCanisterId>,
+}
+
+/// Tops the pool up to its target size, one drive at a time. Only one refill
+/// runs at once, since each step awaits canister creation.
+async fn refill_standby_pool() {
+    let already_running = STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        std::mem::replace(&mut state.standby_pool_refilling, true)
+    });
+    if already_running {
+        return;
+    }
+
+    loop {
+        let missing = STATE.with(|state| {
+            let state = state.borrow();
+            (state.standby_drives.len() as u32) < state.standby_pool_size
+        });
+        if !missing {
+            break;
+        }
+        // The factory owns standby drives until `configure_drive` hands them over
+        match install_new_drive(ic_cdk::id(), STANDBY_USERNAME, vec![ic_cdk::id()], None).await {
+            Ok(drive_canister_id) => {
+                ic_cdk::println!("Added standby drive {}", drive_canister_id);
+                STATE.with(|state| state.borrow_mut().standby_drives.push_back(drive_canister_id));
+            }
+            Err(e) => {
+                ic_cdk::println!("Failed to add standby drive: {}", e);
+                break;
+            }
+        }
+    }
+
+    STATE.with(|state| state.borrow_mut().standby_pool_refilling = false);
+}
+
+pub fn start_standby_pool_refill() {
+    ic_cdk_timers::set_timer_interval(POOL_REFILL_INTERVAL, || ic_cdk::spawn(refill_standby_pool()));
+}
+
+/// Hands a standby drive over to `owner`: brings it to the current WASM if a
+/// newer one was committed since it was installed, sets its owner (and its
+/// members, for a team drive), and makes `owner` a controller alongside the
+/// factory.
+async fn configure_standby_drive(
+    drive_canister_id: DriveCanisterId,
+    owner: Principal,
+    username: &str,
+    members: Option<&[TeamMember]>,
+) -> Result<(), String> {
+    let current_hash = wasm_hash(&current_drive_wasm());
+    let outdated = STATE.with(|state| {
+        state
+            .borrow()
+            .drive_wasm_records
+            .get(&drive_canister_id)
+            .is_none_or(|record| record.wasm_hash != current_hash)
+    });
+    // A standby drive holds nothing yet, so one that kept no state may start over
+    if outdated {
+        upgrade_drive_canister(drive_canister_id, true).await?;
+    }
+
+    let result: Result<(Result<(), candid::Reserved>,), _> =
+        ic_cdk::call(drive_canister_id, "configure_drive", (owner, username, members)).await;
+    match result {
+        Ok((Ok(()),)) => {}
+        Ok((Err(_),)) => return Err("Drive refused configuration".to_string()),
+        Err((code, message)) => return Err(format!("Failed to configure drive: {:?} {}", code, message)),
+    }
+
+    update_settings(UpdateSettingsArgument {
+        canister_id: drive_canister_id,
+        settings: CanisterSettings {
+            controllers: Some(vec![ic_cdk::id(), owner]),
+            ..Default::default()
+        },
+    })
+    .await
+    .map_err(|e| format!("Failed to update drive controllers: {:?}", e))
+}
+
+/// Takes a drive from the standby pool and configures it for `owner`. Returns
+/// `None` when the pool is empty or the drive could not be configured, in
+/// which case the caller should create a drive from scratch. A drive that
+/// fails configuration may already be half handed over, so it is deleted
+/// rather than returned to the pool.
+pub async fn claim_standby_drive(
+    owner: Principal,
+    username: &str,
+    members: Option<&[TeamMember]>,
+) -> Option<DriveCanisterId> {
+    let drive_canister_id = STATE.with(|state| state.borrow_mut().standby_drives.pop_front())?;
+
+    // Replace the claimed drive right away rather than at the next tick
+    ic_cdk_timers::set_timer(Duration::ZERO, || ic_cdk::spawn(refill_standby_pool()));
+
+    match configure_standby_drive(drive_canister_id, owner, username, members).await {
+        Ok(()) => {
+            ic_cdk::println!("Claimed standby drive {} for {}", drive_canister_id, owner);
+            Some(drive_canister_id)
+        }
+        Err(e) => {
+            ic_cdk::println!("Failed to claim standby drive {}: {}", drive_canister_id, e);
+            if let Err(e) = delete_drive_canister(drive_canister_id).await {
+                ic_cdk::println!("Failed to delete standby drive {}: {}", drive_canister_id, e);
+            }
+            None
+        }
+    }
+}
+
+/// Number of standby drives to keep ready. Each one holds its initial cycles
+/// until claimed, so 0 (the default) turns the pool off.
+#[update(guard = "is_admin")]
+fn set_standby_pool_size(size: u32) {
+    STATE.with(|state| state.borrow_mut().standby_pool_size = size);
+    ic_cdk_timers::set_timer(Duration::ZERO, || ic_cdk::spawn(refill_standby_pool()));
+}
+
+#[query(guard = "is_admin")]
+fn get_standby_pool() -> StandbyPoolInfo {
+    STATE.with(|state| {
+        let state = state.borrow();
+        StandbyPoolInfo {
+            target_size: state.standby_pool_size,
+            standby_drives: state.standby_drives.iter().copied().collect(),
+        }
+    })
+}
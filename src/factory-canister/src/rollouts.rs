@@ -0,0 +1,281 @@
+// rollouts.rs
+
+use candid::{CandidType, Principal};
+use ic_cdk_macros::*;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashSet};
+use std::time::Duration;
+
+use crate::upgrades::{outdated_drives, upgrade_drive_canister, wasm_hash, UpgradeFailure};
+use crate::wasm_store::{current_drive_wasm, version_for_hash};
+use crate::{is_admin, DriveCanisterId, STATE};
+
+// Soak time between stages, so problems surface before the next batch
+const STAGE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct DriveVersion {
+    canister_id: DriveCanisterId,
+    wasm_hash: String,
+    // None when the WASM is no longer held by the factory
+    version: Option<u64>,
+    installed_at_ns: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct VersionGroup {
+    // None for drives installed before versions were recorded
+    wasm_hash: Option<String>,
+    version: Option<u64>,
+    drives: Vec<DriveCanisterId>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum RolloutStatus {
+    Running,
+    Halted { reason: String },
+    Completed,
+    Cancelled,
+}
+
+/// Fleet upgrade to `target_hash` that moves `stage_percent` of the drives
+/// outdated at the start per stage, and stops as soon as a stage fails.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct StagedRollout {
+    target_hash: String,
+    stage_percent: u8,
+    total_drives: u64,
+    stages_completed: u32,
+    upgraded: Vec<DriveCanisterId>,
+    // Drives that failed to upgrade or to answer `ping` afterwards; skipped
+    // by later stages
+    failed: Vec<UpgradeFailure>,
+    status: RolloutStatus,
+    started_at_ns: u64,
+    updated_at_ns: u64,
+}
+
+impl StagedRollout {
+    fn stage_size(&self) -> usize {
+        let size = (self.total_drives * self.stage_percent as u64).div_ceil(100);
+        size.max(1) as usize
+    }
+
+    fn set_status(&mut self, status: RolloutStatus) {
+        self.status = status;
+        self.updated_at_ns = ic_cdk::api::time();
+    }
+}
+
+fn with_rollout<R>(f: impl FnOnce(&mut StagedRollout) -> R) -> Option<R> {
+    STATE.with(|state| state.borrow_mut().rollout.as_mut().map(f))
+}
+
+async fn check_drive_health(canister_id: DriveCanisterId) -> Result<(), String> {
+    let result: Result<(String,), _> = ic_cdk::call(canister_id, "ping", ()).await;
+    match result {
+        Ok((reply,)) if reply == "pong" => Ok(()),
+        Ok((reply,)) => Err(format!("Health check returned {:?}", reply)),
+        Err((code, message)) => Err(format!("Health check failed: {:?} {}", code, message)),
+    }
+}
+
+async fn run_rollout_stage() {
+    let Some((target_hash, stage_size, skipped)) = STATE.with(|state| {
+        let state = state.borrow();
+        let rollout = state.rollout.as_ref().filter(|rollout| rollout.status == RolloutStatus::Running)?;
+        let skipped: HashSet<DriveCanisterId> = rollout.failed.iter().map(|failure| failure.canister_id).collect();
+        Some((rollout.target_hash.clone(), rollout.stage_size(), skipped))
+    }) else {
+        return;
+    };
+
+    if wasm_hash(&current_drive_wasm()) != target_hash {
+        with_rollout(|rollout| {
+            rollout.set_status(RolloutStatus::Halted {
+                reason: "The active drive WASM changed during the rollout".to_string(),
+            })
+        });
+        return;
+    }
+
+    let pending: Vec<DriveCanisterId> = outdated_drives(&target_hash)
+        .into_iter()
+        .filter(|id| !skipped.contains(id))
+        .collect();
+    if pending.is_empty() {
+        with_rollout(|rollout| rollout.set_status(RolloutStatus::Completed));
+        return;
+    }
+
+    let mut upgraded = Vec::new();
+    let mut failed = Vec::new();
+    for canister_id in pending.iter().take(stage_size).copied() {
+        let result = match upgrade_drive_canister(canister_id, false).await {
+            Ok(()) => check_drive_health(canister_id).await,
+            Err(error) => Err(error),
+        };
+        match result {
+            Ok(()) => upgraded.push(canister_id),
+            Err(error) => failed.push(UpgradeFailure { canister_id, error }),
+        }
+    }
+
+    let remaining = pending.len().saturating_sub(stage_size);
+    let continue_rollout = with_rollout(|rollout| {
+        // An admin may have cancelled the rollout while this stage ran
+        if rollout.status != RolloutStatus::Running {
+            return false;
+        }
+        rollout.upgraded.extend(upgraded);
+        let failures = failed.len();
+        rollout.failed.extend(failed);
+        rollout.stages_completed += 1;
+        if failures > 0 {
+            rollout.set_status(RolloutStatus::Halted {
+                reason: format!("{} drives failed in stage {}", failures, rollout.stages_completed),
+            });
+            false
+        } else if remaining == 0 {
+            rollout.set_status(RolloutStatus::Completed);
+            false
+        } else {
+            rollout.updated_at_ns = ic_cdk::api::time();
+            true
+        }
+    });
+
+    if continue_rollout == Some(true) {
+        schedule_rollout_stage(STAGE_INTERVAL);
+    }
+}
+
+fn schedule_rollout_stage(delay: Duration) {
+    ic_cdk_timers::set_timer(delay, || ic_cdk::spawn(run_rollout_stage()));
+}
+
+/// Picks a running rollout back up after an upgrade, since timers are not kept.
+pub fn resume_staged_rollout() {
+    let running = STATE.with(|state| {
+        state
+            .borrow()
+            .rollout
+            .as_ref()
+            .is_some_and(|rollout| rollout.status == RolloutStatus::Running)
+    });
+    if running {
+        schedule_rollout_stage(STAGE_INTERVAL);
+    }
+}
+
+#[query]
+fn get_drive_version(canister_id: Principal) -> Option<DriveVersion> {
+    let record = STATE.with(|state| state.borrow().drive_wasm_records.get(&canister_id).cloned())?;
+    Some(DriveVersion {
+        canister_id,
+        version: version_for_hash(&record.wasm_hash),
+        wasm_hash: record.wasm_hash,
+        installed_at_ns: record.installed_at_ns,
+    })
+}
+
+/// Drives grouped by the WASM they run.
+#[query(guard = "is_admin")]
+fn list_drives_by_version() -> Vec<VersionGroup> {
+    let groups = STATE.with(|state| {
+        let state = state.borrow();
+        let mut groups: BTreeMap<Option<String>, Vec<DriveCanisterId>> = BTreeMap::new();
+        for id in state.drives_directory.values() {
+            let hash = state.drive_wasm_records.get(id).map(|record| record.wasm_hash.clone());
+            groups.entry(hash).or_default().push(*id);
+        }
+        groups
+    });
+    groups
+        .into_iter()
+        .map(|(wasm_hash, drives)| VersionGroup {
+            version: wasm_hash.as_deref().and_then(version_for_hash),
+            wasm_hash,
+            drives,
+        })
+        .collect()
+}
+
+/// Starts upgrading every outdated drive to the active WASM, `stage_percent`
+/// of them at a time. Each upgraded drive must answer `ping` before the next
+/// stage starts; any failure halts the rollout.
+#[update(guard = "is_admin")]
+fn start_staged_rollout(stage_percent: u8) -> Result<StagedRollout, String> {
+    if !(1..=100).contains(&stage_percent) {
+        return Err("Stage percent must be between 1 and 100".to_string());
+    }
+    let running = STATE.with(|state| {
+        state
+            .borrow()
+            .rollout
+            .as_ref()
+            .is_some_and(|rollout| rollout.status == RolloutStatus::Running)
+    });
+    if running {
+        return Err("A rollout is already running".to_string());
+    }
+
+    let target_hash = wasm_hash(&current_drive_wasm());
+    let outdated = outdated_drives(&target_hash);
+    if outdated.is_empty() {
+        return Err("Every drive already runs the active WASM".to_string());
+    }
+
+    let now = ic_cdk::api::time();
+    let rollout = StagedRollout {
+        target_hash,
+        stage_percent,
+        total_drives: outdated.len() as u64,
+        stages_completed: 0,
+        upgraded: Vec::new(),
+        failed: Vec::new(),
+        status: RolloutStatus::Running,
+        started_at_ns: now,
+        updated_at_ns: now,
+    };
+    STATE.with(|state| state.borrow_mut().rollout = Some(rollout.clone()));
+    schedule_rollout_stage(Duration::ZERO);
+    Ok(rollout)
+}
+
+/// Continues a halted rollout. Drives that already failed are left alone.
+#[update(guard = "is_admin")]
+fn resume_rollout() -> Result<(), String> {
+    let resumed = with_rollout(|rollout| {
+        if !matches!(rollout.status, RolloutStatus::Halted { .. }) {
+            return false;
+        }
+        rollout.set_status(RolloutStatus::Running);
+        true
+    });
+    if resumed != Some(true) {
+        return Err("No halted rollout to resume".to_string());
+    }
+    schedule_rollout_stage(Duration::ZERO);
+    Ok(())
+}
+
+#[update(guard = "is_admin")]
+fn cancel_rollout() -> Result<(), String> {
+    let cancelled = with_rollout(|rollout| {
+        if matches!(rollout.status, RolloutStatus::Completed | RolloutStatus::Cancelled) {
+            return false;
+        }
+        rollout.set_status(RolloutStatus::Cancelled);
+        true
+    });
+    if cancelled != Some(true) {
+        return Err("No rollout in progress".to_string());
+    }
+    Ok(())
+}
+
+#[query(guard = "is_admin")]
+fn get_rollout_status() -> Option<StagedRollout> {
+    STATE.with(|state| state.borrow().rollout.clone())
+}
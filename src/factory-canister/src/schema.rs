@@ -0,0 +1,76 @@
+// schema.rs
+//
+// Versioning for the factory State carried across upgrades. It is stored as
+// CBOR, so it is upgraded as a generic CBOR value before being decoded into
+// the State of the new code:
+//
+// 1. Migrations run in order from the stored version up to SCHEMA_VERSION,
+//    for changes a default cannot cover: renamed fields, changed types, or
+//    values derived from existing data.
+// 2. Fields still missing are filled in from a fresh State, so adding a
+//    field needs no migration as long as its `State::new` value suits
+//    existing factories too.
+
+use serde_cbor::Value;
+use std::collections::BTreeMap;
+
+use crate::State;
+
+/// Bump when adding a migration below.
+pub const SCHEMA_VERSION: u32 = 2;
+
+/// State saved before versioning has no `schema_version` field.
+const UNVERSIONED: u32 = 1;
+
+type StateFields = BTreeMap<Value, Value>;
+type Migration = fn(&mut StateFields);
+
+/// Each entry upgrades state from the version it is listed with to the next.
+const MIGRATIONS: &[(u32, Migration)] = &[(1, migrate_v1_to_v2)];
+
+/// Introduces `schema_version` itself; the field is written after migrating.
+fn migrate_v1_to_v2(_fields: &mut StateFields) {}
+
+fn key(name: &str) -> Value {
+    Value::Text(name.to_string())
+}
+
+fn stored_version(fields: &StateFields) -> u32 {
+    match fields.get(&key("schema_version")) {
+        Some(Value::Integer(version)) => u32::try_from(*version).unwrap_or(UNVERSIONED),
+        _ => UNVERSIONED,
+    }
+}
+
+/// Brings stored state up to the current schema. Traps on state written by
+/// newer code, as decoding it could silently drop data.
+pub fn migrate(value: Value) -> Value {
+    let Value::Map(mut fields) = value else {
+        ic_cdk::trap("Stored factory state is not a map");
+    };
+    let version = stored_version(&fields);
+    if version > SCHEMA_VERSION {
+        ic_cdk::trap(&format!(
+            "Stored factory state has schema version {}, newer than this build's {}",
+            version, SCHEMA_VERSION
+        ));
+    }
+
+    for (from, migration) in MIGRATIONS {
+        if *from >= version {
+            migration(&mut fields);
+        }
+    }
+
+    // Round-tripped through bytes, since `to_value` rejects the u128 cycle amounts
+    let fresh: Value = serde_cbor::to_vec(&State::new())
+        .and_then(|bytes| serde_cbor::from_slice(&bytes))
+        .expect("Failed to serialize default factory state");
+    if let Value::Map(defaults) = fresh {
+        for (name, default) in defaults {
+            fields.entry(name).or_insert(default);
+        }
+    }
+    fields.insert(key("schema_version"), Value::Integer(SCHEMA_VERSION.into()));
+    Value::Map(fields)
+}
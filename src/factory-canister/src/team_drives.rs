@@ -0,0 +1,86 @@
+// team_drives.rs
+
+use candid::{CandidType, Principal};
+use ic_cdk::caller;
+use ic_cdk_macros::*;
+use serde::{Deserialize, Serialize};
+
+use crate::creation_lock::CreationLock;
+use crate::deletion::delete_drive_canister;
+use crate::payments::PaymentToken;
+use crate::{check_can_create_drive, is_valid_username, provision_drive, sanitize_username, DriveCanisterId, STATE};
+
+// Matches the drive's own member limit
+const MAX_TEAM_MEMBERS: usize = 500;
+
+/// Role of a team drive member, as understood by the drive canister.
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MemberRole {
+    Viewer,
+    Editor,
+    Admin,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct TeamMember {
+    pub principal: Principal,
+    pub role: MemberRole,
+}
+
+/// Creates a team drive owned by the caller, shared with `members` from the
+/// start. Unlike personal drives a user may own any number of these; each
+/// needs its own username and, when prices are set, its own payment.
+#[update]
+async fn create_team_drive(
+    username: String,
+    members: Vec<TeamMember>,
+    payment_token: Option<PaymentToken>,
+) -> Result<String, String> {
+    let caller: Principal = caller();
+    check_can_create_drive(caller)?;
+
+    let sanitized_username = sanitize_username(&username);
+    if !is_valid_username(&sanitized_username) {
+        return Err("Invalid username format".to_string());
+    }
+    if members.len() > MAX_TEAM_MEMBERS {
+        return Err(format!("A team drive can have at most {} members", MAX_TEAM_MEMBERS));
+    }
+    if members
+        .iter()
+        .any(|member| member.principal == caller || member.principal == Principal::anonymous())
+    {
+        return Err("Members cannot include the owner or the anonymous principal".to_string());
+    }
+
+    let _lock = CreationLock::acquire(caller)?;
+    provision_drive(caller, &sanitized_username, payment_token, Some(&members))
+        .await
+        .map(|drive_canister_id| drive_canister_id.to_string())
+}
+
+/// Team drives the caller owns.
+#[query]
+fn list_my_team_drives() -> Vec<DriveCanisterId> {
+    let caller: Principal = caller();
+    STATE.with(|state| {
+        state
+            .borrow()
+            .team_drives
+            .iter()
+            .filter(|(_, owner)| **owner == caller)
+            .map(|(id, _)| *id)
+            .collect()
+    })
+}
+
+/// Deletes a team drive the caller owns and returns the cycles reclaimed.
+#[update]
+async fn delete_team_drive(canister_id: Principal) -> Result<u128, String> {
+    let caller: Principal = caller();
+    let owned = STATE.with(|state| state.borrow().team_drives.get(&canister_id) == Some(&caller));
+    if !owned {
+        return Err("Caller does not own this team drive".to_string());
+    }
+    delete_drive_canister(canister_id).await
+}
@@ -0,0 +1,155 @@
+// upgrades.rs
+
+use candid::{CandidType, Encode, Principal};
+use ic_cdk::api::management_canister::main::{install_code, CanisterInstallMode, InstallCodeArgument};
+use ic_cdk_macros::*;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::team_drives::TeamMember;
+use crate::wasm_store::current_drive_wasm;
+use crate::{is_admin, DriveCanisterId, STATE};
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct DriveWasmRecord {
+    pub wasm_hash: String,
+    pub installed_at_ns: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct UpgradeFailure {
+    pub canister_id: DriveCanisterId,
+    pub error: String,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default)]
+pub(crate) struct UpgradeReport {
+    upgraded: Vec<DriveCanisterId>,
+    failed: Vec<UpgradeFailure>,
+    remaining: u64,
+}
+
+pub fn wasm_hash(wasm: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(wasm);
+    format!("{:x}", hasher.finalize())
+}
+
+pub fn record_installed_wasm(canister_id: DriveCanisterId, wasm_hash: String) {
+    STATE.with(|state| {
+        state.borrow_mut().drive_wasm_records.insert(
+            canister_id,
+            DriveWasmRecord {
+                wasm_hash,
+                installed_at_ns: ic_cdk::api::time(),
+            },
+        );
+    });
+}
+
+fn is_known_drive(canister_id: &DriveCanisterId) -> bool {
+    STATE.with(|state| state.borrow().drives_directory.values().any(|id| id == canister_id))
+}
+
+/// Drives whose recorded WASM hash differs from `target_hash`, in creation order.
+pub fn outdated_drives(target_hash: &str) -> Vec<DriveCanisterId> {
+    STATE.with(|state| {
+        let state = state.borrow();
+        let mut indexed: Vec<(&u64, &DriveCanisterId)> = state.drives_directory.iter().collect();
+        indexed.sort_by_key(|(index, _)| **index);
+        indexed
+            .into_iter()
+            .filter(|(_, id)| {
+                state
+                    .drive_wasm_records
+                    .get(*id)
+                    .is_none_or(|record| record.wasm_hash != target_hash)
+            })
+            .map(|(_, id)| *id)
+            .collect()
+    })
+}
+
+/// The owner and username on record for a drive, in the drive's upgrade
+/// argument format. Drives that kept no state across upgrades start over
+/// from it when `fresh_start` is set, and refuse the upgrade otherwise; the
+/// rest ignore it.
+fn upgrade_arg(canister_id: DriveCanisterId, fresh_start: bool) -> Vec<u8> {
+    let (owner, username) = STATE.with(|state| {
+        let state = state.borrow();
+        let owner = state
+            .user_drive_directory
+            .iter()
+            .find(|(_, drive)| **drive == canister_id)
+            .map(|(owner, _)| *owner);
+        let username = state
+            .usernames
+            .iter()
+            .find(|(_, drive)| **drive == canister_id)
+            .map(|(username, _)| username.clone());
+        (owner, username)
+    });
+    Encode!(&owner, &username, &None::<Vec<TeamMember>>, &Some(fresh_start)).unwrap()
+}
+
+pub async fn upgrade_drive_canister(canister_id: DriveCanisterId, fresh_start: bool) -> Result<(), String> {
+    let wasm_module = current_drive_wasm();
+    let hash = wasm_hash(&wasm_module);
+
+    let install_code_arg = InstallCodeArgument {
+        mode: CanisterInstallMode::Upgrade(None),
+        canister_id,
+        wasm_module,
+        arg: upgrade_arg(canister_id, fresh_start),
+    };
+
+    match install_code(install_code_arg).await {
+        Ok(()) => {
+            ic_cdk::println!("Upgraded drive {} to wasm {}", canister_id, hash);
+            record_installed_wasm(canister_id, hash);
+            Ok(())
+        }
+        Err(e) => {
+            ic_cdk::println!("Failed to upgrade drive {}: {:?}", canister_id, e);
+            Err(format!("Failed to upgrade drive: {:?}", e))
+        }
+    }
+}
+
+/// Set `fresh_start` only for a drive that kept no state across upgrades and
+/// whose `snapshot_hashtables` export has been taken; it starts over empty.
+#[update(guard = "is_admin")]
+async fn upgrade_drive(canister_id: Principal, fresh_start: Option<bool>) -> Result<(), String> {
+    if !is_known_drive(&canister_id) {
+        return Err("Unknown drive canister".to_string());
+    }
+    upgrade_drive_canister(canister_id, fresh_start.unwrap_or(false)).await
+}
+
+/// Upgrades up to `batch_size` drives that are not yet running the current
+/// drive WASM. Call repeatedly until `remaining` reaches zero.
+#[update(guard = "is_admin")]
+async fn upgrade_all_drives(batch_size: u32) -> UpgradeReport {
+    let target_hash = wasm_hash(&current_drive_wasm());
+    let pending = outdated_drives(&target_hash);
+    let batch_len = pending.len().min(batch_size as usize);
+
+    let mut report = UpgradeReport {
+        remaining: (pending.len() - batch_len) as u64,
+        ..Default::default()
+    };
+
+    for canister_id in pending.into_iter().take(batch_len) {
+        match upgrade_drive_canister(canister_id, false).await {
+            Ok(()) => report.upgraded.push(canister_id),
+            Err(error) => report.failed.push(UpgradeFailure { canister_id, error }),
+        }
+    }
+
+    report
+}
+
+#[query]
+fn get_drive_wasm_record(canister_id: Principal) -> Option<DriveWasmRecord> {
+    STATE.with(|state| state.borrow().drive_wasm_records.get(&canister_id).cloned())
+}
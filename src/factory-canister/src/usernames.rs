@@ -0,0 +1,91 @@
+// usernames.rs
+
+use candid::Principal;
+use ic_cdk::caller;
+use ic_cdk_macros::*;
+
+use crate::{is_valid_username, sanitize_username, DriveCanisterId, STATE};
+
+/// Directory key for a username. Names are unique regardless of case.
+pub fn username_key(username: &str) -> String {
+    sanitize_username(username).to_lowercase()
+}
+
+fn is_key_taken(key: &str) -> bool {
+    STATE.with(|state| {
+        let state = state.borrow();
+        state.usernames.contains_key(key) || state.reserved_usernames.contains(key)
+    })
+}
+
+/// Holds a username for a drive that is still being created, so two signups
+/// cannot both pass the availability check. Released on drop unless the name
+/// was registered first.
+pub struct UsernameReservation {
+    key: String,
+}
+
+impl UsernameReservation {
+    pub fn acquire(username: &str) -> Result<Self, String> {
+        let key = username_key(username);
+        if is_key_taken(&key) {
+            return Err("Username is already taken".to_string());
+        }
+        STATE.with(|state| state.borrow_mut().reserved_usernames.insert(key.clone()));
+        Ok(Self { key })
+    }
+
+    /// Makes the reserved name point at the newly created drive.
+    pub fn register(self, drive_canister_id: DriveCanisterId) {
+        STATE.with(|state| state.borrow_mut().usernames.insert(self.key.clone(), drive_canister_id));
+    }
+}
+
+impl Drop for UsernameReservation {
+    fn drop(&mut self) {
+        STATE.with(|state| state.borrow_mut().reserved_usernames.remove(&self.key));
+    }
+}
+
+pub fn release_drive_usernames(drive_canister_id: DriveCanisterId) {
+    STATE.with(|state| state.borrow_mut().usernames.retain(|_, id| *id != drive_canister_id));
+}
+
+#[query]
+fn lookup_drive_by_username(username: String) -> Option<String> {
+    let key = username_key(&username);
+    STATE.with(|state| state.borrow().usernames.get(&key).map(|id| id.to_string()))
+}
+
+#[query]
+fn is_username_available(username: String) -> bool {
+    let sanitized_username = sanitize_username(&username);
+    is_valid_username(&sanitized_username) && !is_key_taken(&username_key(&sanitized_username))
+}
+
+/// Called by a drive canister before it renames its user. Moves the drive's
+/// directory entry to the new name, or rejects a name that is taken.
+#[update]
+fn notify_drive_username_changed(new_username: String) -> Result<(), String> {
+    let drive_canister_id: Principal = caller();
+    let sanitized_username = sanitize_username(&new_username);
+    if !is_valid_username(&sanitized_username) {
+        return Err("Invalid username format".to_string());
+    }
+    let key = username_key(&sanitized_username);
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if !state.drives_directory.values().any(|id| *id == drive_canister_id) {
+            return Err("Caller is not a registered drive".to_string());
+        }
+        let taken_by_other = state.usernames.get(&key).is_some_and(|id| *id != drive_canister_id)
+            || state.reserved_usernames.contains(&key);
+        if taken_by_other {
+            return Err("Username is already taken".to_string());
+        }
+        state.usernames.retain(|_, id| *id != drive_canister_id);
+        state.usernames.insert(key, drive_canister_id);
+        Ok(())
+    })
+}
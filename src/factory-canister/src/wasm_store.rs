@@ -0,0 +1,184 @@
+// wasm_store.rs
+
+use candid::{CandidType, Decode, Encode};
+use ic_cdk_macros::*;
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, StableCell, Storable};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use crate::upgrades::wasm_hash;
+use crate::{is_admin, DRIVE_WASM};
+
+pub type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+const STAGED_CHUNKS_MEMORY_ID: MemoryId = MemoryId::new(0);
+const WASM_BLOBS_MEMORY_ID: MemoryId = MemoryId::new(1);
+const WASM_VERSIONS_MEMORY_ID: MemoryId = MemoryId::new(2);
+const ACTIVE_VERSION_MEMORY_ID: MemoryId = MemoryId::new(3);
+
+/// Version number reserved for the drive WASM bundled into the factory binary.
+const BUNDLED_WASM_VERSION: u64 = 0;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct DriveWasmVersion {
+    pub version: u64,
+    pub wasm_hash: String,
+    pub size_bytes: u64,
+    pub uploaded_at_ns: u64,
+    pub uploaded_by: candid::Principal,
+}
+
+impl Storable for DriveWasmVersion {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+thread_local! {
+    pub static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+
+    static STAGED_CHUNKS: RefCell<StableBTreeMap<u32, Vec<u8>, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(STAGED_CHUNKS_MEMORY_ID)))
+    );
+
+    static WASM_BLOBS: RefCell<StableBTreeMap<u64, Vec<u8>, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(WASM_BLOBS_MEMORY_ID)))
+    );
+
+    static WASM_VERSIONS: RefCell<StableBTreeMap<u64, DriveWasmVersion, Memory>> = RefCell::new(
+        StableBTreeMap::init(MEMORY_MANAGER.with(|m| m.borrow().get(WASM_VERSIONS_MEMORY_ID)))
+    );
+
+    static ACTIVE_VERSION: RefCell<StableCell<u64, Memory>> = RefCell::new(
+        StableCell::init(
+            MEMORY_MANAGER.with(|m| m.borrow().get(ACTIVE_VERSION_MEMORY_ID)),
+            BUNDLED_WASM_VERSION,
+        ).expect("Failed to initialize active drive wasm version")
+    );
+}
+
+/// The drive WASM new and upgraded drives should run: the active uploaded
+/// version if one has been committed, otherwise the bundled build.
+pub fn current_drive_wasm() -> Vec<u8> {
+    let active = ACTIVE_VERSION.with(|cell| *cell.borrow().get());
+    if active == BUNDLED_WASM_VERSION {
+        return DRIVE_WASM.to_vec();
+    }
+    WASM_BLOBS
+        .with(|blobs| blobs.borrow().get(&active))
+        .unwrap_or_else(|| DRIVE_WASM.to_vec())
+}
+
+#[update(guard = "is_admin")]
+fn upload_drive_wasm(chunk: Vec<u8>, index: u32) -> Result<(), String> {
+    if chunk.is_empty() {
+        return Err("Empty chunk".to_string());
+    }
+    STAGED_CHUNKS.with(|chunks| chunks.borrow_mut().insert(index, chunk));
+    Ok(())
+}
+
+#[update(guard = "is_admin")]
+fn clear_staged_drive_wasm() {
+    STAGED_CHUNKS.with(|chunks| chunks.borrow_mut().clear_new());
+}
+
+/// Assembles the staged chunks, checks them against `sha256` and stores the
+/// result as a new active version. Returns the new version number.
+#[update(guard = "is_admin")]
+fn commit_drive_wasm(sha256: String) -> Result<u64, String> {
+    let wasm = STAGED_CHUNKS.with(|chunks| {
+        let chunks = chunks.borrow();
+        let mut wasm = Vec::new();
+        for (expected_index, (index, chunk)) in chunks.iter().enumerate() {
+            if index as usize != expected_index {
+                return Err(format!("Missing chunk at index {}", expected_index));
+            }
+            wasm.extend_from_slice(&chunk);
+        }
+        Ok(wasm)
+    })?;
+
+    if wasm.is_empty() {
+        return Err("No staged chunks to commit".to_string());
+    }
+
+    let hash = wasm_hash(&wasm);
+    if hash != sha256.to_lowercase() {
+        return Err(format!("Checksum mismatch: staged wasm hashes to {}", hash));
+    }
+
+    let version = WASM_VERSIONS.with(|versions| {
+        versions
+            .borrow()
+            .last_key_value()
+            .map_or(BUNDLED_WASM_VERSION + 1, |(last, _)| last + 1)
+    });
+
+    let record = DriveWasmVersion {
+        version,
+        wasm_hash: hash,
+        size_bytes: wasm.len() as u64,
+        uploaded_at_ns: ic_cdk::api::time(),
+        uploaded_by: ic_cdk::caller(),
+    };
+
+    WASM_BLOBS.with(|blobs| blobs.borrow_mut().insert(version, wasm));
+    WASM_VERSIONS.with(|versions| versions.borrow_mut().insert(version, record));
+    set_active_version(version)?;
+    STAGED_CHUNKS.with(|chunks| chunks.borrow_mut().clear_new());
+
+    ic_cdk::println!("Committed drive wasm version {}", version);
+    Ok(version)
+}
+
+/// Makes a previously committed version (or `0` for the bundled build) the
+/// WASM used for new drives and subsequent upgrades.
+#[update(guard = "is_admin")]
+fn rollback_drive_wasm(version: u64) -> Result<(), String> {
+    if version != BUNDLED_WASM_VERSION && !WASM_BLOBS.with(|blobs| blobs.borrow().contains_key(&version)) {
+        return Err("Unknown drive wasm version".to_string());
+    }
+    set_active_version(version)
+}
+
+fn set_active_version(version: u64) -> Result<(), String> {
+    ACTIVE_VERSION
+        .with(|cell| cell.borrow_mut().set(version))
+        .map(|_| ())
+        .map_err(|e| format!("Failed to persist active wasm version: {:?}", e))
+}
+
+#[query]
+fn list_drive_wasm_versions() -> Vec<DriveWasmVersion> {
+    WASM_VERSIONS.with(|versions| versions.borrow().iter().map(|(_, record)| record).collect())
+}
+
+#[query]
+fn get_active_drive_wasm_version() -> u64 {
+    ACTIVE_VERSION.with(|cell| *cell.borrow().get())
+}
+
+/// Version number of a drive WASM by hash, if the factory still knows it.
+pub fn version_for_hash(hash: &str) -> Option<u64> {
+    if wasm_hash(DRIVE_WASM) == hash {
+        return Some(BUNDLED_WASM_VERSION);
+    }
+    WASM_VERSIONS.with(|versions| {
+        versions
+            .borrow()
+            .iter()
+            .find(|(_, record)| record.wasm_hash == hash)
+            .map(|(version, _)| version)
+    })
+}
@@ -0,0 +1,96 @@
+// access.rs
+
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::DriveError;
+use crate::memory::StableMapExt;
+use crate::profiling::profile;
+use crate::rate_limit::check_update_allowed;
+use crate::system_api;
+use crate::{FileMetadata, FileUUID, State, STATE};
+
+const MAX_MOST_ACCESSED: u32 = 100;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub(crate) struct AccessStats {
+    file_id: FileUUID,
+    access_count: u64,
+    last_accessed_ms: Option<u64>,
+}
+
+impl From<&FileMetadata> for AccessStats {
+    fn from(file: &FileMetadata) -> Self {
+        AccessStats {
+            file_id: file.id.clone(),
+            access_count: file.access_count,
+            last_accessed_ms: file.last_accessed_ms,
+        }
+    }
+}
+
+impl State {
+    // An access is not a change, so last_changed_unix_ms is left alone
+    fn record_access(&mut self, file_id: &FileUUID) -> Result<(), DriveError> {
+        let now_ms = system_api::time() / 1_000_000;
+        self.file_uuid_to_metadata
+            .update(file_id, |file| {
+                file.access_count += 1;
+                file.last_accessed_ms = Some(now_ms);
+            })
+            .ok_or_else(|| DriveError::file_not_found(file_id))
+    }
+
+    fn list_most_accessed(&self, limit: u32) -> Vec<AccessStats> {
+        let mut stats: Vec<AccessStats> = self
+            .file_uuid_to_metadata
+            .values()
+            .filter(|file| file.next_version.is_none() && file.access_count > 0)
+            .map(|file| AccessStats::from(&file))
+            .collect();
+        stats.sort_by(|a, b| {
+            b.access_count
+                .cmp(&a.access_count)
+                .then_with(|| b.last_accessed_ms.cmp(&a.last_accessed_ms))
+        });
+        stats.truncate(limit.min(MAX_MOST_ACCESSED) as usize);
+        stats
+    }
+}
+
+/// Called by clients when a file is opened; bumps its access counter.
+#[ic_cdk::update]
+fn record_access(file_id: FileUUID) -> Result<(), DriveError> {
+    let _profile = profile("record_access");
+    check_update_allowed("record_access")?;
+    STATE.with(|state| state.borrow_mut().record_access(&file_id))
+}
+
+#[ic_cdk::query]
+fn get_access_stats(file_id: FileUUID) -> Result<AccessStats, DriveError> {
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let state = state.borrow();
+        if !state.is_drive_admin(caller) {
+            return Err(DriveError::unauthorized("Only a drive admin can view access stats"));
+        }
+        state
+            .file_uuid_to_metadata
+            .get(&file_id)
+            .map(|file| AccessStats::from(&file))
+            .ok_or_else(|| DriveError::file_not_found(&file_id))
+    })
+}
+
+/// The drive's most opened files, most accessed first.
+#[ic_cdk::query]
+fn list_most_accessed(limit: u32) -> Result<Vec<AccessStats>, DriveError> {
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let state = state.borrow();
+        if !state.is_drive_admin(caller) {
+            return Err(DriveError::unauthorized("Only a drive admin can view access stats"));
+        }
+        Ok(state.list_most_accessed(limit))
+    })
+}
@@ -0,0 +1,170 @@
+// activity.rs
+
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+use crate::audit::{AuditAction, AuditEntry};
+use crate::errors::DriveError;
+use crate::members::check_read_access;
+use crate::{FolderUUID, State, STATE};
+
+const MAX_ACTIVITY_PAGE_SIZE: u32 = 100;
+// Bounds the audit entries one call looks at, so sparse folders cannot make
+// a query run out of instructions; callers continue from `next_cursor`
+const MAX_SCANNED_ENTRIES: usize = 5_000;
+// Entries this close together by the same actor and kind collapse into one item
+const COALESCE_WINDOW_MS: u64 = 10 * 60 * 1000;
+const MAX_ITEM_RESOURCES: usize = 20;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ActivityKind {
+    FilesAdded,
+    FoldersCreated,
+    Renamed,
+    Moved,
+    Deleted,
+    Updated,
+}
+
+impl ActivityKind {
+    fn for_action(action: AuditAction) -> Option<Self> {
+        match action {
+            AuditAction::UpsertFile => Some(ActivityKind::FilesAdded),
+            AuditAction::CreateFolder => Some(ActivityKind::FoldersCreated),
+            AuditAction::RenameFile | AuditAction::RenameFolder => Some(ActivityKind::Renamed),
+            AuditAction::MoveFile | AuditAction::MigrateStorage => Some(ActivityKind::Moved),
+            AuditAction::DeleteFile | AuditAction::DeleteFolder => Some(ActivityKind::Deleted),
+            AuditAction::SyncFile
+            | AuditAction::SyncFolder
+            | AuditAction::UpdateFileMetadata
+            | AuditAction::SetTags
+            | AuditAction::SetProperty
+            | AuditAction::SetEncryption
+            | AuditAction::UploadComplete => Some(ActivityKind::Updated),
+            _ => None,
+        }
+    }
+}
+
+/// One line of the activity sidebar: a run of same-kind changes by one actor.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct ActivityItem {
+    actor: Principal,
+    kind: ActivityKind,
+    // e.g. "Renamed draft.docx to final.docx" or "Added 3 files"
+    summary: String,
+    count: u32,
+    // The first few resources involved, newest first
+    resource_ids: Vec<String>,
+    started_at_ms: u64,
+    ended_at_ms: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct ActivityPage {
+    items: Vec<ActivityItem>,
+    // Pass as `before` to fetch older activity
+    next_cursor: Option<u64>,
+}
+
+/// Last segment of a drive path, without the storage prefix or trailing slash.
+fn display_name(path: &str) -> &str {
+    let path = path.split_once("::").map_or(path, |(_, rest)| rest);
+    path.trim_end_matches('/').rsplit('/').next().unwrap_or(path)
+}
+
+fn describe(kind: ActivityKind, count: u32, entry: &AuditEntry) -> String {
+    let old_name = entry.old_path.as_deref().map(display_name).unwrap_or_default();
+    let new_name = entry.new_path.as_deref().map(display_name).unwrap_or_default();
+    if count > 1 {
+        return match kind {
+            ActivityKind::FilesAdded => format!("Added {} files", count),
+            ActivityKind::FoldersCreated => format!("Created {} folders", count),
+            ActivityKind::Renamed => format!("Renamed {} items", count),
+            ActivityKind::Moved => format!("Moved {} items", count),
+            ActivityKind::Deleted => format!("Deleted {} items", count),
+            ActivityKind::Updated => format!("Updated {} items", count),
+        };
+    }
+    match kind {
+        ActivityKind::FilesAdded => format!("Added {}", new_name),
+        ActivityKind::FoldersCreated => format!("Created folder {}", new_name),
+        ActivityKind::Renamed => format!("Renamed {} to {}", old_name, new_name),
+        ActivityKind::Moved => {
+            let destination = entry.new_path.as_deref().unwrap_or_default();
+            let parent = destination.trim_end_matches('/').rsplit_once('/').map_or("", |(parent, _)| parent);
+            format!("Moved {} to {}", old_name, display_name(parent))
+        }
+        ActivityKind::Deleted => format!("Deleted {}", old_name),
+        ActivityKind::Updated => format!("Updated {}", old_name),
+    }
+}
+
+impl State {
+    /// Changes to anything inside `folder_id`, at any depth, newest first.
+    fn get_activity(&self, folder_id: &FolderUUID, before: Option<u64>, limit: u32) -> Result<ActivityPage, DriveError> {
+        let folder = self
+            .folder_uuid_to_metadata
+            .get(folder_id)
+            .ok_or_else(|| DriveError::folder_not_found(folder_id))?;
+        let prefix = folder.full_folder_path;
+        let limit = limit.min(MAX_ACTIVITY_PAGE_SIZE) as usize;
+        let in_folder = |path: &Option<String>| path.as_ref().is_some_and(|path| path.starts_with(&prefix));
+
+        let mut items: Vec<ActivityItem> = Vec::new();
+        let mut next_cursor = None;
+        let mut scanned = 0;
+        for (seq, entry) in self.audit_log.range(..before.unwrap_or(u64::MAX)).rev() {
+            scanned += 1;
+            if scanned > MAX_SCANNED_ENTRIES {
+                next_cursor = Some(seq + 1);
+                break;
+            }
+            let Some(kind) = ActivityKind::for_action(entry.action) else {
+                continue;
+            };
+            if !in_folder(&entry.old_path) && !in_folder(&entry.new_path) {
+                continue;
+            }
+
+            // Walking backwards in time, so the current item started after this entry
+            if let Some(item) = items.last_mut() {
+                if item.actor == entry.caller
+                    && item.kind == kind
+                    && item.started_at_ms.saturating_sub(entry.timestamp_ms) <= COALESCE_WINDOW_MS
+                {
+                    item.count += 1;
+                    item.started_at_ms = entry.timestamp_ms;
+                    if item.resource_ids.len() < MAX_ITEM_RESOURCES {
+                        item.resource_ids.push(entry.resource_id.clone());
+                    }
+                    item.summary = describe(kind, item.count, &entry);
+                    continue;
+                }
+            }
+            // Stop only where a new item would begin, so items are never split
+            if items.len() == limit {
+                next_cursor = Some(seq + 1);
+                break;
+            }
+            items.push(ActivityItem {
+                actor: entry.caller,
+                kind,
+                summary: describe(kind, 1, &entry),
+                count: 1,
+                resource_ids: vec![entry.resource_id.clone()],
+                started_at_ms: entry.timestamp_ms,
+                ended_at_ms: entry.timestamp_ms,
+            });
+        }
+        Ok(ActivityPage { items, next_cursor })
+    }
+}
+
+/// Recent changes inside a folder and its subfolders, newest first, with
+/// bursts of similar changes by the same person merged into one item.
+#[ic_cdk::query]
+fn get_activity(folder_id: FolderUUID, before: Option<u64>, limit: u32) -> Result<ActivityPage, DriveError> {
+    check_read_access()?;
+    STATE.with(|state| state.borrow().get_activity(&folder_id, before, limit))
+}
@@ -0,0 +1,44 @@
+// ancestors.rs
+
+use crate::errors::DriveError;
+use crate::members::check_read_access;
+use crate::{FolderMetadata, State, STATE};
+
+// Far deeper than any real tree; stops a corrupted parent link from looping
+const MAX_FOLDER_DEPTH: usize = 1_000;
+
+impl State {
+    /// The folder chain from the storage root down to `id` if it is a folder,
+    /// or down to the folder holding it if it is a file.
+    pub(crate) fn get_ancestors(&self, id: &str) -> Result<Vec<FolderMetadata>, DriveError> {
+        let id = id.to_string();
+        let mut next = match self.file_uuid_to_metadata.get(&id) {
+            Some(file) => Some(file.folder_uuid),
+            None if self.folder_uuid_to_metadata.contains_key(&id) => Some(id),
+            None => return Err(DriveError::NotFound { resource: "item".to_string(), id }),
+        };
+
+        let mut chain = Vec::new();
+        while let Some(folder_id) = next {
+            if chain.len() >= MAX_FOLDER_DEPTH {
+                return Err(DriveError::Internal { message: format!("Folder {} is nested too deeply", folder_id) });
+            }
+            let folder = self
+                .folder_uuid_to_metadata
+                .get(&folder_id)
+                .ok_or_else(|| DriveError::folder_not_found(&folder_id))?;
+            next = folder.parent_folder_uuid.clone();
+            chain.push(folder);
+        }
+        chain.reverse();
+        Ok(chain)
+    }
+}
+
+/// Everything needed to render breadcrumbs for a folder or file in one call,
+/// root first.
+#[ic_cdk::query]
+fn get_ancestors(uuid: String) -> Result<Vec<FolderMetadata>, DriveError> {
+    check_read_access()?;
+    STATE.with(|state| state.borrow().get_ancestors(&uuid))
+}
@@ -0,0 +1,89 @@
+// anonymous.rs
+
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+use crate::audit::AuditAction;
+use crate::errors::DriveError;
+use crate::members::check_read_access;
+use crate::profiling::profile;
+use crate::read_only::check_writable;
+use crate::system_api;
+use crate::{State, STATE};
+
+/// Matches every update method in `denied_update_methods`.
+const ALL_METHODS: &str = "*";
+const MAX_DENIED_METHODS: usize = 200;
+
+/// Who may read the drive, and what callers without an identity may call.
+/// `inspect_message` drops denied ingress early, but the checks in
+/// `check_update_allowed` and `check_read_access` are the ones that count,
+/// as inspection can be skipped.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct AnonymousAccessConfig {
+    // Public drives answer queries from anyone, signed in or not; private
+    // drives only from members
+    pub public_drive: bool,
+    // Update methods refused to anonymous callers; "*" refuses all of them
+    pub denied_update_methods: Vec<String>,
+}
+
+impl Default for AnonymousAccessConfig {
+    fn default() -> Self {
+        Self { public_drive: false, denied_update_methods: vec![ALL_METHODS.to_string()] }
+    }
+}
+
+impl AnonymousAccessConfig {
+    fn denies(&self, method: &str) -> bool {
+        self.denied_update_methods.iter().any(|denied| denied == ALL_METHODS || denied == method)
+    }
+}
+
+impl State {
+    /// Fails if `caller` is anonymous and the drive refuses it `method`.
+    pub(crate) fn check_anonymous_update(&self, caller: Principal, method: &str) -> Result<(), DriveError> {
+        if caller == Principal::anonymous() && self.anonymous_access.denies(method) {
+            return Err(DriveError::unauthorized(&format!("Anonymous callers cannot call {}", method)));
+        }
+        Ok(())
+    }
+
+    /// Whether anyone, members or not, may read the drive.
+    pub(crate) fn is_public_drive(&self) -> bool {
+        self.anonymous_access.public_drive
+    }
+
+    fn set_anonymous_access(&mut self, caller: Principal, config: AnonymousAccessConfig) -> Result<(), DriveError> {
+        if caller != self.owner {
+            return Err(DriveError::unauthorized("Only the owner can change anonymous access"));
+        }
+        if config.denied_update_methods.len() > MAX_DENIED_METHODS {
+            return Err(DriveError::InvalidInput {
+                field: "denied_update_methods".to_string(),
+                reason: format!("At most {} methods", MAX_DENIED_METHODS),
+            });
+        }
+        self.anonymous_access = config;
+        let owner = self.owner.to_text();
+        self.record_audit(AuditAction::SetAnonymousAccess, &owner, None, None);
+        Ok(())
+    }
+}
+
+/// Makes the drive readable by anyone, or closes it to all but its members
+/// again, and sets which update methods anonymous callers are refused. New
+/// drives are private and refuse anonymous callers everywhere.
+#[ic_cdk::update]
+fn set_anonymous_access(config: AnonymousAccessConfig) -> Result<(), DriveError> {
+    let _profile = profile("set_anonymous_access");
+    check_writable()?;
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow_mut().set_anonymous_access(caller, config))
+}
+
+#[ic_cdk::query]
+fn get_anonymous_access() -> Result<AnonymousAccessConfig, DriveError> {
+    check_read_access()?;
+    STATE.with(|state| Ok(state.borrow().anonymous_access.clone()))
+}
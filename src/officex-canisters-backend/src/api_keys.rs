@@ -0,0 +1,203 @@
+// api_keys.rs
+
+use candid::{CandidType, Principal};
+use ic_cdk::api::management_canister::main::raw_rand;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::audit::AuditAction;
+use crate::errors::DriveError;
+use crate::metrics::count_call;
+use crate::profiling::profile;
+use crate::rate_limit::{charge_rate_limit, check_update_allowed};
+use crate::read_only::check_writable;
+use crate::system_api;
+use crate::{generate_unique_id, State, STATE};
+
+const MAX_API_KEYS: usize = 20;
+// Lets leaked keys be recognized by secret scanners
+const API_KEY_PREFIX: &str = "ofx_";
+
+/// What an API key may do on the owner's behalf.
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ApiScope {
+    // Issue download links, e.g. for backup bots
+    Read,
+    // Create folders and files and update file metadata, e.g. for importers
+    Write,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct ApiKey {
+    id: String,
+    name: String,
+    scopes: Vec<ApiScope>,
+    created_at_ms: u64,
+    expires_at_ms: Option<u64>,
+    last_used_ms: Option<u64>,
+    // Only the hash is kept; the secret itself is shown once, on creation
+    secret_sha256: String,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct CreatedApiKey {
+    key: ApiKey,
+    secret: String,
+}
+
+pub(crate) fn hash_secret(secret: &str) -> String {
+    format!("{:x}", Sha256::digest(secret.as_bytes()))
+}
+
+/// 32 random bytes from the management canister, hex encoded after `prefix`.
+pub(crate) async fn generate_secret(prefix: &str) -> Result<String, DriveError> {
+    let (random_bytes,) = raw_rand().await.map_err(|(code, message)| DriveError::Internal {
+        message: format!("Failed to generate a secret: {:?} {}", code, message),
+    })?;
+    Ok(std::iter::once(prefix.to_string())
+        .chain(random_bytes.iter().map(|byte| format!("{:02x}", byte)))
+        .collect())
+}
+
+impl State {
+    fn create_api_key(
+        &mut self,
+        caller: Principal,
+        name: String,
+        scopes: Vec<ApiScope>,
+        expires_at_ms: Option<u64>,
+        secret: &str,
+    ) -> Result<ApiKey, DriveError> {
+        if caller != self.owner {
+            return Err(DriveError::unauthorized("Only the owner can create API keys"));
+        }
+        if scopes.is_empty() {
+            return Err(DriveError::InvalidInput {
+                field: "scopes".to_string(),
+                reason: "Grant at least one scope".to_string(),
+            });
+        }
+        let now_ms = system_api::time() / 1_000_000;
+        if expires_at_ms.is_some_and(|expires_at_ms| expires_at_ms <= now_ms) {
+            return Err(DriveError::InvalidInput {
+                field: "expires_at_ms".to_string(),
+                reason: "Expiry must be in the future".to_string(),
+            });
+        }
+        if self.api_keys.len() >= MAX_API_KEYS {
+            return Err(DriveError::InvalidState {
+                reason: format!("A drive can have at most {} API keys", MAX_API_KEYS),
+            });
+        }
+
+        let key = ApiKey {
+            id: generate_unique_id(),
+            name,
+            scopes,
+            created_at_ms: now_ms,
+            expires_at_ms,
+            last_used_ms: None,
+            secret_sha256: hash_secret(secret),
+        };
+        self.api_keys.insert(key.id.clone(), key.clone());
+        self.record_audit(AuditAction::CreateApiKey, &key.id, None, None);
+        Ok(key)
+    }
+
+    fn revoke_api_key(&mut self, caller: Principal, id: &str) -> Result<(), DriveError> {
+        if caller != self.owner {
+            return Err(DriveError::unauthorized("Only the owner can revoke API keys"));
+        }
+        if self.api_keys.remove(id).is_none() {
+            return Err(DriveError::NotFound {
+                resource: "api key".to_string(),
+                id: id.to_string(),
+            });
+        }
+        self.record_audit(AuditAction::RevokeApiKey, id, None, None);
+        Ok(())
+    }
+
+    /// Checks `secret` against the stored hashes. A valid key with `scope`
+    /// lets the call act as the drive owner.
+    fn authorize_api_key(&mut self, secret: &str, scope: ApiScope) -> Result<Principal, DriveError> {
+        let secret_sha256 = hash_secret(secret);
+        let now_ms = system_api::time() / 1_000_000;
+        let key = self
+            .api_keys
+            .values_mut()
+            .find(|key| key.secret_sha256 == secret_sha256)
+            .ok_or_else(|| DriveError::unauthorized("Unknown or revoked API key"))?;
+        if key.expires_at_ms.is_some_and(|expires_at_ms| expires_at_ms <= now_ms) {
+            return Err(DriveError::unauthorized("API key has expired"));
+        }
+        if !key.scopes.contains(&scope) {
+            return Err(DriveError::unauthorized(&format!("API key lacks the {:?} scope", scope)));
+        }
+        key.last_used_ms = Some(now_ms);
+        Ok(self.owner)
+    }
+}
+
+/// `check_update_allowed` for endpoints that accept an `api_key`. Returns the
+/// principal the call acts as: the caller itself without a key, or the owner
+/// with a valid one. A key also stands in for membership.
+pub(crate) fn check_update_allowed_with_api_key(
+    method: &str,
+    api_key: Option<String>,
+    scope: ApiScope,
+) -> Result<Principal, DriveError> {
+    let caller = system_api::caller();
+    let Some(secret) = api_key else {
+        check_update_allowed(method)?;
+        return Ok(caller);
+    };
+    count_call(method);
+    check_writable()?;
+    charge_rate_limit(caller, method, 1)?;
+    STATE.with(|state| state.borrow_mut().authorize_api_key(&secret, scope))
+}
+
+/// Creates a key that server-side integrations pass to endpoints taking an
+/// `api_key` argument. The secret is returned only here; the drive keeps
+/// just its hash. Keys without `expires_at_ms` last until revoked.
+#[ic_cdk::update]
+async fn create_api_key(
+    name: String,
+    scopes: Vec<ApiScope>,
+    expires_at_ms: Option<u64>,
+) -> Result<CreatedApiKey, DriveError> {
+    let _profile = profile("create_api_key");
+    check_writable()?;
+    let caller = system_api::caller();
+    if STATE.with(|state| state.borrow().owner != caller) {
+        return Err(DriveError::unauthorized("Only the owner can create API keys"));
+    }
+    let secret = generate_secret(API_KEY_PREFIX).await?;
+    let key = STATE.with(|state| {
+        state
+            .borrow_mut()
+            .create_api_key(caller, name, scopes, expires_at_ms, &secret)
+    })?;
+    Ok(CreatedApiKey { key, secret })
+}
+
+#[ic_cdk::update]
+fn revoke_api_key(id: String) -> Result<(), DriveError> {
+    let _profile = profile("revoke_api_key");
+    check_writable()?;
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow_mut().revoke_api_key(caller, &id))
+}
+
+#[ic_cdk::query]
+fn list_api_keys() -> Result<Vec<ApiKey>, DriveError> {
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let state = state.borrow();
+        if caller != state.owner {
+            return Err(DriveError::unauthorized("Only the owner can list API keys"));
+        }
+        Ok(state.api_keys.values().cloned().collect())
+    })
+}
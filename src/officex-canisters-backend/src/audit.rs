@@ -0,0 +1,195 @@
+// audit.rs
+
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::Storable;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+use crate::errors::DriveError;
+use crate::system_api;
+use crate::{State, STATE};
+
+/// Oldest entries are dropped once the log holds this many.
+const MAX_AUDIT_ENTRIES: u64 = 10_000;
+pub(crate) const MAX_AUDIT_PAGE_SIZE: u32 = 500;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AuditAction {
+    CreateFolder,
+    UpsertFile,
+    RenameFolder,
+    RenameFile,
+    MoveFile,
+    DeleteFolder,
+    DeleteFile,
+    SyncFolder,
+    SyncFile,
+    UpdateFileMetadata,
+    SetTags,
+    UploadComplete,
+    UploadFailed,
+    UpdateUsername,
+    SetQuota,
+    SetDedup,
+    TransferOwnership,
+    CancelOwnershipTransfer,
+    AcceptOwnership,
+    RepairState,
+    AddComment,
+    DeleteComment,
+    SetProperty,
+    MigrateStorage,
+    SetEncryption,
+    CreateApiKey,
+    RevokeApiKey,
+    ShareItem,
+    UnshareItem,
+    SetMemberRole,
+    RemoveMember,
+    InviteMember,
+    AcceptInvitation,
+    DeclineInvitation,
+    RevokeInvitation,
+    SyncConflict,
+    ResolveConflict,
+    RegisterDevice,
+    RevokeDevice,
+    CompactTombstones,
+    ImportSnapshot,
+    SetBackupConfig,
+    RestoreBackup,
+    SetThumbnail,
+    SetMimeType,
+    CompactDocument,
+    SetAnonymousAccess,
+    SetFolderSettings,
+    SetPathLimits,
+    SetCaseInsensitivePaths,
+    CreateShortcut,
+    SetFolderColor,
+    PinFolder,
+    PublishFolder,
+    UnpublishFolder,
+    SetDirectoryProfile,
+    SetProfile,
+    AddContact,
+    RemoveContact,
+    SetExpiry,
+    ExpireFile,
+    RunJob,
+    SetMaxFileVersions,
+    SetStorageCredentials,
+    PinFile,
+    SetTokenGate,
+    SetPaywall,
+    PayForFile,
+    PlaceLegalHold,
+    ReleaseLegalHold,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct AuditEntry {
+    pub seq: u64,
+    pub timestamp_ms: u64,
+    pub caller: Principal,
+    pub action: AuditAction,
+    pub resource_id: String,
+    pub old_path: Option<String>,
+    pub new_path: Option<String>,
+}
+
+impl Storable for AuditEntry {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default)]
+pub(crate) struct AuditLogFilter {
+    caller: Option<Principal>,
+    action: Option<AuditAction>,
+    resource_id: Option<String>,
+    since_ms: Option<u64>,
+    until_ms: Option<u64>,
+}
+
+impl AuditLogFilter {
+    fn matches(&self, entry: &AuditEntry) -> bool {
+        self.caller.is_none_or(|caller| caller == entry.caller)
+            && self.action.is_none_or(|action| action == entry.action)
+            && self.resource_id.as_ref().is_none_or(|id| *id == entry.resource_id)
+            && self.since_ms.is_none_or(|since| entry.timestamp_ms >= since)
+            && self.until_ms.is_none_or(|until| entry.timestamp_ms <= until)
+    }
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct AuditLogPage {
+    entries: Vec<AuditEntry>,
+    // Pass as `before` to fetch the next (older) page
+    next_cursor: Option<u64>,
+}
+
+impl State {
+    /// Appends an entry attributed to the current caller.
+    pub(crate) fn record_audit(
+        &mut self,
+        action: AuditAction,
+        resource_id: &str,
+        old_path: Option<String>,
+        new_path: Option<String>,
+    ) {
+        let seq = self.audit_log.last_key_value().map_or(0, |(seq, _)| seq + 1);
+        let entry = AuditEntry {
+            seq,
+            timestamp_ms: system_api::time() / 1_000_000,
+            caller: system_api::caller(),
+            action,
+            resource_id: resource_id.to_string(),
+            old_path,
+            new_path,
+        };
+        self.enqueue_webhooks(&entry);
+        self.audit_log.insert(seq, entry);
+        while self.audit_log.len() > MAX_AUDIT_ENTRIES {
+            self.audit_log.pop_first();
+        }
+    }
+
+    fn get_audit_log(&self, filter: &AuditLogFilter, before: Option<u64>, limit: u32) -> AuditLogPage {
+        let limit = limit.min(MAX_AUDIT_PAGE_SIZE) as usize;
+        let upper = before.unwrap_or(u64::MAX);
+        let mut matching = self
+            .audit_log
+            .range(..upper)
+            .rev()
+            .map(|(_, entry)| entry)
+            .filter(|entry| filter.matches(entry));
+        let entries: Vec<AuditEntry> = matching.by_ref().take(limit).collect();
+        let next_cursor = match (entries.last(), matching.next()) {
+            (Some(last), Some(_)) => Some(last.seq),
+            _ => None,
+        };
+        AuditLogPage { entries, next_cursor }
+    }
+}
+
+/// Mutations recorded on this drive, newest first.
+#[ic_cdk::query]
+fn get_audit_log(filter: Option<AuditLogFilter>, before: Option<u64>, limit: u32) -> Result<AuditLogPage, DriveError> {
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let state = state.borrow();
+        if !state.is_drive_admin(caller) {
+            return Err(DriveError::unauthorized("Only a drive admin can read the audit log"));
+        }
+        Ok(state.get_audit_log(&filter.unwrap_or_default(), before, limit))
+    })
+}
@@ -0,0 +1,58 @@
+// autocomplete.rs
+
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::DriveError;
+use crate::members::check_read_access;
+use crate::{DriveFullFilePath, State, STATE};
+
+const MAX_SUGGESTIONS: u32 = 50;
+// Bounds the work per keystroke when many matches are deleted
+const MAX_SCANNED_PATHS: usize = 1_000;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct PathSuggestion {
+    full_path: DriveFullFilePath,
+    id: String,
+    is_folder: bool,
+}
+
+impl State {
+    /// Folders and files whose full path starts with `prefix`, in path order.
+    /// Matching is case-sensitive, as paths are.
+    fn autocomplete_path(&self, prefix: &str, limit: u32) -> Vec<PathSuggestion> {
+        let limit = limit.clamp(1, MAX_SUGGESTIONS) as usize;
+        let prefix = prefix.to_string();
+
+        let folders = self
+            .full_folder_path_to_uuid
+            .range(prefix.clone()..)
+            .take(MAX_SCANNED_PATHS)
+            .take_while(|(path, _)| path.starts_with(&prefix))
+            .filter(|(_, id)| self.folder_uuid_to_metadata.get(id).is_some_and(|folder| !folder.deleted))
+            .take(limit)
+            .map(|(full_path, id)| PathSuggestion { full_path, id, is_folder: true });
+        let files = self
+            .full_file_path_to_uuid
+            .range(prefix.clone()..)
+            .take(MAX_SCANNED_PATHS)
+            .take_while(|(path, _)| path.starts_with(&prefix))
+            .filter(|(_, id)| self.file_uuid_to_metadata.get(id).is_some_and(|file| !file.deleted))
+            .take(limit)
+            .map(|(full_path, id)| PathSuggestion { full_path, id, is_folder: false });
+
+        let mut suggestions: Vec<PathSuggestion> = folders.chain(files).collect();
+        suggestions.sort();
+        suggestions.truncate(limit);
+        suggestions
+    }
+}
+
+/// Suggestions for "move to…" and quick-open dialogs as the user types a
+/// path, e.g. `storage::Projects/Re`. Returns at most 50.
+#[ic_cdk::query]
+fn autocomplete_path(prefix: DriveFullFilePath, limit: u32) -> Result<Vec<PathSuggestion>, DriveError> {
+    check_read_access()?;
+    STATE.with(|state| Ok(state.borrow().autocomplete_path(&prefix, limit)))
+}
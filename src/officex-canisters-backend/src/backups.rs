@@ -0,0 +1,287 @@
+// backups.rs
+//
+// Periodic backups to a companion backup canister. Each backup is a
+// `StateSnapshot` of every record, Candid-encoded and streamed in chunks so no
+// single message exceeds the inter-canister size limit. The backup canister
+// must implement:
+//
+//   create_backup : (BackupManifest) -> (Result<text, text>)
+//   put_backup_chunk : (text, nat32, blob) -> (Result<(), text>)
+//   finish_backup : (text) -> (Result<(), text>)
+//   list_backups : () -> (vec BackupInfo)
+//   get_backup_chunk : (text, nat32) -> (Result<blob, text>)
+//
+// scoping every backup to the drive that created it.
+
+use candid::{CandidType, Decode, Encode, Principal};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::audit::AuditAction;
+use crate::errors::DriveError;
+use crate::profiling::profile;
+use crate::read_only::check_writable;
+use crate::restore::ImportMode;
+use crate::system_api;
+use crate::{StateSnapshot, STATE};
+
+const BACKUP_CHUNK_BYTES: usize = 1_500_000;
+const MIN_BACKUP_INTERVAL_HOURS: u32 = 1;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct BackupConfig {
+    target: Principal,
+    interval_hours: u32,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+struct BackupManifest {
+    created_at_ms: u64,
+    chunk_count: u32,
+    total_bytes: u64,
+    sha256: String,
+}
+
+/// A backup as listed by the backup canister.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct BackupInfo {
+    id: String,
+    created_at_ms: u64,
+    chunk_count: u32,
+    total_bytes: u64,
+    sha256: String,
+    // Unfinished backups were interrupted and cannot be restored
+    complete: bool,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default)]
+pub struct BackupState {
+    config: Option<BackupConfig>,
+    last_backup_id: Option<String>,
+    last_backup_at_ms: Option<u64>,
+    // Audit log position at the last backup; unchanged drives are not backed up again
+    last_backup_seq: Option<u64>,
+    last_error: Option<String>,
+    #[serde(skip)]
+    in_progress: bool,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct BackupStatus {
+    config: Option<BackupConfig>,
+    last_backup_id: Option<String>,
+    last_backup_at_ms: Option<u64>,
+    last_error: Option<String>,
+    in_progress: bool,
+}
+
+fn backup_error(message: String) -> DriveError {
+    DriveError::Internal { message }
+}
+
+async fn call_backup_canister<A, R>(target: Principal, method: &str, args: A) -> Result<R, DriveError>
+where
+    A: candid::utils::ArgumentEncoder,
+    R: for<'a> Deserialize<'a> + CandidType,
+{
+    let result: Result<(Result<R, String>,), _> = ic_cdk::call(target, method, args).await;
+    match result {
+        Ok((Ok(value),)) => Ok(value),
+        Ok((Err(message),)) => Err(backup_error(format!("Backup canister rejected {}: {}", method, message))),
+        Err((code, message)) => Err(backup_error(format!("Failed to reach backup canister: {:?} {}", code, message))),
+    }
+}
+
+fn latest_audit_seq() -> Option<u64> {
+    STATE.with(|state| state.borrow().audit_log.last_key_value().map(|(seq, _)| seq))
+}
+
+/// Streams a snapshot of the drive to the backup canister and returns the
+/// new backup's ID. The snapshot is taken up front, so changes made while
+/// chunks are in flight go into the next backup.
+async fn run_backup(target: Principal) -> Result<String, DriveError> {
+    let seq = latest_audit_seq();
+    // Backups hold the whole drive, so this is the one place it is copied
+    let bytes = STATE.with(|state| {
+        let state = state.borrow();
+        Encode!(&state.snapshot_page(state.owner, None, usize::MAX).snapshot)
+    })
+        .map_err(|e| backup_error(format!("Failed to encode snapshot: {}", e)))?;
+    let chunks: Vec<&[u8]> = bytes.chunks(BACKUP_CHUNK_BYTES).collect();
+    let manifest = BackupManifest {
+        created_at_ms: system_api::time() / 1_000_000,
+        chunk_count: chunks.len() as u32,
+        total_bytes: bytes.len() as u64,
+        sha256: format!("{:x}", Sha256::digest(&bytes)),
+    };
+
+    let backup_id: String = call_backup_canister(target, "create_backup", (&manifest,)).await?;
+    for (index, chunk) in chunks.into_iter().enumerate() {
+        call_backup_canister::<_, ()>(target, "put_backup_chunk", (&backup_id, index as u32, chunk.to_vec())).await?;
+    }
+    call_backup_canister::<_, ()>(target, "finish_backup", (&backup_id,)).await?;
+
+    STATE.with(|state| {
+        let backups = &mut state.borrow_mut().backups;
+        backups.last_backup_id = Some(backup_id.clone());
+        backups.last_backup_at_ms = Some(manifest.created_at_ms);
+        backups.last_backup_seq = seq;
+    });
+    Ok(backup_id)
+}
+
+/// Runs `run_backup` unless one is already under way, recording the outcome.
+async fn run_exclusive_backup(target: Principal) -> Result<String, DriveError> {
+    let already_running = STATE.with(|state| {
+        let backups = &mut state.borrow_mut().backups;
+        std::mem::replace(&mut backups.in_progress, true)
+    });
+    if already_running {
+        return Err(DriveError::InvalidState { reason: "A backup is already in progress".to_string() });
+    }
+    let result = run_backup(target).await;
+    STATE.with(|state| {
+        let backups = &mut state.borrow_mut().backups;
+        backups.in_progress = false;
+        backups.last_error = result.as_ref().err().map(|e| e.to_string());
+    });
+    result
+}
+
+/// Runs the `Backup` job: backs up if the configured interval has passed
+/// and the drive changed since the last backup.
+pub(crate) async fn run_backup_if_due() -> Result<String, DriveError> {
+    let now_ms = system_api::time() / 1_000_000;
+    let due_target = STATE.with(|state| {
+        let state = state.borrow();
+        let backups = &state.backups;
+        let config = backups.config.as_ref()?;
+        let interval_ms = config.interval_hours as u64 * 60 * 60 * 1000;
+        let due = backups.last_backup_at_ms.is_none_or(|last| now_ms >= last + interval_ms);
+        let changed = backups.last_backup_seq != state.audit_log.last_key_value().map(|(seq, _)| seq);
+        (due && changed && !backups.in_progress).then_some(config.target)
+    });
+    match due_target {
+        Some(target) => run_exclusive_backup(target).await.map(|backup_id| format!("Created backup {}", backup_id)),
+        None => Ok("No backup due".to_string()),
+    }
+}
+
+fn require_admin(action: &str) -> Result<(), DriveError> {
+    let caller = system_api::caller();
+    if STATE.with(|state| state.borrow().is_drive_admin(caller)) {
+        Ok(())
+    } else {
+        Err(DriveError::unauthorized(&format!("Only a drive admin can {}", action)))
+    }
+}
+
+fn configured_target() -> Result<Principal, DriveError> {
+    STATE.with(|state| state.borrow().backups.config.as_ref().map(|config| config.target))
+        .ok_or_else(|| DriveError::InvalidState { reason: "No backup canister configured".to_string() })
+}
+
+/// Sets the backup canister and how often to back up, or turns scheduled
+/// backups off with `None`.
+#[ic_cdk::update]
+fn set_backup_config(config: Option<BackupConfig>) -> Result<(), DriveError> {
+    let _profile = profile("set_backup_config");
+    check_writable()?;
+    require_admin("configure backups")?;
+    if let Some(config) = &config {
+        if config.interval_hours < MIN_BACKUP_INTERVAL_HOURS {
+            return Err(DriveError::InvalidInput {
+                field: "interval_hours".to_string(),
+                reason: format!("Must be at least {}", MIN_BACKUP_INTERVAL_HOURS),
+            });
+        }
+        if config.target == system_api::canister_id() || config.target == Principal::anonymous() {
+            return Err(DriveError::InvalidInput {
+                field: "target".to_string(),
+                reason: "Not a valid backup canister".to_string(),
+            });
+        }
+    }
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.backups.config = config;
+        let owner = state.owner.to_text();
+        state.record_audit(AuditAction::SetBackupConfig, &owner, None, None);
+    });
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn get_backup_status() -> Result<BackupStatus, DriveError> {
+    require_admin("view backups")?;
+    STATE.with(|state| {
+        let backups = &state.borrow().backups;
+        Ok(BackupStatus {
+            config: backups.config.clone(),
+            last_backup_id: backups.last_backup_id.clone(),
+            last_backup_at_ms: backups.last_backup_at_ms,
+            last_error: backups.last_error.clone(),
+            in_progress: backups.in_progress,
+        })
+    })
+}
+
+/// Backs up now instead of waiting for the schedule.
+#[ic_cdk::update]
+async fn run_backup_now() -> Result<String, DriveError> {
+    let _profile = profile("run_backup_now");
+    check_writable()?;
+    require_admin("run backups")?;
+    run_exclusive_backup(configured_target()?).await
+}
+
+/// Backups of this drive held by the backup canister, as it reports them.
+#[ic_cdk::update]
+async fn list_backups() -> Result<Vec<BackupInfo>, DriveError> {
+    let _profile = profile("list_backups");
+    require_admin("list backups")?;
+    let target = configured_target()?;
+    let result: Result<(Vec<BackupInfo>,), _> = ic_cdk::call(target, "list_backups", ()).await;
+    result
+        .map(|(backups,)| backups)
+        .map_err(|(code, message)| backup_error(format!("Failed to reach backup canister: {:?} {}", code, message)))
+}
+
+/// Replaces every folder and file with the contents of backup `id`. Changes
+/// made since that backup are lost, so take a fresh backup first if unsure.
+#[ic_cdk::update]
+async fn restore_backup(id: String) -> Result<(), DriveError> {
+    let _profile = profile("restore_backup");
+    check_writable()?;
+    let caller = system_api::caller();
+    if STATE.with(|state| state.borrow().owner != caller) {
+        return Err(DriveError::unauthorized("Only the owner can restore a backup"));
+    }
+    STATE.with(|state| state.borrow().check_no_legal_holds())?;
+    let target = configured_target()?;
+    let listed: Result<(Vec<BackupInfo>,), _> = ic_cdk::call(target, "list_backups", ()).await;
+    let (backups,) = listed
+        .map_err(|(code, message)| backup_error(format!("Failed to reach backup canister: {:?} {}", code, message)))?;
+    let backup = backups
+        .into_iter()
+        .find(|backup| backup.id == id && backup.complete)
+        .ok_or_else(|| DriveError::NotFound { resource: "backup".to_string(), id: id.clone() })?;
+
+    let mut bytes = Vec::with_capacity(backup.total_bytes as usize);
+    for index in 0..backup.chunk_count {
+        let chunk: Vec<u8> = call_backup_canister(target, "get_backup_chunk", (&id, index)).await?;
+        bytes.extend_from_slice(&chunk);
+    }
+    if format!("{:x}", Sha256::digest(&bytes)) != backup.sha256 {
+        return Err(backup_error("Backup checksum does not match its contents".to_string()));
+    }
+    let snapshot = Decode!(&bytes, StateSnapshot)
+        .map_err(|e| backup_error(format!("Failed to decode backup: {}", e)))?;
+
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.import_state_snapshot(snapshot, ImportMode::Replace);
+        state.record_audit(AuditAction::RestoreBackup, &id, None, None);
+    });
+    Ok(())
+}
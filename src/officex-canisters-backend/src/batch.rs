@@ -0,0 +1,316 @@
+// batch.rs
+
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+use crate::audit::AuditAction;
+use crate::errors::DriveError;
+use crate::memory::StableMapExt;
+use crate::profiling::profile;
+use crate::rate_limit::check_batch_allowed;
+use crate::system_api;
+use crate::{
+    DriveFullFilePath, FileUUID, FolderMetadata, FolderUUID, State, StorageLocationID, Tag,
+    UserID, STATE,
+};
+
+/// Most items `delete_items` takes per call.
+const MAX_DELETE_ITEMS: usize = 500;
+/// Most paths `create_folders` takes per call.
+const MAX_CREATE_FOLDERS: usize = 500;
+
+/// A file or folder UUID.
+pub type ResourceId = String;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) enum DriveOperation {
+    CreateFolder {
+        full_folder_path: DriveFullFilePath,
+        storage_location: StorageLocationID,
+    },
+    UpsertFile {
+        file_path: DriveFullFilePath,
+        storage_location: StorageLocationID,
+        sha256_checksum: Option<String>,
+    },
+    RenameFolder {
+        folder_id: FolderUUID,
+        new_name: String,
+        expected_revision: Option<u64>,
+    },
+    RenameFile {
+        file_id: FileUUID,
+        new_name: String,
+        expected_revision: Option<u64>,
+    },
+    MoveFile {
+        file_id: FileUUID,
+        destination_folder_path: DriveFullFilePath,
+        expected_revision: Option<u64>,
+    },
+    DeleteFolder {
+        folder_id: FolderUUID,
+        expected_revision: Option<u64>,
+    },
+    DeleteFile {
+        file_id: FileUUID,
+        expected_revision: Option<u64>,
+    },
+    SetFolderTags {
+        folder_id: FolderUUID,
+        tags: Vec<Tag>,
+        expected_revision: Option<u64>,
+    },
+    SetFileTags {
+        file_id: FileUUID,
+        tags: Vec<Tag>,
+        expected_revision: Option<u64>,
+    },
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) enum DriveOperationResult {
+    Folder(Box<FolderMetadata>),
+    File(FileUUID),
+    Done,
+}
+
+impl State {
+    fn check_operation_revision(&self, operation: &DriveOperation) -> Result<(), DriveError> {
+        match operation {
+            DriveOperation::CreateFolder { .. } | DriveOperation::UpsertFile { .. } => Ok(()),
+            DriveOperation::RenameFolder { folder_id, expected_revision, .. }
+            | DriveOperation::DeleteFolder { folder_id, expected_revision }
+            | DriveOperation::SetFolderTags { folder_id, expected_revision, .. } => {
+                self.check_folder_revision(folder_id, *expected_revision)
+            }
+            DriveOperation::RenameFile { file_id, expected_revision, .. }
+            | DriveOperation::MoveFile { file_id, expected_revision, .. }
+            | DriveOperation::DeleteFile { file_id, expected_revision }
+            | DriveOperation::SetFileTags { file_id, expected_revision, .. } => {
+                self.check_file_revision(file_id, *expected_revision)
+            }
+        }
+    }
+
+    fn apply_operation(&mut self, operation: DriveOperation, user_id: UserID) -> Result<DriveOperationResult, DriveError> {
+        self.check_operation_revision(&operation)?;
+        match operation {
+            DriveOperation::CreateFolder { full_folder_path, storage_location } => self
+                .create_folder(full_folder_path, storage_location, user_id)
+                .map(|folder| DriveOperationResult::Folder(Box::new(folder))),
+            DriveOperation::UpsertFile { file_path, storage_location, sha256_checksum } => self
+                .upsert_file_to_hash_tables(file_path, storage_location, user_id, sha256_checksum)
+                .map(DriveOperationResult::File),
+            DriveOperation::RenameFolder { folder_id, new_name, .. } => self
+                .rename_folder(folder_id, new_name)
+                .map(|_| DriveOperationResult::Done),
+            DriveOperation::RenameFile { file_id, new_name, .. } => self
+                .rename_file(file_id, new_name)
+                .map(|_| DriveOperationResult::Done),
+            DriveOperation::MoveFile { file_id, destination_folder_path, .. } => self
+                .move_file(&file_id, &destination_folder_path)
+                .map(|_| DriveOperationResult::Done),
+            DriveOperation::DeleteFolder { folder_id, .. } => self
+                .delete_folder(&folder_id)
+                .map(|_| DriveOperationResult::Done),
+            DriveOperation::DeleteFile { file_id, .. } => self
+                .delete_file(&file_id)
+                .map(|_| DriveOperationResult::Done),
+            DriveOperation::SetFolderTags { folder_id, tags, .. } => self
+                .set_folder_tags(&folder_id, tags)
+                .map(|_| DriveOperationResult::Done),
+            DriveOperation::SetFileTags { file_id, tags, .. } => self
+                .set_file_tags(&file_id, tags)
+                .map(|_| DriveOperationResult::Done),
+        }
+    }
+
+    fn apply_batch(
+        &mut self,
+        operations: Vec<DriveOperation>,
+        atomic: bool,
+        user_id: UserID,
+    ) -> Vec<Result<DriveOperationResult, DriveError>> {
+        let mut results = Vec::with_capacity(operations.len());
+        for (index, operation) in operations.into_iter().enumerate() {
+            let result = self.apply_operation(operation, user_id);
+            if atomic {
+                if let Err(e) = &result {
+                    // Trapping discards every state change made during this call,
+                    // so earlier operations in the batch are rolled back as well.
+                    ic_cdk::trap(&format!("Batch aborted at operation {}: {}", index, e));
+                }
+            }
+            results.push(result);
+        }
+        results
+    }
+
+    /// Deletes files before folders, so a file inside a folder that is also
+    /// being deleted is not reported missing. Results follow the input order.
+    fn delete_items(&mut self, ids: &[ResourceId]) -> Vec<Result<(), DriveError>> {
+        let mut results: Vec<Option<Result<(), DriveError>>> = vec![None; ids.len()];
+        for (index, id) in ids.iter().enumerate() {
+            if self.file_uuid_to_metadata.contains_key(id) {
+                results[index] = Some(self.delete_file(id));
+            }
+        }
+        for (index, id) in ids.iter().enumerate() {
+            if results[index].is_none() {
+                results[index] = Some(if self.folder_uuid_to_metadata.contains_key(id) {
+                    self.delete_folder(id)
+                } else {
+                    Err(DriveError::NotFound { resource: "item".to_string(), id: id.clone() })
+                });
+            }
+        }
+        results.into_iter().flatten().collect()
+    }
+
+    /// Creates each folder that does not exist yet, with its missing
+    /// ancestors. Folders made for an earlier path are reused by later ones.
+    fn create_folders(
+        &mut self,
+        paths: &[DriveFullFilePath],
+        storage_location: &StorageLocationID,
+        user_id: UserID,
+    ) -> Vec<Result<FolderMetadata, DriveError>> {
+        paths
+            .iter()
+            .map(|path| {
+                let mut sanitized_path = Self::sanitize_file_path(path)?;
+                if !sanitized_path.ends_with('/') {
+                    sanitized_path.push('/');
+                }
+                if let Some(existing) = self.get_folder_by_path(&sanitized_path).filter(|folder| !folder.deleted) {
+                    return Ok(existing);
+                }
+                self.create_folder(path.clone(), storage_location.clone(), user_id)
+            })
+            .collect()
+    }
+
+    pub(crate) fn move_file(&mut self, file_id: &FileUUID, destination_folder_path: &DriveFullFilePath) -> Result<(), DriveError> {
+        let file = self.file_uuid_to_metadata.get(file_id).ok_or_else(|| DriveError::file_not_found(file_id))?;
+        self.check_file_legal_hold(&file)?;
+        let destination = self
+            .get_folder_by_path(destination_folder_path)
+            .filter(|folder| !folder.deleted)
+            .ok_or_else(|| DriveError::folder_not_found(destination_folder_path))?;
+        self.check_folder_legal_hold(&destination.id)?;
+
+        if destination.storage_location != file.storage_location {
+            return Err(DriveError::StorageMismatch {
+                expected: file.storage_location.to_string(),
+                found: destination.storage_location.to_string(),
+            });
+        }
+        if destination.id == file.folder_uuid {
+            return Ok(());
+        }
+
+        let new_path = self.canonical_path(&format!("{}{}", destination.full_folder_path, file.original_file_name));
+        if self.full_file_path_to_uuid.contains_key(&new_path) {
+            return Err(DriveError::AlreadyExists { path: new_path });
+        }
+        self.check_path_limits(&new_path)?;
+
+        self.account_file_removed(&file);
+        let listed = self.unindex_child_file(&file);
+
+        self.full_file_path_to_uuid.remove(&file.full_file_path);
+        self.full_file_path_to_uuid.insert(new_path.clone(), file_id.clone());
+        self.record_audit(AuditAction::MoveFile, file_id, Some(file.full_file_path.clone()), Some(new_path.clone()));
+
+        let mut moved_file = file;
+        moved_file.folder_uuid = destination.id.clone();
+        moved_file.full_file_path = new_path;
+        moved_file.last_changed_unix_ms = system_api::time() / 1_000_000;
+        moved_file.revision += 1;
+        self.account_file_added(&moved_file);
+        if listed {
+            self.index_child_file(&moved_file);
+        }
+        self.file_uuid_to_metadata.insert(file_id.clone(), moved_file);
+        Ok(())
+    }
+
+    fn set_folder_tags(&mut self, folder_id: &FolderUUID, tags: Vec<Tag>) -> Result<(), DriveError> {
+        let path = self
+            .folder_uuid_to_metadata
+            .update(folder_id, |folder| {
+                folder.tags = tags;
+                folder.last_changed_unix_ms = system_api::time() / 1_000_000;
+                folder.revision += 1;
+                folder.full_folder_path.clone()
+            })
+            .ok_or_else(|| DriveError::folder_not_found(folder_id))?;
+        self.record_audit(AuditAction::SetTags, folder_id, Some(path), None);
+        Ok(())
+    }
+
+    fn set_file_tags(&mut self, file_id: &FileUUID, tags: Vec<Tag>) -> Result<(), DriveError> {
+        let mut file = self
+            .file_uuid_to_metadata
+            .get(file_id)
+            .ok_or_else(|| DriveError::file_not_found(file_id))?;
+        file.tags = tags;
+        file.last_changed_unix_ms = system_api::time() / 1_000_000;
+        file.revision += 1;
+        let path = file.full_file_path.clone();
+        self.replace_file_metadata(file);
+        self.record_audit(AuditAction::SetTags, file_id, Some(path), None);
+        Ok(())
+    }
+}
+
+/// Applies a list of operations in order. With `atomic` set, the first failure
+/// aborts the call and no operation in the batch takes effect; otherwise every
+/// operation is attempted and its individual result is returned.
+#[ic_cdk::update]
+fn apply_batch(operations: Vec<DriveOperation>, atomic: bool) -> Vec<Result<DriveOperationResult, DriveError>> {
+    let _profile = profile("apply_batch");
+    // Each operation counts against the budget as if it were its own call
+    if let Err(e) = check_batch_allowed("apply_batch", operations.len() as u32) {
+        return vec![Err(e)];
+    }
+    let user_id = system_api::caller();
+    STATE.with(|state| state.borrow_mut().apply_batch(operations, atomic, user_id))
+}
+
+/// `create_folder` for up to 500 paths, e.g. to mirror a local tree. Folders
+/// that already exist are returned as they are; the result for each path
+/// follows the order given.
+#[ic_cdk::update]
+fn create_folders(
+    paths: Vec<DriveFullFilePath>,
+    storage_location: StorageLocationID,
+) -> Result<Vec<Result<FolderMetadata, DriveError>>, DriveError> {
+    let _profile = profile("create_folders");
+    if paths.len() > MAX_CREATE_FOLDERS {
+        return Err(DriveError::InvalidInput {
+            field: "paths".to_string(),
+            reason: format!("At most {} paths per call", MAX_CREATE_FOLDERS),
+        });
+    }
+    check_batch_allowed("create_folders", paths.len() as u32)?;
+    let user_id = system_api::caller();
+    STATE.with(|state| Ok(state.borrow_mut().create_folders(&paths, &storage_location, user_id)))
+}
+
+/// Deletes up to 500 files and folders in one call. Every item is attempted;
+/// the result for each is returned in the order given.
+#[ic_cdk::update]
+fn delete_items(ids: Vec<ResourceId>) -> Result<Vec<Result<(), DriveError>>, DriveError> {
+    let _profile = profile("delete_items");
+    if ids.len() > MAX_DELETE_ITEMS {
+        return Err(DriveError::InvalidInput {
+            field: "ids".to_string(),
+            reason: format!("At most {} items per call", MAX_DELETE_ITEMS),
+        });
+    }
+    // Each item counts against the budget as if it were its own call
+    check_batch_allowed("delete_items", ids.len() as u32)?;
+    STATE.with(|state| Ok(state.borrow_mut().delete_items(&ids)))
+}
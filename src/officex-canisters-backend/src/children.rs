@@ -0,0 +1,117 @@
+// children.rs
+
+use candid::{CandidType, Decode, Encode};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::Storable;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+use crate::{FileMetadata, FileUUID, FolderMetadata, FolderUUID, State};
+
+// Folders sort ahead of files within a parent
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ChildKind {
+    Folder,
+    File,
+}
+
+/// Entry in the ordered child index. Keys sort by parent, then kind, then
+/// name, so the children of a folder form one contiguous range. The child
+/// UUID is part of the key because a deleted folder keeps its entry while a
+/// new sibling with the same name is created.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ChildKey {
+    pub parent: FolderUUID,
+    pub kind: ChildKind,
+    pub name: String,
+    pub child: String,
+}
+
+impl Storable for ChildKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl ChildKey {
+    fn for_folder(folder: &FolderMetadata) -> Option<Self> {
+        folder.parent_folder_uuid.as_ref().map(|parent| ChildKey {
+            parent: parent.clone(),
+            kind: ChildKind::Folder,
+            name: folder.original_folder_name.clone(),
+            child: folder.id.clone(),
+        })
+    }
+
+    fn for_file(file: &FileMetadata) -> Self {
+        ChildKey {
+            parent: file.folder_uuid.clone(),
+            kind: ChildKind::File,
+            name: file.original_file_name.clone(),
+            child: file.id.clone(),
+        }
+    }
+
+    // Smallest possible key under `parent`
+    fn range_start(parent: &FolderUUID) -> Self {
+        ChildKey {
+            parent: parent.clone(),
+            kind: ChildKind::Folder,
+            name: String::new(),
+            child: String::new(),
+        }
+    }
+}
+
+impl State {
+    pub(crate) fn index_child_folder(&mut self, folder: &FolderMetadata) {
+        if let Some(key) = ChildKey::for_folder(folder) {
+            self.folder_children.insert(key, ());
+        }
+    }
+
+    pub(crate) fn unindex_child_folder(&mut self, folder: &FolderMetadata) {
+        if let Some(key) = ChildKey::for_folder(folder) {
+            self.folder_children.remove(&key);
+        }
+    }
+
+    pub(crate) fn index_child_file(&mut self, file: &FileMetadata) {
+        self.folder_children.insert(ChildKey::for_file(file), ());
+    }
+
+    /// Returns whether the file was listed; only the newest version of a file is.
+    pub(crate) fn unindex_child_file(&mut self, file: &FileMetadata) -> bool {
+        self.folder_children.remove(&ChildKey::for_file(file)).is_some()
+    }
+
+    /// Lazily walks the children of `parent` in index order: subfolders by
+    /// name, then files by name.
+    pub(crate) fn children_of<'a>(&'a self, parent: &FolderUUID) -> impl Iterator<Item = ChildKey> + 'a {
+        let parent = parent.clone();
+        self.folder_children
+            .range(ChildKey::range_start(&parent)..)
+            .map(|(key, _)| key)
+            .take_while(move |key| key.parent == parent)
+    }
+
+    pub(crate) fn child_folder_ids(&self, parent: &FolderUUID) -> Vec<FolderUUID> {
+        self.children_of(parent)
+            .take_while(|key| key.kind == ChildKind::Folder)
+            .map(|key| key.child)
+            .collect()
+    }
+
+    pub(crate) fn child_file_ids(&self, parent: &FolderUUID) -> Vec<FileUUID> {
+        self.children_of(parent)
+            .skip_while(|key| key.kind == ChildKind::Folder)
+            .map(|key| key.child)
+            .collect()
+    }
+}
@@ -0,0 +1,223 @@
+// comments.rs
+
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::Storable;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+use crate::audit::AuditAction;
+use crate::errors::DriveError;
+use crate::members::require_read_access;
+use crate::memory::StableMapExt;
+use crate::notifications::{mentioned_principals, NotificationKind};
+use crate::profiling::profile;
+use crate::rate_limit::check_update_allowed;
+use crate::system_api;
+use crate::{State, STATE};
+
+const MAX_COMMENT_LENGTH: usize = 10_000;
+const MAX_COMMENT_PAGE_SIZE: u32 = 200;
+
+/// Comments sort by resource, then in the order they were posted.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct CommentKey {
+    resource_id: String,
+    comment_id: u64,
+}
+
+impl Storable for CommentKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Comment {
+    id: u64,
+    resource_id: String,
+    // Comment this one replies to, on the same resource
+    parent_id: Option<u64>,
+    author: Principal,
+    text: String,
+    created_at_ms: u64,
+    // Deleted comments keep their place in the thread with the text removed
+    deleted: bool,
+}
+
+impl Storable for Comment {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct CommentPage {
+    comments: Vec<Comment>,
+    // Pass as `after` to fetch the next page
+    next_cursor: Option<u64>,
+}
+
+impl State {
+    fn commentable_resource_exists(&self, resource_id: &str) -> bool {
+        let id = resource_id.to_string();
+        self.folder_uuid_to_metadata.get(&id).is_some_and(|folder| !folder.deleted)
+            || self.file_uuid_to_metadata.contains_key(&id)
+    }
+
+    fn add_comment(
+        &mut self,
+        author: Principal,
+        resource_id: String,
+        text: String,
+        parent_id: Option<u64>,
+    ) -> Result<Comment, DriveError> {
+        if !self.commentable_resource_exists(&resource_id) {
+            return Err(DriveError::NotFound { resource: "item".to_string(), id: resource_id });
+        }
+        let text = text.trim().to_string();
+        if text.is_empty() || text.chars().count() > MAX_COMMENT_LENGTH {
+            return Err(DriveError::InvalidInput {
+                field: "text".to_string(),
+                reason: format!("Comments must be between 1 and {} characters", MAX_COMMENT_LENGTH),
+            });
+        }
+        let parent_author = match parent_id {
+            Some(parent_id) => {
+                let parent_key = CommentKey { resource_id: resource_id.clone(), comment_id: parent_id };
+                let parent = self.comments.get(&parent_key).ok_or_else(|| DriveError::NotFound {
+                    resource: "comment".to_string(),
+                    id: parent_id.to_string(),
+                })?;
+                Some(parent.author)
+            }
+            None => None,
+        };
+
+        let comment = Comment {
+            id: self.next_comment_id,
+            resource_id: resource_id.clone(),
+            parent_id,
+            author,
+            text,
+            created_at_ms: system_api::time() / 1_000_000,
+            deleted: false,
+        };
+        self.next_comment_id += 1;
+        self.comments.insert(
+            CommentKey { resource_id: resource_id.clone(), comment_id: comment.id },
+            comment.clone(),
+        );
+        self.record_audit(AuditAction::AddComment, &resource_id, None, None);
+        self.notify_comment(&comment, parent_author);
+        Ok(comment)
+    }
+
+    /// Notifies everyone mentioned, then the resource's owner and the author
+    /// of the comment replied to, each at most once.
+    fn notify_comment(&mut self, comment: &Comment, parent_author: Option<Principal>) {
+        let mentioned = mentioned_principals(&comment.text);
+        for principal in &mentioned {
+            let kind = NotificationKind::Mention { comment_id: comment.id };
+            self.notify(*principal, comment.author, kind, &comment.resource_id);
+        }
+        let id = comment.resource_id.clone();
+        let resource_owner = match self.folder_uuid_to_metadata.get(&id) {
+            Some(folder) => Some(folder.owner),
+            None => self.file_uuid_to_metadata.get(&id).map(|file| file.owner),
+        };
+        let mut watchers: Vec<Principal> = resource_owner.into_iter().chain(parent_author).collect();
+        watchers.dedup();
+        for principal in watchers.into_iter().filter(|principal| !mentioned.contains(principal)) {
+            let kind = NotificationKind::Comment { comment_id: comment.id };
+            self.notify(principal, comment.author, kind, &comment.resource_id);
+        }
+    }
+
+    fn list_comments(&self, resource_id: String, after: Option<u64>, limit: u32) -> CommentPage {
+        let limit = limit.min(MAX_COMMENT_PAGE_SIZE) as usize;
+        let start = CommentKey {
+            resource_id: resource_id.clone(),
+            comment_id: after.map_or(0, |id| id + 1),
+        };
+        let mut comments = self
+            .comments
+            .range(start..)
+            .map(|(_, comment)| comment)
+            .take_while(|comment| comment.resource_id == resource_id);
+        let page: Vec<Comment> = comments.by_ref().take(limit).collect();
+        let next_cursor = match (page.last(), comments.next()) {
+            (Some(last), Some(_)) => Some(last.id),
+            _ => None,
+        };
+        CommentPage { comments: page, next_cursor }
+    }
+
+    fn delete_comment(&mut self, caller: Principal, resource_id: String, comment_id: u64) -> Result<(), DriveError> {
+        let key = CommentKey { resource_id: resource_id.clone(), comment_id };
+        let comment = self
+            .comments
+            .get(&key)
+            .ok_or_else(|| DriveError::NotFound { resource: "comment".to_string(), id: comment_id.to_string() })?;
+        if caller != comment.author && !self.is_drive_admin(caller) {
+            return Err(DriveError::unauthorized("Only the author or a drive admin can delete a comment"));
+        }
+        self.comments.update(&key, |comment| {
+            comment.deleted = true;
+            comment.text.clear();
+        });
+        self.record_audit(AuditAction::DeleteComment, &resource_id, None, None);
+        Ok(())
+    }
+
+    /// Drops every comment on a resource that no longer exists.
+    pub(crate) fn remove_comments_for(&mut self, resource_id: &str) {
+        let start = CommentKey { resource_id: resource_id.to_string(), comment_id: 0 };
+        let keys: Vec<CommentKey> = self
+            .comments
+            .range(start..)
+            .map(|(key, _)| key)
+            .take_while(|key| key.resource_id == resource_id)
+            .collect();
+        for key in keys {
+            self.comments.remove(&key);
+        }
+    }
+}
+
+/// Posts a comment on a folder or file. Set `parent_id` to reply to an
+/// existing comment on the same resource.
+#[ic_cdk::update]
+fn add_comment(resource_id: String, text: String, parent_id: Option<u64>) -> Result<Comment, DriveError> {
+    let _profile = profile("add_comment");
+    check_update_allowed("add_comment")?;
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow_mut().add_comment(caller, resource_id, text, parent_id))
+}
+
+/// Comments on a resource, oldest first.
+#[ic_cdk::query]
+fn list_comments(resource_id: String, after: Option<u64>, limit: u32) -> CommentPage {
+    require_read_access();
+    STATE.with(|state| state.borrow().list_comments(resource_id, after, limit))
+}
+
+#[ic_cdk::update]
+fn delete_comment(resource_id: String, comment_id: u64) -> Result<(), DriveError> {
+    let _profile = profile("delete_comment");
+    check_update_allowed("delete_comment")?;
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow_mut().delete_comment(caller, resource_id, comment_id))
+}
@@ -0,0 +1,145 @@
+// compaction.rs
+
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+use crate::audit::AuditAction;
+use crate::errors::DriveError;
+use crate::profiling::profile;
+use crate::read_only::check_writable;
+use crate::system_api;
+use crate::{FileUUID, FolderUUID, State, STATE};
+
+pub(crate) const DEFAULT_TOMBSTONE_RETENTION_MS: u64 = 30 * 24 * 60 * 60 * 1000;
+// Keeps one run well inside the instruction limit; the next run continues
+const MAX_TOMBSTONES_PER_RUN: usize = 1_000;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct CompactionReport {
+    // Tombstones last changed before this time were eligible
+    cutoff_ms: u64,
+    folders_removed: u64,
+    files_removed: u64,
+    more_remaining: bool,
+}
+
+impl CompactionReport {
+    pub(crate) fn summary(&self) -> String {
+        format!("Compacted {} folder and {} file tombstones", self.folders_removed, self.files_removed)
+    }
+}
+
+impl State {
+    /// Tombstones must be older than the retention period and already seen
+    /// by every device that syncs incrementally. Devices without a cursor, or
+    /// whose cursor fell out of the audit log, take a fresh snapshot anyway.
+    fn tombstone_cutoff_ms(&self, now_ms: u64) -> u64 {
+        let retention_cutoff = now_ms.saturating_sub(self.tombstone_retention_ms);
+        self.devices
+            .values()
+            .flat_map(|devices| devices.values())
+            .filter_map(|device| device.sync_cursor)
+            .filter_map(|cursor| self.audit_log.get(&cursor))
+            .map(|entry| entry.timestamp_ms)
+            .fold(retention_cutoff, u64::min)
+    }
+
+    /// Permanently removes deleted folders and files past the cutoff, along
+    /// with older versions of files that lived in those folders.
+    pub(crate) fn compact_tombstones(&mut self, now_ms: u64) -> CompactionReport {
+        let cutoff_ms = self.tombstone_cutoff_ms(now_ms);
+        let mut folders = self
+            .folder_uuid_to_metadata
+            .values()
+            .filter(|folder| folder.deleted && folder.last_changed_unix_ms < cutoff_ms);
+        let folder_ids: HashSet<FolderUUID> = folders
+            .by_ref()
+            .take(MAX_TOMBSTONES_PER_RUN)
+            .map(|folder| folder.id)
+            .collect();
+        let mut more_remaining = folders.next().is_some();
+
+        let mut files = self.file_uuid_to_metadata.values().filter(|file| {
+            (file.deleted && file.last_changed_unix_ms < cutoff_ms) || folder_ids.contains(&file.folder_uuid)
+        });
+        let file_ids: Vec<FileUUID> = files
+            .by_ref()
+            .take(MAX_TOMBSTONES_PER_RUN)
+            .map(|file| file.id)
+            .collect();
+        if files.next().is_some() {
+            more_remaining = true;
+        }
+
+        let mut files_removed = 0;
+        for file_id in &file_ids {
+            if self.remove_file_record(file_id).is_ok() {
+                files_removed += 1;
+            }
+        }
+
+        // A folder goes only once nothing in it is left, so files are never orphaned
+        let occupied: HashSet<FolderUUID> = self
+            .file_uuid_to_metadata
+            .values()
+            .filter(|file| folder_ids.contains(&file.folder_uuid))
+            .map(|file| file.folder_uuid)
+            .collect();
+        let mut folders_removed = 0;
+        for folder_id in &folder_ids {
+            if occupied.contains(folder_id) {
+                more_remaining = true;
+                continue;
+            }
+            if let Some(folder) = self.folder_uuid_to_metadata.remove(folder_id) {
+                self.unindex_child_folder(&folder);
+                self.remove_comments_for(folder_id);
+                self.share_grants.remove(folder_id);
+                self.token_gates.remove(folder_id);
+                folders_removed += 1;
+            }
+        }
+
+        CompactionReport { cutoff_ms, folders_removed, files_removed, more_remaining }
+    }
+}
+
+/// Runs a compaction pass now instead of waiting for the daily timer.
+#[ic_cdk::update]
+fn compact_tombstones() -> Result<CompactionReport, DriveError> {
+    let _profile = profile("compact_tombstones");
+    check_writable()?;
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if !state.is_drive_admin(caller) {
+            return Err(DriveError::unauthorized("Only a drive admin can compact tombstones"));
+        }
+        let report = state.compact_tombstones(system_api::time() / 1_000_000);
+        let owner = state.owner.to_text();
+        state.record_audit(AuditAction::CompactTombstones, &owner, None, None);
+        Ok(report)
+    })
+}
+
+/// How long deleted folders and files are kept for syncing clients.
+#[ic_cdk::update]
+fn set_tombstone_retention(retention_days: u32) -> Result<(), DriveError> {
+    let _profile = profile("set_tombstone_retention");
+    check_writable()?;
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if !state.is_drive_admin(caller) {
+            return Err(DriveError::unauthorized("Only a drive admin can change tombstone retention"));
+        }
+        state.tombstone_retention_ms = retention_days as u64 * 24 * 60 * 60 * 1000;
+        Ok(())
+    })
+}
+
+#[ic_cdk::query]
+fn get_tombstone_retention_days() -> u32 {
+    STATE.with(|state| (state.borrow().tombstone_retention_ms / (24 * 60 * 60 * 1000)) as u32)
+}
@@ -0,0 +1,177 @@
+// conflicts.rs
+
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+use crate::audit::AuditAction;
+use crate::errors::DriveError;
+use crate::members::check_read_access;
+use crate::memory::StableMapExt;
+use crate::notifications::NotificationKind;
+use crate::profiling::profile;
+use crate::rate_limit::check_update_allowed;
+use crate::system_api;
+use crate::{FileMetadata, FileUUID, State, STATE};
+
+/// A client's offline edit that could not be applied because the cloud copy
+/// had changed since the version the edit started from. The client's copy is
+/// held outside the version chain until `resolve_conflict` picks a side.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct SyncConflict {
+    // The cloud version that was newest when the conflict was detected
+    file_id: FileUUID,
+    conflict_copy_id: FileUUID,
+    base_version: u32,
+    detected_by: Principal,
+    detected_at_ms: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct ConflictDetails {
+    conflict: SyncConflict,
+    // Newest cloud version, which may have moved on since detection
+    cloud_file: Option<FileMetadata>,
+    conflict_copy: Option<FileMetadata>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ConflictSide {
+    // Keep the cloud version and discard the client's copy
+    Cloud,
+    // Make the client's copy the newest version
+    Local,
+}
+
+impl State {
+    /// Follows `next_version` links from `file` to the newest version.
+    pub(crate) fn latest_file_version(&self, mut file: FileMetadata) -> FileMetadata {
+        while let Some(next) = file.next_version.as_ref().and_then(|id| self.file_uuid_to_metadata.get(id)) {
+            file = next;
+        }
+        file
+    }
+
+    /// Stores `conflict_copy` beside `cloud_file` and flags the file as
+    /// conflicted. Returns the copy's UUID.
+    pub(crate) fn record_sync_conflict(
+        &mut self,
+        cloud_file: &FileMetadata,
+        conflict_copy: FileMetadata,
+        base_version: u32,
+    ) -> FileUUID {
+        let copy_id = conflict_copy.id.clone();
+        self.account_file_added(&conflict_copy);
+        self.file_uuid_to_metadata.insert(copy_id.clone(), conflict_copy);
+        self.file_uuid_to_metadata.update(&cloud_file.id, |file| file.conflicted = true);
+        self.sync_conflicts.insert(
+            cloud_file.id.clone(),
+            SyncConflict {
+                file_id: cloud_file.id.clone(),
+                conflict_copy_id: copy_id.clone(),
+                base_version,
+                detected_by: system_api::caller(),
+                detected_at_ms: system_api::time() / 1_000_000,
+            },
+        );
+        self.record_audit(AuditAction::SyncConflict, &cloud_file.id, Some(cloud_file.full_file_path.clone()), None);
+        let kind = NotificationKind::SyncConflict { conflict_copy_id: copy_id.clone() };
+        self.notify(cloud_file.owner, system_api::caller(), kind.clone(), &cloud_file.id);
+        if cloud_file.owner != self.owner {
+            self.notify(self.owner, system_api::caller(), kind, &cloud_file.id);
+        }
+        copy_id
+    }
+
+    /// Drops the conflict recorded on `file_id`, if any, along with the
+    /// client's copy. Called when the cloud file itself is deleted.
+    pub(crate) fn discard_sync_conflict(&mut self, file_id: &FileUUID) {
+        let Some(conflict) = self.sync_conflicts.remove(file_id) else {
+            return;
+        };
+        if let Some(copy) = self.file_uuid_to_metadata.remove(&conflict.conflict_copy_id) {
+            self.account_file_removed(&copy);
+        }
+        self.file_uuid_to_metadata.update(file_id, |file| file.conflicted = false);
+    }
+
+    fn list_conflicts(&self, caller: Principal) -> Vec<ConflictDetails> {
+        self.sync_conflicts
+            .values()
+            .map(|conflict| ConflictDetails {
+                conflict: conflict.clone(),
+                cloud_file: self
+                    .file_uuid_to_metadata
+                    .get(&conflict.file_id)
+                    .map(|file| self.redact_unpaid(caller, self.latest_file_version(file))),
+                conflict_copy: self
+                    .file_uuid_to_metadata
+                    .get(&conflict.conflict_copy_id)
+                    .map(|file| self.redact_unpaid(caller, file)),
+            })
+            .collect()
+    }
+
+    /// Returns the UUID of the file's newest version after resolution.
+    fn resolve_conflict(&mut self, file_id: &FileUUID, keep: ConflictSide) -> Result<FileUUID, DriveError> {
+        let conflict = self.sync_conflicts.get(file_id).cloned().ok_or_else(|| DriveError::NotFound {
+            resource: "sync conflict".to_string(),
+            id: file_id.clone(),
+        })?;
+        let cloud_file = self
+            .file_uuid_to_metadata
+            .get(&conflict.file_id)
+            .map(|file| self.latest_file_version(file))
+            .ok_or_else(|| DriveError::file_not_found(&conflict.file_id))?;
+
+        let kept_id = match keep {
+            ConflictSide::Cloud => {
+                self.discard_sync_conflict(file_id);
+                cloud_file.id.clone()
+            }
+            ConflictSide::Local => {
+                let copy = self
+                    .file_uuid_to_metadata
+                    .update(&conflict.conflict_copy_id, |copy| {
+                        copy.file_version = cloud_file.file_version + 1;
+                        copy.prior_version = Some(cloud_file.id.clone());
+                        copy.conflicted = false;
+                        copy.clone()
+                    })
+                    .ok_or_else(|| DriveError::file_not_found(&conflict.conflict_copy_id))?;
+                self.sync_conflicts.remove(file_id);
+                self.file_uuid_to_metadata.update(file_id, |file| file.conflicted = false);
+                self.file_uuid_to_metadata.update(&cloud_file.id, |file| {
+                    file.next_version = Some(copy.id.clone());
+                });
+                // Only the newest version is listed and addressable by path
+                self.unindex_child_file(&cloud_file);
+                self.index_child_file(&copy);
+                if cloud_file.full_file_path != copy.full_file_path {
+                    self.full_file_path_to_uuid.remove(&cloud_file.full_file_path);
+                }
+                self.full_file_path_to_uuid.insert(copy.full_file_path.clone(), copy.id.clone());
+                copy.id
+            }
+        };
+        self.record_audit(AuditAction::ResolveConflict, &kept_id, None, None);
+        Ok(kept_id)
+    }
+}
+
+/// Sync conflicts waiting to be resolved.
+#[ic_cdk::query]
+fn list_conflicts() -> Result<Vec<ConflictDetails>, DriveError> {
+    check_read_access()?;
+    let caller = system_api::caller();
+    STATE.with(|state| Ok(state.borrow().list_conflicts(caller)))
+}
+
+/// Settles the conflict detected on `file_id`. Keeping `Cloud` deletes the
+/// client's copy; keeping `Local` appends it as the newest version, so the
+/// cloud version stays in the history.
+#[ic_cdk::update]
+fn resolve_conflict(file_id: FileUUID, keep: ConflictSide) -> Result<FileUUID, DriveError> {
+    let _profile = profile("resolve_conflict");
+    check_update_allowed("resolve_conflict")?;
+    STATE.with(|state| state.borrow_mut().resolve_conflict(&file_id, keep))
+}
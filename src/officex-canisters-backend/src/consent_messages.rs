@@ -0,0 +1,351 @@
+// consent_messages.rs
+//
+// ICRC-21 consent messages, so wallets can show what a call will do before
+// the user signs it, e.g. "Delete folder 'Taxes' with 52 files". Only the
+// calls that destroy data or hand out access are described; wallets get
+// `UnsupportedCanisterCall` for the rest. Names are only spelled out for
+// callers who could see them anyway, and items are otherwise named by ID.
+
+use candid::{CandidType, Nat, Principal};
+use serde::Deserialize;
+
+use crate::batch::ResourceId;
+use crate::members::TeamMember;
+use crate::profiling::profile;
+use crate::sharing::ShareRole;
+use crate::system_api;
+use crate::{FileUUID, FolderUUID, State, STATE};
+
+const SUPPORTED_LANGUAGE: &str = "en";
+
+// ICRC-21 and ICRC-10 types, as the standards declare them
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub(crate) struct Icrc21ConsentMessageMetadata {
+    language: String,
+    utc_offset_minutes: Option<i16>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub(crate) enum Icrc21DeviceSpec {
+    GenericDisplay,
+    LineDisplay { characters_per_line: u16, lines_per_page: u16 },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub(crate) struct Icrc21ConsentMessageSpec {
+    metadata: Icrc21ConsentMessageMetadata,
+    device_spec: Option<Icrc21DeviceSpec>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub(crate) struct Icrc21ConsentMessageRequest {
+    method: String,
+    arg: Vec<u8>,
+    user_preferences: Icrc21ConsentMessageSpec,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub(crate) struct Icrc21LineDisplayPage {
+    lines: Vec<String>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub(crate) enum Icrc21ConsentMessage {
+    GenericDisplayMessage(String),
+    LineDisplayMessage { pages: Vec<Icrc21LineDisplayPage> },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub(crate) struct Icrc21ConsentInfo {
+    consent_message: Icrc21ConsentMessage,
+    metadata: Icrc21ConsentMessageMetadata,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub(crate) struct Icrc21ErrorInfo {
+    description: String,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub(crate) enum Icrc21Error {
+    UnsupportedCanisterCall(Icrc21ErrorInfo),
+    ConsentMessageUnavailable(Icrc21ErrorInfo),
+    InsufficientPayment(Icrc21ErrorInfo),
+    GenericError { error_code: Nat, description: String },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub(crate) enum Icrc21ConsentMessageResponse {
+    Ok(Icrc21ConsentInfo),
+    Err(Icrc21Error),
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub(crate) struct Icrc10SupportedStandard {
+    url: String,
+    name: String,
+}
+
+/// A consent message: a title and the sentences below it.
+struct Consent {
+    title: String,
+    details: Vec<String>,
+}
+
+fn unavailable(description: String) -> Icrc21Error {
+    Icrc21Error::ConsentMessageUnavailable(Icrc21ErrorInfo { description })
+}
+
+fn decode<T: for<'de> candid::utils::ArgumentDecoder<'de>>(method: &str, arg: &[u8]) -> Result<T, Icrc21Error> {
+    candid::decode_args(arg).map_err(|e| unavailable(format!("Could not decode the arguments of {}: {}", method, e)))
+}
+
+fn plural(count: u64, noun: &str) -> String {
+    format!("{} {}{}", count, noun, if count == 1 { "" } else { "s" })
+}
+
+/// Greedily wraps `text` at word boundaries into lines of at most `width`
+/// characters, breaking words longer than a line.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let width = width.max(1);
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let mut word: Vec<char> = word.chars().collect();
+        while word.len() > width {
+            if !line.is_empty() {
+                lines.push(std::mem::take(&mut line));
+            }
+            lines.push(word.drain(..width).collect());
+        }
+        let word: String = word.into_iter().collect();
+        if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > width {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(&word);
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+impl Consent {
+    fn render(self, device_spec: Option<Icrc21DeviceSpec>) -> Icrc21ConsentMessage {
+        match device_spec {
+            Some(Icrc21DeviceSpec::LineDisplay { characters_per_line, lines_per_page }) => {
+                let lines: Vec<String> = std::iter::once(&self.title)
+                    .chain(self.details.iter())
+                    .flat_map(|text| wrap(text, characters_per_line as usize))
+                    .collect();
+                let pages = lines
+                    .chunks((lines_per_page as usize).max(1))
+                    .map(|lines| Icrc21LineDisplayPage { lines: lines.to_vec() })
+                    .collect();
+                Icrc21ConsentMessage::LineDisplayMessage { pages }
+            }
+            _ => {
+                let body = self.details.join("\n\n");
+                Icrc21ConsentMessage::GenericDisplayMessage(format!("## {}\n\n{}", self.title, body))
+            }
+        }
+    }
+}
+
+impl State {
+    fn folder_label(&self, caller: Principal, folder_id: &FolderUUID) -> String {
+        match self.folder_uuid_to_metadata.get(folder_id) {
+            Some(folder)
+                if self.can_read_drive(caller) || self.has_folder_share_role(caller, folder_id, ShareRole::Viewer) =>
+            {
+                format!("folder '{}'", folder.original_folder_name)
+            }
+            _ => format!("folder {}", folder_id),
+        }
+    }
+
+    fn file_label(&self, caller: Principal, file_id: &FileUUID) -> String {
+        match self.file_uuid_to_metadata.get(file_id) {
+            Some(file) if self.can_read_drive(caller) || self.can_view_file(caller, &file) => {
+                format!("file '{}'", file.original_file_name)
+            }
+            _ => format!("file {}", file_id),
+        }
+    }
+
+    fn resource_label(&self, caller: Principal, resource_id: &ResourceId) -> String {
+        if self.folder_uuid_to_metadata.contains_key(resource_id) {
+            self.folder_label(caller, resource_id)
+        } else {
+            self.file_label(caller, resource_id)
+        }
+    }
+
+    /// What deleting the folder takes with it, counting every file version.
+    fn folder_contents(&self, folder_id: &FolderUUID) -> String {
+        self.folder_uuid_to_metadata.get(folder_id).map_or_else(String::new, |folder| {
+            format!(
+                " with {} and {}",
+                plural(folder.subtree_file_count, "file"),
+                plural(folder.subtree_folder_count, "subfolder")
+            )
+        })
+    }
+
+    fn consent_for(&self, caller: Principal, method: &str, arg: &[u8]) -> Result<Consent, Icrc21Error> {
+        let consent = match method {
+            "delete_folder" => {
+                let (folder_id, _, _): (FolderUUID, Option<u64>, Option<String>) = decode(method, arg)?;
+                Consent {
+                    title: "Delete folder".to_string(),
+                    details: vec![
+                        format!(
+                            "Delete {}{}.",
+                            self.folder_label(caller, &folder_id),
+                            self.folder_contents(&folder_id)
+                        ),
+                        "Everything inside the folder is deleted with it.".to_string(),
+                    ],
+                }
+            }
+            "delete_file" => {
+                let (file_id, _, _): (FileUUID, Option<u64>, Option<String>) = decode(method, arg)?;
+                Consent {
+                    title: "Delete file".to_string(),
+                    details: vec![format!("Delete {} and all of its versions.", self.file_label(caller, &file_id))],
+                }
+            }
+            "delete_items" => {
+                let (ids,): (Vec<ResourceId>,) = decode(method, arg)?;
+                let mut details = vec![format!("Delete {}:", plural(ids.len() as u64, "item"))];
+                details.extend(ids.iter().map(|id| format!("- {}", self.resource_label(caller, id))));
+                Consent { title: "Delete items".to_string(), details }
+            }
+            "share_item" => {
+                let (resource_id, grantee, role): (ResourceId, Principal, ShareRole) = decode(method, arg)?;
+                Consent {
+                    title: "Share".to_string(),
+                    details: vec![format!(
+                        "Give {} {:?} access to {}.",
+                        grantee,
+                        role,
+                        self.resource_label(caller, &resource_id)
+                    )],
+                }
+            }
+            "unshare_item" => {
+                let (resource_id, grantee): (ResourceId, Principal) = decode(method, arg)?;
+                Consent {
+                    title: "Stop sharing".to_string(),
+                    details: vec![format!(
+                        "Remove the access of {} to {}.",
+                        grantee,
+                        self.resource_label(caller, &resource_id)
+                    )],
+                }
+            }
+            "publish_folder" => {
+                let (folder_id,): (FolderUUID,) = decode(method, arg)?;
+                Consent {
+                    title: "Publish folder".to_string(),
+                    details: vec![format!(
+                        "Make {}{} readable by anyone, without signing in.",
+                        self.folder_label(caller, &folder_id),
+                        self.folder_contents(&folder_id)
+                    )],
+                }
+            }
+            "set_member" => {
+                let (member,): (TeamMember,) = decode(method, arg)?;
+                Consent {
+                    title: "Change member role".to_string(),
+                    details: vec![format!("Make {} a drive {:?}.", member.principal, member.role)],
+                }
+            }
+            "remove_member" => {
+                let (principal,): (Principal,) = decode(method, arg)?;
+                Consent {
+                    title: "Remove member".to_string(),
+                    details: vec![format!("Remove {} from the drive's members.", principal)],
+                }
+            }
+            "transfer_ownership" => {
+                let (new_owner,): (Principal,) = decode(method, arg)?;
+                Consent {
+                    title: "Transfer drive ownership".to_string(),
+                    details: vec![
+                        format!("Offer ownership of this drive to {}.", new_owner),
+                        "Once they accept, you lose control of the drive.".to_string(),
+                    ],
+                }
+            }
+            "restore_backup" => {
+                let (backup_id,): (String,) = decode(method, arg)?;
+                Consent {
+                    title: "Restore backup".to_string(),
+                    details: vec![
+                        format!("Replace every folder and file with the contents of backup {}.", backup_id),
+                        "Changes made since that backup are lost.".to_string(),
+                    ],
+                }
+            }
+            "pay_for_file" => {
+                let (file_id,): (FileUUID,) = decode(method, arg)?;
+                let paywall = self
+                    .file_uuid_to_metadata
+                    .get(&file_id)
+                    .and_then(|file| file.paywall)
+                    .ok_or_else(|| unavailable(format!("File {} has no paywall", file_id)))?;
+                Consent {
+                    title: "Pay for file".to_string(),
+                    details: vec![format!(
+                        "Pay {} to the drive owner {} for {}.",
+                        paywall.describe(),
+                        self.owner,
+                        self.file_label(caller, &file_id)
+                    )],
+                }
+            }
+            _ => {
+                return Err(Icrc21Error::UnsupportedCanisterCall(Icrc21ErrorInfo {
+                    description: format!("No consent message is available for {}", method),
+                }))
+            }
+        };
+        Ok(consent)
+    }
+}
+
+/// Describes a call to this drive for a wallet to show before signing it.
+/// Messages are in English whatever language is asked for.
+#[ic_cdk::update]
+fn icrc21_canister_call_consent_message(request: Icrc21ConsentMessageRequest) -> Icrc21ConsentMessageResponse {
+    let _profile = profile("icrc21_canister_call_consent_message");
+    let caller = system_api::caller();
+    let consent = STATE.with(|state| state.borrow().consent_for(caller, &request.method, &request.arg));
+    match consent {
+        Ok(consent) => Icrc21ConsentMessageResponse::Ok(Icrc21ConsentInfo {
+            consent_message: consent.render(request.user_preferences.device_spec),
+            metadata: Icrc21ConsentMessageMetadata {
+                language: SUPPORTED_LANGUAGE.to_string(),
+                utc_offset_minutes: request.user_preferences.metadata.utc_offset_minutes,
+            },
+        }),
+        Err(e) => Icrc21ConsentMessageResponse::Err(e),
+    }
+}
+
+#[ic_cdk::query]
+fn icrc10_supported_standards() -> Vec<Icrc10SupportedStandard> {
+    ["ICRC-10", "ICRC-21"]
+        .into_iter()
+        .map(|name| Icrc10SupportedStandard {
+            url: format!("https://github.com/dfinity/ICRC/blob/main/ICRCs/{0}/{0}.md", name),
+            name: name.to_string(),
+        })
+        .collect()
+}
@@ -0,0 +1,131 @@
+// contacts.rs
+//
+// The drive's address book of known collaborators, so share dialogs can
+// offer names instead of asking for a raw principal every time. Sharing an
+// item with a contact marks them as recently used.
+
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+use crate::audit::AuditAction;
+use crate::errors::DriveError;
+use crate::profiling::profile;
+use crate::read_only::check_writable;
+use crate::system_api;
+use crate::{State, STATE};
+
+const MAX_CONTACTS: usize = 1_000;
+const MAX_NICKNAME_CHARS: usize = 64;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Contact {
+    pub principal: Principal,
+    pub nickname: String,
+    pub added_at_ms: u64,
+    pub last_shared_at_ms: Option<u64>,
+}
+
+fn contact_not_found(principal: Principal) -> DriveError {
+    DriveError::NotFound { resource: "contact".to_string(), id: principal.to_text() }
+}
+
+impl State {
+    fn add_contact(&mut self, principal: Principal, nickname: String) -> Result<Contact, DriveError> {
+        if principal == Principal::anonymous() {
+            return Err(DriveError::InvalidInput {
+                field: "principal".to_string(),
+                reason: "Cannot add the anonymous principal as a contact".to_string(),
+            });
+        }
+        let nickname = nickname.trim().to_string();
+        if nickname.is_empty() || nickname.chars().count() > MAX_NICKNAME_CHARS {
+            return Err(DriveError::InvalidInput {
+                field: "nickname".to_string(),
+                reason: format!("Must be 1 to {} characters", MAX_NICKNAME_CHARS),
+            });
+        }
+        // Adding a known contact again only renames them
+        if let Some(contact) = self.contacts.get_mut(&principal) {
+            contact.nickname = nickname;
+            return Ok(contact.clone());
+        }
+        if self.contacts.len() >= MAX_CONTACTS {
+            return Err(DriveError::InvalidState {
+                reason: format!("A drive can have at most {} contacts", MAX_CONTACTS),
+            });
+        }
+        let contact = Contact {
+            principal,
+            nickname,
+            added_at_ms: system_api::time() / 1_000_000,
+            last_shared_at_ms: None,
+        };
+        self.contacts.insert(principal, contact.clone());
+        self.record_audit(AuditAction::AddContact, &principal.to_text(), None, None);
+        Ok(contact)
+    }
+
+    fn remove_contact(&mut self, principal: Principal) -> Result<(), DriveError> {
+        self.contacts.remove(&principal).ok_or_else(|| contact_not_found(principal))?;
+        self.record_audit(AuditAction::RemoveContact, &principal.to_text(), None, None);
+        Ok(())
+    }
+
+    /// Most recently shared with first, then the rest by nickname.
+    fn list_contacts(&self) -> Vec<Contact> {
+        let mut contacts: Vec<Contact> = self.contacts.values().cloned().collect();
+        contacts.sort_by(|a, b| {
+            b.last_shared_at_ms
+                .cmp(&a.last_shared_at_ms)
+                .then_with(|| a.nickname.to_lowercase().cmp(&b.nickname.to_lowercase()))
+        });
+        contacts
+    }
+
+    pub(crate) fn touch_contact(&mut self, principal: Principal) {
+        if let Some(contact) = self.contacts.get_mut(&principal) {
+            contact.last_shared_at_ms = Some(system_api::time() / 1_000_000);
+        }
+    }
+}
+
+/// Adds a collaborator to the drive's contacts, or renames a known one.
+#[ic_cdk::update]
+fn add_contact(principal: Principal, nickname: String) -> Result<Contact, DriveError> {
+    let _profile = profile("add_contact");
+    check_writable()?;
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if !state.is_drive_admin(caller) {
+            return Err(DriveError::unauthorized("Only a drive admin can manage contacts"));
+        }
+        state.add_contact(principal, nickname)
+    })
+}
+
+#[ic_cdk::update]
+fn remove_contact(principal: Principal) -> Result<(), DriveError> {
+    let _profile = profile("remove_contact");
+    check_writable()?;
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if !state.is_drive_admin(caller) {
+            return Err(DriveError::unauthorized("Only a drive admin can manage contacts"));
+        }
+        state.remove_contact(principal)
+    })
+}
+
+#[ic_cdk::query]
+fn list_contacts() -> Result<Vec<Contact>, DriveError> {
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let state = state.borrow();
+        if !state.is_drive_admin(caller) {
+            return Err(DriveError::unauthorized("Only a drive admin can list contacts"));
+        }
+        Ok(state.list_contacts())
+    })
+}
@@ -0,0 +1,247 @@
+// credential_vault.rs
+//
+// Storage credentials the owner hands to the drive, so clients can get
+// presigned URLs (see `presigning.rs`) without holding storage keys, and the
+// drive can call pinning services (see `pinning.rs`). Secret
+// keys are sealed with AES-256-GCM under a vault key drawn from `raw_rand`,
+// so they never appear in plaintext in the heap state, backups, logs or any
+// response. Anyone able to read the canister's memory wholesale could still
+// recover them; the vault guards against leaks, not against node providers.
+
+use aes_gcm::aead::{Aead, KeyInit};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use candid::CandidType;
+use ic_cdk::api::management_canister::main::raw_rand;
+use serde::{Deserialize, Serialize};
+
+use crate::audit::AuditAction;
+use crate::errors::DriveError;
+use crate::profiling::profile;
+use crate::read_only::check_writable;
+use crate::storage::StorageLocationID;
+use crate::system_api;
+use crate::{State, STATE};
+
+const STORJ_GATEWAY_ENDPOINT: &str = "https://gateway.storjshare.io";
+const STORJ_GATEWAY_REGION: &str = "us-1";
+const MAX_CREDENTIAL_FIELD_BYTES: usize = 256;
+const NONCE_BYTES: usize = 12;
+
+/// What the owner submits. Storj is reached through its S3-compatible
+/// gateway, with the S3 credentials registered for an access grant.
+#[derive(CandidType, Deserialize, Clone)]
+pub enum StorageCredentials {
+    Storj { bucket: String, access_key_id: String, secret_access_key: String },
+    S3 { endpoint: String, region: String, bucket: String, access_key_id: String, secret_access_key: String },
+    // Bearer token for the `pin_endpoint` of an Ipfs or Arweave location
+    PinningService { api_token: String },
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum CredentialProvider {
+    Storj,
+    S3,
+    PinningService,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct SealedCredentials {
+    provider: CredentialProvider,
+    pub(crate) endpoint: String,
+    pub(crate) region: String,
+    pub(crate) bucket: String,
+    pub(crate) access_key_id: String,
+    nonce: Vec<u8>,
+    sealed_secret: Vec<u8>,
+    updated_at_ms: u64,
+}
+
+impl SealedCredentials {
+    /// Whether these are S3 keys that can presign URLs.
+    pub(crate) fn can_presign(&self) -> bool {
+        self.provider != CredentialProvider::PinningService
+    }
+}
+
+/// Everything but the secret, for the owner's settings screen.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct CredentialInfo {
+    storage_location: StorageLocationID,
+    provider: CredentialProvider,
+    endpoint: String,
+    region: String,
+    bucket: String,
+    access_key_id: String,
+    updated_at_ms: u64,
+}
+
+fn check_field(field: &str, value: &str) -> Result<(), DriveError> {
+    if value.is_empty() || value.len() > MAX_CREDENTIAL_FIELD_BYTES {
+        return Err(DriveError::InvalidInput {
+            field: field.to_string(),
+            reason: format!("Must be 1 to {} bytes", MAX_CREDENTIAL_FIELD_BYTES),
+        });
+    }
+    Ok(())
+}
+
+async fn random_bytes() -> Result<Vec<u8>, DriveError> {
+    let (bytes,) = raw_rand().await.map_err(|(code, message)| DriveError::Internal {
+        message: format!("Failed to get randomness: {:?} {}", code, message),
+    })?;
+    Ok(bytes)
+}
+
+fn vault_cipher(vault_key: &[u8]) -> Aes256Gcm {
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(vault_key))
+}
+
+impl State {
+    fn seal_credentials(
+        &self,
+        credentials: StorageCredentials,
+        nonce: Vec<u8>,
+        now_ms: u64,
+    ) -> Result<SealedCredentials, DriveError> {
+        let (provider, endpoint, region, bucket, access_key_id, secret) = match credentials {
+            StorageCredentials::Storj { bucket, access_key_id, secret_access_key } => (
+                CredentialProvider::Storj,
+                STORJ_GATEWAY_ENDPOINT.to_string(),
+                STORJ_GATEWAY_REGION.to_string(),
+                bucket,
+                access_key_id,
+                secret_access_key,
+            ),
+            StorageCredentials::S3 { endpoint, region, bucket, access_key_id, secret_access_key } => {
+                if !endpoint.starts_with("https://") {
+                    return Err(DriveError::InvalidInput {
+                        field: "endpoint".to_string(),
+                        reason: "Must be an https URL".to_string(),
+                    });
+                }
+                let endpoint = endpoint.trim_end_matches('/').to_string();
+                (CredentialProvider::S3, endpoint, region, bucket, access_key_id, secret_access_key)
+            }
+            // The endpoint is in the location's config, which holds no secrets
+            StorageCredentials::PinningService { api_token } => (
+                CredentialProvider::PinningService,
+                String::new(),
+                String::new(),
+                String::new(),
+                String::new(),
+                api_token,
+            ),
+        };
+        if provider == CredentialProvider::PinningService {
+            check_field("api_token", &secret)?;
+        } else {
+            check_field("region", &region)?;
+            check_field("bucket", &bucket)?;
+            check_field("access_key_id", &access_key_id)?;
+            check_field("secret_access_key", &secret)?;
+        }
+
+        let vault_key = self.vault_key.as_ref().ok_or_else(|| DriveError::Internal {
+            message: "Credential vault key is missing".to_string(),
+        })?;
+        let sealed_secret = vault_cipher(vault_key)
+            .encrypt(Nonce::from_slice(&nonce), secret.as_bytes())
+            .map_err(|_| DriveError::Internal { message: "Failed to seal credentials".to_string() })?;
+        Ok(SealedCredentials {
+            provider,
+            endpoint,
+            region,
+            bucket,
+            access_key_id,
+            nonce,
+            sealed_secret,
+            updated_at_ms: now_ms,
+        })
+    }
+
+    /// The secret access key or API token of `credentials`, decrypted.
+    pub(crate) fn unseal_secret(&self, credentials: &SealedCredentials) -> Result<String, DriveError> {
+        let vault_key = self.vault_key.as_ref().ok_or_else(|| DriveError::Internal {
+            message: "Credential vault key is missing".to_string(),
+        })?;
+        let secret = vault_cipher(vault_key)
+            .decrypt(Nonce::from_slice(&credentials.nonce), credentials.sealed_secret.as_slice())
+            .map_err(|_| DriveError::Internal { message: "Failed to unseal credentials".to_string() })?;
+        String::from_utf8(secret)
+            .map_err(|_| DriveError::Internal { message: "Sealed secret is not UTF-8".to_string() })
+    }
+
+    fn list_credentials(&self) -> Vec<CredentialInfo> {
+        self.storage_credentials
+            .iter()
+            .map(|(storage_location, sealed)| CredentialInfo {
+                storage_location: storage_location.clone(),
+                provider: sealed.provider.clone(),
+                endpoint: sealed.endpoint.clone(),
+                region: sealed.region.clone(),
+                bucket: sealed.bucket.clone(),
+                access_key_id: sealed.access_key_id.clone(),
+                updated_at_ms: sealed.updated_at_ms,
+            })
+            .collect()
+    }
+}
+
+/// Stores credentials used to presign URLs for `storage_location`, or
+/// removes them with `None`. The secret is sealed before it is kept.
+#[ic_cdk::update]
+async fn set_storage_credentials(
+    storage_location: StorageLocationID,
+    credentials: Option<StorageCredentials>,
+) -> Result<(), DriveError> {
+    let _profile = profile("set_storage_credentials");
+    check_writable()?;
+    let caller = system_api::caller();
+    let needs_vault_key = STATE.with(|state| {
+        let state = state.borrow();
+        if caller != state.owner {
+            return Err(DriveError::unauthorized("Only the owner can manage storage credentials"));
+        }
+        state.check_storage_location(&storage_location)?;
+        Ok(state.vault_key.is_none())
+    })?;
+
+    let sealing = match credentials {
+        Some(credentials) => {
+            let vault_key = if needs_vault_key { Some(random_bytes().await?) } else { None };
+            let nonce = random_bytes().await?[..NONCE_BYTES].to_vec();
+            Some((credentials, vault_key, nonce))
+        }
+        None => None,
+    };
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        match sealing {
+            Some((credentials, vault_key, nonce)) => {
+                // Another call may have created the key while this one awaited
+                if state.vault_key.is_none() {
+                    state.vault_key = vault_key;
+                }
+                let sealed = state.seal_credentials(credentials, nonce, system_api::time() / 1_000_000)?;
+                state.storage_credentials.insert(storage_location.clone(), sealed);
+            }
+            None => {
+                state.storage_credentials.remove(&storage_location);
+            }
+        }
+        state.record_audit(AuditAction::SetStorageCredentials, &storage_location, None, None);
+        Ok(())
+    })
+}
+
+#[ic_cdk::query]
+fn list_storage_credentials() -> Result<Vec<CredentialInfo>, DriveError> {
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let state = state.borrow();
+        if caller != state.owner {
+            return Err(DriveError::unauthorized("Only the owner can list storage credentials"));
+        }
+        Ok(state.list_credentials())
+    })
+}
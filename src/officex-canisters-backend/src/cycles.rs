@@ -0,0 +1,152 @@
+// cycles.rs
+
+use candid::CandidType;
+use ic_cdk::api::management_canister::main::{deposit_cycles as management_deposit_cycles, CanisterIdRecord};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::errors::DriveError;
+use crate::logs::{log, LogLevel};
+use crate::profiling::profile;
+use crate::system_api;
+use crate::{State, STATE};
+
+/// Cycles kept back when reclaiming so the drive can still pay for the
+/// deposit call itself before it is stopped and deleted.
+const RECLAIM_CYCLES_RESERVE: u128 = 20_000_000_000;
+const CYCLES_SAMPLE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+// One week of hourly samples
+const MAX_CYCLES_SAMPLES: usize = 24 * 7;
+const MS_PER_DAY: u64 = 24 * 60 * 60 * 1000;
+
+/// Balance observed at a point in time, alongside everything deposited so far,
+/// so top-ups are not mistaken for negative burn.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct CyclesSample {
+    taken_at_ms: u64,
+    balance: u64,
+    deposited_total: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct WalletReceiveResult {
+    accepted: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct CyclesBurnEstimate {
+    balance: u64,
+    // Unknown until at least one sample older than now has been taken
+    burn_per_day: Option<u64>,
+    // Days until the balance runs out at the current rate; the canister
+    // freezes somewhat earlier, once it dips below its freezing threshold
+    days_remaining: Option<u64>,
+    sampled_since_ms: Option<u64>,
+}
+
+impl State {
+    fn current_cycles_sample(&self, now_ms: u64) -> CyclesSample {
+        CyclesSample {
+            taken_at_ms: now_ms,
+            balance: ic_cdk::api::canister_balance(),
+            deposited_total: self.cycles_deposited_total,
+        }
+    }
+
+    fn record_cycles_sample(&mut self, now_ms: u64) {
+        let sample = self.current_cycles_sample(now_ms);
+        self.cycles_samples.push_back(sample);
+        while self.cycles_samples.len() > MAX_CYCLES_SAMPLES {
+            self.cycles_samples.pop_front();
+        }
+    }
+
+    fn record_cycles_deposit(&mut self, amount: u128) {
+        let amount = u64::try_from(amount).unwrap_or(u64::MAX);
+        self.cycles_deposited_total = self.cycles_deposited_total.saturating_add(amount);
+    }
+
+    fn cycles_burn_estimate(&self, now_ms: u64) -> CyclesBurnEstimate {
+        let current = self.current_cycles_sample(now_ms);
+        let oldest = self.cycles_samples.front().filter(|oldest| oldest.taken_at_ms < now_ms);
+        let burn_per_day = oldest.map(|oldest| {
+            let burned = (oldest.balance as u128 + (current.deposited_total - oldest.deposited_total) as u128)
+                .saturating_sub(current.balance as u128);
+            let elapsed_ms = (now_ms - oldest.taken_at_ms) as u128;
+            u64::try_from(burned * MS_PER_DAY as u128 / elapsed_ms).unwrap_or(u64::MAX)
+        });
+        CyclesBurnEstimate {
+            balance: current.balance,
+            burn_per_day,
+            days_remaining: burn_per_day.filter(|burn| *burn > 0).map(|burn| current.balance / burn),
+            sampled_since_ms: oldest.map(|oldest| oldest.taken_at_ms),
+        }
+    }
+}
+
+pub fn start_cycles_sampling() {
+    STATE.with(|state| state.borrow_mut().record_cycles_sample(system_api::time() / 1_000_000));
+    ic_cdk_timers::set_timer_interval(CYCLES_SAMPLE_INTERVAL, || {
+        STATE.with(|state| state.borrow_mut().record_cycles_sample(system_api::time() / 1_000_000));
+    });
+}
+
+fn accept_cycles() -> u128 {
+    let available = ic_cdk::api::call::msg_cycles_available128();
+    let accepted = ic_cdk::api::call::msg_cycles_accept128(available);
+    if accepted > 0 {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            state.record_cycles_deposit(accepted);
+            state.log(LogLevel::Info, format!("Accepted {} cycles from {}", accepted, system_api::caller()));
+        });
+    }
+    accepted
+}
+
+/// Accepts all cycles attached to the call and returns the amount taken.
+/// Anyone may top up a drive.
+#[ic_cdk::update]
+fn deposit_cycles() -> u128 {
+    let _profile = profile("deposit_cycles");
+    accept_cycles()
+}
+
+/// Same as `deposit_cycles`, under the name cycles wallets send to.
+#[ic_cdk::update]
+fn wallet_receive() -> WalletReceiveResult {
+    let _profile = profile("wallet_receive");
+    WalletReceiveResult { accepted: u64::try_from(accept_cycles()).unwrap_or(u64::MAX) }
+}
+
+/// Average daily burn over the last week of hourly balance samples, so the
+/// UI can warn well before the drive freezes.
+#[ic_cdk::query]
+fn get_cycles_burn_estimate() -> CyclesBurnEstimate {
+    STATE.with(|state| state.borrow().cycles_burn_estimate(system_api::time() / 1_000_000))
+}
+
+/// Sends the drive's remaining cycles (minus a small reserve) to the calling
+/// controller canister. Used by the factory right before it deletes the drive.
+#[ic_cdk::update]
+async fn reclaim_cycles() -> Result<u128, DriveError> {
+    let _profile = profile("reclaim_cycles");
+    let caller = system_api::caller();
+    if !ic_cdk::api::is_controller(&caller) {
+        return Err(DriveError::unauthorized("Only a controller can reclaim cycles"));
+    }
+
+    let amount = ic_cdk::api::canister_balance128().saturating_sub(RECLAIM_CYCLES_RESERVE);
+    if amount == 0 {
+        return Ok(0);
+    }
+
+    management_deposit_cycles(CanisterIdRecord { canister_id: caller }, amount)
+        .await
+        .map_err(|(code, message)| DriveError::Internal {
+            message: format!("Failed to deposit cycles: {:?} {}", code, message),
+        })?;
+
+    log(LogLevel::Info, format!("Reclaimed {} cycles to {}", amount, caller));
+    Ok(amount)
+}
@@ -0,0 +1,146 @@
+// dedup.rs
+
+use candid::{CandidType, Decode, Encode};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::Storable;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+use crate::audit::AuditAction;
+use crate::errors::DriveError;
+use crate::members::check_read_access;
+use crate::profiling::profile;
+use crate::read_only::check_writable;
+use crate::system_api;
+use crate::upload_status::UploadStatus;
+use crate::{FileMetadata, FileUUID, State, STATE};
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub(crate) struct DuplicateGroup {
+    sha256_checksum: String,
+    file_size: u64,
+    file_ids: Vec<FileUUID>,
+}
+
+impl DuplicateGroup {
+    /// Bytes held by every copy but one.
+    pub(crate) fn wasted_bytes(&self) -> u64 {
+        self.file_size * (self.file_ids.len() as u64).saturating_sub(1)
+    }
+}
+
+/// Entry in the checksum index, grouping the versions that share content.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ChecksumKey {
+    checksum: String,
+    file_id: FileUUID,
+}
+
+impl Storable for ChecksumKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl ChecksumKey {
+    fn for_file(file: &FileMetadata) -> Option<Self> {
+        file.sha256_checksum.as_ref().map(|checksum| ChecksumKey {
+            checksum: checksum.clone(),
+            file_id: file.id.clone(),
+        })
+    }
+}
+
+impl State {
+    pub(crate) fn index_file_checksum(&mut self, file: &FileMetadata) {
+        if let Some(key) = ChecksumKey::for_file(file) {
+            self.checksum_to_file_uuids.insert(key, ());
+        }
+    }
+
+    pub(crate) fn unindex_file_checksum(&mut self, file: &FileMetadata) {
+        if let Some(key) = ChecksumKey::for_file(file) {
+            self.checksum_to_file_uuids.remove(&key);
+        }
+    }
+
+    /// UUIDs of the file versions whose content has `checksum`.
+    fn file_ids_with_checksum<'a>(&'a self, checksum: &'a str) -> impl Iterator<Item = FileUUID> + 'a {
+        let first = ChecksumKey { checksum: checksum.to_string(), file_id: String::new() };
+        self.checksum_to_file_uuids
+            .range(first..)
+            .map(|(key, _)| key)
+            .take_while(move |key| key.checksum == checksum)
+            .map(|key| key.file_id)
+    }
+
+    /// An already uploaded file with the given content, whose stored object a
+    /// new upsert can point at instead of uploading the bytes again.
+    pub(crate) fn find_uploaded_by_checksum(&self, checksum: &str) -> Option<FileMetadata> {
+        self.file_ids_with_checksum(checksum)
+            .filter_map(|id| self.file_uuid_to_metadata.get(&id))
+            .find(|file| file.upload_status == UploadStatus::Uploaded)
+    }
+
+    /// Walks the checksum index in order, so the groups come out sorted by
+    /// checksum.
+    pub(crate) fn find_duplicates(&self) -> Vec<DuplicateGroup> {
+        let mut groups = Vec::new();
+        let mut entries = self.checksum_to_file_uuids.iter().map(|(key, _)| key).peekable();
+        while let Some(first) = entries.next() {
+            let mut file_ids = vec![first.file_id];
+            while let Some(key) = entries.next_if(|key| key.checksum == first.checksum) {
+                file_ids.push(key.file_id);
+            }
+            if file_ids.len() < 2 {
+                continue;
+            }
+            let file_size = file_ids
+                .iter()
+                .filter_map(|id| self.file_uuid_to_metadata.get(id))
+                .map(|file| file.file_size)
+                .max()
+                .unwrap_or(0);
+            groups.push(DuplicateGroup { sha256_checksum: first.checksum, file_size, file_ids });
+        }
+        groups
+    }
+}
+
+/// Groups of files across the drive that share the same SHA-256 content checksum.
+#[ic_cdk::query]
+fn find_duplicates() -> Result<Vec<DuplicateGroup>, DriveError> {
+    check_read_access()?;
+    STATE.with(|state| Ok(state.borrow().find_duplicates()))
+}
+
+/// When enabled, upserts carrying a checksum that matches an uploaded file are
+/// linked to that file's stored object and come back already `Uploaded`.
+#[ic_cdk::update]
+fn set_dedup_enabled(enabled: bool) -> Result<(), DriveError> {
+    let _profile = profile("set_dedup_enabled");
+    check_writable()?;
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if !state.is_drive_admin(caller) {
+            return Err(DriveError::unauthorized("Only a drive admin can change deduplication settings"));
+        }
+        state.dedup_enabled = enabled;
+        let owner = state.owner.to_text();
+        state.record_audit(AuditAction::SetDedup, &owner, None, None);
+        Ok(())
+    })
+}
+
+#[ic_cdk::query]
+fn get_dedup_enabled() -> Result<bool, DriveError> {
+    check_read_access()?;
+    STATE.with(|state| Ok(state.borrow().dedup_enabled))
+}
@@ -0,0 +1,171 @@
+// devices.rs
+
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+use crate::audit::AuditAction;
+use crate::errors::DriveError;
+use crate::members::check_read_access;
+use crate::profiling::profile;
+use crate::rate_limit::check_update_allowed;
+use crate::read_only::check_writable;
+use crate::system_api;
+use crate::{State, STATE};
+
+const MAX_DEVICES_PER_USER: usize = 20;
+const MAX_DEVICE_ID_LEN: usize = 128;
+const MAX_DEVICE_LABEL_LEN: usize = 100;
+
+/// A client install that syncs the drive, e.g. a laptop or phone. Each keeps
+/// its own position in the event log, so devices resume independently.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Device {
+    device_id: String,
+    user: Principal,
+    label: String,
+    registered_at_ms: u64,
+    last_seen_ms: u64,
+    // Sequence number of the last event the device applied; see `poll_events`
+    pub(crate) sync_cursor: Option<u64>,
+}
+
+fn device_not_found(device_id: &str) -> DriveError {
+    DriveError::NotFound { resource: "device".to_string(), id: device_id.to_string() }
+}
+
+impl State {
+    fn register_device(&mut self, user: Principal, device_id: String, label: String) -> Result<Device, DriveError> {
+        if user == Principal::anonymous() {
+            return Err(DriveError::unauthorized("Anonymous callers cannot register devices"));
+        }
+        if device_id.is_empty() || device_id.len() > MAX_DEVICE_ID_LEN {
+            return Err(DriveError::InvalidInput {
+                field: "device_id".to_string(),
+                reason: format!("Must be 1 to {} bytes", MAX_DEVICE_ID_LEN),
+            });
+        }
+        if label.len() > MAX_DEVICE_LABEL_LEN {
+            return Err(DriveError::InvalidInput {
+                field: "label".to_string(),
+                reason: format!("Must be at most {} bytes", MAX_DEVICE_LABEL_LEN),
+            });
+        }
+
+        let now_ms = system_api::time() / 1_000_000;
+        let devices = self.devices.entry(user).or_default();
+        // Registering again only relabels the device and keeps its cursor
+        if let Some(device) = devices.get_mut(&device_id) {
+            device.label = label;
+            device.last_seen_ms = now_ms;
+            return Ok(device.clone());
+        }
+        if devices.len() >= MAX_DEVICES_PER_USER {
+            return Err(DriveError::InvalidState {
+                reason: format!("A user can register at most {} devices", MAX_DEVICES_PER_USER),
+            });
+        }
+        let device = Device {
+            device_id: device_id.clone(),
+            user,
+            label,
+            registered_at_ms: now_ms,
+            last_seen_ms: now_ms,
+            sync_cursor: None,
+        };
+        devices.insert(device_id.clone(), device.clone());
+        self.record_audit(AuditAction::RegisterDevice, &device_id, None, None);
+        Ok(device)
+    }
+
+    fn device(&self, user: Principal, device_id: &str) -> Result<&Device, DriveError> {
+        self.devices
+            .get(&user)
+            .and_then(|devices| devices.get(device_id))
+            .ok_or_else(|| device_not_found(device_id))
+    }
+
+    fn commit_sync_cursor(&mut self, user: Principal, device_id: &str, cursor: u64) -> Result<(), DriveError> {
+        let latest_seq = self.audit_log.last_key_value().map(|(seq, _)| seq);
+        if latest_seq.is_none_or(|latest| cursor > latest) {
+            return Err(DriveError::InvalidInput {
+                field: "cursor".to_string(),
+                reason: "Cursor is past the newest event".to_string(),
+            });
+        }
+        let device = self
+            .devices
+            .get_mut(&user)
+            .and_then(|devices| devices.get_mut(device_id))
+            .ok_or_else(|| device_not_found(device_id))?;
+        device.sync_cursor = Some(cursor);
+        device.last_seen_ms = system_api::time() / 1_000_000;
+        Ok(())
+    }
+
+    /// Users may revoke their own devices; admins anyone's.
+    fn revoke_device(&mut self, caller: Principal, user: Principal, device_id: &str) -> Result<(), DriveError> {
+        if caller != user && !self.is_drive_admin(caller) {
+            return Err(DriveError::unauthorized("Only a drive admin can revoke other users' devices"));
+        }
+        let devices = self.devices.get_mut(&user).ok_or_else(|| device_not_found(device_id))?;
+        devices.remove(device_id).ok_or_else(|| device_not_found(device_id))?;
+        if devices.is_empty() {
+            self.devices.remove(&user);
+        }
+        self.record_audit(AuditAction::RevokeDevice, device_id, None, None);
+        Ok(())
+    }
+
+    /// The caller's devices; for admins, every registered device.
+    fn list_devices(&self, caller: Principal) -> Vec<Device> {
+        let is_admin = self.is_drive_admin(caller);
+        self.devices
+            .iter()
+            .filter(|(user, _)| is_admin || **user == caller)
+            .flat_map(|(_, devices)| devices.values().cloned())
+            .collect()
+    }
+}
+
+/// Registers one of the caller's devices, or relabels it if already known.
+#[ic_cdk::update]
+fn register_device(device_id: String, label: String) -> Result<Device, DriveError> {
+    let _profile = profile("register_device");
+    check_update_allowed("register_device")?;
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow_mut().register_device(caller, device_id, label))
+}
+
+/// Where the device should resume syncing, or `None` to start from a snapshot.
+#[ic_cdk::query]
+fn get_sync_cursor(device_id: String) -> Result<Option<u64>, DriveError> {
+    check_read_access()?;
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow().device(caller, &device_id).map(|device| device.sync_cursor))
+}
+
+/// Records that the device has applied every event up to and including `cursor`.
+#[ic_cdk::update]
+fn commit_sync_cursor(device_id: String, cursor: u64) -> Result<(), DriveError> {
+    let _profile = profile("commit_sync_cursor");
+    check_update_allowed("commit_sync_cursor")?;
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow_mut().commit_sync_cursor(caller, &device_id, cursor))
+}
+
+#[ic_cdk::query]
+fn list_devices() -> Result<Vec<Device>, DriveError> {
+    check_read_access()?;
+    let caller = system_api::caller();
+    STATE.with(|state| Ok(state.borrow().list_devices(caller)))
+}
+
+/// Forgets a device along with its cursor. `user` defaults to the caller.
+#[ic_cdk::update]
+fn revoke_device(device_id: String, user: Option<Principal>) -> Result<(), DriveError> {
+    let _profile = profile("revoke_device");
+    check_writable()?;
+    let caller = system_api::caller();
+    let user = user.unwrap_or(caller);
+    STATE.with(|state| state.borrow_mut().revoke_device(caller, user, &device_id))
+}
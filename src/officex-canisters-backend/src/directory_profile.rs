@@ -0,0 +1,121 @@
+// directory_profile.rs
+//
+// Lists the drive in its factory's public profile directory, where others
+// can search for the owner and find their published folders. Nothing is
+// listed until the owner opts in. The listing shows the drive profile and is
+// refreshed whenever the profile changes or a folder is published or
+// unpublished.
+
+use candid::{CandidType, Principal};
+use serde::Deserialize;
+
+use crate::audit::AuditAction;
+use crate::errors::DriveError;
+use crate::logs::{log, LogLevel};
+use crate::members::check_read_access;
+use crate::profiling::profile;
+use crate::read_only::check_writable;
+use crate::system_api;
+use crate::{State, STATE};
+
+// The factory accepts at most this many folders per listing
+const MAX_LISTED_FOLDERS: usize = 100;
+
+#[derive(CandidType, Deserialize)]
+struct ListedFolder {
+    folder_id: String,
+    full_folder_path: String,
+}
+
+/// `PublicProfileListing` on the factory.
+#[derive(CandidType, Deserialize)]
+struct PublicProfileListing {
+    display_name: String,
+    bio: String,
+    avatar_url: Option<String>,
+    published_folders: Vec<ListedFolder>,
+}
+
+impl State {
+    fn directory_listing(&self) -> Option<PublicProfileListing> {
+        if !self.listed_in_directory {
+            return None;
+        }
+        let profile = self.drive_profile();
+        let published_folders = self
+            .published_folders
+            .keys()
+            .filter_map(|folder_id| self.folder_uuid_to_metadata.get(folder_id))
+            .filter(|folder| !folder.deleted)
+            .take(MAX_LISTED_FOLDERS)
+            .map(|folder| ListedFolder { folder_id: folder.id, full_folder_path: folder.full_folder_path })
+            .collect();
+        Some(PublicProfileListing {
+            display_name: profile.display_name,
+            bio: profile.bio,
+            avatar_url: self.avatar_url(),
+            published_folders,
+        })
+    }
+}
+
+/// Sends the current listing to the factory, or withdraws it when the drive
+/// is not listed.
+async fn push_directory_listing(factory_id: Principal) -> Result<(), DriveError> {
+    let listing = STATE.with(|state| state.borrow().directory_listing());
+    let result: Result<(Result<(), String>,), _> = match listing {
+        Some(listing) => ic_cdk::call(factory_id, "set_public_profile", (listing,)).await,
+        None => ic_cdk::call(factory_id, "clear_public_profile", ()).await,
+    };
+    match result {
+        Ok((Ok(()),)) => Ok(()),
+        Ok((Err(message),)) => Err(DriveError::Internal { message: format!("Factory rejected the profile: {}", message) }),
+        Err((code, message)) => Err(DriveError::Internal {
+            message: format!("Failed to reach the factory: {:?} {}", code, message),
+        }),
+    }
+}
+
+/// Refreshes the listing in the background if the drive is listed.
+pub(crate) fn sync_directory_listing() {
+    let factory_id = STATE.with(|state| {
+        let state = state.borrow();
+        state.factory_id.filter(|_| state.listed_in_directory)
+    });
+    if let Some(factory_id) = factory_id {
+        ic_cdk::spawn(async move {
+            if let Err(e) = push_directory_listing(factory_id).await {
+                log(LogLevel::Warn, format!("Failed to refresh directory listing: {}", e));
+            }
+        });
+    }
+}
+
+/// Lists the drive in the factory directory, or takes it out. The choice is
+/// kept even if the factory cannot be reached, so calling again retries.
+#[ic_cdk::update]
+async fn set_directory_listing(listed: bool) -> Result<(), DriveError> {
+    let _profile = profile("set_directory_listing");
+    check_writable()?;
+    let caller = system_api::caller();
+    let factory_id = STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if caller != state.owner {
+            return Err(DriveError::unauthorized("Only the owner can change the directory listing"));
+        }
+        let factory_id = state.factory_id.ok_or_else(|| DriveError::InvalidState {
+            reason: "This drive was not created by a factory and has no directory".to_string(),
+        })?;
+        state.listed_in_directory = listed;
+        let owner = state.owner.to_text();
+        state.record_audit(AuditAction::SetDirectoryProfile, &owner, None, None);
+        Ok(factory_id)
+    })?;
+    push_directory_listing(factory_id).await
+}
+
+#[ic_cdk::query]
+fn get_directory_listing() -> Result<bool, DriveError> {
+    check_read_access()?;
+    STATE.with(|state| Ok(state.borrow().listed_in_directory))
+}
@@ -0,0 +1,255 @@
+// documents.rs
+//
+// Autosave for OfficeX Docs and Sheets. Editors append their edits to a file
+// as opaque deltas, numbered in the order the drive receives them, and every
+// so often fold them into a snapshot with `compact_document`. A client
+// opening a document loads the snapshot, then replays the deltas after it.
+
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::Storable;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+use crate::audit::AuditAction;
+use crate::errors::DriveError;
+use crate::members::check_read_access;
+use crate::profiling::profile;
+use crate::rate_limit::check_update_allowed;
+use crate::system_api;
+use crate::{FileUUID, State, STATE};
+
+const MAX_DELTA_BYTES: usize = 64 * 1024;
+// Stays under the ingress size limit enforced in `inspect.rs`
+const MAX_SNAPSHOT_BYTES: usize = 480 * 1024;
+// Editors should compact well before this; appends fail once it is reached
+const MAX_PENDING_DELTAS: u64 = 10_000;
+const MAX_DELTA_PAGE_SIZE: u32 = 500;
+
+/// Deltas sort by document, then in the order they were appended.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DocumentDeltaKey {
+    file_id: FileUUID,
+    seq: u64,
+}
+
+impl Storable for DocumentDeltaKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct DocumentDelta {
+    seq: u64,
+    author: Principal,
+    created_at_ms: u64,
+    bytes: Vec<u8>,
+}
+
+impl Storable for DocumentDelta {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct DocumentSnapshot {
+    // The snapshot includes every delta up to and including this one
+    up_to_seq: u64,
+    author: Principal,
+    created_at_ms: u64,
+    bytes: Vec<u8>,
+}
+
+impl Storable for DocumentSnapshot {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// Per document bookkeeping, kept on the heap so appends need no scan.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default)]
+pub struct DocumentHead {
+    last_seq: u64,
+    pending_deltas: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct DocumentDeltaPage {
+    // Set when deltas after `since_seq` were compacted away; load it first
+    snapshot: Option<DocumentSnapshot>,
+    deltas: Vec<DocumentDelta>,
+    // Pass as `since_seq` for the next page
+    last_seq: u64,
+    has_more: bool,
+}
+
+impl State {
+    fn document_file(&self, caller: Principal, file_id: &FileUUID, edit: bool) -> Result<(), DriveError> {
+        let file = self
+            .file_uuid_to_metadata
+            .get(file_id)
+            .filter(|file| !file.deleted)
+            .ok_or_else(|| DriveError::file_not_found(file_id))?;
+        let allowed = if edit { self.can_edit_file(caller, &file) } else { self.can_view_file(caller, &file) };
+        if !allowed {
+            return Err(DriveError::unauthorized("Caller cannot access this document"));
+        }
+        Ok(())
+    }
+
+    fn append_document_delta(&mut self, caller: Principal, file_id: FileUUID, bytes: Vec<u8>) -> Result<u64, DriveError> {
+        self.document_file(caller, &file_id, true)?;
+        if bytes.is_empty() || bytes.len() > MAX_DELTA_BYTES {
+            return Err(DriveError::InvalidInput {
+                field: "delta_bytes".to_string(),
+                reason: format!("Must be 1 to {} bytes", MAX_DELTA_BYTES),
+            });
+        }
+        let head = self.document_heads.entry(file_id.clone()).or_default();
+        if head.pending_deltas >= MAX_PENDING_DELTAS {
+            return Err(DriveError::InvalidState {
+                reason: "Too many deltas since the last compaction; call compact_document".to_string(),
+            });
+        }
+        head.last_seq += 1;
+        head.pending_deltas += 1;
+        let seq = head.last_seq;
+        let delta = DocumentDelta { seq, author: caller, created_at_ms: system_api::time() / 1_000_000, bytes };
+        self.document_deltas.insert(DocumentDeltaKey { file_id, seq }, delta);
+        Ok(seq)
+    }
+
+    fn get_document_deltas(
+        &self,
+        caller: Principal,
+        file_id: FileUUID,
+        since_seq: u64,
+        limit: u32,
+    ) -> Result<DocumentDeltaPage, DriveError> {
+        self.document_file(caller, &file_id, false)?;
+        let snapshot = self
+            .document_snapshots
+            .get(&file_id)
+            .filter(|snapshot| snapshot.up_to_seq > since_seq);
+        let after = snapshot.as_ref().map_or(since_seq, |snapshot| snapshot.up_to_seq);
+
+        let limit = limit.clamp(1, MAX_DELTA_PAGE_SIZE) as usize;
+        let start = DocumentDeltaKey { file_id: file_id.clone(), seq: after + 1 };
+        let mut deltas = self
+            .document_deltas
+            .range(start..)
+            .take_while(|(key, _)| key.file_id == file_id)
+            .map(|(_, delta)| delta);
+        let page: Vec<DocumentDelta> = deltas.by_ref().take(limit).collect();
+        let has_more = deltas.next().is_some();
+        let last_seq = page.last().map_or(after, |delta| delta.seq);
+        Ok(DocumentDeltaPage { snapshot, deltas: page, last_seq, has_more })
+    }
+
+    fn compact_document(
+        &mut self,
+        caller: Principal,
+        file_id: FileUUID,
+        bytes: Vec<u8>,
+        up_to_seq: u64,
+    ) -> Result<(), DriveError> {
+        self.document_file(caller, &file_id, true)?;
+        if bytes.len() > MAX_SNAPSHOT_BYTES {
+            return Err(DriveError::InvalidInput {
+                field: "snapshot_bytes".to_string(),
+                reason: format!("Must be at most {} bytes", MAX_SNAPSHOT_BYTES),
+            });
+        }
+        let last_seq = self.document_heads.get(&file_id).map_or(0, |head| head.last_seq);
+        let compacted_seq = self.document_snapshots.get(&file_id).map_or(0, |snapshot| snapshot.up_to_seq);
+        if up_to_seq > last_seq || up_to_seq < compacted_seq {
+            return Err(DriveError::InvalidInput {
+                field: "up_to_seq".to_string(),
+                reason: format!("Must be between {} and {}", compacted_seq, last_seq),
+            });
+        }
+
+        let start = DocumentDeltaKey { file_id: file_id.clone(), seq: 0 };
+        let folded: Vec<DocumentDeltaKey> = self
+            .document_deltas
+            .range(start..)
+            .map(|(key, _)| key)
+            .take_while(|key| key.file_id == file_id && key.seq <= up_to_seq)
+            .collect();
+        for key in &folded {
+            self.document_deltas.remove(key);
+        }
+        let head = self.document_heads.entry(file_id.clone()).or_default();
+        head.pending_deltas = head.pending_deltas.saturating_sub(folded.len() as u64);
+        let snapshot = DocumentSnapshot { up_to_seq, author: caller, created_at_ms: system_api::time() / 1_000_000, bytes };
+        self.document_snapshots.insert(file_id.clone(), snapshot);
+        self.record_audit(AuditAction::CompactDocument, &file_id, None, None);
+        Ok(())
+    }
+
+    /// Drops a document's deltas and snapshot along with its file.
+    pub(crate) fn remove_document(&mut self, file_id: &FileUUID) {
+        if self.document_heads.remove(file_id).is_none() {
+            return;
+        }
+        let start = DocumentDeltaKey { file_id: file_id.clone(), seq: 0 };
+        let keys: Vec<DocumentDeltaKey> = self
+            .document_deltas
+            .range(start..)
+            .map(|(key, _)| key)
+            .take_while(|key| &key.file_id == file_id)
+            .collect();
+        for key in keys {
+            self.document_deltas.remove(&key);
+        }
+        self.document_snapshots.remove(file_id);
+    }
+}
+
+/// Saves one edit to a document, returning its sequence number. Deltas are
+/// opaque to the drive; their format belongs to the editor.
+#[ic_cdk::update]
+fn append_document_delta(file_id: FileUUID, delta_bytes: Vec<u8>) -> Result<u64, DriveError> {
+    let _profile = profile("append_document_delta");
+    check_update_allowed("append_document_delta")?;
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow_mut().append_document_delta(caller, file_id, delta_bytes))
+}
+
+/// Deltas after `since_seq`, oldest first. Start from 0 to load a document.
+#[ic_cdk::query]
+fn get_document_deltas(file_id: FileUUID, since_seq: u64, limit: u32) -> Result<DocumentDeltaPage, DriveError> {
+    check_read_access()?;
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow().get_document_deltas(caller, file_id, since_seq, limit))
+}
+
+/// Replaces every delta up to and including `up_to_seq` with a snapshot of
+/// the document at that point. Deltas appended meanwhile are kept.
+#[ic_cdk::update]
+fn compact_document(file_id: FileUUID, snapshot_bytes: Vec<u8>, up_to_seq: u64) -> Result<(), DriveError> {
+    let _profile = profile("compact_document");
+    check_update_allowed("compact_document")?;
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow_mut().compact_document(caller, file_id, snapshot_bytes, up_to_seq))
+}
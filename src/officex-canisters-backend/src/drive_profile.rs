@@ -0,0 +1,102 @@
+// drive_profile.rs
+//
+// How the drive's owner is shown to collaborators. `username` stays the
+// unique handle registered with the factory; the profile is free-form.
+
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+use crate::audit::AuditAction;
+use crate::directory_profile::sync_directory_listing;
+use crate::errors::DriveError;
+use crate::members::check_read_access;
+use crate::profiling::profile;
+use crate::read_only::check_writable;
+use crate::system_api;
+use crate::{FileUUID, State, STATE};
+
+const MAX_DISPLAY_NAME_CHARS: usize = 64;
+const MAX_BIO_CHARS: usize = 500;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DriveProfile {
+    pub display_name: String,
+    // An image file in this drive
+    pub avatar_file_id: Option<FileUUID>,
+    pub bio: String,
+}
+
+impl State {
+    /// The profile the owner set, or one made from the username until then.
+    pub(crate) fn drive_profile(&self) -> DriveProfile {
+        self.profile.clone().unwrap_or_else(|| DriveProfile {
+            display_name: self.username.split('@').next().unwrap_or_default().to_string(),
+            avatar_file_id: None,
+            bio: String::new(),
+        })
+    }
+
+    /// Where the avatar can be downloaded, once its upload has completed.
+    pub(crate) fn avatar_url(&self) -> Option<String> {
+        let avatar_id = self.profile.as_ref()?.avatar_file_id.as_ref()?;
+        self.file_uuid_to_metadata
+            .get(avatar_id)
+            .filter(|file| !file.deleted && !file.raw_url.is_empty())
+            .map(|file| file.raw_url)
+    }
+
+    fn set_profile(&mut self, drive_profile: DriveProfile) -> Result<(), DriveError> {
+        let display_name = drive_profile.display_name.trim();
+        if display_name.is_empty() || display_name.chars().count() > MAX_DISPLAY_NAME_CHARS {
+            return Err(DriveError::InvalidInput {
+                field: "display_name".to_string(),
+                reason: format!("Must be 1 to {} characters", MAX_DISPLAY_NAME_CHARS),
+            });
+        }
+        if drive_profile.bio.chars().count() > MAX_BIO_CHARS {
+            return Err(DriveError::InvalidInput {
+                field: "bio".to_string(),
+                reason: format!("At most {} characters", MAX_BIO_CHARS),
+            });
+        }
+        if let Some(avatar_id) = &drive_profile.avatar_file_id {
+            let avatar = self
+                .file_uuid_to_metadata
+                .get(avatar_id)
+                .filter(|file| !file.deleted)
+                .ok_or_else(|| DriveError::file_not_found(avatar_id))?;
+            if !avatar.mime_type.starts_with("image/") {
+                return Err(DriveError::InvalidInput {
+                    field: "avatar_file_id".to_string(),
+                    reason: format!("Must be an image, not {}", avatar.mime_type),
+                });
+            }
+        }
+        self.profile = Some(DriveProfile { display_name: display_name.to_string(), ..drive_profile });
+        let owner = self.owner.to_text();
+        self.record_audit(AuditAction::SetProfile, &owner, None, None);
+        Ok(())
+    }
+}
+
+#[ic_cdk::update]
+fn set_profile(drive_profile: DriveProfile) -> Result<(), DriveError> {
+    let _profile = profile("set_profile");
+    check_writable()?;
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if caller != state.owner {
+            return Err(DriveError::unauthorized("Only the owner can change the profile"));
+        }
+        state.set_profile(drive_profile)
+    })?;
+    sync_directory_listing();
+    Ok(())
+}
+
+#[ic_cdk::query]
+fn get_profile() -> Result<DriveProfile, DriveError> {
+    check_read_access()?;
+    STATE.with(|state| Ok(state.borrow().drive_profile()))
+}
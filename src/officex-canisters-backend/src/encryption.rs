@@ -0,0 +1,126 @@
+// encryption.rs
+
+use candid::Principal;
+
+use crate::audit::AuditAction;
+use crate::errors::DriveError;
+use crate::profiling::profile;
+use crate::rate_limit::check_update_allowed;
+use crate::system_api;
+use crate::{FileMetadata, FileUUID, State, STATE};
+
+const MAX_ENCRYPTION_SCHEME_LENGTH: usize = 64;
+const MAX_WRAPPED_DEK_BYTES: usize = 512;
+const MAX_NONCE_BYTES: usize = 64;
+
+fn invalid(field: &str, reason: String) -> DriveError {
+    DriveError::InvalidInput { field: field.to_string(), reason }
+}
+
+/// Checks the encryption fields a client submits. An encrypted file must
+/// name its scheme and carry a wrapped data encryption key.
+pub(crate) fn validate_encryption_fields(file: &FileMetadata) -> Result<(), DriveError> {
+    if !file.encrypted {
+        return Ok(());
+    }
+    let scheme = file.encryption_scheme.as_deref().unwrap_or("");
+    if scheme.is_empty() || scheme.len() > MAX_ENCRYPTION_SCHEME_LENGTH {
+        return Err(invalid(
+            "encryption_scheme",
+            format!("Encrypted files need a scheme of at most {} characters", MAX_ENCRYPTION_SCHEME_LENGTH),
+        ));
+    }
+    let wrapped_dek_len = file.wrapped_dek.as_ref().map_or(0, Vec::len);
+    if wrapped_dek_len == 0 || wrapped_dek_len > MAX_WRAPPED_DEK_BYTES {
+        return Err(invalid(
+            "wrapped_dek",
+            format!("Encrypted files need a wrapped key of at most {} bytes", MAX_WRAPPED_DEK_BYTES),
+        ));
+    }
+    if file.nonce.as_ref().is_some_and(|nonce| nonce.len() > MAX_NONCE_BYTES) {
+        return Err(invalid("nonce", format!("Nonces are limited to {} bytes", MAX_NONCE_BYTES)));
+    }
+    Ok(())
+}
+
+impl State {
+    fn save_encryption_change(&mut self, mut file: FileMetadata) -> FileMetadata {
+        file.last_changed_unix_ms = system_api::time() / 1_000_000;
+        file.revision += 1;
+        self.replace_file_metadata(file.clone());
+        self.record_audit(AuditAction::SetEncryption, &file.id, Some(file.full_file_path.clone()), None);
+        file
+    }
+
+    fn set_file_encryption(
+        &mut self,
+        caller: Principal,
+        file_id: &FileUUID,
+        encryption_scheme: String,
+        wrapped_dek: Vec<u8>,
+        nonce: Option<Vec<u8>>,
+    ) -> Result<FileMetadata, DriveError> {
+        let mut file = self.editable_file(caller, file_id)?;
+        file.encrypted = true;
+        file.encryption_scheme = Some(encryption_scheme);
+        file.wrapped_dek = Some(wrapped_dek);
+        file.nonce = nonce;
+        validate_encryption_fields(&file)?;
+        Ok(self.save_encryption_change(file))
+    }
+
+    /// Swaps the wrapped key after the client re-wraps the same data key
+    /// under a new key encryption key. The content is unchanged.
+    fn rotate_wrapped_key(&mut self, caller: Principal, file_id: &FileUUID, wrapped_dek: Vec<u8>) -> Result<FileMetadata, DriveError> {
+        let mut file = self.editable_file(caller, file_id)?;
+        if !file.encrypted {
+            return Err(DriveError::InvalidState {
+                reason: "File is not encrypted".to_string(),
+            });
+        }
+        file.wrapped_dek = Some(wrapped_dek);
+        validate_encryption_fields(&file)?;
+        Ok(self.save_encryption_change(file))
+    }
+
+    fn clear_file_encryption(&mut self, caller: Principal, file_id: &FileUUID) -> Result<FileMetadata, DriveError> {
+        let mut file = self.editable_file(caller, file_id)?;
+        file.encrypted = false;
+        file.encryption_scheme = None;
+        file.wrapped_dek = None;
+        file.nonce = None;
+        Ok(self.save_encryption_change(file))
+    }
+}
+
+/// Marks a file as client-side encrypted and stores the material needed to
+/// decrypt it: the scheme, the wrapped data key and an optional nonce.
+#[ic_cdk::update]
+fn set_file_encryption(
+    file_id: FileUUID,
+    encryption_scheme: String,
+    wrapped_dek: Vec<u8>,
+    nonce: Option<Vec<u8>>,
+) -> Result<FileMetadata, DriveError> {
+    let _profile = profile("set_file_encryption");
+    check_update_allowed("set_file_encryption")?;
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow_mut().set_file_encryption(caller, &file_id, encryption_scheme, wrapped_dek, nonce))
+}
+
+#[ic_cdk::update]
+fn rotate_wrapped_key(file_id: FileUUID, wrapped_dek: Vec<u8>) -> Result<FileMetadata, DriveError> {
+    let _profile = profile("rotate_wrapped_key");
+    check_update_allowed("rotate_wrapped_key")?;
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow_mut().rotate_wrapped_key(caller, &file_id, wrapped_dek))
+}
+
+/// Records that a file's content was re-uploaded in plaintext.
+#[ic_cdk::update]
+fn clear_file_encryption(file_id: FileUUID) -> Result<FileMetadata, DriveError> {
+    let _profile = profile("clear_file_encryption");
+    check_update_allowed("clear_file_encryption")?;
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow_mut().clear_file_encryption(caller, &file_id))
+}
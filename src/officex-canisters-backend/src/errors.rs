@@ -0,0 +1,81 @@
+// errors.rs
+
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::path_limits::PathRule;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum DriveError {
+    NotFound { resource: String, id: String },
+    AlreadyExists { path: String },
+    Unauthorized { reason: String },
+    InvalidPath { path: String, reason: String },
+    StorageMismatch { expected: String, found: String },
+    QuotaExceeded { limit_bytes: u64, requested_bytes: u64 },
+    InvalidInput { field: String, reason: String },
+    InvalidState { reason: String },
+    RateLimited { method: String, retry_after_ms: u64 },
+    ReadOnlyMode { balance_cycles: u64, threshold_cycles: u64 },
+    // The item changed since the revision the caller based its update on
+    RevisionMismatch { id: String, expected: u64, actual: u64 },
+    // The path breaks one of the drive's limits; see `get_path_limits`
+    PathLimitExceeded { path: String, rule: PathRule },
+    // The file is behind a paywall; see `pay_for_file`
+    PaymentRequired { file_id: String, token_ledger: String, amount: u64 },
+    Internal { message: String },
+}
+
+impl DriveError {
+    pub fn folder_not_found(id: &str) -> Self {
+        DriveError::NotFound { resource: "folder".to_string(), id: id.to_string() }
+    }
+
+    pub fn file_not_found(id: &str) -> Self {
+        DriveError::NotFound { resource: "file".to_string(), id: id.to_string() }
+    }
+
+    pub fn invalid_path(path: &str, reason: &str) -> Self {
+        DriveError::InvalidPath { path: path.to_string(), reason: reason.to_string() }
+    }
+
+    pub fn unauthorized(reason: &str) -> Self {
+        DriveError::Unauthorized { reason: reason.to_string() }
+    }
+}
+
+impl fmt::Display for DriveError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DriveError::NotFound { resource, id } => write!(f, "{} not found: {}", resource, id),
+            DriveError::AlreadyExists { path } => write!(f, "Already exists: {}", path),
+            DriveError::Unauthorized { reason } => write!(f, "Unauthorized: {}", reason),
+            DriveError::InvalidPath { path, reason } => write!(f, "Invalid path '{}': {}", path, reason),
+            DriveError::StorageMismatch { expected, found } => {
+                write!(f, "Storage location mismatch: expected {}, found {}", expected, found)
+            }
+            DriveError::QuotaExceeded { limit_bytes, requested_bytes } => {
+                write!(f, "Quota exceeded: {} bytes requested, limit is {} bytes", requested_bytes, limit_bytes)
+            }
+            DriveError::InvalidInput { field, reason } => write!(f, "Invalid {}: {}", field, reason),
+            DriveError::InvalidState { reason } => write!(f, "Invalid state: {}", reason),
+            DriveError::RateLimited { method, retry_after_ms } => {
+                write!(f, "Rate limited on {}: retry in {}ms", method, retry_after_ms)
+            }
+            DriveError::ReadOnlyMode { balance_cycles, threshold_cycles } => write!(
+                f,
+                "Drive is read-only: {} cycles left, writes resume above {}",
+                balance_cycles, threshold_cycles
+            ),
+            DriveError::RevisionMismatch { id, expected, actual } => {
+                write!(f, "Revision mismatch on {}: expected {}, found {}", id, expected, actual)
+            }
+            DriveError::PathLimitExceeded { path, rule } => write!(f, "Path '{}' is not allowed: {}", path, rule),
+            DriveError::PaymentRequired { file_id, token_ledger, amount } => {
+                write!(f, "Payment required for {}: {} units on ledger {}", file_id, amount, token_ledger)
+            }
+            DriveError::Internal { message } => write!(f, "Internal error: {}", message),
+        }
+    }
+}
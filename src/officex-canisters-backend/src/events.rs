@@ -0,0 +1,51 @@
+// events.rs
+
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+use crate::audit::{AuditEntry, MAX_AUDIT_PAGE_SIZE};
+use crate::errors::DriveError;
+use crate::system_api;
+use crate::{State, STATE};
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct EventBatch {
+    // Oldest first
+    events: Vec<AuditEntry>,
+    // Pass as `since_seq` on the next poll
+    cursor: Option<u64>,
+    // Set when entries after `since_seq` were already dropped from the log;
+    // the client should refetch a snapshot instead of applying the batch
+    missed_events: bool,
+}
+
+impl State {
+    fn poll_events(&self, since_seq: Option<u64>, limit: u32) -> EventBatch {
+        let limit = limit.min(MAX_AUDIT_PAGE_SIZE) as usize;
+        let start = since_seq.map_or(0, |seq| seq + 1);
+        let missed_events = since_seq.is_some()
+            && self.audit_log.first_key_value().is_some_and(|(oldest, _)| oldest > start);
+        let events: Vec<AuditEntry> = self
+            .audit_log
+            .range(start..)
+            .map(|(_, entry)| entry)
+            .take(limit)
+            .collect();
+        let cursor = events.last().map(|entry| entry.seq).or(since_seq);
+        EventBatch { events, cursor, missed_events }
+    }
+}
+
+/// Changes made to the drive after `since_seq`, for clients that keep in
+/// sync by polling. Start with no cursor, then pass back the returned one.
+#[ic_cdk::query]
+fn poll_events(since_seq: Option<u64>, limit: u32) -> Result<EventBatch, DriveError> {
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let state = state.borrow();
+        if !state.is_drive_admin(caller) {
+            return Err(DriveError::unauthorized("Only a drive admin can poll drive events"));
+        }
+        Ok(state.poll_events(since_seq, limit))
+    })
+}
@@ -0,0 +1,155 @@
+// expiry.rs
+//
+// Files can be given an expiry time, e.g. temporary exports and shared
+// scratch files. The `FileExpiry` job then removes them the way the drive's
+// expiry action says: as ordinary deletions that syncing clients pick up, or
+// by purging them outright. Either way the expiry is recorded in the audit log.
+
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+use crate::audit::AuditAction;
+use crate::errors::DriveError;
+use crate::logs::LogLevel;
+use crate::members::check_read_access;
+use crate::profiling::profile;
+use crate::rate_limit::check_update_allowed;
+use crate::read_only::check_writable;
+use crate::system_api;
+use crate::{FileMetadata, FileUUID, State, STATE};
+
+// Keeps one run well inside the instruction limit; the next run continues
+const MAX_EXPIRIES_PER_RUN: usize = 500;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ExpiryAction {
+    // Leave a tombstone, compacted like any other deletion
+    #[default]
+    MarkDeleted,
+    // Remove the record at once; syncing clients will not see a tombstone
+    Purge,
+}
+
+impl State {
+    /// `file` and its older versions, newest first.
+    pub(crate) fn version_chain(&self, file: FileMetadata) -> Vec<FileMetadata> {
+        let mut chain = vec![file];
+        while let Some(prior) = chain
+            .last()
+            .and_then(|file| file.prior_version.as_ref())
+            .and_then(|id| self.file_uuid_to_metadata.get(id))
+        {
+            chain.push(prior);
+        }
+        chain
+    }
+
+    /// Sets the expiry of the file and every version of it.
+    fn set_expiry(&mut self, file_id: &FileUUID, expires_at_ms: Option<u64>, now_ms: u64) -> Result<(), DriveError> {
+        let file = self
+            .file_uuid_to_metadata
+            .get(file_id)
+            .filter(|file| !file.deleted)
+            .ok_or_else(|| DriveError::file_not_found(file_id))?;
+        if file.next_version.is_some() {
+            return Err(DriveError::InvalidInput {
+                field: "file_id".to_string(),
+                reason: "Set the expiry on the newest version of the file".to_string(),
+            });
+        }
+        if expires_at_ms.is_some_and(|expires_at_ms| expires_at_ms <= now_ms) {
+            return Err(DriveError::InvalidInput {
+                field: "expires_at_ms".to_string(),
+                reason: "Must be in the future".to_string(),
+            });
+        }
+        let path = file.full_file_path.clone();
+        for mut version in self.version_chain(file) {
+            version.expires_at_ms = expires_at_ms;
+            version.last_changed_unix_ms = now_ms;
+            version.revision += 1;
+            self.file_uuid_to_metadata.insert(version.id.clone(), version);
+        }
+        self.record_audit(AuditAction::SetExpiry, file_id, Some(path), None);
+        Ok(())
+    }
+
+    /// Applies the expiry action to files past their expiry. Returns how
+    /// many were expired.
+    pub(crate) fn expire_due_files(&mut self, now_ms: u64) -> usize {
+        let due: Vec<FileUUID> = self
+            .file_uuid_to_metadata
+            .values()
+            .filter(|file| !file.deleted && file.expires_at_ms.is_some_and(|expires_at_ms| expires_at_ms <= now_ms))
+            // Held files wait for the hold to be released
+            .filter(|file| !self.is_file_under_legal_hold(file))
+            .take(MAX_EXPIRIES_PER_RUN)
+            .map(|file| file.id)
+            .collect();
+        let mut expired = 0;
+        for file_id in &due {
+            let result = match self.expiry_action {
+                ExpiryAction::MarkDeleted => self.mark_file_deleted(file_id, now_ms),
+                ExpiryAction::Purge => self.remove_file_record(file_id),
+            };
+            match result {
+                Ok(file) => {
+                    self.record_audit(AuditAction::ExpireFile, file_id, Some(file.full_file_path), None);
+                    expired += 1;
+                }
+                Err(e) => self.log(LogLevel::Error, format!("Failed to expire file {}: {}", file_id, e)),
+            }
+        }
+        expired
+    }
+
+    fn mark_file_deleted(&mut self, file_id: &FileUUID, now_ms: u64) -> Result<FileMetadata, DriveError> {
+        let mut file = self
+            .file_uuid_to_metadata
+            .get(file_id)
+            .ok_or_else(|| DriveError::file_not_found(file_id))?;
+        file.deleted = true;
+        file.last_changed_unix_ms = now_ms;
+        file.revision += 1;
+        self.replace_file_metadata(file.clone());
+        self.unstar_item(file_id);
+        Ok(file)
+    }
+}
+
+/// Expires the file at `expires_at_ms`, along with its older versions.
+/// `None` keeps it indefinitely.
+#[ic_cdk::update]
+fn set_expiry(file_id: FileUUID, expires_at_ms: Option<u64>, expected_revision: Option<u64>) -> Result<(), DriveError> {
+    let _profile = profile("set_expiry");
+    check_update_allowed("set_expiry")?;
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.check_file_revision(&file_id, expected_revision)?;
+        state.editable_file(caller, &file_id)?;
+        state.set_expiry(&file_id, expires_at_ms, system_api::time() / 1_000_000)
+    })
+}
+
+/// What happens to files once they expire.
+#[ic_cdk::update]
+fn set_expiry_action(action: ExpiryAction) -> Result<(), DriveError> {
+    let _profile = profile("set_expiry_action");
+    check_writable()?;
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if !state.is_drive_admin(caller) {
+            return Err(DriveError::unauthorized("Only a drive admin can change the expiry action"));
+        }
+        state.expiry_action = action;
+        Ok(())
+    })
+}
+
+#[ic_cdk::query]
+fn get_expiry_action() -> Result<ExpiryAction, DriveError> {
+    check_read_access()?;
+    STATE.with(|state| Ok(state.borrow().expiry_action))
+}
@@ -0,0 +1,162 @@
+// export.rs
+
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use std::ops::Bound;
+
+use crate::errors::DriveError;
+use crate::members::check_read_access;
+use crate::system_api;
+use crate::upload_status::UploadStatus;
+use crate::{DriveFullFilePath, FolderUUID, State, STATE};
+
+const MAX_MANIFEST_PAGE_SIZE: u32 = 1_000;
+
+/// One line of the JSON manifest. Paths are relative to the exported folder.
+#[derive(Serialize)]
+struct ManifestEntry<'a> {
+    kind: &'static str,
+    id: &'a str,
+    path: &'a str,
+    size: Option<u64>,
+    raw_url: Option<&'a str>,
+    sha256: Option<&'a str>,
+    mime_type: Option<&'a str>,
+    version: Option<u32>,
+    last_changed_ms: u64,
+    encrypted: bool,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct ManifestPage {
+    // JSON Lines: one object per folder or file, in path order, so pages can
+    // be concatenated as they arrive
+    json: String,
+    entry_count: u32,
+    // Pass as `after` for the next page
+    next_cursor: Option<DriveFullFilePath>,
+}
+
+impl State {
+    /// Lists everything below `folder_id` by walking the path indexes, which
+    /// keep full paths in order, instead of the folder tree.
+    pub(crate) fn export_manifest(
+        &self,
+        caller: Principal,
+        folder_id: &FolderUUID,
+        after: Option<DriveFullFilePath>,
+        limit: u32,
+    ) -> Result<ManifestPage, DriveError> {
+        let folder = self
+            .folder_uuid_to_metadata
+            .get(folder_id)
+            .filter(|folder| !folder.deleted)
+            .ok_or_else(|| DriveError::folder_not_found(folder_id))?;
+        let prefix = folder.full_folder_path;
+        let limit = limit.clamp(1, MAX_MANIFEST_PAGE_SIZE) as usize;
+        let start = match after {
+            Some(path) => Bound::Excluded(path),
+            None => Bound::Included(prefix.clone()),
+        };
+
+        let in_folder = |(path, _): &(String, String)| path.starts_with(&prefix);
+        let mut folders = self
+            .full_folder_path_to_uuid
+            .range((start.clone(), Bound::Unbounded))
+            .take_while(in_folder)
+            .peekable();
+        let mut files = self
+            .full_file_path_to_uuid
+            .range((start, Bound::Unbounded))
+            .take_while(in_folder)
+            .peekable();
+
+        let mut json = String::new();
+        let mut entry_count = 0;
+        let mut last_path = None;
+        while entry_count < limit {
+            let take_folder = match (folders.peek(), files.peek()) {
+                (Some((folder_path, _)), Some((file_path, _))) => folder_path < file_path,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => break,
+            };
+            let line = if take_folder {
+                let (path, id) = folders.next().expect("peeked");
+                let line = self
+                    .folder_uuid_to_metadata
+                    .get(&id)
+                    .filter(|folder| !folder.deleted && path != prefix)
+                    .map(|folder| {
+                        serde_json::to_string(&ManifestEntry {
+                            kind: "folder",
+                            id: &folder.id,
+                            path: &path[prefix.len()..],
+                            size: None,
+                            raw_url: None,
+                            sha256: None,
+                            mime_type: None,
+                            version: None,
+                            last_changed_ms: folder.last_changed_unix_ms,
+                            encrypted: false,
+                        })
+                    });
+                last_path = Some(path);
+                line
+            } else {
+                let (path, id) = files.next().expect("peeked");
+                // Files still uploading have no content to mirror yet
+                let line = self
+                    .file_uuid_to_metadata
+                    .get(&id)
+                    .filter(|file| !file.deleted && file.upload_status == UploadStatus::Uploaded)
+                    .map(|file| {
+                        serde_json::to_string(&ManifestEntry {
+                            kind: "file",
+                            id: &file.id,
+                            path: &path[prefix.len()..],
+                            size: Some(file.file_size),
+                            // Left out until the caller pays for a paywalled file
+                            raw_url: self.check_paywall(caller, &file).is_ok().then_some(file.raw_url.as_str()),
+                            sha256: file.sha256_checksum.as_deref(),
+                            mime_type: Some(&file.mime_type),
+                            version: Some(file.file_version),
+                            last_changed_ms: file.last_changed_unix_ms,
+                            encrypted: file.encrypted,
+                        })
+                    });
+                last_path = Some(path);
+                line
+            };
+            if let Some(line) = line {
+                let line = line.map_err(|e| DriveError::Internal {
+                    message: format!("Failed to encode manifest entry: {}", e),
+                })?;
+                json.push_str(&line);
+                json.push('\n');
+                entry_count += 1;
+            }
+        }
+
+        let more = folders.peek().is_some() || files.peek().is_some();
+        Ok(ManifestPage {
+            json,
+            entry_count: entry_count as u32,
+            next_cursor: if more { last_path } else { None },
+        })
+    }
+}
+
+/// A JSON Lines manifest of every folder and uploaded file below `folder_id`,
+/// with raw URLs, sizes and checksums, for tools that mirror or download a
+/// whole drive. Start with no cursor and keep passing back `next_cursor`.
+#[ic_cdk::query]
+fn export_manifest(
+    folder_id: FolderUUID,
+    after: Option<DriveFullFilePath>,
+    limit: u32,
+) -> Result<ManifestPage, DriveError> {
+    check_read_access()?;
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow().export_manifest(caller, &folder_id, after, limit))
+}
@@ -0,0 +1,102 @@
+// file_updates.rs
+
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+use crate::api_keys::{check_update_allowed_with_api_key, ApiScope};
+use crate::audit::AuditAction;
+use crate::errors::DriveError;
+use crate::profiling::profile;
+use crate::sharing::ShareRole;
+use crate::system_api;
+use crate::{FileMetadata, FileUUID, State, Tag, STATE};
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default)]
+pub(crate) struct UpdateFileMetadataRequest {
+    file_size: Option<u64>,
+    raw_url: Option<String>,
+    tags: Option<Vec<Tag>>,
+}
+
+impl State {
+    /// The owner and editors of the drive may edit any file; other principals
+    /// only the files they created or were made an editor of.
+    pub(crate) fn can_edit_file(&self, caller: Principal, file: &FileMetadata) -> bool {
+        self.can_edit_drive(caller)
+            || caller == file.owner
+            || self.has_file_share_role(caller, file, ShareRole::Editor)
+    }
+
+    /// Like `can_edit_file`, also letting in viewers of the drive or the file.
+    pub(crate) fn can_view_file(&self, caller: Principal, file: &FileMetadata) -> bool {
+        self.can_edit_file(caller, file)
+            || self.member_role(caller).is_some()
+            || self.has_file_share_role(caller, file, ShareRole::Viewer)
+    }
+
+    /// Replaces a file's metadata record, keeping usage accounting in step.
+    pub(crate) fn replace_file_metadata(&mut self, updated: FileMetadata) {
+        if let Some(previous) = self.file_uuid_to_metadata.get(&updated.id) {
+            self.account_file_removed(&previous);
+        }
+        self.account_file_added(&updated);
+        self.file_uuid_to_metadata.insert(updated.id.clone(), updated);
+    }
+
+    pub(crate) fn update_file_metadata(
+        &mut self,
+        caller: Principal,
+        file_id: &FileUUID,
+        request: UpdateFileMetadataRequest,
+    ) -> Result<FileMetadata, DriveError> {
+        let mut file = self
+            .file_uuid_to_metadata
+            .get(file_id)
+            .ok_or_else(|| DriveError::file_not_found(file_id))?;
+        if !self.can_edit_file(caller, &file) {
+            return Err(DriveError::unauthorized("Caller cannot edit this file"));
+        }
+
+        // Tags may change under a hold, the content may not
+        if request.file_size.is_some() || request.raw_url.is_some() {
+            self.check_file_legal_hold(&file)?;
+        }
+        if let Some(file_size) = request.file_size {
+            if file_size > file.file_size {
+                self.check_quota(file_size - file.file_size)?;
+            }
+            file.file_size = file_size;
+        }
+        if let Some(raw_url) = request.raw_url {
+            file.raw_url = raw_url;
+        }
+        if let Some(tags) = request.tags {
+            file.tags = tags;
+        }
+        file.last_changed_unix_ms = system_api::time() / 1_000_000;
+        file.revision += 1;
+
+        self.replace_file_metadata(file.clone());
+        self.record_audit(AuditAction::UpdateFileMetadata, file_id, Some(file.full_file_path.clone()), None);
+        Ok(file)
+    }
+}
+
+/// Fills in details known only once the client finishes uploading the file
+/// content. Fields left as `None` are unchanged. An `api_key` with the
+/// `Write` scope may update any file.
+#[ic_cdk::update]
+fn update_file_metadata(
+    file_id: FileUUID,
+    request: UpdateFileMetadataRequest,
+    api_key: Option<String>,
+    expected_revision: Option<u64>,
+) -> Result<FileMetadata, DriveError> {
+    let _profile = profile("update_file_metadata");
+    let caller = check_update_allowed_with_api_key("update_file_metadata", api_key, ApiScope::Write)?;
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.check_file_revision(&file_id, expected_revision)?;
+        state.update_file_metadata(caller, &file_id, request)
+    })
+}
@@ -0,0 +1,90 @@
+// folder_labels.rs
+//
+// How a folder is presented in drive UIs: a color label, and a rank that
+// pins it above its unpinned siblings when a listing asks for pinned first.
+
+use crate::audit::AuditAction;
+use crate::children::ChildKind;
+use crate::errors::DriveError;
+use crate::profiling::profile;
+use crate::rate_limit::check_update_allowed;
+use crate::system_api;
+use crate::{FolderMetadata, FolderUUID, State, STATE};
+
+/// Lowercase `#rrggbb`, or an error naming the expected format.
+fn normalize_color(color: &str) -> Result<String, DriveError> {
+    let hex = color.strip_prefix('#').filter(|hex| hex.len() == 6 && hex.chars().all(|c| c.is_ascii_hexdigit()));
+    match hex {
+        Some(hex) => Ok(format!("#{}", hex.to_ascii_lowercase())),
+        None => Err(DriveError::InvalidInput {
+            field: "color".to_string(),
+            reason: "Must be a hex color like #1a73e8".to_string(),
+        }),
+    }
+}
+
+impl State {
+    /// Pinned subfolders of `parent`, lowest rank first and then by name.
+    pub(crate) fn pinned_child_folders(&self, parent: &FolderUUID) -> Vec<FolderMetadata> {
+        let mut pinned: Vec<FolderMetadata> = self
+            .children_of(parent)
+            .take_while(|key| key.kind == ChildKind::Folder)
+            .filter_map(|key| self.folder_uuid_to_metadata.get(&key.child))
+            .filter(|folder| folder.pinned_rank.is_some())
+            .collect();
+        pinned.sort_by(|a, b| {
+            (a.pinned_rank, &a.original_folder_name).cmp(&(b.pinned_rank, &b.original_folder_name))
+        });
+        pinned
+    }
+
+    fn update_folder_label(
+        &mut self,
+        folder_id: &FolderUUID,
+        action: AuditAction,
+        apply: impl FnOnce(&mut FolderMetadata),
+    ) -> Result<(), DriveError> {
+        let mut folder = self
+            .folder_uuid_to_metadata
+            .get(folder_id)
+            .filter(|folder| !folder.deleted)
+            .ok_or_else(|| DriveError::folder_not_found(folder_id))?;
+        apply(&mut folder);
+        folder.last_changed_unix_ms = system_api::time() / 1_000_000;
+        folder.revision += 1;
+        let path = folder.full_folder_path.clone();
+        self.folder_uuid_to_metadata.insert(folder_id.clone(), folder);
+        self.record_audit(action, folder_id, Some(path), None);
+        Ok(())
+    }
+}
+
+/// Sets the folder's color label, e.g. `#1a73e8`; `None` removes it.
+#[ic_cdk::update]
+fn set_folder_color(folder_id: FolderUUID, color: Option<String>, expected_revision: Option<u64>) -> Result<(), DriveError> {
+    let _profile = profile("set_folder_color");
+    check_update_allowed("set_folder_color")?;
+    let color = color.as_deref().map(normalize_color).transpose()?;
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.check_folder_revision(&folder_id, expected_revision)?;
+        state.update_folder_label(&folder_id, AuditAction::SetFolderColor, |folder| folder.color = color)
+    })
+}
+
+/// Pins the folder among its siblings; lower ranks are listed first. `None`
+/// unpins it.
+#[ic_cdk::update]
+fn set_folder_pinned_rank(
+    folder_id: FolderUUID,
+    pinned_rank: Option<u32>,
+    expected_revision: Option<u64>,
+) -> Result<(), DriveError> {
+    let _profile = profile("set_folder_pinned_rank");
+    check_update_allowed("set_folder_pinned_rank")?;
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.check_folder_revision(&folder_id, expected_revision)?;
+        state.update_folder_label(&folder_id, AuditAction::PinFolder, |folder| folder.pinned_rank = pinned_rank)
+    })
+}
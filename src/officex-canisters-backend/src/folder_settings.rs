@@ -0,0 +1,139 @@
+// folder_settings.rs
+//
+// Defaults a folder sets for what is created inside it. Every setting is
+// inherited down the tree: the nearest folder that sets it wins, so a
+// subfolder can override a single setting and keep the rest.
+//
+// A folder cannot choose the storage location of its children, since the
+// prefix of a path is its location.
+
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+use crate::audit::AuditAction;
+use crate::errors::DriveError;
+use crate::members::check_read_access;
+use crate::profiling::profile;
+use crate::rate_limit::check_update_allowed;
+use crate::system_api;
+use crate::{FileUUID, FolderUUID, State, Tag, STATE};
+
+const MAX_DEFAULT_TAGS: usize = 32;
+// Far deeper than any real tree; stops a corrupted parent link from looping
+const MAX_FOLDER_DEPTH: usize = 1_000;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+pub struct FolderSettings {
+    // Tags given to new files and folders inside
+    pub default_tags: Option<Vec<Tag>>,
+    // Versions of each file kept once a new one is added; older ones are removed
+    pub max_versions: Option<u32>,
+}
+
+impl FolderSettings {
+    fn inherit_from(&mut self, ancestor: &FolderSettings) {
+        if self.default_tags.is_none() {
+            self.default_tags = ancestor.default_tags.clone();
+        }
+        if self.max_versions.is_none() {
+            self.max_versions = ancestor.max_versions;
+        }
+    }
+}
+
+impl State {
+    /// The settings in force in `folder_id`, after inheritance.
+    pub(crate) fn effective_folder_settings(&self, folder_id: &FolderUUID) -> FolderSettings {
+        let mut effective = FolderSettings::default();
+        let mut next = Some(folder_id.clone());
+        for _ in 0..MAX_FOLDER_DEPTH {
+            let Some(folder) = next.and_then(|id| self.folder_uuid_to_metadata.get(&id)) else {
+                break;
+            };
+            if let Some(settings) = &folder.settings {
+                effective.inherit_from(settings);
+            }
+            next = folder.parent_folder_uuid;
+        }
+        effective
+    }
+
+    pub(crate) fn default_tags_for(&self, folder_id: &FolderUUID) -> Vec<Tag> {
+        self.effective_folder_settings(folder_id).default_tags.unwrap_or_default()
+    }
+
+    /// Removes the oldest versions of the file ending at `latest_id` beyond
+    /// the `max_versions` in force in its folder.
+    pub(crate) fn apply_version_retention(&mut self, latest_id: &FileUUID) {
+        let Some(latest) = self.file_uuid_to_metadata.get(latest_id) else {
+            return;
+        };
+        let Some(max_versions) = self.effective_folder_settings(&latest.folder_uuid).max_versions else {
+            return;
+        };
+        let mut kept = 1;
+        let mut prior = latest.prior_version;
+        while let Some(id) = prior {
+            prior = self.file_uuid_to_metadata.get(&id).and_then(|file| file.prior_version);
+            if kept < max_versions.max(1) {
+                kept += 1;
+            } else {
+                // Relinks the remaining versions around the removed one
+                let _ = self.delete_file(&id);
+            }
+        }
+    }
+
+    fn set_folder_settings(&mut self, folder_id: &FolderUUID, settings: Option<FolderSettings>) -> Result<(), DriveError> {
+        if let Some(tags) = settings.as_ref().and_then(|settings| settings.default_tags.as_ref()) {
+            if tags.len() > MAX_DEFAULT_TAGS {
+                return Err(DriveError::InvalidInput {
+                    field: "default_tags".to_string(),
+                    reason: format!("At most {} tags", MAX_DEFAULT_TAGS),
+                });
+            }
+        }
+        let mut folder = self
+            .folder_uuid_to_metadata
+            .get(folder_id)
+            .filter(|folder| !folder.deleted)
+            .ok_or_else(|| DriveError::folder_not_found(folder_id))?;
+        folder.settings = settings;
+        folder.last_changed_unix_ms = system_api::time() / 1_000_000;
+        folder.revision += 1;
+        let path = folder.full_folder_path.clone();
+        self.folder_uuid_to_metadata.insert(folder_id.clone(), folder);
+        self.record_audit(AuditAction::SetFolderSettings, folder_id, Some(path), None);
+        Ok(())
+    }
+}
+
+/// Replaces the folder's own settings; `None` clears them so the folder
+/// inherits everything. Version limits apply as new versions are added.
+#[ic_cdk::update]
+fn set_folder_settings(
+    folder_id: FolderUUID,
+    settings: Option<FolderSettings>,
+    expected_revision: Option<u64>,
+) -> Result<(), DriveError> {
+    let _profile = profile("set_folder_settings");
+    check_update_allowed("set_folder_settings")?;
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.check_folder_revision(&folder_id, expected_revision)?;
+        state.set_folder_settings(&folder_id, settings)
+    })
+}
+
+/// What applies in the folder once inherited settings are taken into account.
+#[ic_cdk::query]
+fn get_effective_folder_settings(folder_id: FolderUUID) -> Result<FolderSettings, DriveError> {
+    check_read_access()?;
+    STATE.with(|state| {
+        let state = state.borrow();
+        if !state.folder_uuid_to_metadata.contains_key(&folder_id) {
+            return Err(DriveError::folder_not_found(&folder_id));
+        }
+        Ok(state.effective_folder_settings(&folder_id))
+    })
+}
@@ -0,0 +1,199 @@
+// fsck.rs
+
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+use crate::audit::AuditAction;
+use crate::children::{ChildKey, ChildKind};
+use crate::errors::DriveError;
+use crate::memory::StableMapExt;
+use crate::profiling::profile;
+use crate::read_only::check_writable;
+use crate::system_api;
+use crate::{DriveFullFilePath, FileUUID, FolderUUID, State, STATE};
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+enum VersionLink {
+    Prior,
+    Next,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+enum StateIssue {
+    // File whose folder_uuid has no folder metadata
+    OrphanFile { file_id: FileUUID, folder_id: FolderUUID },
+    // Child index entry whose folder or file no longer exists
+    DanglingChild { parent_id: FolderUUID, kind: ChildKind, name: String, child_id: String },
+    DanglingFolderPath { path: DriveFullFilePath, folder_id: FolderUUID },
+    DanglingFilePath { path: DriveFullFilePath, file_id: FileUUID },
+    // prior_version/next_version pointing at a missing file, or at one that
+    // does not point back
+    BrokenVersionLink { file_id: FileUUID, link: VersionLink, target_id: FileUUID },
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct StateIntegrityReport {
+    folders_checked: u64,
+    files_checked: u64,
+    issues: Vec<StateIssue>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct RepairReport {
+    dry_run: bool,
+    issues: Vec<StateIssue>,
+    repaired: u64,
+}
+
+impl State {
+    fn find_state_issues(&self) -> Vec<StateIssue> {
+        let mut issues = Vec::new();
+
+        for (file_id, file) in self.file_uuid_to_metadata.iter() {
+            if !self.folder_uuid_to_metadata.contains_key(&file.folder_uuid) {
+                issues.push(StateIssue::OrphanFile {
+                    file_id: file_id.clone(),
+                    folder_id: file.folder_uuid.clone(),
+                });
+            }
+            if let Some(prior) = &file.prior_version {
+                let linked_back = self
+                    .file_uuid_to_metadata
+                    .get(prior)
+                    .is_some_and(|prior_file| prior_file.next_version.as_ref() == Some(&file_id));
+                if !linked_back {
+                    issues.push(StateIssue::BrokenVersionLink {
+                        file_id: file_id.clone(),
+                        link: VersionLink::Prior,
+                        target_id: prior.clone(),
+                    });
+                }
+            }
+            if let Some(next) = &file.next_version {
+                let linked_back = self
+                    .file_uuid_to_metadata
+                    .get(next)
+                    .is_some_and(|next_file| next_file.prior_version.as_ref() == Some(&file_id));
+                if !linked_back {
+                    issues.push(StateIssue::BrokenVersionLink {
+                        file_id: file_id.clone(),
+                        link: VersionLink::Next,
+                        target_id: next.clone(),
+                    });
+                }
+            }
+        }
+
+        for (key, _) in self.folder_children.iter() {
+            let exists = match key.kind {
+                ChildKind::Folder => self.folder_uuid_to_metadata.contains_key(&key.child),
+                ChildKind::File => self.file_uuid_to_metadata.contains_key(&key.child),
+            };
+            if !exists {
+                issues.push(StateIssue::DanglingChild {
+                    parent_id: key.parent,
+                    kind: key.kind,
+                    name: key.name,
+                    child_id: key.child,
+                });
+            }
+        }
+
+        for (path, folder_id) in self.full_folder_path_to_uuid.iter() {
+            if !self.folder_uuid_to_metadata.contains_key(&folder_id) {
+                issues.push(StateIssue::DanglingFolderPath { path, folder_id });
+            }
+        }
+        for (path, file_id) in self.full_file_path_to_uuid.iter() {
+            if !self.file_uuid_to_metadata.contains_key(&file_id) {
+                issues.push(StateIssue::DanglingFilePath { path, file_id });
+            }
+        }
+
+        issues
+    }
+
+    fn verify_state_integrity(&self) -> StateIntegrityReport {
+        StateIntegrityReport {
+            folders_checked: self.folder_uuid_to_metadata.len(),
+            files_checked: self.file_uuid_to_metadata.len(),
+            issues: self.find_state_issues(),
+        }
+    }
+
+    fn repair_issue(&mut self, issue: &StateIssue) -> bool {
+        match issue {
+            // Without its folder the file cannot be listed or reached by path
+            StateIssue::OrphanFile { file_id, .. } => self.delete_file(file_id).is_ok(),
+            StateIssue::DanglingChild { parent_id, kind, name, child_id } => {
+                let key = ChildKey {
+                    parent: parent_id.clone(),
+                    kind: *kind,
+                    name: name.clone(),
+                    child: child_id.clone(),
+                };
+                self.folder_children.remove(&key).is_some()
+            }
+            StateIssue::DanglingFolderPath { path, .. } => self.full_folder_path_to_uuid.remove(path).is_some(),
+            StateIssue::DanglingFilePath { path, .. } => self.full_file_path_to_uuid.remove(path).is_some(),
+            StateIssue::BrokenVersionLink { file_id, link, .. } => self
+                .file_uuid_to_metadata
+                .update(file_id, |file| match link {
+                    VersionLink::Prior => file.prior_version = None,
+                    VersionLink::Next => file.next_version = None,
+                })
+                .is_some(),
+        }
+    }
+
+    fn repair_state(&mut self, dry_run: bool) -> RepairReport {
+        let issues = self.find_state_issues();
+        let mut repaired = 0;
+        if !dry_run {
+            for issue in &issues {
+                if self.repair_issue(issue) {
+                    repaired += 1;
+                }
+            }
+            if repaired > 0 {
+                self.recompute_storage_usage();
+                let owner = self.owner.to_text();
+                self.record_audit(AuditAction::RepairState, &owner, None, None);
+            }
+        }
+        RepairReport { dry_run, issues, repaired }
+    }
+}
+
+/// Scans the metadata, path and child indexes for references that do not
+/// resolve. Read-only; see `repair_state` to fix what it finds.
+#[ic_cdk::query]
+fn verify_state_integrity() -> Result<StateIntegrityReport, DriveError> {
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let state = state.borrow();
+        if !state.is_drive_admin(caller) {
+            return Err(DriveError::unauthorized("Only a drive admin can check drive integrity"));
+        }
+        Ok(state.verify_state_integrity())
+    })
+}
+
+/// Fixes the issues `verify_state_integrity` reports: orphan files are
+/// deleted, dangling index entries dropped and broken version links cut.
+/// With `dry_run` nothing is changed.
+#[ic_cdk::update]
+fn repair_state(dry_run: bool) -> Result<RepairReport, DriveError> {
+    let _profile = profile("repair_state");
+    if !dry_run {
+        check_writable()?;
+    }
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if !state.is_drive_admin(caller) {
+            return Err(DriveError::unauthorized("Only a drive admin can repair the drive"));
+        }
+        Ok(state.repair_state(dry_run))
+    })
+}
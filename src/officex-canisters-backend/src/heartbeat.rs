@@ -0,0 +1,54 @@
+// heartbeat.rs
+
+use candid::{CandidType, Principal};
+use serde::Deserialize;
+use std::time::Duration;
+
+use crate::logs::{log, LogLevel};
+use crate::STATE;
+
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// What a drive tells its factory on every check-in, so the factory dashboard
+/// can watch the fleet without calling `canister_status` on every drive.
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct DriveHeartbeat {
+    cycle_balance: u128,
+    file_count: u64,
+    folder_count: u64,
+    stable_memory_bytes: u64,
+    wasm_version: String,
+}
+
+async fn send_heartbeat(factory_id: Principal) {
+    let report = STATE.with(|state| {
+        let state = state.borrow();
+        DriveHeartbeat {
+            cycle_balance: ic_cdk::api::canister_balance128(),
+            file_count: state.file_uuid_to_metadata.len(),
+            folder_count: state.folder_uuid_to_metadata.len(),
+            stable_memory_bytes: ic_cdk::api::stable::stable64_size() * 64 * 1024,
+            wasm_version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    });
+    let result: Result<(Result<(), String>,), _> =
+        ic_cdk::call(factory_id, "report_drive_heartbeat", (report,)).await;
+    match result {
+        Ok((Ok(()),)) => {}
+        Ok((Err(message),)) => log(LogLevel::Warn, format!("Factory rejected heartbeat: {}", message)),
+        Err((code, message)) => log(LogLevel::Warn, format!("Failed to send heartbeat: {:?} {}", code, message)),
+    }
+}
+
+fn send_heartbeat_if_factory_drive() {
+    if let Some(factory_id) = STATE.with(|state| state.borrow().factory_id) {
+        ic_cdk::spawn(send_heartbeat(factory_id));
+    }
+}
+
+/// Checks in with the factory now and then every few hours. Drives deployed
+/// without a factory have no one to report to and stay quiet.
+pub fn start_heartbeat() {
+    ic_cdk_timers::set_timer(Duration::ZERO, send_heartbeat_if_factory_drive);
+    ic_cdk_timers::set_timer_interval(HEARTBEAT_INTERVAL, send_heartbeat_if_factory_drive);
+}
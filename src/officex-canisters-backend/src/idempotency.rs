@@ -0,0 +1,100 @@
+// idempotency.rs
+//
+// Lets a client retry a mutating call safely. A call made with an
+// `idempotency_key` has its successful response remembered for a day; a
+// retry with the same key returns that response without running the call
+// again. Failed calls are not remembered, so they can be retried as usual.
+
+use candid::{CandidType, Principal};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::DriveError;
+use crate::system_api;
+use crate::{State, STATE};
+
+const IDEMPOTENCY_TTL_MS: u64 = 24 * 60 * 60 * 1000;
+const MAX_IDEMPOTENCY_KEY_LENGTH: usize = 128;
+const MAX_IDEMPOTENCY_RECORDS: usize = 10_000;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct IdempotencyRecord {
+    method: String,
+    expires_at_ms: u64,
+    // Candid encoding of the successful result
+    response: Vec<u8>,
+}
+
+/// Keys are scoped to the caller, so two clients cannot collide.
+fn record_key(caller: Principal, key: &str) -> String {
+    format!("{}:{}", caller, key)
+}
+
+impl State {
+    fn replay_idempotent(&self, record_key: &str, method: &str, now_ms: u64) -> Result<Option<Vec<u8>>, DriveError> {
+        match self.idempotency_records.get(record_key) {
+            Some(record) if record.expires_at_ms > now_ms => {
+                if record.method != method {
+                    return Err(DriveError::InvalidInput {
+                        field: "idempotency_key".to_string(),
+                        reason: format!("Already used for a call to {}", record.method),
+                    });
+                }
+                Ok(Some(record.response.clone()))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn remember_idempotent(&mut self, record_key: String, method: &str, response: Vec<u8>, now_ms: u64) {
+        if self.idempotency_records.len() >= MAX_IDEMPOTENCY_RECORDS {
+            self.idempotency_records.retain(|_, record| record.expires_at_ms > now_ms);
+        }
+        if self.idempotency_records.len() >= MAX_IDEMPOTENCY_RECORDS {
+            let soonest = self
+                .idempotency_records
+                .iter()
+                .min_by_key(|(_, record)| record.expires_at_ms)
+                .map(|(key, _)| key.clone());
+            if let Some(soonest) = soonest {
+                self.idempotency_records.remove(&soonest);
+            }
+        }
+        let record = IdempotencyRecord {
+            method: method.to_string(),
+            expires_at_ms: now_ms + IDEMPOTENCY_TTL_MS,
+            response,
+        };
+        self.idempotency_records.insert(record_key, record);
+    }
+}
+
+/// Runs `call` unless the caller already made a successful call to `method`
+/// with the same key, in which case its response is returned instead.
+pub(crate) fn idempotent<T, F>(idempotency_key: Option<String>, method: &str, call: F) -> Result<T, DriveError>
+where
+    T: CandidType + DeserializeOwned,
+    F: FnOnce() -> Result<T, DriveError>,
+{
+    let Some(key) = idempotency_key else {
+        return call();
+    };
+    if key.is_empty() || key.len() > MAX_IDEMPOTENCY_KEY_LENGTH {
+        return Err(DriveError::InvalidInput {
+            field: "idempotency_key".to_string(),
+            reason: format!("Must be 1 to {} bytes", MAX_IDEMPOTENCY_KEY_LENGTH),
+        });
+    }
+    let record_key = record_key(system_api::caller(), &key);
+    let now_ms = system_api::time() / 1_000_000;
+
+    if let Some(response) = STATE.with(|state| state.borrow().replay_idempotent(&record_key, method, now_ms))? {
+        return candid::decode_one(&response).map_err(|e| DriveError::InvalidState {
+            reason: format!("Failed to decode remembered response: {}", e),
+        });
+    }
+    let result = call()?;
+    let response = candid::encode_one(&result).expect("Failed to encode response");
+    STATE.with(|state| state.borrow_mut().remember_idempotent(record_key, method, response, now_ms));
+    Ok(result)
+}
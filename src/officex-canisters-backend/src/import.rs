@@ -0,0 +1,184 @@
+// import.rs
+
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::DriveError;
+use crate::profiling::profile;
+use crate::rate_limit::check_batch_allowed;
+use crate::system_api;
+use crate::upload_status::UploadStatus;
+use crate::{DriveFullFilePath, State, StorageLocationID, UserID, STATE};
+
+// Large migrations are sent over several calls to stay inside the message size limit
+const MAX_IMPORT_ENTRIES: usize = 500;
+const MAX_RENAME_ATTEMPTS: u32 = 1_000;
+
+/// One folder or file from another cloud's export, e.g. a Google Takeout or
+/// Dropbox listing. Files point at content already uploaded elsewhere.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct ImportEntry {
+    // Full drive path, e.g. `storage::Photos/2021/beach.jpg`
+    path: DriveFullFilePath,
+    kind: ImportEntryKind,
+    created_at_ms: Option<u64>,
+    modified_at_ms: Option<u64>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) enum ImportEntryKind {
+    Folder,
+    File {
+        raw_url: String,
+        file_size: u64,
+        sha256_checksum: Option<String>,
+    },
+}
+
+/// What to do when a file already exists at an imported path. Existing
+/// folders are always reused, so their contents merge.
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum DuplicatePolicy {
+    Skip,
+    // Import as `name (1).ext`, `name (2).ext`, ...
+    Rename,
+    // Import as a new version of the existing file
+    Overwrite,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) enum ImportOutcome {
+    Created { id: String },
+    Renamed { id: String, path: DriveFullFilePath },
+    Overwritten { id: String },
+    Skipped { existing_id: String },
+}
+
+/// Inserts ` (n)` before the extension of the file name in `path`.
+fn numbered_path(path: &str, n: u32) -> String {
+    let name_start = path.rfind('/').or_else(|| path.rfind("::").map(|i| i + 1)).map_or(0, |i| i + 1);
+    match path[name_start..].rfind('.').filter(|&dot| dot > 0) {
+        Some(dot) => {
+            let dot = name_start + dot;
+            format!("{} ({}){}", &path[..dot], n, &path[dot..])
+        }
+        None => format!("{} ({})", path, n),
+    }
+}
+
+impl State {
+    fn live_file_at(&self, path: &DriveFullFilePath) -> Option<String> {
+        self.full_file_path_to_uuid
+            .get(path)
+            .filter(|id| self.file_uuid_to_metadata.get(id).is_some_and(|file| !file.deleted))
+    }
+
+    fn import_folder(
+        &mut self,
+        entry: &ImportEntry,
+        storage_location: &StorageLocationID,
+        user_id: UserID,
+    ) -> Result<ImportOutcome, DriveError> {
+        let mut path = Self::sanitize_file_path(&entry.path)?;
+        // Root folders are `storage::`, everything else ends with a slash
+        if !path.ends_with("::") {
+            path.push('/');
+        }
+        if let Some(existing_id) = self.full_folder_path_to_uuid.get(&path) {
+            return Ok(ImportOutcome::Skipped { existing_id });
+        }
+        let mut folder = self.create_folder(path, storage_location.clone(), user_id)?;
+        if let Some(created_at_ms) = entry.created_at_ms {
+            folder.created_date = created_at_ms.saturating_mul(1_000_000);
+        }
+        if let Some(modified_at_ms) = entry.modified_at_ms {
+            folder.last_changed_unix_ms = modified_at_ms;
+        }
+        let id = folder.id.clone();
+        self.folder_uuid_to_metadata.insert(id.clone(), folder);
+        Ok(ImportOutcome::Created { id })
+    }
+
+    fn import_entry(
+        &mut self,
+        entry: &ImportEntry,
+        storage_location: &StorageLocationID,
+        user_id: UserID,
+        policy: DuplicatePolicy,
+    ) -> Result<ImportOutcome, DriveError> {
+        let ImportEntryKind::File { raw_url, file_size, sha256_checksum } = &entry.kind else {
+            return self.import_folder(entry, storage_location, user_id);
+        };
+        let path = Self::sanitize_file_path(&entry.path)?;
+        let (path, renamed) = match (self.live_file_at(&path), policy) {
+            (None, _) | (Some(_), DuplicatePolicy::Overwrite) => (path, false),
+            (Some(existing_id), DuplicatePolicy::Skip) => return Ok(ImportOutcome::Skipped { existing_id }),
+            (Some(_), DuplicatePolicy::Rename) => {
+                let free = (1..=MAX_RENAME_ATTEMPTS)
+                    .map(|n| numbered_path(&path, n))
+                    .find(|candidate| self.live_file_at(candidate).is_none())
+                    .ok_or_else(|| DriveError::AlreadyExists { path: path.clone() })?;
+                (free, true)
+            }
+        };
+        let overwritten = self.live_file_at(&path).is_some();
+        self.check_quota(*file_size)?;
+
+        let id = self.upsert_file_to_hash_tables(path.clone(), storage_location.clone(), user_id, sha256_checksum.clone())?;
+        let mut file = self.file_uuid_to_metadata.get(&id).ok_or_else(|| DriveError::file_not_found(&id))?;
+        file.file_size = *file_size;
+        file.raw_url = raw_url.clone();
+        file.upload_status = UploadStatus::Uploaded;
+        if let Some(created_at_ms) = entry.created_at_ms {
+            file.created_date = created_at_ms.saturating_mul(1_000_000);
+        }
+        if let Some(modified_at_ms) = entry.modified_at_ms {
+            file.last_changed_unix_ms = modified_at_ms;
+        }
+        self.replace_file_metadata(file);
+
+        Ok(if renamed {
+            ImportOutcome::Renamed { id, path }
+        } else if overwritten {
+            ImportOutcome::Overwritten { id }
+        } else {
+            ImportOutcome::Created { id }
+        })
+    }
+
+    fn import_manifest(
+        &mut self,
+        storage_location: StorageLocationID,
+        entries: Vec<ImportEntry>,
+        policy: DuplicatePolicy,
+        user_id: UserID,
+    ) -> Vec<Result<ImportOutcome, DriveError>> {
+        entries
+            .iter()
+            .map(|entry| self.import_entry(entry, &storage_location, user_id, policy))
+            .collect()
+    }
+}
+
+/// Creates folders and already-uploaded files from another cloud's listing,
+/// keeping their original timestamps. Parent folders are created as needed,
+/// so entries may come in any order. Each entry gets its own result; send
+/// large migrations in calls of up to 500 entries.
+#[ic_cdk::update]
+fn import_manifest(
+    storage_location: StorageLocationID,
+    entries: Vec<ImportEntry>,
+    on_duplicate: DuplicatePolicy,
+) -> Result<Vec<Result<ImportOutcome, DriveError>>, DriveError> {
+    let _profile = profile("import_manifest");
+    if entries.len() > MAX_IMPORT_ENTRIES {
+        return Err(DriveError::InvalidInput {
+            field: "entries".to_string(),
+            reason: format!("At most {} entries per call", MAX_IMPORT_ENTRIES),
+        });
+    }
+    // Each entry counts against the budget as if it were its own call
+    check_batch_allowed("import_manifest", entries.len() as u32)?;
+    let user_id = system_api::caller();
+    STATE.with(|state| Ok(state.borrow_mut().import_manifest(storage_location, entries, on_duplicate, user_id)))
+}
@@ -0,0 +1,121 @@
+// inspect.rs
+
+use candid::Principal;
+
+use crate::STATE;
+use crate::system_api;
+
+/// Largest ingress argument accepted. Real payloads are metadata only, so
+/// anything bigger is a buggy or hostile client.
+const MAX_INGRESS_ARG_BYTES: usize = 512 * 1024;
+
+/// Methods that reject everyone but the owner. Mirrors the checks inside each
+/// endpoint, which stay authoritative; these lists only drop doomed calls early.
+const OWNER_ONLY_METHODS: &[&str] = &[
+    "add_reader",
+    "cancel_ownership_transfer",
+    "create_api_key",
+    "get_logs",
+    "import_legacy_snapshot",
+    "import_state_snapshot",
+    "remove_reader",
+    "restore_backup",
+    "set_anonymous_access",
+    "set_storage_credentials",
+    "set_directory_listing",
+    "set_profile",
+    "list_api_keys",
+    "revoke_api_key",
+    "run_job_now",
+    "transfer_ownership",
+    "update_username",
+];
+
+/// Methods open to the owner and members with the `Admin` role.
+const ADMIN_ONLY_METHODS: &[&str] = &[
+    "add_contact",
+    "compact_tombstones",
+    "get_access_stats",
+    "get_audit_log",
+    "invite_member",
+    "list_most_accessed",
+    "list_backups",
+    "list_contacts",
+    "list_my_shares",
+    "list_webhooks",
+    "migrate_storage",
+    "mount_external_folder",
+    "poll_events",
+    "publish_folder",
+    "recompute_storage_usage",
+    "register_storage_location",
+    "register_webhook",
+    "remove_paywall",
+    "remove_contact",
+    "repair_state",
+    "revoke_invitation",
+    "run_backup_now",
+    "set_backup_config",
+    "set_case_insensitive_paths",
+    "set_dedup_enabled",
+    "set_ecdsa_key_name",
+    "set_expiry_action",
+    "set_legal_hold",
+    "set_max_file_versions",
+    "set_member",
+    "set_path_limits",
+    "set_paywall",
+    "set_rate_limit",
+    "set_read_only_threshold",
+    "set_storage_quota",
+    "set_token_gate",
+    "set_tombstone_retention",
+    "set_vetkd_key_name",
+    "share_item",
+    "unmount_external_folder",
+    "unpublish_folder",
+    "unregister_webhook",
+    "unshare_item",
+    "verify_state_integrity",
+];
+
+const CONTROLLER_ONLY_METHODS: &[&str] = &["reclaim_cycles"];
+
+/// The HTTP gateway calls anonymously; these authenticate with an API key instead.
+const ANONYMOUS_METHODS: &[&str] = &["http_request_update"];
+
+/// Reason an ingress message was dropped, or `None` to accept it.
+fn rejection_reason(caller: Principal, method: &str, arg_size: usize) -> Option<&'static str> {
+    if !ANONYMOUS_METHODS.contains(&method)
+        && STATE.with(|state| state.borrow().check_anonymous_update(caller, method).is_err())
+    {
+        return Some("anonymous caller");
+    }
+    if arg_size > MAX_INGRESS_ARG_BYTES {
+        return Some("argument too large");
+    }
+    if OWNER_ONLY_METHODS.contains(&method) && STATE.with(|state| state.borrow().owner != caller) {
+        return Some("owner-only method");
+    }
+    if ADMIN_ONLY_METHODS.contains(&method) && !STATE.with(|state| state.borrow().is_drive_admin(caller)) {
+        return Some("admin-only method");
+    }
+    if CONTROLLER_ONLY_METHODS.contains(&method) && !ic_cdk::api::is_controller(&caller) {
+        return Some("controller-only method");
+    }
+    None
+}
+
+/// Runs before every ingress update call. Messages that are not accepted are
+/// dropped before execution, so they cost the drive no cycles. Inter-canister
+/// calls (e.g. from the factory) never pass through here.
+#[ic_cdk::inspect_message]
+fn inspect_message() {
+    let caller = system_api::caller();
+    let method = ic_cdk::api::call::method_name();
+    let arg_size = ic_cdk::api::call::arg_data_raw_size();
+    match rejection_reason(caller, &method, arg_size) {
+        None => ic_cdk::api::call::accept_message(),
+        Some(reason) => ic_cdk::println!("Rejected ingress {} from {}: {}", method, caller, reason),
+    }
+}
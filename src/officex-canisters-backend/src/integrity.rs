@@ -0,0 +1,56 @@
+// integrity.rs
+
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::DriveError;
+use crate::members::check_read_access;
+use crate::{FileUUID, State, STATE};
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub(crate) enum IntegrityCheck {
+    Match,
+    Mismatch { stored_checksum: String },
+    // No checksum was ever recorded for this file
+    Unknown,
+}
+
+/// Validates a client-supplied SHA-256 hex digest and returns it lowercased.
+pub fn normalize_checksum(checksum: Option<String>) -> Result<Option<String>, DriveError> {
+    match checksum {
+        None => Ok(None),
+        Some(checksum) => {
+            if checksum.len() != 64 || !checksum.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(DriveError::InvalidInput {
+                    field: "sha256_checksum".to_string(),
+                    reason: "Expected a 64 character hex SHA-256 digest".to_string(),
+                });
+            }
+            Ok(Some(checksum.to_lowercase()))
+        }
+    }
+}
+
+impl State {
+    fn verify_file_integrity(&self, file_id: &FileUUID, checksum: String) -> Result<IntegrityCheck, DriveError> {
+        let file = self
+            .file_uuid_to_metadata
+            .get(file_id)
+            .ok_or_else(|| DriveError::file_not_found(file_id))?;
+        let checksum = normalize_checksum(Some(checksum))?.unwrap_or_default();
+        Ok(match &file.sha256_checksum {
+            None => IntegrityCheck::Unknown,
+            Some(stored) if *stored == checksum => IntegrityCheck::Match,
+            Some(stored) => IntegrityCheck::Mismatch {
+                stored_checksum: stored.clone(),
+            },
+        })
+    }
+}
+
+/// Compares a locally computed SHA-256 digest with the one recorded for the file.
+#[ic_cdk::query]
+fn verify_file_integrity(file_id: FileUUID, checksum: String) -> Result<IntegrityCheck, DriveError> {
+    check_read_access()?;
+    STATE.with(|state| state.borrow().verify_file_integrity(&file_id, checksum))
+}
@@ -0,0 +1,212 @@
+// invitations.rs
+
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+use crate::api_keys::{generate_secret, hash_secret};
+use crate::audit::AuditAction;
+use crate::errors::DriveError;
+use crate::members::{MemberRole, TeamMember};
+use crate::profiling::profile;
+use crate::read_only::check_writable;
+use crate::system_api;
+use crate::{generate_unique_id, State, STATE};
+
+const MAX_PENDING_INVITATIONS: usize = 100;
+const INVITATION_TTL_MS: u64 = 7 * 24 * 60 * 60 * 1000;
+const INVITATION_TOKEN_PREFIX: &str = "ofxinv_";
+
+/// Who an invitation is for. Invitations sent by email are addressed by the
+/// SHA-256 of the address, since the invitee's principal is not known yet.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum Invitee {
+    Principal(Principal),
+    EmailHash(String),
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Invitation {
+    id: String,
+    invitee: Invitee,
+    role: MemberRole,
+    invited_by: Principal,
+    created_at_ms: u64,
+    expires_at_ms: u64,
+    // Only the hash is kept; the token itself is shown once, on creation
+    token_sha256: String,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct CreatedInvitation {
+    invitation: Invitation,
+    token: String,
+}
+
+impl State {
+    fn prune_expired_invitations(&mut self, now_ms: u64) {
+        self.invitations.retain(|_, invitation| invitation.expires_at_ms > now_ms);
+    }
+
+    fn invite_member(
+        &mut self,
+        caller: Principal,
+        invitee: Invitee,
+        role: MemberRole,
+        token: &str,
+    ) -> Result<Invitation, DriveError> {
+        if !self.is_drive_admin(caller) {
+            return Err(DriveError::unauthorized("Only a drive admin can invite members"));
+        }
+        match &invitee {
+            Invitee::Principal(principal) => {
+                if *principal == Principal::anonymous() || self.member_role(*principal).is_some() {
+                    return Err(DriveError::InvalidInput {
+                        field: "invitee".to_string(),
+                        reason: "Principal is anonymous or already a member".to_string(),
+                    });
+                }
+            }
+            Invitee::EmailHash(hash) => {
+                if hash.len() != 64 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                    return Err(DriveError::InvalidInput {
+                        field: "invitee".to_string(),
+                        reason: "Expected a 64 character hex SHA-256 of the email address".to_string(),
+                    });
+                }
+            }
+        }
+
+        let now_ms = system_api::time() / 1_000_000;
+        self.prune_expired_invitations(now_ms);
+        if self.invitations.len() >= MAX_PENDING_INVITATIONS {
+            return Err(DriveError::InvalidState {
+                reason: format!("A drive can have at most {} pending invitations", MAX_PENDING_INVITATIONS),
+            });
+        }
+
+        let invitation = Invitation {
+            id: generate_unique_id(),
+            invitee,
+            role,
+            invited_by: caller,
+            created_at_ms: now_ms,
+            expires_at_ms: now_ms + INVITATION_TTL_MS,
+            token_sha256: hash_secret(token),
+        };
+        self.invitations.insert(invitation.id.clone(), invitation.clone());
+        self.record_audit(AuditAction::InviteMember, &invitation.id, None, None);
+        Ok(invitation)
+    }
+
+    /// Takes the invitation `token` belongs to out of the pending set, if the
+    /// caller may answer it. Invitations addressed to a principal can only be
+    /// answered by that principal; for email invitations the token is the proof.
+    fn take_invitation(&mut self, caller: Principal, token: &str) -> Result<Invitation, DriveError> {
+        let now_ms = system_api::time() / 1_000_000;
+        self.prune_expired_invitations(now_ms);
+        let token_sha256 = hash_secret(token);
+        let id = self
+            .invitations
+            .values()
+            .find(|invitation| invitation.token_sha256 == token_sha256)
+            .map(|invitation| invitation.id.clone())
+            .ok_or_else(|| DriveError::unauthorized("Unknown or expired invitation"))?;
+        if let Some(Invitee::Principal(invitee)) = self.invitations.get(&id).map(|invitation| &invitation.invitee) {
+            if *invitee != caller {
+                return Err(DriveError::unauthorized("Invitation is addressed to another principal"));
+            }
+        }
+        Ok(self.invitations.remove(&id).expect("invitation was just found"))
+    }
+
+    fn accept_invitation(&mut self, caller: Principal, token: &str) -> Result<MemberRole, DriveError> {
+        let invitation = self.take_invitation(caller, token)?;
+        if self.member_role(caller).is_some() {
+            return Err(DriveError::InvalidState {
+                reason: "Caller is already a member".to_string(),
+            });
+        }
+        self.add_members(vec![TeamMember { principal: caller, role: invitation.role }])?;
+        self.record_audit(AuditAction::AcceptInvitation, &invitation.id, None, None);
+        Ok(invitation.role)
+    }
+
+    fn decline_invitation(&mut self, caller: Principal, token: &str) -> Result<(), DriveError> {
+        let invitation = self.take_invitation(caller, token)?;
+        self.record_audit(AuditAction::DeclineInvitation, &invitation.id, None, None);
+        Ok(())
+    }
+
+    fn revoke_invitation(&mut self, caller: Principal, id: &str) -> Result<(), DriveError> {
+        if !self.is_drive_admin(caller) {
+            return Err(DriveError::unauthorized("Only a drive admin can revoke invitations"));
+        }
+        if self.invitations.remove(id).is_none() {
+            return Err(DriveError::NotFound {
+                resource: "invitation".to_string(),
+                id: id.to_string(),
+            });
+        }
+        self.record_audit(AuditAction::RevokeInvitation, id, None, None);
+        Ok(())
+    }
+
+    /// Every pending invitation for admins; only their own for anyone else.
+    fn list_invitations(&self, caller: Principal) -> Vec<Invitation> {
+        let now_ms = system_api::time() / 1_000_000;
+        let is_admin = self.is_drive_admin(caller);
+        self.invitations
+            .values()
+            .filter(|invitation| invitation.expires_at_ms > now_ms)
+            .filter(|invitation| is_admin || invitation.invitee == Invitee::Principal(caller))
+            .cloned()
+            .collect()
+    }
+}
+
+/// Invites someone to join the drive with `role`. Nothing changes until they
+/// call `accept_invitation` with the returned token, which the inviter passes
+/// on, e.g. in an email link. Invitations expire after seven days.
+#[ic_cdk::update]
+async fn invite_member(invitee: Invitee, role: MemberRole) -> Result<CreatedInvitation, DriveError> {
+    let _profile = profile("invite_member");
+    check_writable()?;
+    let caller = system_api::caller();
+    if STATE.with(|state| !state.borrow().is_drive_admin(caller)) {
+        return Err(DriveError::unauthorized("Only a drive admin can invite members"));
+    }
+    let token = generate_secret(INVITATION_TOKEN_PREFIX).await?;
+    let invitation = STATE.with(|state| state.borrow_mut().invite_member(caller, invitee, role, &token))?;
+    Ok(CreatedInvitation { invitation, token })
+}
+
+/// Joins the drive with the role the invitation offers.
+#[ic_cdk::update]
+fn accept_invitation(token: String) -> Result<MemberRole, DriveError> {
+    let _profile = profile("accept_invitation");
+    check_writable()?;
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow_mut().accept_invitation(caller, &token))
+}
+
+#[ic_cdk::update]
+fn decline_invitation(token: String) -> Result<(), DriveError> {
+    let _profile = profile("decline_invitation");
+    check_writable()?;
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow_mut().decline_invitation(caller, &token))
+}
+
+#[ic_cdk::update]
+fn revoke_invitation(id: String) -> Result<(), DriveError> {
+    let _profile = profile("revoke_invitation");
+    check_writable()?;
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow_mut().revoke_invitation(caller, &id))
+}
+
+#[ic_cdk::query]
+fn list_invitations() -> Vec<Invitation> {
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow().list_invitations(caller))
+}
@@ -0,0 +1,266 @@
+// jobs.rs
+//
+// Background maintenance runs as jobs on one scheduler timer. Each job has a
+// row in the job table with its interval and next run time; a job that fails
+// is retried with exponential backoff, never waiting longer than its
+// interval. Nothing runs while the drive is read-only. Timers do not survive
+// upgrades, but the table does, so jobs keep their schedule.
+
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::audit::AuditAction;
+use crate::backups::run_backup_if_due;
+use crate::errors::DriveError;
+use crate::link_checks::sample_links;
+use crate::logs::{log, LogLevel};
+use crate::profiling::profile;
+use crate::read_only::check_writable;
+use crate::system_api;
+use crate::webhooks::deliver_due_webhooks;
+use crate::{State, STATE};
+
+const SCHEDULER_TICK: Duration = Duration::from_secs(60);
+const RETRY_BASE_DELAY_MS: u64 = 60 * 1000;
+// A job still marked running after this long was cut short, e.g. by a trap
+const STALE_RUN_MS: u64 = 60 * 60 * 1000;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum JobId {
+    // Deletes uploads left pending for over a day
+    PendingUploadCleanup,
+    TombstoneCompaction,
+    FileExpiry,
+    VersionPruning,
+    Backup,
+    WebhookRetries,
+    // HEAD-checks a few raw_urls; see `link_checks.rs`
+    LinkSampling,
+}
+
+impl JobId {
+    const ALL: [JobId; 7] = [
+        JobId::PendingUploadCleanup,
+        JobId::TombstoneCompaction,
+        JobId::FileExpiry,
+        JobId::VersionPruning,
+        JobId::Backup,
+        JobId::WebhookRetries,
+        JobId::LinkSampling,
+    ];
+
+    fn interval_ms(self) -> u64 {
+        const MINUTE_MS: u64 = 60 * 1000;
+        match self {
+            JobId::PendingUploadCleanup => 60 * MINUTE_MS,
+            JobId::TombstoneCompaction => 24 * 60 * MINUTE_MS,
+            JobId::FileExpiry => 15 * MINUTE_MS,
+            JobId::VersionPruning => 24 * 60 * MINUTE_MS,
+            // Only checks whether a backup is due; the backup config decides
+            JobId::Backup => 60 * MINUTE_MS,
+            JobId::WebhookRetries => MINUTE_MS,
+            JobId::LinkSampling => 6 * 60 * MINUTE_MS,
+        }
+    }
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub enum JobOutcome {
+    Succeeded { summary: String },
+    Failed { error: String },
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct JobRecord {
+    next_run_ms: u64,
+    running_since_ms: Option<u64>,
+    last_run_ms: Option<u64>,
+    last_outcome: Option<JobOutcome>,
+    consecutive_failures: u32,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct JobInfo {
+    id: JobId,
+    interval_ms: u64,
+    record: JobRecord,
+}
+
+impl State {
+    /// Adds a row for every job missing from the table, first due one
+    /// interval from now.
+    fn ensure_jobs(&mut self, now_ms: u64) {
+        for id in JobId::ALL {
+            self.jobs.entry(id).or_insert_with(|| JobRecord {
+                next_run_ms: now_ms + id.interval_ms(),
+                running_since_ms: None,
+                last_run_ms: None,
+                last_outcome: None,
+                consecutive_failures: 0,
+            });
+        }
+    }
+
+    fn is_job_running(&self, id: JobId, now_ms: u64) -> bool {
+        self.jobs
+            .get(&id)
+            .and_then(|record| record.running_since_ms)
+            .is_some_and(|since| now_ms < since + STALE_RUN_MS)
+    }
+
+    fn due_jobs(&self, now_ms: u64) -> Vec<JobId> {
+        self.jobs
+            .iter()
+            .filter(|(id, record)| record.next_run_ms <= now_ms && !self.is_job_running(**id, now_ms))
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    fn start_job(&mut self, id: JobId, now_ms: u64) {
+        if let Some(record) = self.jobs.get_mut(&id) {
+            record.running_since_ms = Some(now_ms);
+        }
+    }
+
+    fn finish_job(&mut self, id: JobId, outcome: JobOutcome, now_ms: u64) {
+        let Some(record) = self.jobs.get_mut(&id) else {
+            return;
+        };
+        let delay_ms = match outcome {
+            JobOutcome::Succeeded { .. } => {
+                record.consecutive_failures = 0;
+                id.interval_ms()
+            }
+            JobOutcome::Failed { .. } => {
+                record.consecutive_failures += 1;
+                let backoff = RETRY_BASE_DELAY_MS.saturating_mul(1 << (record.consecutive_failures - 1).min(20));
+                backoff.min(id.interval_ms())
+            }
+        };
+        record.running_since_ms = None;
+        record.last_run_ms = Some(now_ms);
+        record.last_outcome = Some(outcome);
+        record.next_run_ms = now_ms + delay_ms;
+    }
+
+    fn list_jobs(&self) -> Vec<JobInfo> {
+        self.jobs
+            .iter()
+            .map(|(id, record)| JobInfo { id: *id, interval_ms: id.interval_ms(), record: record.clone() })
+            .collect()
+    }
+}
+
+fn now_ms() -> u64 {
+    system_api::time() / 1_000_000
+}
+
+async fn run_job_body(id: JobId) -> Result<String, DriveError> {
+    match id {
+        JobId::PendingUploadCleanup => {
+            let purged = STATE.with(|state| state.borrow_mut().purge_stale_pending_uploads(now_ms()));
+            Ok(format!("Purged {} stale pending uploads", purged))
+        }
+        JobId::TombstoneCompaction => {
+            let report = STATE.with(|state| state.borrow_mut().compact_tombstones(now_ms()));
+            Ok(report.summary())
+        }
+        JobId::FileExpiry => {
+            let expired = STATE.with(|state| state.borrow_mut().expire_due_files(now_ms()));
+            Ok(format!("Expired {} files", expired))
+        }
+        JobId::VersionPruning => {
+            let pruned = STATE.with(|state| state.borrow_mut().prune_file_versions());
+            Ok(format!("Pruned {} file versions", pruned))
+        }
+        JobId::Backup => run_backup_if_due().await,
+        JobId::WebhookRetries => {
+            let (sent, failed) = deliver_due_webhooks().await;
+            Ok(format!("Sent {} webhook deliveries, {} failed", sent, failed))
+        }
+        JobId::LinkSampling => sample_links().await,
+    }
+}
+
+/// Runs the job and records its outcome. Fails if it is already running.
+async fn run_job(id: JobId) -> Result<JobOutcome, DriveError> {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if state.is_job_running(id, now_ms()) {
+            return Err(DriveError::InvalidState { reason: format!("Job {:?} is already running", id) });
+        }
+        state.start_job(id, now_ms());
+        Ok(())
+    })?;
+    let outcome = match run_job_body(id).await {
+        Ok(summary) => JobOutcome::Succeeded { summary },
+        Err(e) => {
+            log(LogLevel::Warn, format!("Job {:?} failed: {}", id, e));
+            JobOutcome::Failed { error: e.to_string() }
+        }
+    };
+    STATE.with(|state| state.borrow_mut().finish_job(id, outcome.clone(), now_ms()));
+    Ok(outcome)
+}
+
+fn run_due_jobs() {
+    let due = STATE.with(|state| {
+        let state = state.borrow();
+        if state.is_read_only() {
+            return Vec::new();
+        }
+        state.due_jobs(now_ms())
+    });
+    for id in due {
+        ic_cdk::spawn(async move {
+            let _ = run_job(id).await;
+        });
+    }
+}
+
+/// Fills in the job table and starts the scheduler. Called on install and
+/// after every upgrade.
+pub fn start_jobs() {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let now_ms = now_ms();
+        state.ensure_jobs(now_ms);
+        // Runs interrupted by the upgrade will not finish
+        for record in state.jobs.values_mut() {
+            record.running_since_ms = None;
+        }
+    });
+    ic_cdk_timers::set_timer_interval(SCHEDULER_TICK, run_due_jobs);
+}
+
+#[ic_cdk::query]
+fn list_jobs() -> Result<Vec<JobInfo>, DriveError> {
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let state = state.borrow();
+        if caller != state.owner {
+            return Err(DriveError::unauthorized("Only the owner can list jobs"));
+        }
+        Ok(state.list_jobs())
+    })
+}
+
+/// Runs a job now instead of waiting for its next run. Its schedule restarts
+/// from now.
+#[ic_cdk::update]
+async fn run_job_now(id: JobId) -> Result<JobOutcome, DriveError> {
+    let _profile = profile("run_job_now");
+    check_writable()?;
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if caller != state.owner {
+            return Err(DriveError::unauthorized("Only the owner can run jobs"));
+        }
+        let owner = state.owner.to_text();
+        state.record_audit(AuditAction::RunJob, &owner, None, None);
+        Ok(())
+    })?;
+    run_job(id).await
+}
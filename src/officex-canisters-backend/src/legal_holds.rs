@@ -0,0 +1,194 @@
+// legal_holds.rs
+//
+// A legal hold freezes a folder and everything under it: nothing inside can
+// be deleted, renamed, moved or overwritten, by members, sync clients,
+// expiry or version pruning, until the hold is released. Nothing can be
+// added under it either, whether created there or moved in. Admins place
+// holds but only the owner releases them, and both are recorded in the
+// audit log.
+
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+use crate::audit::AuditAction;
+use crate::errors::DriveError;
+use crate::members::check_read_access;
+use crate::profiling::profile;
+use crate::read_only::check_writable;
+use crate::system_api;
+use crate::{DriveFullFilePath, FileMetadata, FolderUUID, State, STATE};
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct LegalHold {
+    pub(crate) placed_by: Principal,
+    pub(crate) placed_at_ms: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct LegalHoldInfo {
+    folder_id: FolderUUID,
+    full_folder_path: DriveFullFilePath,
+    placed_by: Principal,
+    placed_at_ms: u64,
+}
+
+fn held(path: &str) -> DriveError {
+    DriveError::InvalidState { reason: format!("{} is under legal hold", path) }
+}
+
+impl State {
+    /// The held folder that covers `folder_id`: the folder itself or its
+    /// nearest held ancestor.
+    fn covering_legal_hold(&self, folder_id: &FolderUUID) -> Option<FolderUUID> {
+        if self.legal_holds.is_empty() {
+            return None;
+        }
+        let mut current = Some(folder_id.clone());
+        while let Some(folder_id) = current {
+            if self.legal_holds.contains_key(&folder_id) {
+                return Some(folder_id);
+            }
+            current = self
+                .folder_uuid_to_metadata
+                .get(&folder_id)
+                .and_then(|folder| folder.parent_folder_uuid);
+        }
+        None
+    }
+
+    pub(crate) fn is_file_under_legal_hold(&self, file: &FileMetadata) -> bool {
+        self.covering_legal_hold(&file.folder_uuid).is_some()
+    }
+
+    /// Fails if the folder is held or inside a held folder.
+    pub(crate) fn check_folder_legal_hold(&self, folder_id: &FolderUUID) -> Result<(), DriveError> {
+        match self.covering_legal_hold(folder_id) {
+            Some(_) => {
+                let path = self.folder_uuid_to_metadata.get(folder_id).map(|folder| folder.full_folder_path);
+                Err(held(&path.unwrap_or_else(|| folder_id.clone())))
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Fails if writing sanitized `full_path` would put something inside a
+    /// held folder, judged by the deepest folder along it that exists.
+    pub(crate) fn check_path_legal_hold(&self, full_path: &str) -> Result<(), DriveError> {
+        if self.legal_holds.is_empty() {
+            return Ok(());
+        }
+        // Malformed paths are rejected by the storage location checks
+        let Some((storage, rest)) = full_path.split_once("::") else {
+            return Ok(());
+        };
+        let mut folder_path = format!("{}::", storage);
+        let mut deepest = self.full_folder_path_to_uuid.get(&folder_path);
+        for name in rest.split('/').filter(|name| !name.is_empty()) {
+            folder_path = format!("{}{}/", folder_path, name);
+            match self.full_folder_path_to_uuid.get(&folder_path) {
+                Some(folder_id) => deepest = Some(folder_id),
+                None => break,
+            }
+        }
+        deepest.map_or(Ok(()), |folder_id| self.check_folder_legal_hold(&folder_id))
+    }
+
+    /// Fails if the file is inside a held folder.
+    pub(crate) fn check_file_legal_hold(&self, file: &FileMetadata) -> Result<(), DriveError> {
+        if self.is_file_under_legal_hold(file) {
+            return Err(held(&file.full_file_path));
+        }
+        Ok(())
+    }
+
+    /// Fails if the folder is under a hold or holds a held folder, so that
+    /// deleting it would reach held content.
+    pub(crate) fn check_subtree_legal_hold(&self, folder_id: &FolderUUID) -> Result<(), DriveError> {
+        self.check_folder_legal_hold(folder_id)?;
+        for held_folder_id in self.legal_holds.keys() {
+            let mut current = self
+                .folder_uuid_to_metadata
+                .get(held_folder_id)
+                .and_then(|folder| folder.parent_folder_uuid);
+            while let Some(ancestor_id) = current {
+                if ancestor_id == *folder_id {
+                    let path = self.folder_uuid_to_metadata.get(held_folder_id).map(|folder| folder.full_folder_path);
+                    return Err(held(&path.unwrap_or_else(|| held_folder_id.clone())));
+                }
+                current = self
+                    .folder_uuid_to_metadata
+                    .get(&ancestor_id)
+                    .and_then(|folder| folder.parent_folder_uuid);
+            }
+        }
+        Ok(())
+    }
+
+    /// Fails while any hold is in place, for operations that replace the
+    /// whole drive.
+    pub(crate) fn check_no_legal_holds(&self) -> Result<(), DriveError> {
+        if !self.legal_holds.is_empty() {
+            return Err(DriveError::InvalidState {
+                reason: format!("{} folder(s) are under legal hold", self.legal_holds.len()),
+            });
+        }
+        Ok(())
+    }
+
+    fn set_legal_hold(&mut self, caller: Principal, folder_id: &FolderUUID, enabled: bool) -> Result<(), DriveError> {
+        if enabled && !self.is_drive_admin(caller) {
+            return Err(DriveError::unauthorized("Only a drive admin can place a legal hold"));
+        }
+        if !enabled && caller != self.owner {
+            return Err(DriveError::unauthorized("Only the owner can release a legal hold"));
+        }
+        let folder = self
+            .folder_uuid_to_metadata
+            .get(folder_id)
+            .filter(|folder| !folder.deleted)
+            .ok_or_else(|| DriveError::folder_not_found(folder_id))?;
+        if enabled {
+            if self.legal_holds.contains_key(folder_id) {
+                return Ok(());
+            }
+            let hold = LegalHold { placed_by: caller, placed_at_ms: system_api::time() / 1_000_000 };
+            self.legal_holds.insert(folder_id.clone(), hold);
+            self.record_audit(AuditAction::PlaceLegalHold, folder_id, Some(folder.full_folder_path), None);
+        } else if self.legal_holds.remove(folder_id).is_some() {
+            self.record_audit(AuditAction::ReleaseLegalHold, folder_id, Some(folder.full_folder_path), None);
+        }
+        Ok(())
+    }
+
+    fn list_legal_holds(&self) -> Vec<LegalHoldInfo> {
+        self.legal_holds
+            .iter()
+            .map(|(folder_id, hold)| LegalHoldInfo {
+                folder_id: folder_id.clone(),
+                full_folder_path: self
+                    .folder_uuid_to_metadata
+                    .get(folder_id)
+                    .map(|folder| folder.full_folder_path)
+                    .unwrap_or_default(),
+                placed_by: hold.placed_by,
+                placed_at_ms: hold.placed_at_ms,
+            })
+            .collect()
+    }
+}
+
+/// Places (`enabled`) or releases a legal hold on the folder and everything
+/// under it. Releasing is reserved to the owner.
+#[ic_cdk::update]
+fn set_legal_hold(folder_id: FolderUUID, enabled: bool) -> Result<(), DriveError> {
+    let _profile = profile("set_legal_hold");
+    check_writable()?;
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow_mut().set_legal_hold(caller, &folder_id, enabled))
+}
+
+#[ic_cdk::query]
+fn list_legal_holds() -> Result<Vec<LegalHoldInfo>, DriveError> {
+    check_read_access()?;
+    STATE.with(|state| Ok(state.borrow().list_legal_holds()))
+}
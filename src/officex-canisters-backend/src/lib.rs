@@ -3,8 +3,7 @@
 use candid::{CandidType, Principal};
 use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
-use std::collections::HashMap;
-use std::fmt;
+use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
 use regex::Regex;
 type FolderUUID = String;
 type FileUUID = String;
@@ -12,8 +11,140 @@ type DriveFullFilePath = String;
 type Tag = String;
 type UserID = Principal;
 use std::cell::Cell;
+use std::ops::Bound::{Excluded, Unbounded};
 use sha2::{Sha256, Digest};
 
+mod access;
+mod activity;
+mod ancestors;
+mod anonymous;
+mod api_keys;
+mod audit;
+mod autocomplete;
+mod backups;
+mod batch;
+mod children;
+mod comments;
+mod compaction;
+mod conflicts;
+mod consent_messages;
+mod contacts;
+mod credential_vault;
+mod cycles;
+mod dedup;
+mod devices;
+mod directory_profile;
+mod drive_profile;
+mod documents;
+mod encryption;
+mod errors;
+mod events;
+mod expiry;
+mod export;
+mod file_updates;
+mod folder_labels;
+mod folder_settings;
+mod fsck;
+mod heartbeat;
+mod idempotency;
+mod import;
+mod inspect;
+mod integrity;
+mod invitations;
+mod jobs;
+mod legal_holds;
+mod link_checks;
+mod logs;
+mod lookup;
+mod members;
+mod memory;
+mod metrics;
+mod migration;
+mod mime;
+mod mounts;
+mod notifications;
+mod ownership;
+mod path_case;
+mod path_limits;
+mod path_validation;
+mod paywalls;
+mod pinning;
+mod presence;
+mod presigning;
+mod profiling;
+mod properties;
+mod publishing;
+mod quota;
+mod rate_limit;
+mod read_only;
+mod recent;
+mod rest_api;
+mod restore;
+mod revisions;
+mod rollups;
+mod schema;
+mod sharing;
+mod shortcuts;
+mod signed_urls;
+mod starred;
+mod storage;
+mod storage_report;
+mod system_api;
+mod thumbnails;
+mod token_gates;
+mod upload_status;
+mod version_pruning;
+mod vetkd;
+mod webdav;
+mod webhooks;
+
+use anonymous::AnonymousAccessConfig;
+use api_keys::{check_update_allowed_with_api_key, ApiKey, ApiScope};
+use audit::{AuditAction, AuditEntry};
+use backups::BackupState;
+use children::{ChildKey, ChildKind};
+use comments::{Comment, CommentKey};
+use conflicts::SyncConflict;
+use contacts::Contact;
+use credential_vault::SealedCredentials;
+use devices::Device;
+use drive_profile::DriveProfile;
+use documents::{DocumentDelta, DocumentDeltaKey, DocumentHead, DocumentSnapshot};
+use cycles::CyclesSample;
+use dedup::ChecksumKey;
+use errors::DriveError;
+use expiry::ExpiryAction;
+use folder_settings::FolderSettings;
+use idempotency::{idempotent, IdempotencyRecord};
+use invitations::Invitation;
+use jobs::{JobId, JobRecord};
+use legal_holds::LegalHold;
+use link_checks::LinkCheck;
+use logs::LogEntry;
+use members::{require_read_access, MemberRole, TeamMember};
+use memory::{StableMap, StableMapExt};
+use migration::StorageMigration;
+use mounts::MountPoint;
+use notifications::Notification;
+use path_case::{normalize_nfc, PathIndex};
+use path_limits::PathLimits;
+use paywalls::{Paywall, PaywallPayment};
+use pinning::PermanentId;
+use presence::EditingSession;
+use profiling::{profile, EndpointProfile};
+use publishing::PublishedFolder;
+use quota::StorageUsage;
+use rate_limit::{check_update_allowed, RateLimitBudget, TokenBucket};
+use read_only::check_writable;
+use recent::RecentFileKey;
+use sharing::ShareGrant;
+use storage::{StorageLocation, StorageLocationID};
+use storage_report::FileSizeKey;
+use thumbnails::ThumbnailDimensions;
+use token_gates::TokenGate;
+use upload_status::UploadStatus;
+use webhooks::{PendingDelivery, Webhook};
+
 
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
@@ -26,21 +157,22 @@ struct StateSnapshot {
     username: String,
 }
 
-#[derive(Clone, PartialEq, Eq, Hash, CandidType, Serialize, Deserialize, Debug)]
-enum StorageLocationEnum {
-    BrowserCache,
-    HardDrive,
-    Web3Storj,
+const DEFAULT_SNAPSHOT_PAGE_SIZE: u32 = 500;
+const MAX_SNAPSHOT_PAGE_SIZE: u32 = 2_000;
+
+/// The last record of a snapshot page. Folders are listed first, then files.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+enum SnapshotCursor {
+    Folder(FolderUUID),
+    File(FileUUID),
 }
 
-impl fmt::Display for StorageLocationEnum {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            StorageLocationEnum::BrowserCache => write!(f, "BrowserCache"),
-            StorageLocationEnum::HardDrive => write!(f, "HardDrive"),
-            StorageLocationEnum::Web3Storj => write!(f, "Web3Storj"),
-        }
-    }
+/// Part of a snapshot, holding a page of records and the path entries that
+/// point at them.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+struct StateSnapshotPage {
+    snapshot: StateSnapshot,
+    next_cursor: Option<SnapshotCursor>,
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
@@ -48,15 +180,25 @@ struct FolderMetadata {
     id: FolderUUID,
     original_folder_name: String,
     parent_folder_uuid: Option<FolderUUID>,
-    subfolder_uuids: Vec<FolderUUID>,
-    file_uuids: Vec<FileUUID>,
     full_folder_path: DriveFullFilePath,
     tags: Vec<Tag>,
+    properties: Vec<(String, String)>,
     owner: UserID,
     created_date: u64, // ISO 8601 format
-    storage_location: StorageLocationEnum,
+    storage_location: StorageLocationID,
     last_changed_unix_ms: u64,
-    deleted: bool
+    deleted: bool,
+    // Cached totals for everything below this folder, maintained incrementally
+    subtree_bytes: u64,
+    subtree_file_count: u64,
+    subtree_folder_count: u64,
+    // Bumped on every change; see `revisions.rs`
+    revision: u64,
+    // Defaults for new children, inherited by subfolders; see `folder_settings.rs`
+    settings: Option<FolderSettings>,
+    // Presentation in drive UIs; see `folder_labels.rs`
+    color: Option<String>,
+    pinned_rank: Option<u32>,
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
@@ -68,39 +210,263 @@ struct FileMetadata {
     prior_version: Option<FileUUID>,
     next_version: Option<FileUUID>,
     extension: String,
+    // Derived from the extension unless overridden; see `mime.rs`
+    mime_type: String,
     full_file_path: DriveFullFilePath,
     tags: Vec<Tag>,
+    properties: Vec<(String, String)>,
     owner: UserID,
     created_date: u64, // ISO 8601 format
-    storage_location: StorageLocationEnum,
+    storage_location: StorageLocationID,
     file_size: u64,
     raw_url: String,
     last_changed_unix_ms: u64, 
-    deleted: bool
+    deleted: bool,
+    upload_status: UploadStatus,
+    sha256_checksum: Option<String>,
+    last_accessed_ms: Option<u64>,
+    access_count: u64,
+    // Client-side encryption material; the drive never sees plaintext keys
+    encrypted: bool,
+    encryption_scheme: Option<String>,
+    wrapped_dek: Option<Vec<u8>>,
+    nonce: Option<Vec<u8>>,
+    // Set while a sync conflict on this file waits for `resolve_conflict`
+    conflicted: bool,
+    // Preview image for grid views, registered by whoever renders it
+    thumbnail_url: Option<String>,
+    thumbnail_checksum: Option<String>,
+    thumbnail_dimensions: Option<ThumbnailDimensions>,
+    // Bumped on every change; see `revisions.rs`
+    revision: u64,
+    // The file a shortcut stands for; see `shortcuts.rs`
+    shortcut_target: Option<FileUUID>,
+    // When the file is removed automatically; see `expiry.rs`
+    expires_at_ms: Option<u64>,
+    // IPFS CID or Arweave transaction once pinned; see `pinning.rs`
+    permanent_id: Option<PermanentId>,
+    // Price of a download link for viewers; see `paywalls.rs`
+    paywall: Option<Paywall>,
 }
 
-#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+/// Drive state. The metadata and path maps live in stable memory; the
+/// remaining fields are serialized across upgrades (see `memory.rs`).
+#[derive(Serialize, Deserialize)]
 struct State {
-    folder_uuid_to_metadata: HashMap<FolderUUID, FolderMetadata>,
-    file_uuid_to_metadata: HashMap<FileUUID, FileMetadata>,
-    full_folder_path_to_uuid: HashMap<DriveFullFilePath, FolderUUID>,
-    full_file_path_to_uuid: HashMap<DriveFullFilePath, FileUUID>,
+    #[serde(skip, default = "memory::folder_metadata_map")]
+    folder_uuid_to_metadata: StableMap<FolderUUID, FolderMetadata>,
+    #[serde(skip, default = "memory::file_metadata_map")]
+    file_uuid_to_metadata: StableMap<FileUUID, FileMetadata>,
+    #[serde(skip, default = "path_case::folder_path_index")]
+    full_folder_path_to_uuid: PathIndex,
+    #[serde(skip, default = "path_case::file_path_index")]
+    full_file_path_to_uuid: PathIndex,
+    // Children of every folder, ordered by (parent, kind, name)
+    #[serde(skip, default = "memory::child_index_map")]
+    folder_children: StableMap<ChildKey, ()>,
+    #[serde(skip, default = "memory::audit_log_map")]
+    audit_log: StableMap<u64, AuditEntry>,
+    // Operational log for triage; see `logs.rs`
+    #[serde(skip, default = "memory::logs_map")]
+    logs: StableMap<u64, LogEntry>,
+    // Starred folder and file UUIDs, with the time they were starred
+    #[serde(skip, default = "memory::starred_map")]
+    starred: StableMap<String, u64>,
+    // Files ordered by last change, for the recent files view
+    #[serde(skip, default = "memory::recent_files_map")]
+    recent_files: StableMap<RecentFileKey, ()>,
+    #[serde(skip, default = "memory::comments_map")]
+    comments: StableMap<CommentKey, Comment>,
+    // Autosaved edits to OfficeX documents; see `documents.rs`
+    #[serde(skip, default = "memory::document_deltas_map")]
+    document_deltas: StableMap<DocumentDeltaKey, DocumentDelta>,
+    #[serde(skip, default = "memory::document_snapshots_map")]
+    document_snapshots: StableMap<FileUUID, DocumentSnapshot>,
+    // Every file version by size and name, for the storage report
+    #[serde(skip, default = "memory::file_sizes_map")]
+    files_by_size: StableMap<FileSizeKey, ()>,
+    // Every file version by content checksum, for deduplication
+    #[serde(skip, default = "memory::file_checksums_map")]
+    checksum_to_file_uuids: StableMap<ChecksumKey, ()>,
     owner: Principal,
     username: String,
+    pending_owner: Option<Principal>,
+    // Set while `accept_ownership` waits on the factory; see `ownership.rs`
+    #[serde(skip)]
+    accepting_ownership: bool,
+    factory_id: Option<Principal>,
+    storage_usage: StorageUsage,
+    quota_bytes: Option<u64>,
+    dedup_enabled: bool,
+    webhooks: HashMap<String, Webhook>,
+    // Webhook deliveries waiting for their first attempt or a retry
+    webhook_queue: VecDeque<PendingDelivery>,
+    next_comment_id: u64,
+    storage_locations: BTreeMap<StorageLocationID, StorageLocation>,
+    storage_migrations: BTreeMap<u64, StorageMigration>,
+    next_migration_id: u64,
+    // Threshold ECDSA key that signs download tokens
+    ecdsa_key_name: String,
+    // vetKD master key that per-file encryption keys derive from
+    vetkd_key_name: String,
+    default_rate_limit: RateLimitBudget,
+    rate_limit_budgets: BTreeMap<String, RateLimitBudget>,
+    // Per (caller, method) token buckets; starting full after an upgrade is fine
+    #[serde(skip)]
+    rate_buckets: HashMap<(Principal, String), TokenBucket>,
+    call_counts: BTreeMap<String, u64>,
+    // Reset by upgrades, since new code brings new costs
+    #[serde(skip)]
+    endpoint_profiles: BTreeMap<String, EndpointProfile>,
+    last_upgraded_at_ms: Option<u64>,
+    // Hourly balance history backing the burn estimate
+    cycles_samples: VecDeque<CyclesSample>,
+    cycles_deposited_total: u64,
+    read_only_threshold_cycles: u64,
+    api_keys: BTreeMap<String, ApiKey>,
+    // Share grants by the folder or file they cover
+    share_grants: BTreeMap<String, Vec<ShareGrant>>,
+    // Folders standing in for folders on other drives, by local folder UUID
+    mount_points: BTreeMap<FolderUUID, MountPoint>,
+    // Team drives only let members in; see `members.rs`
+    team_drive: bool,
+    members: BTreeMap<Principal, MemberRole>,
+    invitations: BTreeMap<String, Invitation>,
+    // Unresolved sync conflicts by the cloud file they were detected on
+    sync_conflicts: BTreeMap<FileUUID, SyncConflict>,
+    // Registered sync clients by user, then device ID
+    devices: BTreeMap<Principal, BTreeMap<String, Device>>,
+    // How long deleted records are kept for syncing clients; see `compaction.rs`
+    tombstone_retention_ms: u64,
+    backups: BackupState,
+    document_heads: BTreeMap<FileUUID, DocumentHead>,
+    // Open documents by session ID; rebuilt by heartbeats after an upgrade
+    #[serde(skip)]
+    editing_sessions: HashMap<String, EditingSession>,
+    anonymous_access: AnonymousAccessConfig,
+    path_limits: PathLimits,
+    // Paths matching up to case resolve to one item; see `path_case.rs`
+    case_insensitive_paths: bool,
+    // Layout version of the fields above; see `schema.rs`
+    schema_version: u32,
+    // Responses to retried calls; see `idempotency.rs`
+    idempotency_records: BTreeMap<String, IdempotencyRecord>,
+    // Folders readable by anyone; see `publishing.rs`
+    published_folders: BTreeMap<FolderUUID, PublishedFolder>,
+    // Whether the drive is listed in the factory's public directory; see
+    // `directory_profile.rs`
+    listed_in_directory: bool,
+    // How the owner is shown to others; see `drive_profile.rs`
+    profile: Option<DriveProfile>,
+    // Known collaborators for share dialogs; see `contacts.rs`
+    contacts: BTreeMap<Principal, Contact>,
+    // Inboxes by recipient, then notification ID; see `notifications.rs`
+    notifications: BTreeMap<Principal, BTreeMap<u64, Notification>>,
+    next_notification_id: u64,
+    // What happens to files past their expiry; see `expiry.rs`
+    expiry_action: ExpiryAction,
+    // Background job schedule; see `jobs.rs`
+    jobs: BTreeMap<JobId, JobRecord>,
+    // Versions kept per file, or all when unset; see `version_pruning.rs`
+    max_file_versions: Option<u32>,
+    // Last HEAD check of each file's raw_url; see `link_checks.rs`
+    link_checks: BTreeMap<FileUUID, LinkCheck>,
+    // Sealed storage credentials by location, and the key that seals them;
+    // see `credential_vault.rs`
+    storage_credentials: BTreeMap<StorageLocationID, SealedCredentials>,
+    vault_key: Option<Vec<u8>>,
+    // NFT gates on folders, and the passes holders were granted until the
+    // time given; see `token_gates.rs`
+    token_gates: BTreeMap<FolderUUID, TokenGate>,
+    gate_passes: BTreeMap<(FolderUUID, Principal), u64>,
+    // Paywall payments by the file version paid for and the payer, and the
+    // payments still waiting on the ledger; see `paywalls.rs`
+    paywall_payments: BTreeMap<(FileUUID, Principal), PaywallPayment>,
+    #[serde(skip)]
+    paywall_payments_in_flight: BTreeSet<(FileUUID, Principal)>,
+    // Folders frozen with everything under them; see `legal_holds.rs`
+    legal_holds: BTreeMap<FolderUUID, LegalHold>,
 }
 
 
 impl State {
     fn new(owner: Principal, username: String) -> Self {
         let sanitized_username = sanitize_username(&username);
-        let formatted_username = format!("{}@{}", sanitized_username, owner.to_string());
+        let formatted_username = format!("{}@{}", sanitized_username, owner);
         Self {
-            folder_uuid_to_metadata: HashMap::new(),
-            file_uuid_to_metadata: HashMap::new(),
-            full_folder_path_to_uuid: HashMap::new(),
-            full_file_path_to_uuid: HashMap::new(),
+            folder_uuid_to_metadata: memory::folder_metadata_map(),
+            file_uuid_to_metadata: memory::file_metadata_map(),
+            full_folder_path_to_uuid: path_case::folder_path_index(),
+            full_file_path_to_uuid: path_case::file_path_index(),
+            folder_children: memory::child_index_map(),
+            audit_log: memory::audit_log_map(),
+            logs: memory::logs_map(),
+            starred: memory::starred_map(),
+            recent_files: memory::recent_files_map(),
+            comments: memory::comments_map(),
+            document_deltas: memory::document_deltas_map(),
+            document_snapshots: memory::document_snapshots_map(),
+            files_by_size: memory::file_sizes_map(),
+            checksum_to_file_uuids: memory::file_checksums_map(),
             owner,
             username: formatted_username,
+            pending_owner: None,
+            accepting_ownership: false,
+            factory_id: None,
+            storage_usage: StorageUsage::default(),
+            quota_bytes: None,
+            dedup_enabled: false,
+            webhooks: HashMap::new(),
+            webhook_queue: VecDeque::new(),
+            next_comment_id: 0,
+            storage_locations: storage::builtin_storage_locations(),
+            storage_migrations: BTreeMap::new(),
+            next_migration_id: 0,
+            ecdsa_key_name: signed_urls::DEFAULT_ECDSA_KEY_NAME.to_string(),
+            vetkd_key_name: vetkd::DEFAULT_VETKD_KEY_NAME.to_string(),
+            default_rate_limit: RateLimitBudget::default(),
+            rate_limit_budgets: BTreeMap::new(),
+            rate_buckets: HashMap::new(),
+            call_counts: BTreeMap::new(),
+            endpoint_profiles: BTreeMap::new(),
+            last_upgraded_at_ms: None,
+            cycles_samples: VecDeque::new(),
+            cycles_deposited_total: 0,
+            read_only_threshold_cycles: read_only::DEFAULT_READ_ONLY_THRESHOLD_CYCLES,
+            api_keys: BTreeMap::new(),
+            share_grants: BTreeMap::new(),
+            mount_points: BTreeMap::new(),
+            team_drive: false,
+            members: BTreeMap::new(),
+            invitations: BTreeMap::new(),
+            sync_conflicts: BTreeMap::new(),
+            devices: BTreeMap::new(),
+            tombstone_retention_ms: compaction::DEFAULT_TOMBSTONE_RETENTION_MS,
+            backups: BackupState::default(),
+            document_heads: BTreeMap::new(),
+            editing_sessions: HashMap::new(),
+            anonymous_access: AnonymousAccessConfig::default(),
+            path_limits: PathLimits::default(),
+            case_insensitive_paths: false,
+            schema_version: schema::SCHEMA_VERSION,
+            idempotency_records: BTreeMap::new(),
+            published_folders: BTreeMap::new(),
+            listed_in_directory: false,
+            profile: None,
+            contacts: BTreeMap::new(),
+            notifications: BTreeMap::new(),
+            next_notification_id: 0,
+            expiry_action: ExpiryAction::default(),
+            jobs: BTreeMap::new(),
+            max_file_versions: None,
+            link_checks: BTreeMap::new(),
+            storage_credentials: BTreeMap::new(),
+            vault_key: None,
+            token_gates: BTreeMap::new(),
+            gate_passes: BTreeMap::new(),
+            paywall_payments: BTreeMap::new(),
+            paywall_payments_in_flight: BTreeSet::new(),
+            legal_holds: BTreeMap::new(),
         }
     }
 
@@ -108,49 +474,57 @@ impl State {
         "pong".to_string()
     }
 
-    fn update_username(&mut self, new_username: String) -> Result<(), String> {
-        let caller = ic_cdk::caller();
+    /// Checks that `caller` may rename the drive and returns the sanitized name.
+    fn check_username_update(&self, caller: Principal, new_username: &str) -> Result<String, DriveError> {
         if caller != self.owner {
-            return Err("Only the owner can update the username".to_string());
+            return Err(DriveError::unauthorized("Only the owner can update the username"));
         }
-        let sanitized_username = sanitize_username(&new_username);
+        let sanitized_username = sanitize_username(new_username);
         if !is_valid_username(&sanitized_username) {
-            return Err("Invalid username format".to_string());
+            return Err(DriveError::InvalidInput {
+                field: "username".to_string(),
+                reason: "Invalid username format".to_string(),
+            });
         }
-        let formatted_username = format!("{}@{}", sanitized_username, self.owner.to_string());
+        Ok(sanitized_username)
+    }
+
+    fn apply_username(&mut self, sanitized_username: &str) {
+        let formatted_username = format!("{}@{}", sanitized_username, self.owner);
         self.username = formatted_username;
-        Ok(())
+        self.record_audit(AuditAction::UpdateUsername, &self.owner.to_text(), None, None);
     }
 
     pub fn create_folder(
         &mut self,
         full_folder_path: DriveFullFilePath,
-        storage_location: StorageLocationEnum,
+        storage_location: StorageLocationID,
         user_id: UserID
-    ) -> Result<FolderMetadata, String> {
+    ) -> Result<FolderMetadata, DriveError> {
         // Ensure the path ends with a slash
-        let mut sanitized_path = Self::sanitize_file_path(&full_folder_path);
+        let mut sanitized_path = Self::sanitize_file_path(&full_folder_path)?;
         if !sanitized_path.ends_with('/') {
             sanitized_path.push('/');
         }
+        let sanitized_path = self.canonical_path(&sanitized_path);
     
         if sanitized_path.is_empty() {
-            return Err(String::from("Invalid folder path"));
+            return Err(DriveError::invalid_path(&full_folder_path, "Invalid folder path"));
         }
     
         // Split the path into storage and folder parts
         let parts: Vec<&str> = sanitized_path.split("::").collect();
         if parts.len() < 2 {
-            return Err(String::from("Invalid folder path format"));
+            return Err(DriveError::invalid_path(&full_folder_path, "Invalid folder path format"));
         }
     
         let storage_part = parts[0];
         let folder_path = parts[1..].join("::");
     
-        // Ensure the storage location matches
-        if storage_part != storage_location.to_string() {
-            return Err(String::from("Storage location mismatch"));
-        }
+        // Ensure the storage location is registered and matches the path
+        self.check_path_storage_location(storage_part, &storage_location)?;
+        self.check_path_limits(&sanitized_path)?;
+        self.check_path_legal_hold(&sanitized_path)?;
     
         // Split the folder path into individual parts
         let path_parts: Vec<&str> = folder_path.split('/').filter(|&x| !x.is_empty()).collect();
@@ -161,7 +535,7 @@ impl State {
 
         // root folder case
         if path_parts.is_empty() {
-            return self.folder_uuid_to_metadata.get(&parent_folder_uuid).cloned().ok_or_else(|| "Parent folder not found".to_string());
+            return self.folder_uuid_to_metadata.get(&parent_folder_uuid).ok_or_else(|| DriveError::folder_not_found(&parent_folder_uuid));
         }
     
         // Iterate through path parts and create folders as needed
@@ -175,76 +549,87 @@ impl State {
                     id: new_folder_uuid.clone(),
                     original_folder_name: part.to_string(),
                     parent_folder_uuid: Some(parent_folder_uuid.clone()),
-                    subfolder_uuids: Vec::new(),
-                    file_uuids: Vec::new(),
                     full_folder_path: current_path.clone(),
-                    tags: Vec::new(),
-                    owner: user_id.clone(),
-                    created_date: ic_cdk::api::time(),
+                    tags: self.default_tags_for(&parent_folder_uuid),
+                    properties: Vec::new(),
+                    owner: user_id,
+                    created_date: system_api::time(),
                     storage_location: storage_location.clone(),
-                    last_changed_unix_ms: ic_cdk::api::time() / 1_000_000,
+                    last_changed_unix_ms: system_api::time() / 1_000_000,
                     deleted: false,
+                    subtree_bytes: 0,
+                    subtree_file_count: 0,
+                    subtree_folder_count: 0,
+                    revision: 0,
+                    settings: None,
+                    color: None,
+                    pinned_rank: None,
                 };
     
                 self.full_folder_path_to_uuid.insert(current_path.clone(), new_folder_uuid.clone());
                 self.folder_uuid_to_metadata.insert(new_folder_uuid.clone(), new_folder.clone());
+                self.rollup_folder_added(&new_folder);
     
                 // Update parent folder
-                if let Some(parent_folder) = self.folder_uuid_to_metadata.get_mut(&parent_folder_uuid) {
-                    parent_folder.subfolder_uuids.push(new_folder_uuid.clone());
-                }
+                self.index_child_folder(&new_folder);
     
                 parent_folder_uuid = new_folder_uuid;
     
                 // If this is the last part, return the created folder
                 if i == path_parts.len() - 1 {
+                    self.record_audit(AuditAction::CreateFolder, &new_folder.id, None, Some(current_path));
                     return Ok(new_folder);
                 }
             } else {
-                parent_folder_uuid = self.full_folder_path_to_uuid[&current_path].clone();
+                parent_folder_uuid = self.full_folder_path_to_uuid.get(&current_path).unwrap();
             }
         }
     
         // If we've reached here, it means the folder already existed
-        Err(String::from("Folder already exists"))
-    }
-
-    fn update_folder_file_uuids(&mut self, folder_uuid: &FolderUUID, file_uuid: &FileUUID, is_add: bool) {
-        if let Some(folder) = self.folder_uuid_to_metadata.get_mut(folder_uuid) {
-            if is_add {
-                if !folder.file_uuids.contains(file_uuid) {
-                    folder.file_uuids.push(file_uuid.clone());
-                }
-            } else {
-                folder.file_uuids.retain(|uuid| uuid != file_uuid);
-            }
-        }
+        Err(DriveError::AlreadyExists { path: current_path })
     }
 
     pub fn upsert_file_to_hash_tables(
         &mut self,
         file_path: String,
-        storage_location: StorageLocationEnum,
+        storage_location: StorageLocationID,
         user_id: UserID,
-    ) -> FileUUID {
-        let sanitized_file_path = Self::sanitize_file_path(&file_path);
-        let full_file_path = sanitized_file_path;
+        sha256_checksum: Option<String>,
+    ) -> Result<FileUUID, DriveError> {
+        let sha256_checksum = integrity::normalize_checksum(sha256_checksum)?;
+        // With dedup on, identical content already in the drive is reused rather than re-uploaded
+        let linked_object = match &sha256_checksum {
+            Some(checksum) if self.dedup_enabled => self
+                .find_uploaded_by_checksum(checksum)
+                .map(|file| (file.file_size, file.raw_url.clone())),
+            _ => None,
+        };
+        self.check_quota(linked_object.as_ref().map_or(0, |(size, _)| *size))?;
+
+        let sanitized_file_path = Self::sanitize_file_path(&file_path)?;
+        let full_file_path = self.canonical_path(&sanitized_file_path);
+        self.check_path_storage_location(&full_file_path, &storage_location)?;
+        self.check_path_limits(&full_file_path)?;
+        // Covers overwriting a held file as well as adding one to a held folder
+        self.check_path_legal_hold(&full_file_path)?;
         let new_file_uuid = generate_unique_id();
 
         let (folder_path, file_name) = self.split_path(&full_file_path);
         let folder_uuid = self.ensure_folder_structure(&folder_path, storage_location.clone(), user_id);
 
-        let existing_file_uuid = self.full_file_path_to_uuid.get(&full_file_path).cloned();
+        let existing_file_uuid = self.full_file_path_to_uuid.get(&full_file_path);
 
-        let file_version = if let Some(existing_uuid) = &existing_file_uuid {
+        let extension = file_name.rsplit('.').next().unwrap_or("").to_string();
+
+        // New versions keep an overridden MIME type, the expiry and the paywall
+        let (file_version, mime_type, expires_at_ms, paywall) = if let Some(existing_uuid) = &existing_file_uuid {
             let existing_file = self.file_uuid_to_metadata.get(existing_uuid).unwrap();
-            existing_file.file_version + 1
+            let FileMetadata { file_version, mime_type, expires_at_ms, paywall, .. } = existing_file;
+            (file_version + 1, mime_type, expires_at_ms, paywall)
         } else {
-            1
+            (1, mime::mime_type_for_extension(&extension), None, None)
         };
 
-        let extension = file_name.rsplit('.').next().unwrap_or("").to_string();
-
         let file_metadata = FileMetadata {
             id: new_file_uuid.clone(),
             original_file_name: file_name,
@@ -254,146 +639,146 @@ impl State {
             next_version: None,
             extension,
             full_file_path: full_file_path.clone(),
-            tags: Vec::new(),
+            mime_type,
+            tags: self.default_tags_for(&folder_uuid),
+            properties: Vec::new(),
             owner: user_id,
-            created_date: ic_cdk::api::time(),
+            created_date: system_api::time(),
             storage_location,
             file_size: 0,
             raw_url: String::new(),
-            last_changed_unix_ms: ic_cdk::api::time() / 1_000_000,
+            last_changed_unix_ms: system_api::time() / 1_000_000,
             deleted: false,
+            upload_status: UploadStatus::Pending,
+            sha256_checksum,
+            last_accessed_ms: None,
+            access_count: 0,
+            encrypted: false,
+            encryption_scheme: None,
+            wrapped_dek: None,
+            nonce: None,
+            conflicted: false,
+            thumbnail_url: None,
+            thumbnail_checksum: None,
+            thumbnail_dimensions: None,
+            revision: 0,
+            shortcut_target: None,
+            expires_at_ms,
+            permanent_id: None,
+            paywall,
+        };
+        let file_metadata = match linked_object {
+            Some((file_size, raw_url)) => FileMetadata {
+                file_size,
+                raw_url,
+                upload_status: UploadStatus::Uploaded,
+                ..file_metadata
+            },
+            None => file_metadata,
         };
 
         // Update hashtables
+        self.account_file_added(&file_metadata);
+        self.index_child_file(&file_metadata);
         self.file_uuid_to_metadata.insert(new_file_uuid.clone(), file_metadata);
-        self.full_file_path_to_uuid.insert(full_file_path, new_file_uuid.clone());
-
-        // Update parent folder's file_uuids
-        self.update_folder_file_uuids(&folder_uuid, &new_file_uuid, true);
+        self.full_file_path_to_uuid.insert(full_file_path.clone(), new_file_uuid.clone());
 
         // Update prior version if it exists
         if let Some(existing_uuid) = existing_file_uuid {
-            if let Some(existing_file) = self.file_uuid_to_metadata.get_mut(&existing_uuid) {
+            let existing_file = self.file_uuid_to_metadata.update(&existing_uuid, |existing_file| {
                 existing_file.next_version = Some(new_file_uuid.clone());
+                existing_file.clone()
+            });
+            // Only the newest version is listed in the parent folder
+            if let Some(existing_file) = existing_file {
+                self.unindex_child_file(&existing_file);
             }
-            // Remove the old file UUID from the parent folder
-            self.update_folder_file_uuids(&folder_uuid, &existing_uuid, false);
         }
 
-        new_file_uuid
+        self.record_audit(AuditAction::UpsertFile, &new_file_uuid, None, Some(full_file_path));
+        self.apply_version_retention(&new_file_uuid);
+        Ok(new_file_uuid)
     }
 
-    fn get_folder_by_id(&self, folder_id: &FolderUUID) -> Option<&FolderMetadata> {
+    fn get_folder_by_id(&self, folder_id: &FolderUUID) -> Option<FolderMetadata> {
         self.folder_uuid_to_metadata.get(folder_id)
     }
 
-    fn get_file_by_id(&self, file_id: &FileUUID) -> Option<&FileMetadata> {
+    fn get_file_by_id(&self, file_id: &FileUUID) -> Option<FileMetadata> {
         self.file_uuid_to_metadata.get(file_id)
     }
 
-    fn get_folder_by_path(&self, path: &DriveFullFilePath) -> Option<&FolderMetadata> {
+    fn get_folder_by_path(&self, path: &DriveFullFilePath) -> Option<FolderMetadata> {
         self.full_folder_path_to_uuid
-            .get(path)
-            .and_then(|uuid| self.folder_uuid_to_metadata.get(uuid))
+            .stored_path(&normalize_nfc(path))
+            .and_then(|path| self.full_folder_path_to_uuid.get(&path))
+            .and_then(|uuid| self.folder_uuid_to_metadata.get(&uuid))
     }
 
-    fn get_file_by_path(&self, path: &DriveFullFilePath) -> Option<&FileMetadata> {
+    fn get_file_by_path(&self, path: &DriveFullFilePath) -> Option<FileMetadata> {
         self.full_file_path_to_uuid
-            .get(path)
-            .and_then(|uuid| self.file_uuid_to_metadata.get(uuid))
+            .stored_path(&normalize_nfc(path))
+            .and_then(|path| self.full_file_path_to_uuid.get(&path))
+            .and_then(|uuid| self.file_uuid_to_metadata.get(&uuid))
     }
 
-    fn rename_folder(&mut self, folder_id: FolderUUID, new_name: String) -> Result<(), String> {
-        // Attempt to retrieve the folder metadata
-        if let Some(folder) = self.folder_uuid_to_metadata.get_mut(&folder_id) {
-            let old_path = folder.full_folder_path.clone();
-            ic_cdk::println!("Old folder path: {}", old_path);
-    
-            // Split the path into storage and folder parts
-            let parts: Vec<&str> = old_path.splitn(2, "::").collect();
-            if parts.len() != 2 {
-                return Err("Invalid folder structure".to_string());
-            }
-    
-            let storage_part = parts[0].to_string();
-            let folder_path = parts[1].trim_end_matches('/').to_string(); // Remove trailing slash
-    
-            // Perform path manipulation
-            let path_parts: Vec<&str> = folder_path.rsplitn(2, '/').collect();
-            let (parent_path, _current_folder_name) = match path_parts.len() {
-                2 => (path_parts[1].to_string(), path_parts[0].to_string()),
-                1 => (String::new(), path_parts[0].to_string()),
-                _ => return Err("Invalid folder structure".to_string()),
-            };
-    
-            // Construct the new folder path
-            let new_folder_path = if parent_path.is_empty() {
-                format!("{}::{}{}", storage_part, new_name, "/")
-            } else {
-                format!("{}::{}/{}{}", storage_part, parent_path, new_name, "/")
-            };
-    
-            // Check if a folder with the new path already exists
-            if self.full_folder_path_to_uuid.contains_key(&new_folder_path) {
-                return Err("A folder with the new name already exists in the parent directory".to_string());
-            }
-    
-            // Update folder metadata
-            folder.original_folder_name = new_name.clone();
-            folder.full_folder_path = new_folder_path.clone();
-            folder.last_changed_unix_ms = ic_cdk::api::time() / 1_000_000;
-    
-            // Update path mappings
-            ic_cdk::println!("Removing old path from full_folder_path_to_uuid: {}", old_path);
-            self.full_folder_path_to_uuid.remove(&old_path);
-    
-            ic_cdk::println!("Inserting new path into full_folder_path_to_uuid: {}", new_folder_path);
-            self.full_folder_path_to_uuid.insert(new_folder_path.clone(), folder_id.clone());
-    
-            // Update subfolder paths recursively
-            self.update_subfolder_paths(&folder_id, &old_path, &new_folder_path);
-    
-            // If the folder has a parent, ensure the parent's subfolder_uuids include this folder
-            if !parent_path.is_empty() {
-                let parent_full_path = format!("{}::{}", storage_part, parent_path);
-                if let Some(parent_uuid) = self.full_folder_path_to_uuid.get(&parent_full_path) {
-                    if let Some(parent_folder) = self.folder_uuid_to_metadata.get_mut(parent_uuid) {
-                        // Ensure the folder's UUID is present in the parent's subfolder_uuids
-                        if !parent_folder.subfolder_uuids.contains(&folder_id) {
-                            parent_folder.subfolder_uuids.push(folder_id.clone());
-                            ic_cdk::println!("Added folder UUID to parent folder's subfolder_uuids");
-                        }
-                    }
-                } else {
-                    ic_cdk::println!("Parent folder not found for path: {}", parent_full_path);
-                    return Err("Parent folder not found".to_string());
-                }
-            }
-    
-            ic_cdk::println!("Folder renamed successfully");
-            Ok(())
-        } else {
-            Err("Folder not found".to_string())
+    fn rename_folder(&mut self, folder_id: FolderUUID, new_name: String) -> Result<(), DriveError> {
+        let new_name = normalize_nfc(&new_name);
+        let mut folder = self
+            .folder_uuid_to_metadata
+            .get(&folder_id)
+            .ok_or_else(|| DriveError::folder_not_found(&folder_id))?;
+        self.check_folder_legal_hold(&folder_id)?;
+        let Some(parent_id) = folder.parent_folder_uuid.clone() else {
+            return Err(DriveError::InvalidInput {
+                field: "folder_id".to_string(),
+                reason: "The root folder of a storage location cannot be renamed".to_string(),
+            });
+        };
+        if new_name.is_empty() || new_name.contains('/') || new_name.contains(':') {
+            return Err(DriveError::InvalidInput {
+                field: "new_name".to_string(),
+                reason: "Must be non-empty and contain no '/' or ':'".to_string(),
+            });
+        }
+        let parent = self
+            .folder_uuid_to_metadata
+            .get(&parent_id)
+            .ok_or_else(|| DriveError::folder_not_found(&parent_id))?;
+
+        let old_path = folder.full_folder_path.clone();
+        let new_folder_path = format!("{}{}/", parent.full_folder_path, new_name);
+        if new_folder_path == old_path {
+            return Ok(());
         }
+        let (subfolder_ids, file_ids) = self.check_folder_relocation(&folder_id, &old_path, &new_folder_path, &new_name, false)?;
+
+        // Update folder metadata, re-keying its entry in the parent's child index
+        self.unindex_child_folder(&folder);
+        folder.original_folder_name = new_name;
+        folder.full_folder_path = new_folder_path.clone();
+        folder.last_changed_unix_ms = system_api::time() / 1_000_000;
+        folder.revision += 1;
+        self.index_child_folder(&folder);
+        self.folder_uuid_to_metadata.insert(folder_id.clone(), folder);
+        self.relocate_folder_paths(&folder_id, &subfolder_ids, &file_ids, &old_path, &new_folder_path);
+        self.record_audit(AuditAction::RenameFolder, &folder_id, Some(old_path), Some(new_folder_path));
+        Ok(())
     }
     
     
-    fn rename_file(&mut self, file_id: FileUUID, new_name: String) -> Result<(), String> {
-        ic_cdk::println!(
-            "Attempting to rename file. File ID: {}, New Name: {}",
-            file_id,
-            new_name
-        );
-
+    fn rename_file(&mut self, file_id: FileUUID, new_name: String) -> Result<(), DriveError> {
+        let new_name = normalize_nfc(&new_name);
         // Attempt to retrieve the file metadata
-        if let Some(file) = self.file_uuid_to_metadata.get_mut(&file_id) {
+        if let Some(mut file) = self.file_uuid_to_metadata.get(&file_id) {
+            self.check_file_legal_hold(&file)?;
+            let old_file = file.clone();
             let old_path = file.full_file_path.clone();
-            ic_cdk::println!("Old file path: {}", old_path);
 
             // Split the path into storage part and the rest
             let parts: Vec<&str> = old_path.splitn(2, "::").collect();
             if parts.len() != 2 {
-                return Err("Invalid file structure".to_string());
+                return Err(DriveError::invalid_path(&old_path, "Invalid file structure"));
             }
 
             let storage_part = parts[0].to_string();
@@ -407,147 +792,232 @@ impl State {
                 format!("{}::{}", storage_part, new_name)
             };
 
-            ic_cdk::println!("New file path: {}", new_path);
+            path_validation::check_name(&new_path, &new_name)?;
+            self.check_name_limits(&new_path, &new_name)?;
 
             // Check if a file with the new name already exists
             if self.full_file_path_to_uuid.contains_key(&new_path) {
-                ic_cdk::println!("Error: A file with this name already exists");
-                return Err("A file with this name already exists".to_string());
+                return Err(DriveError::AlreadyExists { path: new_path });
+            }
+            if let Some(existing) = self.case_variant_of(&new_path, &old_path) {
+                return Err(DriveError::AlreadyExists { path: existing });
             }
 
             // Update file metadata
             file.original_file_name = new_name.clone();
             file.full_file_path = new_path.clone();
-            file.last_changed_unix_ms = ic_cdk::api::time() / 1_000_000;
+            file.last_changed_unix_ms = system_api::time() / 1_000_000;
+            file.revision += 1;
             file.extension = new_name
                 .rsplit('.')
                 .next()
                 .unwrap_or("")
                 .to_string();
-            ic_cdk::println!("Updated file metadata: {:?}", file);
+            if file.extension != old_file.extension {
+                file.mime_type = mime::mime_type_for_extension(&file.extension);
+            }
+            self.account_file_removed(&old_file);
+            self.account_file_added(&file);
+            if self.unindex_child_file(&old_file) {
+                self.index_child_file(&file);
+            }
+            self.file_uuid_to_metadata.insert(file_id.clone(), file);
 
             // Update path mappings
-            ic_cdk::println!(
-                "Removing old path from full_file_path_to_uuid: {}",
-                old_path
-            );
             self.full_file_path_to_uuid.remove(&old_path);
 
-            ic_cdk::println!(
-                "Inserting new path into full_file_path_to_uuid: {}",
-                new_path
-            );
-            self.full_file_path_to_uuid.insert(new_path, file_id.clone());
+            self.full_file_path_to_uuid.insert(new_path.clone(), file_id.clone());
 
-            ic_cdk::println!("File renamed successfully");
+            self.record_audit(AuditAction::RenameFile, &file_id, Some(old_path), Some(new_path));
             Ok(())
         } else {
-            ic_cdk::println!("Error: File not found. File ID: {}", file_id);
-            Err("File not found".to_string())
+            Err(DriveError::file_not_found(&file_id))
         }
     }
-    fn delete_folder(&mut self, folder_id: &FolderUUID) -> Result<(), String> {
-        ic_cdk::println!("Attempting to delete folder. Folder ID: {}", folder_id);
-        
+
+    /// Renames a file and moves it into another existing folder of the same
+    /// storage location in one step, e.g. for a drag that also renames.
+    fn rename_or_move_file(&mut self, file_id: FileUUID, new_path: DriveFullFilePath) -> Result<(), DriveError> {
+        let file = self.file_uuid_to_metadata.get(&file_id).ok_or_else(|| DriveError::file_not_found(&file_id))?;
+        self.check_file_legal_hold(&file)?;
+        let new_path = self.canonical_path(&Self::sanitize_file_path(&new_path)?);
+        let old_path = file.full_file_path.clone();
+        if new_path == old_path {
+            return Ok(());
+        }
+        let (mut folder_path, new_name) = self.split_path(&new_path);
+        path_validation::check_name(&new_path, &new_name)?;
+        self.check_name_limits(&new_path, &new_name)?;
+        // Root folders are `storage::`, everything else ends with a slash
+        if !folder_path.ends_with("::") {
+            folder_path.push('/');
+        }
+        let destination = self
+            .get_folder_by_path(&folder_path)
+            .filter(|folder| !folder.deleted)
+            .ok_or_else(|| DriveError::folder_not_found(&folder_path))?;
+        self.check_folder_legal_hold(&destination.id)?;
+        if destination.storage_location != file.storage_location {
+            return Err(DriveError::StorageMismatch {
+                expected: file.storage_location.to_string(),
+                found: destination.storage_location.to_string(),
+            });
+        }
+        if self.full_file_path_to_uuid.contains_key(&new_path) {
+            return Err(DriveError::AlreadyExists { path: new_path });
+        }
+        if let Some(existing) = self.case_variant_of(&new_path, &old_path) {
+            return Err(DriveError::AlreadyExists { path: existing });
+        }
+
+        let mut moved_file = file.clone();
+        moved_file.folder_uuid = destination.id.clone();
+        moved_file.full_file_path = new_path.clone();
+        moved_file.extension = new_name.rsplit('.').next().unwrap_or("").to_string();
+        if moved_file.extension != file.extension {
+            moved_file.mime_type = mime::mime_type_for_extension(&moved_file.extension);
+        }
+        moved_file.original_file_name = new_name;
+        moved_file.last_changed_unix_ms = system_api::time() / 1_000_000;
+        moved_file.revision += 1;
+
+        self.account_file_removed(&file);
+        self.account_file_added(&moved_file);
+        if self.unindex_child_file(&file) {
+            self.index_child_file(&moved_file);
+        }
+        self.file_uuid_to_metadata.insert(file_id.clone(), moved_file);
+        self.full_file_path_to_uuid.remove(&old_path);
+        self.full_file_path_to_uuid.insert(new_path.clone(), file_id.clone());
+
+        let action = if destination.id == file.folder_uuid { AuditAction::RenameFile } else { AuditAction::MoveFile };
+        self.record_audit(action, &file_id, Some(old_path), Some(new_path));
+        Ok(())
+    }
+
+    fn delete_folder(&mut self, folder_id: &FolderUUID) -> Result<(), DriveError> {
         let (folder_path, subfolder_ids, file_ids) = if let Some(folder) = self.folder_uuid_to_metadata.get(folder_id) {
             (
-                folder.full_folder_path.clone(),
-                folder.subfolder_uuids.clone(),
-                folder.file_uuids.clone()
+                folder.full_folder_path,
+                self.child_folder_ids(folder_id),
+                self.child_file_ids(folder_id)
             )
         } else {
-            ic_cdk::println!("Error: Folder not found. Folder ID: {}", folder_id);
-            return Err("Folder not found".to_string());
+            return Err(DriveError::folder_not_found(folder_id));
         };
+        self.check_subtree_legal_hold(folder_id)?;
         
-            ic_cdk::println!("Folder found. Full path: {}", folder_path);
-            
-            ic_cdk::println!("Removing folder path from full_folder_path_to_uuid");
             self.full_folder_path_to_uuid.remove(&folder_path);
 
             // Recursively delete subfolders
-            ic_cdk::println!("Deleting subfolders");
             for subfolder_id in subfolder_ids {
-                ic_cdk::println!("Deleting subfolder: {}", subfolder_id);
                 self.delete_folder(&subfolder_id)?;
             }
 
             // Delete files in this folder
-            ic_cdk::println!("Deleting files in the folder");
             for file_id in file_ids {
-                ic_cdk::println!("Deleting file: {}", file_id);
                 self.delete_file(&file_id)?;
             }
 
-            // Don't Remove folder from parent's subfolders list as we need the folder metadata.deleted to sync offline-cloud
-            // if let Some(parent_id) = folder.parent_folder_uuid {
-            //     ic_cdk::println!("Updating parent folder. Parent ID: {}", parent_id);
-            //     if let Some(parent) = self.folder_uuid_to_metadata.get_mut(&parent_id) {
-            //         parent.subfolder_uuids.retain(|id| id != folder_id);
-            //         ic_cdk::println!("Updated parent's subfolder_uuids: {:?}", parent.subfolder_uuids);
-            //     }
-            // }
+            // Keep the folder in its parent's child index, as we need the folder metadata.deleted to sync offline-cloud
 
             // Mark the folder as deleted
-            if let Some(folder) = self.folder_uuid_to_metadata.get_mut(folder_id) {
-                folder.last_changed_unix_ms = ic_cdk::api::time() / 1_000_000;
+            if let Some(mut folder) = self.folder_uuid_to_metadata.get(folder_id) {
+                let was_deleted = folder.deleted;
+                folder.last_changed_unix_ms = system_api::time() / 1_000_000;
+                folder.revision += 1;
                 folder.deleted = true;
+                self.folder_uuid_to_metadata.insert(folder_id.clone(), folder.clone());
+                if !was_deleted {
+                    self.rollup_folder_removed(&folder);
+                }
             }
 
-            ic_cdk::println!("Folder deleted successfully");
+            self.unstar_item(folder_id);
+            self.mount_points.remove(folder_id);
+
+            self.record_audit(AuditAction::DeleteFolder, folder_id, Some(folder_path), None);
             
             Ok(())
     }
 
-    fn delete_file(&mut self, file_id: &FileUUID) -> Result<(), String> {
-        ic_cdk::println!("Attempting to delete file. File ID: {}", file_id);
-        
-        let file = self.file_uuid_to_metadata.remove(file_id)
-            .ok_or_else(|| {
-                ic_cdk::println!("Error: File not found. File ID: {}", file_id);
-                "File not found".to_string()
-            })?;
+    fn delete_file(&mut self, file_id: &FileUUID) -> Result<(), DriveError> {
+        let file = self.remove_file_record(file_id)?;
+        self.record_audit(AuditAction::DeleteFile, file_id, Some(file.full_file_path), None);
+        Ok(())
+    }
+
+    /// Drops a file record and everything indexed under it, without recording
+    /// the removal in the audit log.
+    fn remove_file_record(&mut self, file_id: &FileUUID) -> Result<FileMetadata, DriveError> {
+        let file = self.file_uuid_to_metadata.get(file_id)
+            .ok_or_else(|| DriveError::file_not_found(file_id))?;
+        self.check_file_legal_hold(&file)?;
+        self.file_uuid_to_metadata.remove(file_id);
 
-        ic_cdk::println!("File found. Full path: {}", file.full_file_path);
+        self.account_file_removed(&file);
         
-        ic_cdk::println!("Removing file path from full_file_path_to_uuid --");
-        self.full_file_path_to_uuid.remove(&file.full_file_path);
+        if self.full_file_path_to_uuid.get(&file.full_file_path).as_ref() == Some(file_id) {
+            self.full_file_path_to_uuid.remove(&file.full_file_path);
+        }
 
-        // Don't Remove file from its parent folder's file list as we need the file metadata.deleted to sync offline-cloud
-        // ic_cdk::println!("Updating parent folder. Folder UUID: {}", file.folder_uuid);
-        // if let Some(parent) = self.folder_uuid_to_metadata.get_mut(&file.folder_uuid) {
-        //     parent.file_uuids.retain(|id| id != file_id);
-        //     ic_cdk::println!("Updated parent's file_uuids: {:?}", parent.file_uuids);
-        // }
+        // The metadata record is gone, so its listing entry goes with it
+        self.unindex_child_file(&file);
 
         // Handle versioning
         if let Some(prior_version) = &file.prior_version {
-            ic_cdk::println!("Updating prior version. Prior version ID: {}", prior_version);
-            if let Some(prior_file) = self.file_uuid_to_metadata.get_mut(prior_version) {
+            self.file_uuid_to_metadata.update(prior_version, |prior_file| {
                 prior_file.next_version = file.next_version.clone();
-                ic_cdk::println!("Updated prior file's next_version: {:?}", prior_file.next_version);
-            }
+            });
         }
         if let Some(next_version) = &file.next_version {
-            ic_cdk::println!("Updating next version. Next version ID: {}", next_version);
-            if let Some(next_file) = self.file_uuid_to_metadata.get_mut(next_version) {
+            self.file_uuid_to_metadata.update(next_version, |next_file| {
                 next_file.prior_version = file.prior_version.clone();
-                ic_cdk::println!("Updated next file's prior_version: {:?}", next_file.prior_version);
-            }
+            });
         }
 
-        ic_cdk::println!("File deleted successfully");
-        Ok(())
+        self.unstar_item(file_id);
+        self.remove_comments_for(file_id);
+        self.discard_sync_conflict(file_id);
+        self.remove_document(file_id);
+        self.link_checks.remove(file_id);
+        self.carry_paywall_payments(file_id, file.next_version.as_ref());
+        Ok(file)
     }
 
-    fn upsert_cloud_file_with_local_sync(&mut self, file_id: &FileUUID, file_metadata: &FileMetadata) -> Result<(FileUUID), String> {
+    /// Applies a file edited on an offline client. `base_version` is the cloud
+    /// version the edit started from, by default that of `file_id`. If the
+    /// cloud copy has changed since, the client's copy is kept next to it as a
+    /// sync conflict instead of overwriting it.
+    fn upsert_cloud_file_with_local_sync(
+        &mut self,
+        file_id: &FileUUID,
+        file_metadata: &FileMetadata,
+        base_version: Option<u32>,
+        user_id: UserID,
+    ) -> Result<FileUUID, DriveError> {
         // overwrite the cloud file metadata with the latest version from offline client
         // must increment the file_version, and append the new file version with client submitted metadata (sanitized)
-        let user_id = ic_cdk::caller();
-        let existing_file = self.file_uuid_to_metadata.get(&file_id.clone()).unwrap().clone();
-
-        let sanitized_new_file_path = Self::sanitize_file_path(&file_metadata.full_file_path);
-        let new_full_file_path = sanitized_new_file_path;
+        let synced_file = self.file_uuid_to_metadata.get(file_id)
+            .ok_or_else(|| DriveError::file_not_found(file_id))?;
+        let base_version = base_version.unwrap_or(synced_file.file_version);
+        let existing_file = self.latest_file_version(synced_file);
+        self.check_file_legal_hold(&existing_file)?;
+        let conflicting = existing_file.file_version != base_version;
+        if conflicting && self.sync_conflicts.contains_key(&existing_file.id) {
+            return Err(DriveError::InvalidState {
+                reason: "File already has an unresolved sync conflict".to_string(),
+            });
+        }
+        self.check_quota(file_metadata.file_size)?;
+        let sha256_checksum = integrity::normalize_checksum(file_metadata.sha256_checksum.clone())?;
+        encryption::validate_encryption_fields(file_metadata)?;
+
+        let sanitized_new_file_path = Self::sanitize_file_path(&file_metadata.full_file_path)?;
+        let new_full_file_path = self.canonical_path(&sanitized_new_file_path);
+        self.check_path_storage_location(&new_full_file_path, &file_metadata.storage_location)?;
+        self.check_path_limits(&new_full_file_path)?;
+        self.check_path_legal_hold(&new_full_file_path)?;
         
         let new_file_uuid = generate_unique_id();
         
@@ -555,19 +1025,11 @@ impl State {
         let folder_uuid = self.ensure_folder_structure(&new_folder_path, file_metadata.storage_location.clone(), user_id);
 
         let extension = new_file_name.rsplit('.').next().unwrap_or("").to_string();
-
-         // Clean up version chain in folder
-        if let Some(folder) = self.folder_uuid_to_metadata.get_mut(&folder_uuid) {
-            let mut current_version = Some(file_id.clone());
-            while let Some(version_id) = current_version {
-                if let Some(version_file) = self.file_uuid_to_metadata.get(&version_id) {
-                    folder.file_uuids.retain(|uuid| uuid != &version_id);
-                    current_version = version_file.prior_version.clone();
-                } else {
-                    break;
-                }
-            }
-        }
+        let mime_type = if extension == existing_file.extension {
+            existing_file.mime_type.clone()
+        } else {
+            mime::mime_type_for_extension(&extension)
+        };
 
         let new_file_metadata = FileMetadata {
             id: new_file_uuid.clone(),
@@ -578,140 +1040,369 @@ impl State {
             next_version: None,
             extension,
             full_file_path: new_full_file_path.clone(),
-            tags: Vec::new(),
+            mime_type,
+            // Labels describe the file rather than one version of its content
+            tags: existing_file.tags.clone(),
+            properties: existing_file.properties.clone(),
             owner: user_id,
             created_date: file_metadata.created_date,
             storage_location: file_metadata.storage_location.clone(),
             file_size: file_metadata.file_size,
             raw_url: file_metadata.raw_url.clone(),
-            last_changed_unix_ms: file_metadata.last_changed_unix_ms | ic_cdk::api::time() / 1_000_000,
+            last_changed_unix_ms: system_api::time() / 1_000_000,
             deleted: file_metadata.deleted,
+            upload_status: file_metadata.upload_status.clone(),
+            sha256_checksum,
+            // Access history follows the file across versions
+            last_accessed_ms: existing_file.last_accessed_ms,
+            access_count: existing_file.access_count,
+            // The syncing client re-encrypted the new content, so its material wins
+            encrypted: file_metadata.encrypted,
+            encryption_scheme: file_metadata.encryption_scheme.clone(),
+            wrapped_dek: file_metadata.wrapped_dek.clone(),
+            nonce: file_metadata.nonce.clone(),
+            conflicted: false,
+            // A thumbnail of the new content, if the client rendered one
+            thumbnail_url: file_metadata.thumbnail_url.clone(),
+            thumbnail_checksum: file_metadata.thumbnail_checksum.clone(),
+            thumbnail_dimensions: file_metadata.thumbnail_dimensions.clone(),
+            revision: 0,
+            shortcut_target: None,
+            expires_at_ms: existing_file.expires_at_ms,
+            // New content has not been pinned
+            permanent_id: None,
+            paywall: existing_file.paywall.clone(),
         };
 
+        if conflicting {
+            // Both sides descend from `base_version`; neither wins until resolved
+            let conflict_copy = FileMetadata {
+                file_version: base_version + 1,
+                prior_version: None,
+                conflicted: true,
+                ..new_file_metadata
+            };
+            return Ok(self.record_sync_conflict(&existing_file, conflict_copy, base_version));
+        }
+
+         // Clean up version chain in folder
+        let mut current_version = Some(existing_file.id.clone());
+        while let Some(version_id) = current_version {
+            if let Some(version_file) = self.file_uuid_to_metadata.get(&version_id) {
+                self.unindex_child_file(&version_file);
+                current_version = version_file.prior_version;
+            } else {
+                break;
+            }
+        }
+
         // Update hashtables
+        self.account_file_added(&new_file_metadata);
+        // Only the new version is listed in the parent folder
+        self.index_child_file(&new_file_metadata);
         self.file_uuid_to_metadata.insert(new_file_uuid.clone(), new_file_metadata);
-        self.full_file_path_to_uuid.insert(new_full_file_path, new_file_uuid.clone());
-
-        // // Update parent folder's file_uuids
-        // self.update_folder_file_uuids(&folder_uuid, &new_file_uuid, true);
-        // Update parent folder's file_uuids (only add new version)
-        if let Some(folder) = self.folder_uuid_to_metadata.get_mut(&folder_uuid) {
-            folder.file_uuids.retain(|uuid| uuid != &new_file_uuid);
-            folder.file_uuids.push(new_file_uuid.clone());
+        if existing_file.full_file_path != new_full_file_path {
+            self.full_file_path_to_uuid.remove(&existing_file.full_file_path);
         }
+        self.full_file_path_to_uuid.insert(new_full_file_path.clone(), new_file_uuid.clone());
 
         // Update version chain
-        if let Some(existing_file) = self.file_uuid_to_metadata.get_mut(&file_id.clone()) {
+        self.file_uuid_to_metadata.update(&existing_file.id, |existing_file| {
             existing_file.next_version = Some(new_file_uuid.clone());
-        }
+        });
 
-        return Ok((new_file_uuid.clone()));
+        self.record_audit(
+            AuditAction::SyncFile,
+            &new_file_uuid,
+            Some(existing_file.full_file_path),
+            Some(new_full_file_path),
+        );
+        Ok(new_file_uuid)
     }
-    fn upsert_cloud_folder_with_local_sync(&mut self, folder_id: &FolderUUID, folder_metadata: &FolderMetadata) -> Result<(FolderUUID), String> {
+    fn upsert_cloud_folder_with_local_sync(&mut self, folder_id: &FolderUUID, folder_metadata: &FolderMetadata) -> Result<FolderUUID, DriveError> {
         // overwrite the cloud folder metadata with the latest version from offline client
         // no need to change folder versions, no version tracking on folders
-        let existing_folder = self.folder_uuid_to_metadata.get_mut(&folder_id.clone()).unwrap();
-        existing_folder.original_folder_name = folder_metadata.original_folder_name.clone();
+        let mut existing_folder = self.folder_uuid_to_metadata.get(folder_id)
+            .ok_or_else(|| DriveError::folder_not_found(folder_id))?;
+        self.check_folder_legal_hold(folder_id)?;
+        if folder_metadata.storage_location != existing_folder.storage_location {
+            return Err(DriveError::StorageMismatch {
+                expected: existing_folder.storage_location.to_string(),
+                found: folder_metadata.storage_location.to_string(),
+            });
+        }
+
+        // The client's path is cleaned up the same way as a created folder's,
+        // and the name and parent are read from it rather than trusted
+        let sanitized = Self::sanitize_file_path(&folder_metadata.full_folder_path)?;
+        self.check_path_storage_location(&sanitized, &existing_folder.storage_location)?;
+        let (parent_path, new_name) = self.split_path(&sanitized);
+        let parent_path = if parent_path.ends_with("::") { parent_path } else { format!("{}/", parent_path) };
+        // Only the parent is respelled, so a case-only rename still applies
+        let parent_path = self.canonical_path(&parent_path);
+        let new_path = if new_name.is_empty() { parent_path.clone() } else { format!("{}{}/", parent_path, new_name) };
+        let old_path = existing_folder.full_folder_path.clone();
+        let parent_id = if new_name.is_empty() { None } else { self.full_folder_path_to_uuid.get(&parent_path) };
+        if folder_metadata.parent_folder_uuid != parent_id {
+            return Err(DriveError::InvalidInput {
+                field: "parent_folder_uuid".to_string(),
+                reason: format!("Does not match the parent of '{}'", new_path),
+            });
+        }
+        let moved = existing_folder.parent_folder_uuid != parent_id;
+
+        let mut relocation = None;
+        if new_path != old_path {
+            let Some(parent_id) = &parent_id else {
+                return Err(DriveError::InvalidInput {
+                    field: "full_folder_path".to_string(),
+                    reason: "The root folder of a storage location cannot be renamed".to_string(),
+                });
+            };
+            if existing_folder.parent_folder_uuid.is_none() {
+                return Err(DriveError::InvalidInput {
+                    field: "full_folder_path".to_string(),
+                    reason: "The root folder of a storage location cannot be moved".to_string(),
+                });
+            }
+            if new_path.starts_with(&old_path) {
+                return Err(DriveError::InvalidInput {
+                    field: "full_folder_path".to_string(),
+                    reason: "A folder cannot be moved into itself".to_string(),
+                });
+            }
+            if moved {
+                self.check_folder_legal_hold(parent_id)?;
+            }
+            relocation = Some(self.check_folder_relocation(folder_id, &old_path, &new_path, &new_name, moved)?);
+        }
+
+        let previous = existing_folder.clone();
+        existing_folder.original_folder_name = if new_name.is_empty() { previous.original_folder_name.clone() } else { new_name };
         existing_folder.tags = folder_metadata.tags.clone();
-        existing_folder.storage_location = folder_metadata.storage_location.clone();
-        existing_folder.full_folder_path = folder_metadata.full_folder_path.clone();
-        existing_folder.parent_folder_uuid = folder_metadata.parent_folder_uuid.clone();
+        existing_folder.full_folder_path = new_path.clone();
+        existing_folder.parent_folder_uuid = parent_id;
         existing_folder.deleted = folder_metadata.deleted;
-        existing_folder.last_changed_unix_ms = folder_metadata.last_changed_unix_ms | ic_cdk::api::time() / 1_000_000;
-        return Ok((folder_id.clone()));
+        existing_folder.last_changed_unix_ms = system_api::time() / 1_000_000;
+        existing_folder.revision += 1;
+        let updated = existing_folder.clone();
+        self.folder_uuid_to_metadata.insert(folder_id.clone(), existing_folder);
+        self.unindex_child_folder(&previous);
+        self.index_child_folder(&updated);
+        if let Some((subfolder_ids, file_ids)) = relocation {
+            self.relocate_folder_paths(folder_id, &subfolder_ids, &file_ids, &old_path, &new_path);
+        }
+        if previous.deleted != updated.deleted || moved {
+            if !previous.deleted {
+                self.rollup_folder_removed(&previous);
+                self.adjust_rollups_for_subtree(&previous, false);
+            }
+            if !updated.deleted {
+                self.rollup_folder_added(&updated);
+                self.adjust_rollups_for_subtree(&updated, true);
+            }
+        }
+        self.record_audit(
+            AuditAction::SyncFolder,
+            folder_id,
+            Some(previous.full_folder_path),
+            Some(updated.full_folder_path),
+        );
+        Ok(folder_id.clone())
     }
 
-    fn update_subfolder_paths(&mut self, folder_id: &FolderUUID, old_path: &str, new_path: &str) {
-        if let Some(folder) = self.folder_uuid_to_metadata.get(folder_id).cloned() {
-            for subfolder_id in &folder.subfolder_uuids {
-                if let Some(subfolder) = self.folder_uuid_to_metadata.get_mut(subfolder_id) {
-                    let old_subfolder_path = subfolder.full_folder_path.clone();
-                    let new_subfolder_path = old_subfolder_path.replace(old_path, new_path);
-                    
-                    self.full_folder_path_to_uuid.remove(&old_subfolder_path);
-                    subfolder.full_folder_path = new_subfolder_path.clone();
-                    self.full_folder_path_to_uuid.insert(new_subfolder_path.clone(), subfolder_id.clone());
-                    
-                    self.update_subfolder_paths(subfolder_id, &old_subfolder_path, &new_subfolder_path);
-                }
+    /// Checks that `folder_id` and everything below it can move from
+    /// `old_path` to `new_path` under the name `new_name`, and returns the
+    /// subtree to pass to `relocate_folder_paths`. Nothing is changed, so a
+    /// collision deep down cannot leave the move half applied. A `moved`
+    /// folder also gets the depth and child count limits of its new place.
+    fn check_folder_relocation(
+        &self,
+        folder_id: &FolderUUID,
+        old_path: &DriveFullFilePath,
+        new_path: &DriveFullFilePath,
+        new_name: &str,
+        moved: bool,
+    ) -> Result<(Vec<FolderUUID>, Vec<FileUUID>), DriveError> {
+        path_validation::check_name(new_path, new_name)?;
+        if moved {
+            self.check_path_limits(new_path)?;
+        } else {
+            self.check_name_limits(new_path, new_name)?;
+        }
+        if self.full_folder_path_to_uuid.contains_key(new_path) {
+            return Err(DriveError::AlreadyExists { path: new_path.clone() });
+        }
+        if let Some(existing) = self.case_variant_of(new_path, old_path) {
+            return Err(DriveError::AlreadyExists { path: existing });
+        }
+        let (subfolder_ids, file_ids) = self.subtree_ids(folder_id);
+        for path in self.live_subtree_paths(&subfolder_ids, &file_ids) {
+            let rebased = rebase_path(&path, old_path, new_path);
+            if self.full_folder_path_to_uuid.contains_key(&rebased) || self.full_file_path_to_uuid.contains_key(&rebased) {
+                return Err(DriveError::AlreadyExists { path: rebased });
+            }
+            if let Some(existing) = self.case_variant_of(&rebased, &path) {
+                return Err(DriveError::AlreadyExists { path: existing });
+            }
+            if moved {
+                self.check_path_limits(&rebased)?;
             }
+        }
+        Ok((subfolder_ids, file_ids))
+    }
 
-            // Update file paths
-            for file_id in &folder.file_uuids {
-                if let Some(file) = self.file_uuid_to_metadata.get_mut(file_id) {
-                    let old_file_path = file.full_file_path.clone();
-                    let new_file_path = old_file_path.replace(old_path, new_path);
-                    
-                    self.full_file_path_to_uuid.remove(&old_file_path);
-                    file.full_file_path = new_file_path.clone();
-                    self.full_file_path_to_uuid.insert(new_file_path, file_id.clone());
-                }
+    /// Moves the path index entry of `folder_id`, if it has one, and every
+    /// path below it from `old_path` to `new_path`.
+    fn relocate_folder_paths(
+        &mut self,
+        folder_id: &FolderUUID,
+        subfolder_ids: &[FolderUUID],
+        file_ids: &[FileUUID],
+        old_path: &DriveFullFilePath,
+        new_path: &DriveFullFilePath,
+    ) {
+        if self.full_folder_path_to_uuid.get(old_path).as_ref() == Some(folder_id) {
+            self.full_folder_path_to_uuid.remove(old_path);
+            self.full_folder_path_to_uuid.insert(new_path.clone(), folder_id.clone());
+        }
+        self.rebase_subtree_paths(subfolder_ids, file_ids, old_path, new_path);
+    }
+
+    /// Every folder and file below `folder_id`, deleted folders included.
+    fn subtree_ids(&self, folder_id: &FolderUUID) -> (Vec<FolderUUID>, Vec<FileUUID>) {
+        let mut folders = Vec::new();
+        let mut files = Vec::new();
+        let mut pending = vec![folder_id.clone()];
+        while let Some(id) = pending.pop() {
+            let children = self.child_folder_ids(&id);
+            files.extend(self.child_file_ids(&id));
+            pending.extend(children.iter().cloned());
+            folders.extend(children);
+        }
+        (folders, files)
+    }
+
+    /// Paths in the subtree that are currently indexed, i.e. those of live
+    /// folders and of the latest version of each file.
+    fn live_subtree_paths(&self, folder_ids: &[FolderUUID], file_ids: &[FileUUID]) -> Vec<String> {
+        let folders = folder_ids
+            .iter()
+            .filter_map(|id| self.folder_uuid_to_metadata.get(id))
+            .map(|folder| folder.full_folder_path)
+            .filter(|path| self.full_folder_path_to_uuid.contains_key(path));
+        let files = file_ids
+            .iter()
+            .filter_map(|id| self.file_uuid_to_metadata.get(id).map(|file| (id, file.full_file_path)))
+            .filter(|(id, path)| self.full_file_path_to_uuid.get(path).as_ref() == Some(*id))
+            .map(|(_, path)| path);
+        folders.chain(files).collect()
+    }
+
+    /// Moves every path in the subtree from under `old_prefix` to under
+    /// `new_prefix`. A path index entry follows only if it pointed at the
+    /// record, so deleted folders and older file versions stay unindexed.
+    fn rebase_subtree_paths(&mut self, folder_ids: &[FolderUUID], file_ids: &[FileUUID], old_prefix: &str, new_prefix: &str) {
+        for folder_id in folder_ids {
+            let Some(mut subfolder) = self.folder_uuid_to_metadata.get(folder_id) else {
+                continue;
+            };
+            let old_subfolder_path = std::mem::take(&mut subfolder.full_folder_path);
+            let new_subfolder_path = rebase_path(&old_subfolder_path, old_prefix, new_prefix);
+            if self.full_folder_path_to_uuid.get(&old_subfolder_path).as_ref() == Some(folder_id) {
+                self.full_folder_path_to_uuid.remove(&old_subfolder_path);
+                self.full_folder_path_to_uuid.insert(new_subfolder_path.clone(), folder_id.clone());
+            }
+            subfolder.full_folder_path = new_subfolder_path;
+            subfolder.revision += 1;
+            self.folder_uuid_to_metadata.insert(folder_id.clone(), subfolder);
+        }
+
+        for file_id in file_ids {
+            let Some(mut file) = self.file_uuid_to_metadata.get(file_id) else {
+                continue;
+            };
+            let old_file_path = std::mem::take(&mut file.full_file_path);
+            let new_file_path = rebase_path(&old_file_path, old_prefix, new_prefix);
+            if self.full_file_path_to_uuid.get(&old_file_path).as_ref() == Some(file_id) {
+                self.full_file_path_to_uuid.remove(&old_file_path);
+                self.full_file_path_to_uuid.insert(new_file_path.clone(), file_id.clone());
             }
+            file.full_file_path = new_file_path;
+            file.revision += 1;
+            self.file_uuid_to_metadata.insert(file_id.clone(), file);
         }
     }
     
+    /// The children of `folder_uuid` in listing order, as a folder or a file
+    /// each. The child index is walked lazily, so skipping to a page only
+    /// resolves the children on it.
+    fn listed_children<'a>(
+        &'a self,
+        folder_uuid: &FolderUUID,
+        exclude_pending: bool,
+        pinned_first: bool,
+    ) -> impl Iterator<Item = (Option<FolderMetadata>, Option<FileMetadata>)> + 'a {
+        // Pinned folders are gathered up front and skipped in the walk below
+        let pinned = if pinned_first {
+            self.pinned_child_folders(folder_uuid)
+        } else {
+            Vec::new()
+        };
+        let pinned_ids: BTreeSet<FolderUUID> = pinned.iter().map(|folder| folder.id.clone()).collect();
+
+        let unpinned = self
+            .children_of(folder_uuid)
+            .filter(move |child| !pinned_ids.contains(&child.child))
+            .filter_map(move |child| match child.kind {
+                ChildKind::Folder => self
+                    .folder_uuid_to_metadata
+                    .get(&child.child)
+                    .map(|folder| (Some(folder), None)),
+                ChildKind::File => self
+                    .file_uuid_to_metadata
+                    .get(&child.child)
+                    .map(|file| self.resolve_shortcut(file))
+                    .filter(|file| !(exclude_pending && file.upload_status == UploadStatus::Pending))
+                    .map(|file| (None, Some(file))),
+            });
+        pinned.into_iter().map(|folder| (Some(folder), None)).chain(unpinned)
+    }
+
     fn fetch_files_at_folder_path(&self, config: FetchFilesAtFolderPathConfig) -> FetchFilesResult {
-        let FetchFilesAtFolderPathConfig { full_folder_path, limit, after } = config;
+        let FetchFilesAtFolderPathConfig { full_folder_path, limit, after, exclude_pending, pinned_first } = config;
+        let exclude_pending = exclude_pending.unwrap_or(false);
         
-        if let Some(folder_uuid) = self.full_folder_path_to_uuid.get(&full_folder_path) {
-            if let Some(folder) = self.folder_uuid_to_metadata.get(folder_uuid) {
-                let mut folders = Vec::new();
-                let mut files = Vec::new();
-
-                // Collect subfolders
-                for subfolder_uuid in &folder.subfolder_uuids {
-                    if let Some(subfolder) = self.folder_uuid_to_metadata.get(subfolder_uuid) {
-                        folders.push(subfolder.clone());
-                    }
-                }
-
-                // Collect files
-                for file_uuid in &folder.file_uuids {
-                    if let Some(file) = self.file_uuid_to_metadata.get(file_uuid) {
-                        files.push(file.clone());
-                    }
-                }
+        let Some(folder_uuid) = self.full_folder_path_to_uuid.get(&full_folder_path) else {
+            return FetchFilesResult::empty();
+        };
+        if !self.folder_uuid_to_metadata.contains_key(&folder_uuid) {
+            return FetchFilesResult::empty();
+        }
 
-                // Apply pagination
-                let total_items = folders.len() + files.len();
-                let start = after as usize;
-                let end = (start + limit as usize).min(total_items);
-
-                let result_folders: Vec<FolderMetadata>;
-                let result_files: Vec<FileMetadata>;
-
-                if start < folders.len() {
-                    let folders_end = end.min(folders.len());
-                    result_folders = folders[start..folders_end].to_vec();
-                    result_files = if end > folders.len() {
-                        files[0..end - folders.len()].to_vec()
-                    } else {
-                        Vec::new()
-                    };
-                } else {
-                    result_folders = Vec::new();
-                    let files_start = start - folders.len();
-                    result_files = files[files_start..end - folders.len()].to_vec();
-                }
+        let mut children = self
+            .listed_children(&folder_uuid, exclude_pending, pinned_first.unwrap_or(false))
+            .skip(after as usize);
 
-                let total_results = result_folders.len() + result_files.len();
+        let mut folders = Vec::new();
+        let mut files = Vec::new();
+        for (folder, file) in children.by_ref().take(limit as usize) {
+            folders.extend(folder);
+            files.extend(file);
+        }
+        let has_more = children.next().is_some();
+        let total = (folders.len() + files.len()) as u32;
 
-                FetchFilesResult {
-                    folders: result_folders,
-                    files: result_files,
-                    total: total_results as u32,
-                    has_more: end < total_items,
-                }
-            } else {
-                FetchFilesResult::empty()
-            }
-        } else {
-            FetchFilesResult::empty()
+        FetchFilesResult {
+            folders,
+            files,
+            total,
+            has_more,
         }
     }
 
-    fn ensure_root_folder(&mut self, storage_location: &StorageLocationEnum, user_id: &UserID) -> FolderUUID {
-        let root_path = format!("{}::", storage_location.to_string());
+    fn ensure_root_folder(&mut self, storage_location: &StorageLocationID, user_id: &UserID) -> FolderUUID {
+        let root_path = format!("{}::", storage_location);
         if let Some(uuid) = self.full_folder_path_to_uuid.get(&root_path) {
             uuid.clone()
         } else {
@@ -720,15 +1411,21 @@ impl State {
                 id: root_folder_uuid.clone(),
                 original_folder_name: String::new(),
                 parent_folder_uuid: None,
-                subfolder_uuids: Vec::new(),
-                file_uuids: Vec::new(),
                 full_folder_path: root_path.clone(),
                 tags: Vec::new(),
-                owner: user_id.clone(),
-                created_date: ic_cdk::api::time(),
+                properties: Vec::new(),
+                owner: *user_id,
+                created_date: system_api::time(),
                 storage_location: storage_location.clone(),
-                last_changed_unix_ms: ic_cdk::api::time() / 1_000_000,
+                last_changed_unix_ms: system_api::time() / 1_000_000,
                 deleted: false,
+                subtree_bytes: 0,
+                subtree_file_count: 0,
+                subtree_folder_count: 0,
+                revision: 0,
+                settings: None,
+                color: None,
+                pinned_rank: None,
             };
 
             self.full_folder_path_to_uuid.insert(root_path, root_folder_uuid.clone());
@@ -741,7 +1438,7 @@ impl State {
     pub fn ensure_folder_structure(
         &mut self,
         folder_path: &str,
-        storage_location: StorageLocationEnum,
+        storage_location: StorageLocationID,
         user_id: UserID,
     ) -> FolderUUID {
         let path_parts: Vec<&str> = folder_path.split("::").collect();
@@ -757,42 +1454,44 @@ impl State {
                     id: new_folder_uuid.clone(),
                     original_folder_name: part.to_string(),
                     parent_folder_uuid: Some(parent_uuid.clone()),
-                    subfolder_uuids: Vec::new(),
-                    file_uuids: Vec::new(),
                     full_folder_path: current_path.clone(),
-                    tags: Vec::new(),
+                    tags: self.default_tags_for(&parent_uuid),
+                    properties: Vec::new(),
                     owner: user_id,
-                    created_date: ic_cdk::api::time(),
+                    created_date: system_api::time(),
                     storage_location: storage_location.clone(),
-                    last_changed_unix_ms: ic_cdk::api::time() / 1_000_000,
+                    last_changed_unix_ms: system_api::time() / 1_000_000,
                     deleted: false,
+                    subtree_bytes: 0,
+                    subtree_file_count: 0,
+                    subtree_folder_count: 0,
+                    revision: 0,
+                    settings: None,
+                    color: None,
+                    pinned_rank: None,
                 };
 
                 self.full_folder_path_to_uuid.insert(current_path.clone(), new_folder_uuid.clone());
+                self.rollup_folder_added(&new_folder);
+                self.index_child_folder(&new_folder);
                 self.folder_uuid_to_metadata.insert(new_folder_uuid.clone(), new_folder);
 
-                // Update parent folder's subfolder_uuids
-                if let Some(parent_folder) = self.folder_uuid_to_metadata.get_mut(&parent_uuid) {
-                    if !parent_folder.subfolder_uuids.contains(&new_folder_uuid) {
-                        parent_folder.subfolder_uuids.push(new_folder_uuid.clone());
-                    }
-                }
-
                 parent_uuid = new_folder_uuid;
             } else {
-                parent_uuid = self.full_folder_path_to_uuid[&current_path].clone();
+                parent_uuid = self.full_folder_path_to_uuid.get(&current_path).unwrap();
             }
         }
 
         parent_uuid
     }
 
-    fn sanitize_file_path(file_path: &str) -> String {
+    fn sanitize_file_path(file_path: &str) -> Result<String, DriveError> {
+        path_validation::check_raw_path(file_path)?;
         let mut parts = file_path.splitn(2, "::");
         let storage_part = parts.next().unwrap_or("");
         let path_part = parts.next().unwrap_or("");
     
-        let sanitized = path_part.replace(':', ";");
+        let sanitized = normalize_nfc(path_part).replace(':', ";");
 
         // Compile a regex to match one or more consecutive slashes
         let re = Regex::new(r"/+").unwrap();
@@ -804,7 +1503,7 @@ impl State {
         // Additional sanitization can be performed here if necessary
     
         // Reconstruct the full path
-        format!("{}::{}", storage_part, sanitized)
+        Ok(format!("{}::{}", storage_part, sanitized))
     }
 
     fn split_path(&self, full_path: &str) -> (String, String) {
@@ -823,39 +1522,87 @@ impl State {
     }
 
 
-    fn snapshot_hashtables(&self) -> StateSnapshot {
-        StateSnapshot {
-            folder_uuid_to_metadata: self.folder_uuid_to_metadata.clone(),
-            file_uuid_to_metadata: self.file_uuid_to_metadata.clone(),
-            full_folder_path_to_uuid: self.full_folder_path_to_uuid.clone(),
-            full_file_path_to_uuid: self.full_file_path_to_uuid.clone(),
+    /// Up to `limit` records following `after`, read straight from the stable
+    /// maps.
+    fn snapshot_page(&self, caller: Principal, after: Option<SnapshotCursor>, limit: usize) -> StateSnapshotPage {
+        let mut snapshot = StateSnapshot {
+            folder_uuid_to_metadata: HashMap::new(),
+            file_uuid_to_metadata: HashMap::new(),
+            full_folder_path_to_uuid: HashMap::new(),
+            full_file_path_to_uuid: HashMap::new(),
             owner: self.owner,
             username: self.username.rsplit("@").next().unwrap_or("").to_string(),
+        };
+        let mut next_cursor = None;
+
+        let folders: Box<dyn Iterator<Item = (FolderUUID, FolderMetadata)>> = match &after {
+            None => Box::new(self.folder_uuid_to_metadata.iter()),
+            Some(SnapshotCursor::Folder(id)) => {
+                Box::new(self.folder_uuid_to_metadata.range((Excluded(id.clone()), Unbounded)))
+            }
+            Some(SnapshotCursor::File(_)) => Box::new(std::iter::empty()),
+        };
+        for (id, folder) in folders.take(limit) {
+            if self.full_folder_path_to_uuid.get(&folder.full_folder_path).as_ref() == Some(&id) {
+                snapshot.full_folder_path_to_uuid.insert(folder.full_folder_path.clone(), id.clone());
+            }
+            next_cursor = Some(SnapshotCursor::Folder(id.clone()));
+            snapshot.folder_uuid_to_metadata.insert(id, folder);
+        }
+
+        let room = limit - snapshot.folder_uuid_to_metadata.len();
+        let files: Box<dyn Iterator<Item = (FileUUID, FileMetadata)>> = match &after {
+            Some(SnapshotCursor::File(id)) => {
+                Box::new(self.file_uuid_to_metadata.range((Excluded(id.clone()), Unbounded)))
+            }
+            _ => Box::new(self.file_uuid_to_metadata.iter()),
+        };
+        for (id, file) in files.take(room) {
+            if self.full_file_path_to_uuid.get(&file.full_file_path).as_ref() == Some(&id) {
+                snapshot.full_file_path_to_uuid.insert(file.full_file_path.clone(), id.clone());
+            }
+            next_cursor = Some(SnapshotCursor::File(id.clone()));
+            snapshot.file_uuid_to_metadata.insert(id, self.redact_unpaid(caller, file));
         }
+
+        // A short page is the last one
+        let listed = snapshot.folder_uuid_to_metadata.len() + snapshot.file_uuid_to_metadata.len();
+        if listed < limit {
+            next_cursor = None;
+        }
+        StateSnapshotPage { snapshot, next_cursor }
     }
 }
 
+/// A compact 128-bit ID, rendered as 32 hex characters: the IC time in
+/// nanoseconds, a counter, and 32 bits of the canister ID's hash. Block time
+/// strictly increases, including across upgrades that reset the counter, and
+/// the counter tells apart IDs made within one round, so IDs never collide
+/// within a drive. The canister bits keep IDs apart when records move between
+/// drives. IDs made earlier are 64-character SHA-256 hex strings; they stay
+/// valid as-is, since IDs are opaque and the two formats differ in length.
 fn generate_unique_id() -> String {
-    let canister_id = ic_cdk::api::id().to_string();          // Canister's unique ID
-    let current_time = ic_cdk::api::time();                   // Nanoseconds timestamp
-    let caller = ic_cdk::api::caller().to_string();           // Principal of the caller
-    
-    // Increment the counter for every call
+    let canister_hash = Sha256::digest(system_api::canister_id().as_slice());
+    let canister_bits = u32::from_be_bytes([canister_hash[0], canister_hash[1], canister_hash[2], canister_hash[3]]);
+    let current_time = system_api::time();
+
     ID_COUNTER.with(|counter| {
         let current_counter = counter.get();
-        counter.set(current_counter + 1);
-
-        // Create a unique string by combining deterministic inputs
-        let input_string = format!("{}-{}-{}-{}", canister_id, current_time, caller, current_counter);
-
-        // Use SHA256 to hash the input string and produce a compact, unique identifier
-        let mut hasher = Sha256::new();
-        hasher.update(input_string);
-        format!("{:x}", hasher.finalize())
+        counter.set(current_counter.wrapping_add(1));
+        format!("{:016x}{:08x}{:08x}", current_time, current_counter, canister_bits)
     })
 }
 
 
+/// Swaps the leading `old_prefix` of `path` for `new_prefix`. Only the start
+/// is rewritten, so the same text further down the path is left alone.
+fn rebase_path(path: &str, old_prefix: &str, new_prefix: &str) -> String {
+    match path.strip_prefix(old_prefix) {
+        Some(rest) => format!("{}{}", new_prefix, rest),
+        None => path.to_string(),
+    }
+}
+
 fn sanitize_username(username: &str) -> String {
     let re = Regex::new(r#"[/\\@:;'"`]"#).unwrap();
     let sanitized = re.replace_all(username, " ");
@@ -869,124 +1616,323 @@ fn is_valid_username(username: &str) -> bool {
 
 thread_local! {
     static STATE: RefCell<State> = RefCell::new(State::new(
-        ic_cdk::api::caller(),
+        system_api::caller(),
         "Anonymous".to_string()
     ));
-    static ID_COUNTER: Cell<u64> = Cell::new(0);
+    static ID_COUNTER: Cell<u32> = const { Cell::new(0) };
 }
 
 #[ic_cdk::query]
 fn ping() -> String {
-    "pong".to_string()
+    State::ping()
 }
 
+/// Drives installed by the factory receive `(owner, username)`, plus the
+/// initial `members` for team drives; a plain `dfx deploy` passes nothing and
+/// the installing principal becomes the owner.
 #[ic_cdk::init]
-fn init() {
+fn init(owner: Option<Principal>, username: Option<String>, members: Option<Vec<TeamMember>>) {
+    install_state(owner, username, members);
+    cycles::start_cycles_sampling();
+    heartbeat::start_heartbeat();
+    jobs::start_jobs();
+}
+
+/// Sets up the state of a freshly installed drive.
+fn install_state(owner: Option<Principal>, username: Option<String>, members: Option<Vec<TeamMember>>) {
+    let caller = system_api::caller();
+    if owner.unwrap_or(caller) == Principal::anonymous() {
+        ic_cdk::trap("Drive cannot be owned by the anonymous principal");
+    }
     STATE.with(|state| {
-        *state.borrow_mut() = State::new(
-            ic_cdk::api::caller(),
-            "Anonymous".to_string()
+        let mut new_state = State::new(
+            owner.unwrap_or(caller),
+            username.unwrap_or_else(|| "Anonymous".to_string())
         );
+        // Only the factory installs drives on behalf of another owner
+        new_state.factory_id = owner.map(|_| caller);
+        if let Some(members) = members {
+            new_state.team_drive = true;
+            new_state
+                .add_members(members)
+                .unwrap_or_else(|e| ic_cdk::trap(&e.to_string()));
+        }
+        new_state.ensure_roots();
+        *state.borrow_mut() = new_state;
     });
 }
 
+#[ic_cdk::pre_upgrade]
+fn pre_upgrade() {
+    memory::save_heap_state();
+}
+
+/// Takes the same arguments as `init`, plus `fresh_start`. They are only
+/// used by drives with no saved state: drives from before the stable maps
+/// kept everything on the heap and had no `pre_upgrade`, so they come back
+/// with no heap state and empty stable maps. Their upgrade traps, leaving
+/// the old drive running, unless `fresh_start` is set; then the drive starts
+/// over like a new install, and its folders and files are brought back by
+/// passing the `snapshot_hashtables` export taken before the upgrade to
+/// `import_legacy_snapshot`.
+#[ic_cdk::post_upgrade]
+fn post_upgrade(
+    owner: Option<Principal>,
+    username: Option<String>,
+    members: Option<Vec<TeamMember>>,
+    fresh_start: Option<bool>,
+) {
+    if !memory::restore_heap_state() {
+        if !fresh_start.unwrap_or(false) {
+            ic_cdk::trap(
+                "Drive has no saved state to restore. Export it with snapshot_hashtables, then upgrade with \
+                 fresh_start to start over and import the export with import_legacy_snapshot",
+            );
+        }
+        install_state(owner, username, members);
+    }
+    STATE.with(|state| state.borrow_mut().last_upgraded_at_ms = Some(system_api::time() / 1_000_000));
+    // Timers do not survive an upgrade
+    migration::resume_storage_migration();
+    cycles::start_cycles_sampling();
+    heartbeat::start_heartbeat();
+    jobs::start_jobs();
+}
+
+/// Integrations pass an `api_key` with the `Write` scope to create folders as
+/// the drive owner; see `create_api_key`. Clients that may retry pass an
+/// `idempotency_key`; see `idempotency.rs`.
 #[ic_cdk::update]
-fn create_folder(full_folder_path: DriveFullFilePath, storage_location: StorageLocationEnum) -> Result<FolderMetadata, String> {
-    let user_id = ic_cdk::caller();
-    STATE.with(|state| state.borrow_mut().create_folder(full_folder_path, storage_location, user_id))
+fn create_folder(
+    full_folder_path: DriveFullFilePath,
+    storage_location: StorageLocationID,
+    api_key: Option<String>,
+    idempotency_key: Option<String>,
+) -> Result<FolderMetadata, DriveError> {
+    let _profile = profile("create_folder");
+    let user_id = check_update_allowed_with_api_key("create_folder", api_key, ApiScope::Write)?;
+    idempotent(idempotency_key, "create_folder", || {
+        STATE.with(|state| state.borrow_mut().create_folder(full_folder_path, storage_location, user_id))
+    })
 }
 
 #[ic_cdk::update]
-fn upsert_file_to_hash_tables(file_path: String, storage_location: StorageLocationEnum) -> FileUUID {
-    let user_id = ic_cdk::caller();
-    STATE.with(|state| state.borrow_mut().upsert_file_to_hash_tables(file_path, storage_location, user_id))
+fn upsert_file_to_hash_tables(
+    file_path: String,
+    storage_location: StorageLocationID,
+    sha256_checksum: Option<String>,
+    api_key: Option<String>,
+    idempotency_key: Option<String>,
+) -> Result<FileUUID, DriveError> {
+    let _profile = profile("upsert_file_to_hash_tables");
+    let user_id = check_update_allowed_with_api_key("upsert_file_to_hash_tables", api_key, ApiScope::Write)?;
+    idempotent(idempotency_key, "upsert_file_to_hash_tables", || {
+        STATE.with(|state| {
+            state
+                .borrow_mut()
+                .upsert_file_to_hash_tables(file_path, storage_location, user_id, sha256_checksum)
+        })
+    })
 }
 
 
 #[ic_cdk::query]
 fn fetch_files_at_folder_path(config: FetchFilesAtFolderPathConfig) -> FetchFilesResult {
+    require_read_access();
+    let caller = system_api::caller();
     STATE.with(|state| {
-        state.borrow().fetch_files_at_folder_path(config)
+        let state = state.borrow();
+        state.redact_unpaid_page(caller, state.fetch_files_at_folder_path(config))
     })
 }
 
 #[ic_cdk::query]
 fn get_folder_by_id(folder_id: FolderUUID) -> Option<FolderMetadata> {
-    STATE.with(|state| state.borrow().get_folder_by_id(&folder_id).cloned())
+    require_read_access();
+    STATE.with(|state| state.borrow().get_folder_by_id(&folder_id))
 }
 
 
 
 #[ic_cdk::query]
 fn get_file_by_id(file_id: FileUUID) -> Option<FileMetadata> {
-    STATE.with(|state| state.borrow().get_file_by_id(&file_id).cloned())
+    require_read_access();
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let state = state.borrow();
+        state.get_file_by_id(&file_id).map(|file| state.redact_unpaid(caller, file))
+    })
 }
 
 
 #[ic_cdk::query]
 fn get_folder_by_path(path: DriveFullFilePath) -> Option<FolderMetadata> {
-    STATE.with(|state| state.borrow().get_folder_by_path(&path).cloned())
+    require_read_access();
+    STATE.with(|state| state.borrow().get_folder_by_path(&path))
 }
 
 
 #[ic_cdk::query]
 fn get_file_by_path(path: DriveFullFilePath) -> Option<FileMetadata> {
-    STATE.with(|state| state.borrow().get_file_by_path(&path).cloned())
+    require_read_access();
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let state = state.borrow();
+        state.get_file_by_path(&path).map(|file| state.redact_unpaid(caller, file))
+    })
 }
 
 #[ic_cdk::update] 
-fn rename_folder(folder_id: FolderUUID, new_name: String) -> Result<(), String> {
+fn rename_folder(folder_id: FolderUUID, new_name: String, expected_revision: Option<u64>) -> Result<(), DriveError> {
+    let _profile = profile("rename_folder");
+    check_update_allowed("rename_folder")?;
     STATE.with(|state| {
         // Borrow the state mutably and call the method
-        state.borrow_mut().rename_folder(folder_id, new_name)
+        let mut state = state.borrow_mut();
+        state.check_folder_revision(&folder_id, expected_revision)?;
+        state.rename_folder(folder_id, new_name)
+    })
+}
+
+#[ic_cdk::update]
+fn rename_file(file_id: FileUUID, new_name: String, expected_revision: Option<u64>) -> Result<(), DriveError> {
+    let _profile = profile("rename_file");
+    check_update_allowed("rename_file")?;
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.check_file_revision(&file_id, expected_revision)?;
+        state.rename_file(file_id, new_name)
     })
 }
 
+/// Like `rename_file`, but takes the file's full new path, which may be in
+/// another folder. The destination folder must already exist.
 #[ic_cdk::update]
-fn rename_file(file_id: FileUUID, new_name: String) -> Result<(), String> {
+fn rename_or_move_file(
+    file_id: FileUUID,
+    new_path: DriveFullFilePath,
+    expected_revision: Option<u64>,
+) -> Result<(), DriveError> {
+    let _profile = profile("rename_or_move_file");
+    check_update_allowed("rename_or_move_file")?;
     STATE.with(|state| {
-        state.borrow_mut().rename_file(file_id, new_name)
+        let mut state = state.borrow_mut();
+        state.check_file_revision(&file_id, expected_revision)?;
+        state.rename_or_move_file(file_id, new_path)
     })
 }
 
 
 #[ic_cdk::update]
-fn delete_folder(folder_id: FolderUUID) -> Result<(), String> {
-    STATE.with(|state| state.borrow_mut().delete_folder(&folder_id))
+fn delete_folder(
+    folder_id: FolderUUID,
+    expected_revision: Option<u64>,
+    idempotency_key: Option<String>,
+) -> Result<(), DriveError> {
+    let _profile = profile("delete_folder");
+    check_update_allowed("delete_folder")?;
+    idempotent(idempotency_key, "delete_folder", || {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            state.check_folder_revision(&folder_id, expected_revision)?;
+            state.delete_folder(&folder_id)
+        })
+    })
 }
 
 #[ic_cdk::update]
-fn delete_file(file_id: FileUUID) -> Result<(), String> {
-    STATE.with(|state| state.borrow_mut().delete_file(&file_id))
+fn delete_file(
+    file_id: FileUUID,
+    expected_revision: Option<u64>,
+    idempotency_key: Option<String>,
+) -> Result<(), DriveError> {
+    let _profile = profile("delete_file");
+    check_update_allowed("delete_file")?;
+    idempotent(idempotency_key, "delete_file", || {
+        STATE.with(|state| {
+            let mut state = state.borrow_mut();
+            state.check_file_revision(&file_id, expected_revision)?;
+            state.delete_file(&file_id)
+        })
+    })
 }
 
 #[ic_cdk::update]
-fn upsert_cloud_file_with_local_sync(file_id: FileUUID, file_metadata: FileMetadata) -> Result<(FileUUID), String> {
-    STATE.with(|state| state.borrow_mut().upsert_cloud_file_with_local_sync(&file_id, &file_metadata))
+fn upsert_cloud_file_with_local_sync(
+    file_id: FileUUID,
+    file_metadata: FileMetadata,
+    base_version: Option<u32>,
+) -> Result<FileUUID, DriveError> {
+    let _profile = profile("upsert_cloud_file_with_local_sync");
+    check_update_allowed("upsert_cloud_file_with_local_sync")?;
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow_mut().upsert_cloud_file_with_local_sync(&file_id, &file_metadata, base_version, caller))
 }
 
 #[ic_cdk::update]
-fn upsert_cloud_folder_with_local_sync(folder_id: FolderUUID, folder_metadata: FolderMetadata) -> Result<(FolderUUID), String> {
-    STATE.with(|state| state.borrow_mut().upsert_cloud_folder_with_local_sync(&folder_id, &folder_metadata))
+fn upsert_cloud_folder_with_local_sync(
+    folder_id: FolderUUID,
+    folder_metadata: FolderMetadata,
+    expected_revision: Option<u64>,
+) -> Result<FolderUUID, DriveError> {
+    let _profile = profile("upsert_cloud_folder_with_local_sync");
+    check_update_allowed("upsert_cloud_folder_with_local_sync")?;
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.check_folder_revision(&folder_id, expected_revision)?;
+        state.upsert_cloud_folder_with_local_sync(&folder_id, &folder_metadata)
+    })
 }
 
+/// Exports the drive a page at a time: pass each page's `next_cursor` back
+/// as `after` until it is `None`, and merge the pages.
 #[ic_cdk::query]
-fn snapshot_hashtables() -> StateSnapshot {
-    STATE.with(|state| state.borrow().snapshot_hashtables())
+fn snapshot_hashtables(after: Option<SnapshotCursor>, limit: Option<u32>) -> StateSnapshotPage {
+    require_read_access();
+    let limit = limit.unwrap_or(DEFAULT_SNAPSHOT_PAGE_SIZE).clamp(1, MAX_SNAPSHOT_PAGE_SIZE);
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow().snapshot_page(caller, after, limit as usize))
 }
 
 #[ic_cdk::query]
 fn get_canister_balance() -> u64 {
-    let balance = ic_cdk::api::canister_balance();
-    // print canister balance
-    ic_cdk::println!("Canister balance: {}", balance);
-    balance
+    ic_cdk::api::canister_balance()
 }
 
+/// Renames the drive's user. Drives created by the factory first claim the
+/// new name in its global directory, so usernames stay unique; if the factory
+/// rejects the name, the drive keeps its current one.
 #[ic_cdk::update]
-fn update_username(new_username: String) -> Result<(), String> {
+async fn update_username(new_username: String) -> Result<(), DriveError> {
+    let _profile = profile("update_username");
+    check_writable()?;
+    let caller = system_api::caller();
+    let (sanitized_username, factory_id) = STATE.with(|state| {
+        let state = state.borrow();
+        state
+            .check_username_update(caller, &new_username)
+            .map(|name| (name, state.factory_id))
+    })?;
+
+    if let Some(factory_id) = factory_id {
+        let result: Result<(Result<(), String>,), _> =
+            ic_cdk::call(factory_id, "notify_drive_username_changed", (sanitized_username.clone(),)).await;
+        match result {
+            Ok((Ok(()),)) => {}
+            Ok((Err(message),)) => return Err(DriveError::InvalidInput { field: "username".to_string(), reason: message }),
+            Err((code, message)) => {
+                return Err(DriveError::Internal {
+                    message: format!("Failed to notify factory: {:?} {}", code, message),
+                })
+            }
+        }
+    }
+
     STATE.with(|state| {
-        state.borrow_mut().update_username(new_username)
+        let mut state = state.borrow_mut();
+        state.check_username_update(caller, &new_username)?;
+        state.apply_username(&sanitized_username);
+        Ok(())
     })
 }
 
@@ -1007,6 +1953,10 @@ struct FetchFilesAtFolderPathConfig {
     full_folder_path: String,
     limit: u32,
     after: u32,
+    // Hide files whose upload has not completed yet
+    exclude_pending: Option<bool>,
+    // List pinned folders before everything else; see `folder_labels.rs`
+    pinned_first: Option<bool>,
 }
 
 #[derive(Clone, CandidType, Serialize, Deserialize)]
@@ -1030,3 +1980,53 @@ impl FetchFilesResult {
 
 #[cfg(test)]
 mod logic_tests;
+
+// Types used in endpoint signatures must be in scope here for export_candid!
+use access::AccessStats;
+use activity::ActivityPage;
+use api_keys::CreatedApiKey;
+use audit::{AuditLogFilter, AuditLogPage};
+use autocomplete::PathSuggestion;
+use backups::{BackupConfig, BackupInfo, BackupStatus};
+use batch::{DriveOperation, DriveOperationResult, ResourceId};
+use comments::CommentPage;
+use compaction::CompactionReport;
+use conflicts::{ConflictDetails, ConflictSide};
+use consent_messages::{Icrc10SupportedStandard, Icrc21ConsentMessageRequest, Icrc21ConsentMessageResponse};
+use credential_vault::{CredentialInfo, StorageCredentials};
+use cycles::{CyclesBurnEstimate, WalletReceiveResult};
+use dedup::DuplicateGroup;
+use documents::DocumentDeltaPage;
+use events::EventBatch;
+use export::ManifestPage;
+use file_updates::UpdateFileMetadataRequest;
+use fsck::{RepairReport, StateIntegrityReport};
+use import::{DuplicatePolicy, ImportEntry, ImportOutcome};
+use ic_cdk::api::management_canister::http_request::{HttpResponse, TransformArgs};
+use integrity::IntegrityCheck;
+use invitations::{CreatedInvitation, Invitee};
+use jobs::{JobInfo, JobOutcome};
+use legal_holds::LegalHoldInfo;
+use logs::{LogFilter, LogPage};
+use metrics::DriveMetrics;
+use migration::StorageMigrationInfo;
+use notifications::NotificationPage;
+use pinning::PermanentLink;
+use presigning::PresignedUrl;
+use profiling::EndpointPerformance;
+use publishing::PublishedFolderInfo;
+use quota::StorageUsageReport;
+use rate_limit::RateLimitConfig;
+use read_only::ReadOnlyStatus;
+use rest_api::{GatewayRequest, GatewayResponse};
+use restore::{ImportMode, ImportReport, LegacyStateSnapshot};
+use rollups::FolderSize;
+use sharing::{ShareGrantPage, ShareRole, SharedWithMePage};
+use signed_urls::SignedDownloadUrl;
+use starred::StarredPage;
+use storage::{LocationStats, StorageKind};
+use storage_report::StorageReport;
+use token_gates::{GatePass, NftStandard};
+use webhooks::{WebhookEvent, WebhookInfo};
+
+ic_cdk::export_candid!();
@@ -0,0 +1,202 @@
+// link_checks.rs
+//
+// File contents live in external storage such as Storj, linked by `raw_url`.
+// Objects can disappear there without the drive noticing, so links are
+// checked with an HTTPS HEAD outcall: on request, and for a small sample of
+// files on every `LinkSampling` job run. A link found dead notifies the
+// file's owner, so they hear about it before opening the file.
+
+use candid::CandidType;
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod, HttpResponse, TransformArgs,
+    TransformContext,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::errors::DriveError;
+use crate::members::check_read_access;
+use crate::notifications::NotificationKind;
+use crate::profiling::profile;
+use crate::rate_limit::check_update_allowed;
+use crate::system_api;
+use crate::upload_status::UploadStatus;
+use crate::{FileUUID, State, STATE};
+
+const LINK_CHECK_OUTCALL_CYCLES: u128 = 1_000_000_000;
+// Only headers are kept, so the response is small
+const LINK_CHECK_MAX_RESPONSE_BYTES: u64 = 4_096;
+const LINKS_SAMPLED_PER_RUN: usize = 10;
+const KEPT_HEADERS: [&str; 2] = ["content-length", "content-type"];
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct LinkCheck {
+    checked_at_ms: u64,
+    raw_url: String,
+    http_status: Option<u16>,
+    content_length: Option<u64>,
+    content_type: Option<String>,
+    // The storage reports the object gone
+    dead: bool,
+    // Set when the object's size differs from the recorded file size
+    size_mismatch: bool,
+    // Why no response was received, e.g. a DNS failure
+    error: Option<String>,
+}
+
+impl State {
+    fn checkable_raw_url(&self, file_id: &FileUUID) -> Result<String, DriveError> {
+        let file = self
+            .file_uuid_to_metadata
+            .get(file_id)
+            .filter(|file| !file.deleted)
+            .ok_or_else(|| DriveError::file_not_found(file_id))?;
+        if !file.raw_url.starts_with("https://") {
+            return Err(DriveError::InvalidState {
+                reason: "Only uploaded files with an https raw_url can be checked".to_string(),
+            });
+        }
+        Ok(file.raw_url)
+    }
+
+    /// Uploaded files whose links were checked longest ago, never-checked
+    /// ones first.
+    fn link_check_sample(&self) -> Vec<FileUUID> {
+        let mut candidates: Vec<(Option<u64>, FileUUID)> = self
+            .file_uuid_to_metadata
+            .values()
+            .filter(|file| {
+                !file.deleted
+                    && file.next_version.is_none()
+                    && file.upload_status == UploadStatus::Uploaded
+                    && file.raw_url.starts_with("https://")
+            })
+            .map(|file| (self.link_checks.get(&file.id).map(|check| check.checked_at_ms), file.id))
+            .collect();
+        candidates.sort();
+        candidates.into_iter().take(LINKS_SAMPLED_PER_RUN).map(|(_, id)| id).collect()
+    }
+
+    fn record_link_check(&mut self, file_id: &FileUUID, check: LinkCheck) {
+        let was_dead = self.link_checks.get(file_id).is_some_and(|previous| previous.dead);
+        if check.dead && !was_dead {
+            if let Some(file) = self.file_uuid_to_metadata.get(file_id) {
+                self.notify(file.owner, system_api::canister_id(), NotificationKind::DeadLink, file_id);
+            }
+        }
+        self.link_checks.insert(file_id.clone(), check);
+    }
+
+    fn dead_links(&self) -> Vec<(FileUUID, LinkCheck)> {
+        self.link_checks
+            .iter()
+            .filter(|(_, check)| check.dead)
+            .map(|(file_id, check)| (file_id.clone(), check.clone()))
+            .collect()
+    }
+}
+
+fn header(response: &HttpResponse, name: &str) -> Option<String> {
+    response
+        .headers
+        .iter()
+        .find(|header| header.name.eq_ignore_ascii_case(name))
+        .map(|header| header.value.clone())
+}
+
+async fn head_raw_url(raw_url: String, file_size: u64) -> LinkCheck {
+    let request = CanisterHttpRequestArgument {
+        url: raw_url.clone(),
+        method: HttpMethod::HEAD,
+        body: None,
+        max_response_bytes: Some(LINK_CHECK_MAX_RESPONSE_BYTES),
+        transform: Some(TransformContext::from_name("transform_link_check_response".to_string(), vec![])),
+        headers: Vec::new(),
+    };
+    let checked_at_ms = system_api::time() / 1_000_000;
+    match http_request(request, LINK_CHECK_OUTCALL_CYCLES).await {
+        Ok((response,)) => {
+            let http_status: Option<u16> = response.status.0.clone().try_into().ok();
+            let content_length = header(&response, "content-length").and_then(|length| length.parse().ok());
+            LinkCheck {
+                checked_at_ms,
+                raw_url,
+                http_status,
+                content_length,
+                content_type: header(&response, "content-type"),
+                dead: matches!(http_status, Some(404 | 410)),
+                size_mismatch: content_length.is_some_and(|length| length != file_size),
+                error: None,
+            }
+        }
+        Err((code, message)) => LinkCheck {
+            checked_at_ms,
+            raw_url,
+            http_status: None,
+            content_length: None,
+            content_type: None,
+            dead: false,
+            size_mismatch: false,
+            error: Some(format!("{:?} {}", code, message)),
+        },
+    }
+}
+
+async fn check_link(file_id: &FileUUID) -> Result<LinkCheck, DriveError> {
+    let (raw_url, file_size) = STATE.with(|state| {
+        let state = state.borrow();
+        let raw_url = state.checkable_raw_url(file_id)?;
+        let file_size = state.file_uuid_to_metadata.get(file_id).map_or(0, |file| file.file_size);
+        Ok::<_, DriveError>((raw_url, file_size))
+    })?;
+    let check = head_raw_url(raw_url, file_size).await;
+    STATE.with(|state| state.borrow_mut().record_link_check(file_id, check.clone()));
+    Ok(check)
+}
+
+/// Runs the `LinkSampling` job: checks a few of the links checked longest ago.
+pub(crate) async fn sample_links() -> Result<String, DriveError> {
+    let sample = STATE.with(|state| state.borrow().link_check_sample());
+    let mut dead = 0;
+    for file_id in &sample {
+        if check_link(file_id).await.is_ok_and(|check| check.dead) {
+            dead += 1;
+        }
+    }
+    Ok(format!("Checked {} links, {} dead", sample.len(), dead))
+}
+
+/// Sends a HEAD request to the file's `raw_url` and records the result.
+#[ic_cdk::update]
+async fn validate_raw_url(file_id: FileUUID) -> Result<LinkCheck, DriveError> {
+    let _profile = profile("validate_raw_url");
+    check_update_allowed("validate_raw_url")?;
+    check_link(&file_id).await
+}
+
+/// The last recorded check of the file's link, if any.
+#[ic_cdk::query]
+fn get_link_check(file_id: FileUUID) -> Result<Option<LinkCheck>, DriveError> {
+    check_read_access()?;
+    STATE.with(|state| Ok(state.borrow().link_checks.get(&file_id).cloned()))
+}
+
+/// Files whose last check found the object gone.
+#[ic_cdk::query]
+fn list_dead_links() -> Result<Vec<(FileUUID, LinkCheck)>, DriveError> {
+    check_read_access()?;
+    STATE.with(|state| Ok(state.borrow().dead_links()))
+}
+
+/// Keeps only the status and the headers the check looks at, so replicas
+/// agree on the response.
+#[ic_cdk::query]
+fn transform_link_check_response(args: TransformArgs) -> HttpResponse {
+    let headers = args
+        .response
+        .headers
+        .into_iter()
+        .filter(|header| KEPT_HEADERS.iter().any(|name| header.name.eq_ignore_ascii_case(name)))
+        .map(|header| HttpHeader { name: header.name.to_ascii_lowercase(), value: header.value })
+        .collect();
+    HttpResponse { status: args.response.status, headers, body: Vec::new() }
+}
@@ -0,0 +1,135 @@
+// logs.rs
+//
+// Operational log kept in stable memory, so failures in timers and outcalls
+// can be looked into after the fact without access to the replica's logs.
+// Every entry is still printed to the replica log as well.
+
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::Storable;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+use crate::errors::DriveError;
+use crate::system_api;
+use crate::{State, STATE};
+
+/// Oldest entries are dropped once the log holds this many.
+const MAX_LOG_ENTRIES: u64 = 5_000;
+const MAX_LOG_PAGE_SIZE: u32 = 500;
+const MAX_MESSAGE_CHARS: usize = 1_000;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct LogEntry {
+    pub seq: u64,
+    pub timestamp_ms: u64,
+    pub level: LogLevel,
+    pub message: String,
+    pub caller: Principal,
+    // Shared by every entry written while handling one message. Messages run
+    // in the same round share it too, so pair it with `caller`.
+    pub correlation_id: String,
+}
+
+impl Storable for LogEntry {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default)]
+pub(crate) struct LogFilter {
+    min_level: Option<LogLevel>,
+    correlation_id: Option<String>,
+    caller: Option<Principal>,
+    since_ms: Option<u64>,
+}
+
+impl LogFilter {
+    fn matches(&self, entry: &LogEntry) -> bool {
+        self.min_level.is_none_or(|level| entry.level >= level)
+            && self.correlation_id.as_ref().is_none_or(|id| *id == entry.correlation_id)
+            && self.caller.is_none_or(|caller| caller == entry.caller)
+            && self.since_ms.is_none_or(|since| entry.timestamp_ms >= since)
+    }
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct LogPage {
+    entries: Vec<LogEntry>,
+    // Pass as `before` to fetch the next (older) page
+    next_cursor: Option<u64>,
+}
+
+impl State {
+    /// Appends an entry for the message being handled. Writes made while
+    /// answering a query are discarded with the rest of its state changes.
+    pub(crate) fn log(&mut self, level: LogLevel, message: String) {
+        let now_ns = system_api::time();
+        let message: String = message.chars().take(MAX_MESSAGE_CHARS).collect();
+        ic_cdk::println!("[{:?}] {}", level, message);
+        let seq = self.logs.last_key_value().map_or(0, |(seq, _)| seq + 1);
+        let entry = LogEntry {
+            seq,
+            timestamp_ms: now_ns / 1_000_000,
+            level,
+            message,
+            caller: system_api::caller(),
+            correlation_id: format!("{:016x}", now_ns),
+        };
+        self.logs.insert(seq, entry);
+        while self.logs.len() > MAX_LOG_ENTRIES {
+            self.logs.pop_first();
+        }
+    }
+
+    fn get_logs(&self, filter: &LogFilter, before: Option<u64>, limit: u32) -> LogPage {
+        let limit = limit.min(MAX_LOG_PAGE_SIZE) as usize;
+        let upper = before.unwrap_or(u64::MAX);
+        let mut matching = self
+            .logs
+            .range(..upper)
+            .rev()
+            .map(|(_, entry)| entry)
+            .filter(|entry| filter.matches(entry));
+        let entries: Vec<LogEntry> = matching.by_ref().take(limit).collect();
+        let next_cursor = match (entries.last(), matching.next()) {
+            (Some(last), Some(_)) => Some(last.seq),
+            _ => None,
+        };
+        LogPage { entries, next_cursor }
+    }
+}
+
+/// `State::log` for code that does not hold the state, such as timers and
+/// the continuations of outcalls.
+pub(crate) fn log(level: LogLevel, message: String) {
+    STATE.with(|state| state.borrow_mut().log(level, message));
+}
+
+/// Log entries, newest first.
+#[ic_cdk::query]
+fn get_logs(filter: Option<LogFilter>, before: Option<u64>, limit: u32) -> Result<LogPage, DriveError> {
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let state = state.borrow();
+        if state.owner != caller {
+            return Err(DriveError::unauthorized("Only the owner can read the logs"));
+        }
+        Ok(state.get_logs(&filter.unwrap_or_default(), before, limit))
+    })
+}
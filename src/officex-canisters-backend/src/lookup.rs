@@ -0,0 +1,46 @@
+// lookup.rs
+
+use crate::errors::DriveError;
+use crate::members::check_read_access;
+use crate::system_api;
+use crate::{FileMetadata, FileUUID, FolderMetadata, FolderUUID, STATE};
+
+const MAX_LOOKUP_IDS: usize = 500;
+
+fn check_lookup_size(count: usize) -> Result<(), DriveError> {
+    if count > MAX_LOOKUP_IDS {
+        return Err(DriveError::InvalidInput {
+            field: "ids".to_string(),
+            reason: format!("At most {} IDs per call", MAX_LOOKUP_IDS),
+        });
+    }
+    Ok(())
+}
+
+/// `get_file_by_id` for up to 500 files at once, e.g. to resolve a page of
+/// the change feed. Results follow the order of `ids`, with `None` for
+/// unknown IDs; deleted files come back with `deleted` set.
+#[ic_cdk::query]
+fn get_files_by_ids(ids: Vec<FileUUID>) -> Result<Vec<Option<FileMetadata>>, DriveError> {
+    check_read_access()?;
+    check_lookup_size(ids.len())?;
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let state = state.borrow();
+        Ok(ids
+            .iter()
+            .map(|id| state.file_uuid_to_metadata.get(id).map(|file| state.redact_unpaid(caller, file)))
+            .collect())
+    })
+}
+
+/// `get_folder_by_id` for up to 500 folders at once, like `get_files_by_ids`.
+#[ic_cdk::query]
+fn get_folders_by_ids(ids: Vec<FolderUUID>) -> Result<Vec<Option<FolderMetadata>>, DriveError> {
+    check_read_access()?;
+    check_lookup_size(ids.len())?;
+    STATE.with(|state| {
+        let state = state.borrow();
+        Ok(ids.iter().map(|id| state.folder_uuid_to_metadata.get(id)).collect())
+    })
+}
@@ -0,0 +1,295 @@
+// members.rs
+
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+use crate::audit::AuditAction;
+use crate::errors::DriveError;
+use crate::profiling::profile;
+use crate::read_only::check_writable;
+use crate::system_api;
+use crate::{State, STATE};
+
+const MAX_MEMBERS: usize = 500;
+
+/// Update methods that check the caller's access to the file or folder they
+/// act on, so share grantees can call them without being members.
+const SHARED_ITEM_METHODS: &[&str] = &[
+    "append_document_delta",
+    "compact_document",
+    "delete_property",
+    "get_encrypted_file_key",
+    "heartbeat_session",
+    "issue_signed_url",
+    "join_editing_session",
+    "leave_editing_session",
+    "list_mounted_folder",
+    "mark_upload_complete",
+    "mark_upload_failed",
+    "pin_file",
+    "presign_download",
+    "set_property",
+    "update_file_metadata",
+];
+
+/// Update methods that members with the `Viewer` role may call; every other
+/// data endpoint needs at least `Editor`.
+const VIEWER_METHODS: &[&str] = &[
+    "add_comment",
+    "commit_sync_cursor",
+    "delete_comment",
+    "get_encrypted_file_key",
+    "heartbeat_session",
+    "issue_signed_url",
+    "join_editing_session",
+    "leave_editing_session",
+    "list_mounted_folder",
+    "record_access",
+    "register_device",
+];
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum MemberRole {
+    Viewer,
+    Editor,
+    // Manages members and drive settings like the owner, but cannot transfer
+    // ownership or rename the drive
+    Admin,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct TeamMember {
+    pub principal: Principal,
+    pub role: MemberRole,
+}
+
+impl State {
+    /// The owner counts as an admin of every drive.
+    pub(crate) fn member_role(&self, principal: Principal) -> Option<MemberRole> {
+        if principal == self.owner {
+            return Some(MemberRole::Admin);
+        }
+        self.members.get(&principal).copied()
+    }
+
+    pub(crate) fn is_drive_admin(&self, principal: Principal) -> bool {
+        self.member_role(principal) == Some(MemberRole::Admin)
+    }
+
+    fn has_member_role(&self, principal: Principal, role: MemberRole) -> bool {
+        self.member_role(principal).is_some_and(|granted| granted >= role)
+    }
+
+    /// Only members may call data update methods, on personal and team drives
+    /// alike, and viewers (see `add_reader`) only the viewer methods. Others
+    /// get as far as the methods that check access per item.
+    pub(crate) fn check_member_access(&self, caller: Principal, method: &str) -> Result<(), DriveError> {
+        self.check_anonymous_update(caller, method)?;
+        if SHARED_ITEM_METHODS.contains(&method) {
+            return Ok(());
+        }
+        let required = if VIEWER_METHODS.contains(&method) {
+            MemberRole::Viewer
+        } else {
+            MemberRole::Editor
+        };
+        if !self.has_member_role(caller, required) {
+            return Err(DriveError::unauthorized(&format!("Calling {} needs the {:?} role", method, required)));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn can_read_drive(&self, caller: Principal) -> bool {
+        self.is_public_drive() || self.has_member_role(caller, MemberRole::Viewer)
+    }
+
+    pub(crate) fn can_edit_drive(&self, caller: Principal) -> bool {
+        self.has_member_role(caller, MemberRole::Editor)
+    }
+
+    pub(crate) fn add_members(&mut self, members: Vec<TeamMember>) -> Result<(), DriveError> {
+        for member in &members {
+            if member.principal == Principal::anonymous() || member.principal == self.owner {
+                return Err(DriveError::InvalidInput {
+                    field: "principal".to_string(),
+                    reason: "The owner and the anonymous principal cannot be members".to_string(),
+                });
+            }
+        }
+        let added = members
+            .iter()
+            .filter(|member| !self.members.contains_key(&member.principal))
+            .count();
+        if self.members.len() + added > MAX_MEMBERS {
+            return Err(DriveError::InvalidState {
+                reason: format!("A drive can have at most {} members", MAX_MEMBERS),
+            });
+        }
+        for member in members {
+            self.members.insert(member.principal, member.role);
+            self.record_audit(AuditAction::SetMemberRole, &member.principal.to_text(), None, None);
+        }
+        Ok(())
+    }
+
+    fn set_member(&mut self, caller: Principal, member: TeamMember) -> Result<(), DriveError> {
+        if !self.is_drive_admin(caller) {
+            return Err(DriveError::unauthorized("Only a drive admin can manage members"));
+        }
+        if !self.members.contains_key(&member.principal) {
+            return Err(DriveError::InvalidState {
+                reason: "Not a member; new members join through invite_member".to_string(),
+            });
+        }
+        self.add_members(vec![member])
+    }
+
+    fn remove_member(&mut self, caller: Principal, principal: Principal) -> Result<(), DriveError> {
+        // Members may always leave on their own
+        if caller != principal && !self.is_drive_admin(caller) {
+            return Err(DriveError::unauthorized("Only a drive admin can remove members"));
+        }
+        if self.members.remove(&principal).is_none() {
+            return Err(DriveError::NotFound {
+                resource: "member".to_string(),
+                id: principal.to_text(),
+            });
+        }
+        self.record_audit(AuditAction::RemoveMember, &principal.to_text(), None, None);
+        Ok(())
+    }
+
+    fn add_reader(&mut self, principal: Principal) -> Result<(), DriveError> {
+        match self.members.get(&principal) {
+            Some(MemberRole::Viewer) => Ok(()),
+            Some(role) => Err(DriveError::InvalidState {
+                reason: format!("Already a member with the {:?} role", role),
+            }),
+            None => self.add_members(vec![TeamMember { principal, role: MemberRole::Viewer }]),
+        }
+    }
+
+    fn remove_reader(&mut self, principal: Principal) -> Result<(), DriveError> {
+        if self.members.get(&principal) != Some(&MemberRole::Viewer) {
+            return Err(DriveError::NotFound {
+                resource: "reader".to_string(),
+                id: principal.to_text(),
+            });
+        }
+        let owner = self.owner;
+        self.remove_member(owner, principal)
+    }
+}
+
+/// Checks the caller's role for `method`. Called from `check_update_allowed`,
+/// which every data update endpoint goes through.
+pub(crate) fn check_member_access(caller: Principal, method: &str) -> Result<(), DriveError> {
+    STATE.with(|state| state.borrow().check_member_access(caller, method))
+}
+
+/// Fails unless the caller may read this drive, i.e. is a member or the
+/// drive is public.
+pub(crate) fn check_read_access() -> Result<(), DriveError> {
+    let caller = system_api::caller();
+    if STATE.with(|state| state.borrow().can_read_drive(caller)) {
+        Ok(())
+    } else {
+        Err(DriveError::unauthorized("Only members can read this drive"))
+    }
+}
+
+/// `check_read_access` for queries without an error in their return type;
+/// rejects the call instead.
+pub(crate) fn require_read_access() {
+    if let Err(e) = check_read_access() {
+        ic_cdk::trap(&e.to_string());
+    }
+}
+
+/// Changes an existing member's role.
+#[ic_cdk::update]
+fn set_member(member: TeamMember) -> Result<(), DriveError> {
+    let _profile = profile("set_member");
+    check_writable()?;
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow_mut().set_member(caller, member))
+}
+
+#[ic_cdk::update]
+fn remove_member(principal: Principal) -> Result<(), DriveError> {
+    let _profile = profile("remove_member");
+    check_writable()?;
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow_mut().remove_member(caller, principal))
+}
+
+/// Lets `principal` read the whole drive without changing anything, on
+/// personal and team drives alike. Readers are members with the `Viewer`
+/// role and also show up in `list_members`.
+#[ic_cdk::update]
+fn add_reader(principal: Principal) -> Result<(), DriveError> {
+    let _profile = profile("add_reader");
+    check_writable()?;
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if caller != state.owner {
+            return Err(DriveError::unauthorized("Only the owner can add readers"));
+        }
+        state.add_reader(principal)
+    })
+}
+
+#[ic_cdk::update]
+fn remove_reader(principal: Principal) -> Result<(), DriveError> {
+    let _profile = profile("remove_reader");
+    check_writable()?;
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if caller != state.owner {
+            return Err(DriveError::unauthorized("Only the owner can remove readers"));
+        }
+        state.remove_reader(principal)
+    })
+}
+
+#[ic_cdk::query]
+fn list_readers() -> Result<Vec<Principal>, DriveError> {
+    check_read_access()?;
+    STATE.with(|state| {
+        Ok(state
+            .borrow()
+            .members
+            .iter()
+            .filter(|(_, role)| **role == MemberRole::Viewer)
+            .map(|(principal, _)| *principal)
+            .collect())
+    })
+}
+
+/// Members other than the owner, who is always an admin.
+#[ic_cdk::query]
+fn list_members() -> Result<Vec<TeamMember>, DriveError> {
+    check_read_access()?;
+    STATE.with(|state| {
+        Ok(state
+            .borrow()
+            .members
+            .iter()
+            .map(|(principal, role)| TeamMember { principal: *principal, role: *role })
+            .collect())
+    })
+}
+
+/// The caller's role on this drive, if any.
+#[ic_cdk::query]
+fn get_my_role() -> Option<MemberRole> {
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow().member_role(caller))
+}
+
+#[ic_cdk::query]
+fn is_team_drive() -> bool {
+    STATE.with(|state| state.borrow().team_drive)
+}
@@ -0,0 +1,215 @@
+// memory.rs
+
+use candid::{CandidType, Decode, Encode};
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{DefaultMemoryImpl, StableBTreeMap, StableCell, Storable};
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use crate::audit::AuditEntry;
+use crate::children::ChildKey;
+use crate::comments::{Comment, CommentKey};
+use crate::dedup::ChecksumKey;
+use crate::documents::{DocumentDelta, DocumentDeltaKey, DocumentSnapshot};
+use crate::logs::LogEntry;
+use crate::recent::RecentFileKey;
+use crate::schema;
+use crate::storage_report::FileSizeKey;
+use crate::{DriveFullFilePath, FileMetadata, FileUUID, FolderMetadata, FolderUUID, State, STATE};
+
+pub type Memory = VirtualMemory<DefaultMemoryImpl>;
+pub type StableMap<K, V> = StableBTreeMap<K, V, Memory>;
+
+const FOLDERS_MEMORY_ID: MemoryId = MemoryId::new(0);
+const FILES_MEMORY_ID: MemoryId = MemoryId::new(1);
+const FOLDER_PATHS_MEMORY_ID: MemoryId = MemoryId::new(2);
+const FILE_PATHS_MEMORY_ID: MemoryId = MemoryId::new(3);
+const HEAP_STATE_MEMORY_ID: MemoryId = MemoryId::new(4);
+const CHILD_INDEX_MEMORY_ID: MemoryId = MemoryId::new(5);
+const AUDIT_LOG_MEMORY_ID: MemoryId = MemoryId::new(6);
+const STARRED_MEMORY_ID: MemoryId = MemoryId::new(7);
+const RECENT_FILES_MEMORY_ID: MemoryId = MemoryId::new(8);
+const COMMENTS_MEMORY_ID: MemoryId = MemoryId::new(9);
+const DOCUMENT_DELTAS_MEMORY_ID: MemoryId = MemoryId::new(10);
+const DOCUMENT_SNAPSHOTS_MEMORY_ID: MemoryId = MemoryId::new(11);
+const LOGS_MEMORY_ID: MemoryId = MemoryId::new(12);
+const FILE_SIZES_MEMORY_ID: MemoryId = MemoryId::new(13);
+const FILE_CHECKSUMS_MEMORY_ID: MemoryId = MemoryId::new(14);
+
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+
+    // Serialized heap-only part of State, written in pre_upgrade
+    static HEAP_STATE: RefCell<StableCell<Vec<u8>, Memory>> = RefCell::new(
+        StableCell::init(get_memory(HEAP_STATE_MEMORY_ID), Vec::new())
+            .expect("Failed to initialize heap state cell")
+    );
+}
+
+fn get_memory(id: MemoryId) -> Memory {
+    MEMORY_MANAGER.with(|m| m.borrow().get(id))
+}
+
+// The map constructors below open existing maps, so they also serve to
+// reattach State to its stable data after an upgrade.
+
+pub fn folder_metadata_map() -> StableMap<FolderUUID, FolderMetadata> {
+    StableBTreeMap::init(get_memory(FOLDERS_MEMORY_ID))
+}
+
+pub fn file_metadata_map() -> StableMap<FileUUID, FileMetadata> {
+    StableBTreeMap::init(get_memory(FILES_MEMORY_ID))
+}
+
+pub fn folder_path_map() -> StableMap<DriveFullFilePath, FolderUUID> {
+    StableBTreeMap::init(get_memory(FOLDER_PATHS_MEMORY_ID))
+}
+
+pub fn file_path_map() -> StableMap<DriveFullFilePath, FileUUID> {
+    StableBTreeMap::init(get_memory(FILE_PATHS_MEMORY_ID))
+}
+
+pub fn child_index_map() -> StableMap<ChildKey, ()> {
+    StableBTreeMap::init(get_memory(CHILD_INDEX_MEMORY_ID))
+}
+
+pub fn audit_log_map() -> StableMap<u64, AuditEntry> {
+    StableBTreeMap::init(get_memory(AUDIT_LOG_MEMORY_ID))
+}
+
+pub fn starred_map() -> StableMap<String, u64> {
+    StableBTreeMap::init(get_memory(STARRED_MEMORY_ID))
+}
+
+pub fn recent_files_map() -> StableMap<RecentFileKey, ()> {
+    StableBTreeMap::init(get_memory(RECENT_FILES_MEMORY_ID))
+}
+
+pub fn comments_map() -> StableMap<CommentKey, Comment> {
+    StableBTreeMap::init(get_memory(COMMENTS_MEMORY_ID))
+}
+
+pub fn document_deltas_map() -> StableMap<DocumentDeltaKey, DocumentDelta> {
+    StableBTreeMap::init(get_memory(DOCUMENT_DELTAS_MEMORY_ID))
+}
+
+pub fn document_snapshots_map() -> StableMap<FileUUID, DocumentSnapshot> {
+    StableBTreeMap::init(get_memory(DOCUMENT_SNAPSHOTS_MEMORY_ID))
+}
+
+pub fn logs_map() -> StableMap<u64, LogEntry> {
+    StableBTreeMap::init(get_memory(LOGS_MEMORY_ID))
+}
+
+pub fn file_sizes_map() -> StableMap<FileSizeKey, ()> {
+    StableBTreeMap::init(get_memory(FILE_SIZES_MEMORY_ID))
+}
+
+pub fn file_checksums_map() -> StableMap<ChecksumKey, ()> {
+    StableBTreeMap::init(get_memory(FILE_CHECKSUMS_MEMORY_ID))
+}
+
+// Records start with a marker no Candid message starts with (they all begin
+// with `DIDL`) and their version, so a later layout Candid cannot decode can
+// be told apart from this one; see `schema.rs`.
+const RECORD_MARKER: u8 = 0xFF;
+const RECORD_VERSION: u8 = 1;
+
+fn encode_record<T: CandidType>(record: &T) -> Vec<u8> {
+    let mut bytes = vec![RECORD_MARKER, RECORD_VERSION];
+    bytes.extend(Encode!(record).unwrap());
+    bytes
+}
+
+/// The Candid body of a record. Traps on records written by newer code.
+fn record_body(bytes: &[u8]) -> &[u8] {
+    match bytes {
+        [RECORD_MARKER, RECORD_VERSION, body @ ..] => body,
+        [RECORD_MARKER, version, ..] => ic_cdk::trap(&format!(
+            "Stored record has version {}, newer than this build's {}",
+            version, RECORD_VERSION
+        )),
+        _ => ic_cdk::trap("Stored record has no version"),
+    }
+}
+
+// Metadata records carry tag lists of arbitrary length, so they are stored unbounded.
+impl Storable for FolderMetadata {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(encode_record(self))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(record_body(&bytes), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl Storable for FileMetadata {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(encode_record(self))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(record_body(&bytes), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+/// In-place edits for values held in a stable map, which only hands out copies.
+pub trait StableMapExt<K, V> {
+    /// Applies `f` to the value under `key` and writes it back. Returns `None`
+    /// when the key is absent.
+    fn update<R>(&mut self, key: &K, f: impl FnOnce(&mut V) -> R) -> Option<R>;
+}
+
+impl<K: Storable + Ord + Clone, V: Storable> StableMapExt<K, V> for StableMap<K, V> {
+    fn update<R>(&mut self, key: &K, f: impl FnOnce(&mut V) -> R) -> Option<R> {
+        let mut value = self.get(key)?;
+        let result = f(&mut value);
+        self.insert(key.clone(), value);
+        Some(result)
+    }
+}
+
+/// Writes the heap fields of State to stable memory. The metadata maps
+/// already live there and are not copied.
+pub fn save_heap_state() {
+    let bytes = STATE.with(|state| serde_cbor::to_vec(&*state.borrow()))
+        .expect("Failed to serialize drive state");
+    HEAP_STATE.with(|cell| cell.borrow_mut().set(bytes))
+        .expect("Failed to write drive state to stable memory");
+}
+
+/// Decodes heap state saved by `save_heap_state`, migrating it to the
+/// current schema. Returns `None` when nothing was saved.
+pub(crate) fn decode_heap_state(bytes: &[u8]) -> Option<State> {
+    if bytes.is_empty() {
+        return None;
+    }
+    let stored: serde_cbor::Value = serde_cbor::from_slice(bytes)
+        .expect("Failed to deserialize drive state");
+    let mut restored: State = serde_cbor::value::from_value(schema::migrate(stored))
+        .expect("Failed to decode migrated drive state");
+    restored.restore_case_folding();
+    Some(restored)
+}
+
+/// Restores the heap fields of State. Returns false when there is no saved
+/// state: drives installed before upgrades saved it had no `pre_upgrade`,
+/// so their heap did not survive the upgrade.
+pub fn restore_heap_state() -> bool {
+    let bytes = HEAP_STATE.with(|cell| cell.borrow().get().clone());
+    let Some(restored) = decode_heap_state(&bytes) else {
+        return false;
+    };
+    STATE.with(|state| *state.borrow_mut() = restored);
+    // Free the copy held by the cell; it is rewritten on the next upgrade
+    HEAP_STATE.with(|cell| cell.borrow_mut().set(Vec::new()))
+        .expect("Failed to clear stored drive state");
+    true
+}
@@ -0,0 +1,79 @@
+// metrics.rs
+
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::DriveError;
+use crate::system_api;
+use crate::{State, STATE};
+
+const WASM_PAGE_BYTES: u64 = 64 * 1024;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct DriveMetrics {
+    heap_memory_bytes: u64,
+    stable_memory_bytes: u64,
+    file_count: u64,
+    folder_count: u64,
+    deleted_file_count: u64,
+    deleted_folder_count: u64,
+    cycle_balance: u128,
+    last_upgraded_at_ms: Option<u64>,
+    schema_version: u32,
+    // Calls per update endpoint since install, including rejected ones
+    call_counts: Vec<(String, u64)>,
+}
+
+#[cfg(target_arch = "wasm32")]
+fn heap_memory_bytes() -> u64 {
+    core::arch::wasm32::memory_size(0) as u64 * WASM_PAGE_BYTES
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn heap_memory_bytes() -> u64 {
+    0
+}
+
+impl State {
+    pub(crate) fn count_call(&mut self, method: &str) {
+        *self.call_counts.entry(method.to_string()).or_insert(0) += 1;
+    }
+
+    fn metrics(&self) -> DriveMetrics {
+        DriveMetrics {
+            heap_memory_bytes: heap_memory_bytes(),
+            stable_memory_bytes: ic_cdk::api::stable::stable64_size() * WASM_PAGE_BYTES,
+            file_count: self.file_uuid_to_metadata.len(),
+            folder_count: self.folder_uuid_to_metadata.len(),
+            deleted_file_count: self.file_uuid_to_metadata.iter().filter(|(_, file)| file.deleted).count() as u64,
+            deleted_folder_count: self
+                .folder_uuid_to_metadata
+                .iter()
+                .filter(|(_, folder)| folder.deleted)
+                .count() as u64,
+            cycle_balance: ic_cdk::api::canister_balance128(),
+            last_upgraded_at_ms: self.last_upgraded_at_ms,
+            schema_version: self.schema_version,
+            call_counts: self.call_counts.iter().map(|(method, count)| (method.clone(), *count)).collect(),
+        }
+    }
+}
+
+/// Counts a call to `method` for `get_metrics`, whether or not it goes ahead.
+pub(crate) fn count_call(method: &str) {
+    STATE.with(|state| state.borrow_mut().count_call(method));
+}
+
+/// Point-in-time health figures for the admin dashboard. Available to the
+/// owner and to controllers such as the factory.
+#[ic_cdk::query]
+fn get_metrics() -> Result<DriveMetrics, DriveError> {
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let state = state.borrow();
+        if !state.is_drive_admin(caller) && !ic_cdk::api::is_controller(&caller) {
+            return Err(DriveError::unauthorized("Only a drive admin or a controller can read metrics"));
+        }
+        Ok(state.metrics())
+    })
+}
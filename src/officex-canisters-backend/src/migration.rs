@@ -0,0 +1,409 @@
+// migration.rs
+
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::audit::AuditAction;
+use crate::errors::DriveError;
+use crate::members::check_read_access;
+use crate::profiling::profile;
+use crate::read_only::{check_writable, READ_ONLY_RETRY_DELAY};
+use crate::storage::StorageLocationID;
+use crate::system_api;
+use crate::{DriveFullFilePath, FileUUID, FolderUUID, State, STATE};
+
+/// Items moved per timer tick, keeping each message well inside the
+/// instruction limit.
+const MIGRATION_BATCH_SIZE: usize = 200;
+const MAX_RECORDED_MIGRATION_ERRORS: usize = 100;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+enum MigrationStatus {
+    Running,
+    Completed,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+enum MigrationItem {
+    // `reparent` is set for the top of the moved subtree, whose parent stays
+    // behind in the old location
+    Folder { id: FolderUUID, reparent: bool },
+    File { id: FileUUID, reparent: bool },
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StorageMigration {
+    id: u64,
+    folder_id: FolderUUID,
+    from_location: StorageLocationID,
+    to_location: StorageLocationID,
+    status: MigrationStatus,
+    total_items: u64,
+    migrated_items: u64,
+    errors: Vec<String>,
+    started_at_ms: u64,
+    finished_at_ms: Option<u64>,
+    pending: VecDeque<MigrationItem>,
+}
+
+/// Progress report for a migration job.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct StorageMigrationInfo {
+    id: u64,
+    folder_id: FolderUUID,
+    from_location: StorageLocationID,
+    to_location: StorageLocationID,
+    status: MigrationStatus,
+    total_items: u64,
+    migrated_items: u64,
+    remaining_items: u64,
+    // Items that could not be moved, with the reason
+    errors: Vec<String>,
+    started_at_ms: u64,
+    finished_at_ms: Option<u64>,
+}
+
+impl From<&StorageMigration> for StorageMigrationInfo {
+    fn from(job: &StorageMigration) -> Self {
+        StorageMigrationInfo {
+            id: job.id,
+            folder_id: job.folder_id.clone(),
+            from_location: job.from_location.clone(),
+            to_location: job.to_location.clone(),
+            status: job.status.clone(),
+            total_items: job.total_items,
+            migrated_items: job.migrated_items,
+            remaining_items: job.pending.len() as u64,
+            errors: job.errors.clone(),
+            started_at_ms: job.started_at_ms,
+            finished_at_ms: job.finished_at_ms,
+        }
+    }
+}
+
+/// Rewrites the storage prefix of `path`, e.g. `BrowserCache::a/b/` to `Web3Storj::a/b/`.
+fn relocate_path(path: &str, to_location: &str) -> DriveFullFilePath {
+    let rest = path.split_once("::").map_or(path, |(_, rest)| rest);
+    format!("{}::{}", to_location, rest)
+}
+
+/// Folder path containing `path`, in the form `ensure_folder_structure` expects.
+fn parent_folder_path(path: &str) -> String {
+    let trimmed = path.trim_end_matches('/');
+    match trimmed.rsplit_once('/') {
+        Some((parent, _)) => parent.to_string(),
+        None => trimmed.split_once("::").map_or(String::new(), |(storage, _)| format!("{}::", storage)),
+    }
+}
+
+impl State {
+    /// Every folder and file under `folder_id`, parents ahead of children.
+    /// File version chains are included so older versions move too.
+    fn collect_migration_items(&self, folder_id: &FolderUUID, include_root: bool) -> VecDeque<MigrationItem> {
+        let mut items = VecDeque::new();
+        if include_root {
+            items.push_back(MigrationItem::Folder { id: folder_id.clone(), reparent: true });
+        }
+        let mut queue = VecDeque::from([(folder_id.clone(), !include_root)]);
+        while let Some((parent_id, children_reparent)) = queue.pop_front() {
+            for subfolder_id in self.child_folder_ids(&parent_id) {
+                items.push_back(MigrationItem::Folder { id: subfolder_id.clone(), reparent: children_reparent });
+                queue.push_back((subfolder_id, false));
+            }
+            for file_id in self.child_file_ids(&parent_id) {
+                let mut version = Some(file_id);
+                while let Some(version_id) = version {
+                    version = self.file_uuid_to_metadata.get(&version_id).and_then(|file| file.prior_version);
+                    items.push_back(MigrationItem::File { id: version_id, reparent: children_reparent });
+                }
+            }
+        }
+        items
+    }
+
+    fn find_migration_conflict(&self, items: &VecDeque<MigrationItem>, to_location: &str) -> Option<DriveFullFilePath> {
+        items.iter().find_map(|item| match item {
+            MigrationItem::Folder { id, .. } => {
+                let folder = self.folder_uuid_to_metadata.get(id).filter(|folder| !folder.deleted)?;
+                let target = relocate_path(&folder.full_folder_path, to_location);
+                self.full_folder_path_to_uuid.contains_key(&target).then_some(target)
+            }
+            MigrationItem::File { id, .. } => {
+                let file = self.file_uuid_to_metadata.get(id)?;
+                let target = relocate_path(&file.full_file_path, to_location);
+                self.full_file_path_to_uuid.get(&target).filter(|existing| existing != id).map(|_| target)
+            }
+        })
+    }
+
+    fn migrate_storage(
+        &mut self,
+        caller: Principal,
+        folder_id: FolderUUID,
+        from_location: StorageLocationID,
+        to_location: StorageLocationID,
+    ) -> Result<StorageMigrationInfo, DriveError> {
+        if !self.is_drive_admin(caller) {
+            return Err(DriveError::unauthorized("Only a drive admin can migrate storage"));
+        }
+        if self.storage_migrations.values().any(|job| job.status == MigrationStatus::Running) {
+            return Err(DriveError::InvalidState {
+                reason: "Another storage migration is still running".to_string(),
+            });
+        }
+        let folder = self
+            .folder_uuid_to_metadata
+            .get(&folder_id)
+            .filter(|folder| !folder.deleted)
+            .ok_or_else(|| DriveError::folder_not_found(&folder_id))?;
+        if folder.storage_location != from_location {
+            return Err(DriveError::StorageMismatch {
+                expected: from_location,
+                found: folder.storage_location,
+            });
+        }
+        self.check_storage_location(&to_location)?;
+        // Migrating moves the whole subtree, so neither end may be held
+        self.check_subtree_legal_hold(&folder_id)?;
+        self.check_path_legal_hold(&relocate_path(&folder.full_folder_path, &to_location))?;
+        if to_location == from_location {
+            return Err(DriveError::InvalidInput {
+                field: "to_location".to_string(),
+                reason: "Destination must differ from the current location".to_string(),
+            });
+        }
+
+        // A storage root stays put; its contents move under the destination root
+        let is_root = folder.parent_folder_uuid.is_none();
+        let pending = self.collect_migration_items(&folder_id, !is_root);
+        if let Some(path) = self.find_migration_conflict(&pending, &to_location) {
+            return Err(DriveError::AlreadyExists { path });
+        }
+
+        let id = self.next_migration_id;
+        self.next_migration_id += 1;
+        let job = StorageMigration {
+            id,
+            folder_id: folder_id.clone(),
+            from_location,
+            to_location: to_location.clone(),
+            status: MigrationStatus::Running,
+            total_items: pending.len() as u64,
+            migrated_items: 0,
+            errors: Vec::new(),
+            started_at_ms: system_api::time() / 1_000_000,
+            finished_at_ms: None,
+            pending,
+        };
+        let info = StorageMigrationInfo::from(&job);
+        self.storage_migrations.insert(id, job);
+        self.record_audit(
+            AuditAction::MigrateStorage,
+            &folder_id,
+            Some(folder.full_folder_path.clone()),
+            Some(relocate_path(&folder.full_folder_path, &to_location)),
+        );
+        schedule_storage_migration(Duration::ZERO);
+        Ok(info)
+    }
+
+    fn migrate_folder_item(
+        &mut self,
+        folder_id: &FolderUUID,
+        reparent: bool,
+        from_location: &str,
+        to_location: &StorageLocationID,
+    ) -> Result<(), DriveError> {
+        let folder = self
+            .folder_uuid_to_metadata
+            .get(folder_id)
+            .ok_or_else(|| DriveError::folder_not_found(folder_id))?;
+        if folder.storage_location != from_location {
+            return Ok(());
+        }
+        let new_path = relocate_path(&folder.full_folder_path, to_location);
+        if !folder.deleted && self.full_folder_path_to_uuid.contains_key(&new_path) {
+            return Err(DriveError::AlreadyExists { path: new_path });
+        }
+
+        let mut updated = folder.clone();
+        updated.storage_location = to_location.clone();
+        updated.full_folder_path = new_path.clone();
+        updated.last_changed_unix_ms = system_api::time() / 1_000_000;
+        updated.revision += 1;
+        if reparent {
+            let owner = self.owner;
+            let parent_path = parent_folder_path(&new_path);
+            updated.parent_folder_uuid = Some(self.ensure_folder_structure(&parent_path, to_location.clone(), owner));
+        }
+
+        if self.full_folder_path_to_uuid.get(&folder.full_folder_path).as_ref() == Some(folder_id) {
+            self.full_folder_path_to_uuid.remove(&folder.full_folder_path);
+        }
+        // Deleted folders keep no path entry
+        if !updated.deleted {
+            self.full_folder_path_to_uuid.insert(new_path, folder_id.clone());
+        }
+        if reparent {
+            self.unindex_child_folder(&folder);
+            if !folder.deleted {
+                self.rollup_folder_removed(&folder);
+                self.adjust_rollups_for_subtree(&folder, false);
+            }
+        }
+        self.folder_uuid_to_metadata.insert(folder_id.clone(), updated.clone());
+        if reparent {
+            self.index_child_folder(&updated);
+            if !updated.deleted {
+                self.rollup_folder_added(&updated);
+                self.adjust_rollups_for_subtree(&updated, true);
+            }
+        }
+        Ok(())
+    }
+
+    fn migrate_file_item(
+        &mut self,
+        file_id: &FileUUID,
+        reparent: bool,
+        from_location: &str,
+        to_location: &StorageLocationID,
+    ) -> Result<(), DriveError> {
+        let file = self
+            .file_uuid_to_metadata
+            .get(file_id)
+            .ok_or_else(|| DriveError::file_not_found(file_id))?;
+        if file.storage_location != from_location {
+            return Ok(());
+        }
+        let new_path = relocate_path(&file.full_file_path, to_location);
+        if self.full_file_path_to_uuid.get(&new_path).is_some_and(|existing| existing != *file_id) {
+            return Err(DriveError::AlreadyExists { path: new_path });
+        }
+
+        let mut updated = file.clone();
+        updated.storage_location = to_location.clone();
+        updated.full_file_path = new_path.clone();
+        updated.last_changed_unix_ms = system_api::time() / 1_000_000;
+        updated.revision += 1;
+        if reparent {
+            let owner = self.owner;
+            let parent_path = parent_folder_path(&new_path);
+            updated.folder_uuid = self.ensure_folder_structure(&parent_path, to_location.clone(), owner);
+        }
+
+        // Older versions share the path but are not what it resolves to
+        if self.full_file_path_to_uuid.get(&file.full_file_path).as_ref() == Some(file_id) {
+            self.full_file_path_to_uuid.remove(&file.full_file_path);
+            self.full_file_path_to_uuid.insert(new_path, file_id.clone());
+        }
+        if self.unindex_child_file(&file) {
+            self.index_child_file(&updated);
+        }
+        self.replace_file_metadata(updated);
+        Ok(())
+    }
+
+    /// Moves up to one batch of pending items for the running migration.
+    /// Returns whether work is left.
+    fn run_storage_migration_batch(&mut self) -> bool {
+        let Some(mut job) = self
+            .storage_migrations
+            .values()
+            .find(|job| job.status == MigrationStatus::Running)
+            .cloned()
+        else {
+            return false;
+        };
+
+        for _ in 0..MIGRATION_BATCH_SIZE {
+            let Some(item) = job.pending.pop_front() else {
+                break;
+            };
+            let result = match &item {
+                MigrationItem::Folder { id, reparent } => {
+                    self.migrate_folder_item(id, *reparent, &job.from_location, &job.to_location).map_err(|e| (id.clone(), e))
+                }
+                MigrationItem::File { id, reparent } => {
+                    self.migrate_file_item(id, *reparent, &job.from_location, &job.to_location).map_err(|e| (id.clone(), e))
+                }
+            };
+            match result {
+                Ok(()) => job.migrated_items += 1,
+                Err((id, error)) if job.errors.len() < MAX_RECORDED_MIGRATION_ERRORS => {
+                    job.errors.push(format!("{}: {}", id, error));
+                }
+                Err(_) => {}
+            }
+        }
+
+        let more = !job.pending.is_empty();
+        if !more {
+            // Per-top-level-folder usage follows the old folder layout until rebuilt
+            self.recompute_storage_usage();
+            job.status = MigrationStatus::Completed;
+            job.finished_at_ms = Some(system_api::time() / 1_000_000);
+        }
+        self.storage_migrations.insert(job.id, job);
+        more
+    }
+}
+
+fn schedule_storage_migration(delay: Duration) {
+    ic_cdk_timers::set_timer(delay, || {
+        if STATE.with(|state| state.borrow().is_read_only()) {
+            schedule_storage_migration(READ_ONLY_RETRY_DELAY);
+            return;
+        }
+        let more = STATE.with(|state| state.borrow_mut().run_storage_migration_batch());
+        if more {
+            schedule_storage_migration(Duration::ZERO);
+        }
+    });
+}
+
+/// Restarts a migration interrupted by an upgrade.
+pub fn resume_storage_migration() {
+    let running = STATE.with(|state| {
+        state.borrow().storage_migrations.values().any(|job| job.status == MigrationStatus::Running)
+    });
+    if running {
+        schedule_storage_migration(Duration::ZERO);
+    }
+}
+
+/// Starts moving a folder and everything below it from one storage location
+/// to another, rewriting path prefixes as it goes. The move runs in batches
+/// in the background; poll `get_storage_migration` for progress.
+#[ic_cdk::update]
+fn migrate_storage(
+    folder_id: FolderUUID,
+    from_location: StorageLocationID,
+    to_location: StorageLocationID,
+) -> Result<StorageMigrationInfo, DriveError> {
+    let _profile = profile("migrate_storage");
+    check_writable()?;
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow_mut().migrate_storage(caller, folder_id, from_location, to_location))
+}
+
+#[ic_cdk::query]
+fn get_storage_migration(job_id: u64) -> Result<StorageMigrationInfo, DriveError> {
+    check_read_access()?;
+    STATE.with(|state| {
+        state
+            .borrow()
+            .storage_migrations
+            .get(&job_id)
+            .map(StorageMigrationInfo::from)
+            .ok_or_else(|| DriveError::NotFound { resource: "storage migration".to_string(), id: job_id.to_string() })
+    })
+}
+
+#[ic_cdk::query]
+fn list_storage_migrations() -> Result<Vec<StorageMigrationInfo>, DriveError> {
+    check_read_access()?;
+    STATE.with(|state| Ok(state.borrow().storage_migrations.values().map(StorageMigrationInfo::from).collect()))
+}
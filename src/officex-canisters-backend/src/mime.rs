@@ -0,0 +1,124 @@
+// mime.rs
+
+use crate::api_keys::{check_update_allowed_with_api_key, ApiScope};
+use crate::audit::AuditAction;
+use crate::errors::DriveError;
+use crate::profiling::profile;
+use crate::{FileMetadata, FileUUID, State, UserID, STATE};
+
+const DEFAULT_MIME_TYPE: &str = "application/octet-stream";
+const MAX_MIME_TYPE_LEN: usize = 255;
+
+/// Extensions every client is expected to preview or serve with a specific
+/// type. Anything else is served as `application/octet-stream`.
+const MIME_TYPES: &[(&str, &str)] = &[
+    ("7z", "application/x-7z-compressed"),
+    ("aac", "audio/aac"),
+    ("avi", "video/x-msvideo"),
+    ("avif", "image/avif"),
+    ("bmp", "image/bmp"),
+    ("css", "text/css"),
+    ("csv", "text/csv"),
+    ("doc", "application/msword"),
+    ("docx", "application/vnd.openxmlformats-officedocument.wordprocessingml.document"),
+    ("epub", "application/epub+zip"),
+    ("flac", "audio/flac"),
+    ("gif", "image/gif"),
+    ("gz", "application/gzip"),
+    ("heic", "image/heic"),
+    ("htm", "text/html"),
+    ("html", "text/html"),
+    ("ico", "image/vnd.microsoft.icon"),
+    ("ics", "text/calendar"),
+    ("jpeg", "image/jpeg"),
+    ("jpg", "image/jpeg"),
+    ("js", "text/javascript"),
+    ("json", "application/json"),
+    ("m4a", "audio/mp4"),
+    ("md", "text/markdown"),
+    ("mkv", "video/x-matroska"),
+    ("mov", "video/quicktime"),
+    ("mp3", "audio/mpeg"),
+    ("mp4", "video/mp4"),
+    ("odp", "application/vnd.oasis.opendocument.presentation"),
+    ("ods", "application/vnd.oasis.opendocument.spreadsheet"),
+    ("odt", "application/vnd.oasis.opendocument.text"),
+    ("ogg", "audio/ogg"),
+    ("opus", "audio/opus"),
+    ("otf", "font/otf"),
+    ("pdf", "application/pdf"),
+    ("png", "image/png"),
+    ("ppt", "application/vnd.ms-powerpoint"),
+    ("pptx", "application/vnd.openxmlformats-officedocument.presentationml.presentation"),
+    ("rar", "application/vnd.rar"),
+    ("rtf", "application/rtf"),
+    ("svg", "image/svg+xml"),
+    ("tar", "application/x-tar"),
+    ("tif", "image/tiff"),
+    ("tiff", "image/tiff"),
+    ("ttf", "font/ttf"),
+    ("txt", "text/plain"),
+    ("wav", "audio/wav"),
+    ("webm", "video/webm"),
+    ("webp", "image/webp"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("xls", "application/vnd.ms-excel"),
+    ("xlsx", "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"),
+    ("xml", "application/xml"),
+    ("yaml", "application/yaml"),
+    ("yml", "application/yaml"),
+    ("zip", "application/zip"),
+];
+
+pub(crate) fn mime_type_for_extension(extension: &str) -> String {
+    let extension = extension.to_ascii_lowercase();
+    MIME_TYPES
+        .binary_search_by(|(known, _)| (*known).cmp(extension.as_str()))
+        .map_or(DEFAULT_MIME_TYPE, |index| MIME_TYPES[index].1)
+        .to_string()
+}
+
+/// Accepts `type/subtype` with optional parameters, e.g. `text/plain; charset=utf-8`.
+fn normalize_mime_type(mime_type: &str) -> Result<String, DriveError> {
+    let mime_type = mime_type.trim().to_ascii_lowercase();
+    let essence = mime_type.split(';').next().unwrap_or("").trim();
+    let valid_token = |token: &str| {
+        !token.is_empty() && token.chars().all(|c| c.is_ascii_alphanumeric() || "!#$&-^_.+".contains(c))
+    };
+    let valid = mime_type.len() <= MAX_MIME_TYPE_LEN
+        && essence.split_once('/').is_some_and(|(kind, subtype)| valid_token(kind) && valid_token(subtype));
+    if !valid {
+        return Err(DriveError::InvalidInput {
+            field: "mime_type".to_string(),
+            reason: "Expected a MIME type such as image/png".to_string(),
+        });
+    }
+    Ok(mime_type)
+}
+
+impl State {
+    fn set_mime_type(&mut self, user_id: UserID, file_id: &FileUUID, mime_type: String) -> Result<FileMetadata, DriveError> {
+        let mut file = self.editable_file(user_id, file_id)?;
+        file.mime_type = if mime_type.is_empty() {
+            mime_type_for_extension(&file.extension)
+        } else {
+            normalize_mime_type(&mime_type)?
+        };
+        file.revision += 1;
+
+        self.file_uuid_to_metadata.insert(file_id.clone(), file.clone());
+        self.record_audit(AuditAction::SetMimeType, file_id, Some(file.full_file_path.clone()), None);
+        Ok(file)
+    }
+}
+
+/// Overrides the MIME type derived from the file's extension. An empty
+/// `mime_type` goes back to the derived one. Renaming the file to another
+/// extension derives it again.
+#[ic_cdk::update]
+fn set_mime_type(file_id: FileUUID, mime_type: String, api_key: Option<String>) -> Result<FileMetadata, DriveError> {
+    let _profile = profile("set_mime_type");
+    let user_id = check_update_allowed_with_api_key("set_mime_type", api_key, ApiScope::Write)?;
+    STATE.with(|state| state.borrow_mut().set_mime_type(user_id, &file_id, mime_type))
+}
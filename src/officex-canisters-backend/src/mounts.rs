@@ -0,0 +1,188 @@
+// mounts.rs
+
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+use crate::errors::DriveError;
+use crate::profiling::profile;
+use crate::rate_limit::check_update_allowed;
+use crate::read_only::check_writable;
+use crate::sharing::ShareRole;
+use crate::system_api;
+use crate::{FetchFilesAtFolderPathConfig, FetchFilesResult, FolderMetadata, FolderUUID, State, STATE};
+
+/// A local folder that stands in for a folder on another drive. Its contents
+/// live on the remote drive and are fetched on every listing.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct MountPoint {
+    local_folder_id: FolderUUID,
+    remote_canister: Principal,
+    remote_folder_id: FolderUUID,
+    mounted_at_ms: u64,
+}
+
+impl State {
+    fn check_can_view_folder(&self, caller: Principal, folder_id: &FolderUUID) -> Result<FolderMetadata, DriveError> {
+        let folder = self
+            .folder_uuid_to_metadata
+            .get(folder_id)
+            .filter(|folder| !folder.deleted)
+            .ok_or_else(|| DriveError::folder_not_found(folder_id))?;
+        if self.member_role(caller).is_none() && !self.has_folder_share_role(caller, folder_id, ShareRole::Viewer) {
+            return Err(DriveError::unauthorized("Folder has not been shared with the caller"));
+        }
+        Ok(folder)
+    }
+
+    fn mount_external_folder(
+        &mut self,
+        caller: Principal,
+        remote_canister: Principal,
+        remote_folder_id: FolderUUID,
+        local_path: String,
+    ) -> Result<MountPoint, DriveError> {
+        if !self.is_drive_admin(caller) {
+            return Err(DriveError::unauthorized("Only a drive admin can mount folders"));
+        }
+        if remote_canister == system_api::canister_id() {
+            return Err(DriveError::InvalidInput {
+                field: "remote_canister".to_string(),
+                reason: "Cannot mount a folder from this drive".to_string(),
+            });
+        }
+        let mut sanitized_path = Self::sanitize_file_path(&local_path)?;
+        if !sanitized_path.ends_with('/') {
+            sanitized_path.push('/');
+        }
+        if self.full_folder_path_to_uuid.contains_key(&sanitized_path) {
+            return Err(DriveError::AlreadyExists { path: sanitized_path });
+        }
+        let Some((storage_location, _)) = sanitized_path.split_once("::") else {
+            return Err(DriveError::invalid_path(&local_path, "Invalid folder path format"));
+        };
+
+        let folder = self.create_folder(sanitized_path.clone(), storage_location.to_string(), caller)?;
+        let mount = MountPoint {
+            local_folder_id: folder.id.clone(),
+            remote_canister,
+            remote_folder_id,
+            mounted_at_ms: system_api::time() / 1_000_000,
+        };
+        self.mount_points.insert(folder.id, mount.clone());
+        Ok(mount)
+    }
+}
+
+/// Lists a folder on this drive for another drive that mounted it. The
+/// calling drive must be a member or hold a share grant on the folder.
+#[ic_cdk::query]
+fn fetch_shared_folder(folder_id: FolderUUID, limit: u32, after: u32) -> Result<FetchFilesResult, DriveError> {
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let state = state.borrow();
+        let folder = state.check_can_view_folder(caller, &folder_id)?;
+        let page = state.fetch_files_at_folder_path(FetchFilesAtFolderPathConfig {
+            full_folder_path: folder.full_folder_path,
+            limit,
+            after,
+            exclude_pending: Some(true),
+            pinned_first: None,
+        });
+        Ok(state.redact_unpaid_page(caller, page))
+    })
+}
+
+/// Mounts `remote_folder_id` from another drive at `local_path`. The remote
+/// owner must first share that folder with this drive's canister id; the
+/// remote drive checks the grant on every listing.
+#[ic_cdk::update]
+async fn mount_external_folder(
+    remote_canister: Principal,
+    remote_folder_id: FolderUUID,
+    local_path: String,
+) -> Result<MountPoint, DriveError> {
+    let _profile = profile("mount_external_folder");
+    check_writable()?;
+    let caller = system_api::caller();
+    if STATE.with(|state| !state.borrow().is_drive_admin(caller)) {
+        return Err(DriveError::unauthorized("Only a drive admin can mount folders"));
+    }
+
+    // Fail now rather than on first listing if the folder is not shared with us
+    let result: Result<(Result<FetchFilesResult, DriveError>,), _> =
+        ic_cdk::call(remote_canister, "fetch_shared_folder", (&remote_folder_id, 0u32, 0u32)).await;
+    match result {
+        Ok((Ok(_),)) => {}
+        Ok((Err(error),)) => return Err(error),
+        Err((code, message)) => {
+            return Err(DriveError::Internal {
+                message: format!("Failed to reach remote drive: {:?} {}", code, message),
+            })
+        }
+    }
+
+    STATE.with(|state| {
+        state
+            .borrow_mut()
+            .mount_external_folder(caller, remote_canister, remote_folder_id, local_path)
+    })
+}
+
+/// Removes a mount point and its local folder. Nothing changes on the
+/// remote drive.
+#[ic_cdk::update]
+fn unmount_external_folder(local_folder_id: FolderUUID) -> Result<(), DriveError> {
+    let _profile = profile("unmount_external_folder");
+    check_writable()?;
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if !state.is_drive_admin(caller) {
+            return Err(DriveError::unauthorized("Only a drive admin can unmount folders"));
+        }
+        if !state.mount_points.contains_key(&local_folder_id) {
+            return Err(DriveError::NotFound {
+                resource: "mount point".to_string(),
+                id: local_folder_id,
+            });
+        }
+        state.delete_folder(&local_folder_id)
+    })
+}
+
+#[ic_cdk::query]
+fn list_mount_points() -> Vec<MountPoint> {
+    STATE.with(|state| state.borrow().mount_points.values().cloned().collect())
+}
+
+/// Lists a mounted folder by asking the remote drive, so the listing reflects
+/// its current contents and the remote side's permission checks.
+#[ic_cdk::update]
+async fn list_mounted_folder(local_folder_id: FolderUUID, limit: u32, after: u32) -> Result<FetchFilesResult, DriveError> {
+    let _profile = profile("list_mounted_folder");
+    check_update_allowed("list_mounted_folder")?;
+    let caller = system_api::caller();
+    let mount = STATE.with(|state| {
+        let state = state.borrow();
+        state.check_can_view_folder(caller, &local_folder_id)?;
+        state.mount_points.get(&local_folder_id).cloned().ok_or_else(|| DriveError::NotFound {
+            resource: "mount point".to_string(),
+            id: local_folder_id.clone(),
+        })
+    })?;
+
+    // An update call, since drives may sit on different subnets and
+    // composite queries cannot cross them
+    let result: Result<(Result<FetchFilesResult, DriveError>,), _> = ic_cdk::call(
+        mount.remote_canister,
+        "fetch_shared_folder",
+        (&mount.remote_folder_id, limit, after),
+    )
+    .await;
+    match result {
+        Ok((listing,)) => listing,
+        Err((code, message)) => Err(DriveError::Internal {
+            message: format!("Failed to reach remote drive: {:?} {}", code, message),
+        }),
+    }
+}
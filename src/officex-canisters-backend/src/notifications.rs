@@ -0,0 +1,160 @@
+// notifications.rs
+//
+// Each user's inbox of things needing their attention on this drive: items
+// shared with them, comments on their items or mentioning them, sync
+// conflicts waiting to be resolved, and files whose content has gone missing. Inboxes keep only the newest
+// notifications; older ones are dropped as new ones arrive.
+
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+use crate::errors::DriveError;
+use crate::metrics::count_call;
+use crate::profiling::profile;
+use crate::rate_limit::charge_rate_limit;
+use crate::read_only::check_writable;
+use crate::sharing::ShareRole;
+use crate::system_api;
+use crate::{FileUUID, State, STATE};
+
+const MAX_NOTIFICATIONS_PER_USER: usize = 500;
+const MAX_NOTIFICATION_PAGE_SIZE: u32 = 200;
+const MAX_MARK_READ_IDS: usize = 500;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub enum NotificationKind {
+    Shared { role: ShareRole },
+    Comment { comment_id: u64 },
+    // A comment naming the recipient as `@<principal>`
+    Mention { comment_id: u64 },
+    SyncConflict { conflict_copy_id: FileUUID },
+    // The file's content is gone from its storage; see `link_checks.rs`
+    DeadLink,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Notification {
+    id: u64,
+    kind: NotificationKind,
+    // The folder or file it is about
+    resource_id: String,
+    actor: Principal,
+    created_at_ms: u64,
+    read: bool,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct NotificationPage {
+    notifications: Vec<Notification>,
+    // Pass as `before` to fetch the next page
+    next_cursor: Option<u64>,
+    unread: u32,
+}
+
+/// Principals written as `@<principal>` in comment text.
+pub(crate) fn mentioned_principals(text: &str) -> Vec<Principal> {
+    let mut mentioned: Vec<Principal> = text
+        .split_whitespace()
+        .filter_map(|word| word.strip_prefix('@'))
+        .map(|name| name.trim_end_matches(|c: char| !c.is_ascii_alphanumeric()))
+        .filter_map(|name| Principal::from_text(name).ok())
+        .collect();
+    mentioned.sort();
+    mentioned.dedup();
+    mentioned
+}
+
+impl State {
+    /// Adds a notification to `recipient`'s inbox. Nobody is notified of
+    /// their own actions, except for sync conflicts, which another of their
+    /// devices may have to resolve.
+    pub(crate) fn notify(&mut self, recipient: Principal, actor: Principal, kind: NotificationKind, resource_id: &str) {
+        let is_conflict = matches!(kind, NotificationKind::SyncConflict { .. });
+        if recipient == Principal::anonymous() || (recipient == actor && !is_conflict) {
+            return;
+        }
+        let notification = Notification {
+            id: self.next_notification_id,
+            kind,
+            resource_id: resource_id.to_string(),
+            actor,
+            created_at_ms: system_api::time() / 1_000_000,
+            read: false,
+        };
+        self.next_notification_id += 1;
+        let inbox = self.notifications.entry(recipient).or_default();
+        inbox.insert(notification.id, notification);
+        while inbox.len() > MAX_NOTIFICATIONS_PER_USER {
+            inbox.pop_first();
+        }
+    }
+
+    /// Newest first, starting below `before` when given.
+    fn list_notifications(
+        &self,
+        user: Principal,
+        unread_only: bool,
+        before: Option<u64>,
+        limit: u32,
+    ) -> NotificationPage {
+        let Some(inbox) = self.notifications.get(&user) else {
+            return NotificationPage { notifications: Vec::new(), next_cursor: None, unread: 0 };
+        };
+        let limit = limit.min(MAX_NOTIFICATION_PAGE_SIZE) as usize;
+        let mut matching = inbox
+            .range(..before.unwrap_or(u64::MAX))
+            .rev()
+            .map(|(_, notification)| notification)
+            .filter(|notification| !unread_only || !notification.read);
+        let page: Vec<Notification> = matching.by_ref().take(limit).cloned().collect();
+        let next_cursor = match (page.last(), matching.next()) {
+            (Some(last), Some(_)) => Some(last.id),
+            _ => None,
+        };
+        let unread = inbox.values().filter(|notification| !notification.read).count() as u32;
+        NotificationPage { notifications: page, next_cursor, unread }
+    }
+
+    /// Returns how many of `ids` were unread. Unknown IDs are ignored, since
+    /// they may have been dropped from a full inbox.
+    fn mark_read(&mut self, user: Principal, ids: &[u64]) -> Result<u32, DriveError> {
+        if ids.len() > MAX_MARK_READ_IDS {
+            return Err(DriveError::InvalidInput {
+                field: "notification_ids".to_string(),
+                reason: format!("At most {} IDs per call", MAX_MARK_READ_IDS),
+            });
+        }
+        let Some(inbox) = self.notifications.get_mut(&user) else {
+            return Ok(0);
+        };
+        let mut marked = 0;
+        for id in ids {
+            if let Some(notification) = inbox.get_mut(id).filter(|notification| !notification.read) {
+                notification.read = true;
+                marked += 1;
+            }
+        }
+        Ok(marked)
+    }
+}
+
+/// The caller's notifications on this drive. Open to anyone, since an inbox
+/// only ever holds what was addressed to its owner.
+#[ic_cdk::query]
+fn list_notifications(unread_only: bool, before: Option<u64>, limit: u32) -> NotificationPage {
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow().list_notifications(caller, unread_only, before, limit))
+}
+
+/// Marks the caller's notifications as read. Not subject to team drive
+/// roles, so people an item was shared with can clear their inbox too.
+#[ic_cdk::update]
+fn mark_read(notification_ids: Vec<u64>) -> Result<u32, DriveError> {
+    let _profile = profile("mark_read");
+    let caller = system_api::caller();
+    count_call("mark_read");
+    check_writable()?;
+    // Anyone notified may mark their own notifications read
+    charge_rate_limit(caller, "mark_read", 1)?;
+    STATE.with(|state| state.borrow_mut().mark_read(caller, &notification_ids))
+}
@@ -0,0 +1,171 @@
+// ownership.rs
+
+use candid::Principal;
+
+use crate::audit::AuditAction;
+use crate::errors::DriveError;
+use crate::members::TeamMember;
+use crate::profiling::profile;
+use crate::read_only::check_writable;
+use crate::system_api;
+use crate::{sanitize_username, State, STATE};
+
+impl State {
+    fn propose_ownership_transfer(&mut self, caller: Principal, new_owner: Principal) -> Result<(), DriveError> {
+        if caller != self.owner {
+            return Err(DriveError::unauthorized("Only the owner can transfer ownership"));
+        }
+        self.check_not_accepting_ownership()?;
+        if new_owner == Principal::anonymous() {
+            return Err(DriveError::InvalidInput {
+                field: "new_owner".to_string(),
+                reason: "Cannot transfer ownership to the anonymous principal".to_string(),
+            });
+        }
+        if new_owner == self.owner {
+            return Err(DriveError::InvalidInput {
+                field: "new_owner".to_string(),
+                reason: "Principal already owns this drive".to_string(),
+            });
+        }
+        self.pending_owner = Some(new_owner);
+        self.record_audit(AuditAction::TransferOwnership, &new_owner.to_text(), None, None);
+        Ok(())
+    }
+
+    /// The pending transfer cannot change while the factory is being told
+    /// about it, or the factory and the drive would disagree on the owner.
+    fn check_not_accepting_ownership(&self) -> Result<(), DriveError> {
+        if self.accepting_ownership {
+            return Err(DriveError::InvalidState {
+                reason: "The pending ownership transfer is being accepted".to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    fn check_pending_owner(&self, caller: Principal) -> Result<(), DriveError> {
+        match self.pending_owner {
+            Some(pending) if pending == caller => Ok(()),
+            _ => Err(DriveError::unauthorized("No ownership transfer is pending for the caller")),
+        }
+    }
+
+    fn complete_ownership_transfer(&mut self, new_owner: Principal) {
+        let name = self.username.split('@').next().unwrap_or("").to_string();
+        self.owner = new_owner;
+        self.username = format!("{}@{}", name, new_owner);
+        self.pending_owner = None;
+        self.record_audit(AuditAction::AcceptOwnership, &new_owner.to_text(), None, None);
+    }
+
+    /// Hands a standby drive to its first real owner. A drive is a standby
+    /// while the factory that installed it is still its owner.
+    fn configure_standby_drive(
+        &mut self,
+        caller: Principal,
+        owner: Principal,
+        username: String,
+        members: Option<Vec<TeamMember>>,
+    ) -> Result<(), DriveError> {
+        if self.factory_id != Some(caller) {
+            return Err(DriveError::unauthorized("Only the factory can configure a drive"));
+        }
+        if self.owner != caller {
+            return Err(DriveError::InvalidState {
+                reason: "Drive has already been configured".to_string(),
+            });
+        }
+        if owner == Principal::anonymous() {
+            return Err(DriveError::InvalidInput {
+                field: "owner".to_string(),
+                reason: "Drive cannot be owned by the anonymous principal".to_string(),
+            });
+        }
+        self.owner = owner;
+        self.username = format!("{}@{}", sanitize_username(&username), owner);
+        if let Some(members) = members {
+            self.team_drive = true;
+            self.add_members(members)?;
+        }
+        Ok(())
+    }
+}
+
+/// First step of an ownership transfer. The new owner must call
+/// `accept_ownership` before anything changes.
+#[ic_cdk::update]
+fn transfer_ownership(new_owner: Principal) -> Result<(), DriveError> {
+    let _profile = profile("transfer_ownership");
+    check_writable()?;
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow_mut().propose_ownership_transfer(caller, new_owner))
+}
+
+#[ic_cdk::update]
+fn cancel_ownership_transfer() -> Result<(), DriveError> {
+    let _profile = profile("cancel_ownership_transfer");
+    check_writable()?;
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if caller != state.owner {
+            return Err(DriveError::unauthorized("Only the owner can cancel an ownership transfer"));
+        }
+        state.check_not_accepting_ownership()?;
+        if let Some(pending) = state.pending_owner.take() {
+            state.record_audit(AuditAction::CancelOwnershipTransfer, &pending.to_text(), None, None);
+        }
+        Ok(())
+    })
+}
+
+/// Called by the factory when it assigns a pre-provisioned drive to a user,
+/// with `members` when it becomes a team drive.
+#[ic_cdk::update]
+fn configure_drive(owner: Principal, username: String, members: Option<Vec<TeamMember>>) -> Result<(), DriveError> {
+    let _profile = profile("configure_drive");
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow_mut().configure_standby_drive(caller, owner, username, members))
+}
+
+#[ic_cdk::query]
+fn get_pending_owner() -> Option<Principal> {
+    STATE.with(|state| state.borrow().pending_owner)
+}
+
+/// Second step of an ownership transfer, called by the proposed owner. Drives
+/// created by the factory notify it first so its user directory stays in sync;
+/// if the factory rejects the change, ownership is left untouched.
+#[ic_cdk::update]
+async fn accept_ownership() -> Result<(), DriveError> {
+    let _profile = profile("accept_ownership");
+    check_writable()?;
+    let caller = system_api::caller();
+    let factory_id = STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.check_pending_owner(caller)?;
+        state.check_not_accepting_ownership()?;
+        state.accepting_ownership = state.factory_id.is_some();
+        Ok::<_, DriveError>(state.factory_id)
+    })?;
+
+    if let Some(factory_id) = factory_id {
+        let result: Result<(Result<(), String>,), _> =
+            ic_cdk::call(factory_id, "notify_drive_owner_changed", (caller,)).await;
+        let notified = match result {
+            Ok((Ok(()),)) => Ok(()),
+            Ok((Err(message),)) => Err(DriveError::Internal { message }),
+            Err((code, message)) => Err(DriveError::Internal {
+                message: format!("Failed to notify factory: {:?} {}", code, message),
+            }),
+        };
+        // The transfer stays pending if the factory rejected it
+        STATE.with(|state| state.borrow_mut().accepting_ownership = false);
+        notified?;
+    }
+
+    // The pending transfer was locked while the factory was told, so it still stands
+    STATE.with(|state| state.borrow_mut().complete_ownership_transfer(caller));
+    Ok(())
+}
@@ -0,0 +1,216 @@
+// path_case.rs
+//
+// How paths are compared. `sanitize_file_path` puts every path in Unicode
+// NFC, so a name typed in composed or decomposed form is the same path.
+// Letter case counts unless the drive turns on case-insensitive paths:
+// paths then keep the case they were created with, but a path that differs
+// only in case resolves to the existing folder or file instead of creating
+// a second one.
+
+use ic_stable_structures::btreemap::Iter;
+use std::collections::BTreeMap;
+use std::ops::RangeBounds;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::audit::AuditAction;
+use crate::errors::DriveError;
+use crate::members::check_read_access;
+use crate::memory::{self, Memory, StableMap};
+use crate::profiling::profile;
+use crate::read_only::check_writable;
+use crate::system_api;
+use crate::{DriveFullFilePath, State, STATE};
+
+pub(crate) fn normalize_nfc(text: &str) -> String {
+    text.nfc().collect()
+}
+
+/// Lowercasing stands in for full Unicode case folding, which std lacks.
+fn fold_case(path: &str) -> String {
+    path.to_lowercase()
+}
+
+/// A path to UUID map in stable memory. While paths are case-insensitive it
+/// also keeps a heap index from case-folded paths to the stored ones; that
+/// index is rebuilt after an upgrade rather than persisted.
+pub struct PathIndex {
+    map: StableMap<DriveFullFilePath, String>,
+    folded: Option<BTreeMap<String, DriveFullFilePath>>,
+}
+
+impl PathIndex {
+    fn new(map: StableMap<DriveFullFilePath, String>) -> Self {
+        Self { map, folded: None }
+    }
+
+    pub fn get(&self, path: &DriveFullFilePath) -> Option<String> {
+        self.map.get(path)
+    }
+
+    pub fn contains_key(&self, path: &DriveFullFilePath) -> bool {
+        self.map.contains_key(path)
+    }
+
+    pub fn insert(&mut self, path: DriveFullFilePath, id: String) -> Option<String> {
+        if let Some(folded) = &mut self.folded {
+            folded.insert(fold_case(&path), path.clone());
+        }
+        self.map.insert(path, id)
+    }
+
+    pub fn remove(&mut self, path: &DriveFullFilePath) -> Option<String> {
+        if let Some(folded) = &mut self.folded {
+            let key = fold_case(path);
+            // A case-only rename may already have pointed the key at the new path
+            if folded.get(&key) == Some(path) {
+                folded.remove(&key);
+            }
+        }
+        self.map.remove(path)
+    }
+
+    pub fn iter(&self) -> Iter<'_, DriveFullFilePath, String, Memory> {
+        self.map.iter()
+    }
+
+    pub fn range(&self, paths: impl RangeBounds<DriveFullFilePath>) -> Iter<'_, DriveFullFilePath, String, Memory> {
+        self.map.range(paths)
+    }
+
+    pub fn clear_new(&mut self) {
+        self.map.clear_new();
+        if let Some(folded) = &mut self.folded {
+            folded.clear();
+        }
+    }
+
+    /// How `path` is stored: as given, or with different case when folding is on.
+    pub(crate) fn stored_path(&self, path: &str) -> Option<DriveFullFilePath> {
+        if self.map.contains_key(&path.to_string()) {
+            return Some(path.to_string());
+        }
+        self.folded.as_ref()?.get(&fold_case(path)).cloned()
+    }
+
+    /// Builds the folded index. Returns the first two stored paths that
+    /// differ only in case, if any; the later one wins in the index.
+    fn enable_case_folding(&mut self) -> Result<(), (DriveFullFilePath, DriveFullFilePath)> {
+        let mut folded = BTreeMap::new();
+        let mut conflict = None;
+        for (path, _) in self.map.iter() {
+            if let Some(other) = folded.insert(fold_case(&path), path.clone()) {
+                conflict.get_or_insert((other, path));
+            }
+        }
+        self.folded = Some(folded);
+        conflict.map_or(Ok(()), Err)
+    }
+
+    fn disable_case_folding(&mut self) {
+        self.folded = None;
+    }
+}
+
+pub fn folder_path_index() -> PathIndex {
+    PathIndex::new(memory::folder_path_map())
+}
+
+pub fn file_path_index() -> PathIndex {
+    PathIndex::new(memory::file_path_map())
+}
+
+impl State {
+    /// Rebuilds the folded indexes after an upgrade. Paths that collide
+    /// were already tolerated before it, so they are not an error here.
+    pub(crate) fn restore_case_folding(&mut self) {
+        if self.case_insensitive_paths {
+            let _ = self.full_folder_path_to_uuid.enable_case_folding();
+            let _ = self.full_file_path_to_uuid.enable_case_folding();
+        }
+    }
+
+    /// Spells a sanitized path the way existing folders and file along it
+    /// are stored, so a path differing only in case reuses them. Returns
+    /// `path` unchanged while paths are case-sensitive.
+    pub(crate) fn canonical_path(&self, path: &str) -> DriveFullFilePath {
+        if !self.case_insensitive_paths {
+            return path.to_string();
+        }
+        let Some((storage, rest)) = path.split_once("::") else {
+            return path.to_string();
+        };
+        let ends_in_file = !rest.is_empty() && !rest.ends_with('/');
+        let names: Vec<&str> = rest.split('/').filter(|name| !name.is_empty()).collect();
+
+        let mut canonical = format!("{}::", storage);
+        for (index, name) in names.iter().enumerate() {
+            let is_file = ends_in_file && index + 1 == names.len();
+            let (candidate, paths) = if is_file {
+                (format!("{}{}", canonical, name), &self.full_file_path_to_uuid)
+            } else {
+                (format!("{}{}/", canonical, name), &self.full_folder_path_to_uuid)
+            };
+            canonical = paths.stored_path(&candidate).unwrap_or(candidate);
+        }
+        canonical
+    }
+
+    /// A stored folder or file path other than `own_path` that `path` matches
+    /// up to case, which would make `path` ambiguous. Exact matches are left
+    /// to the callers' own existence checks.
+    pub(crate) fn case_variant_of(&self, path: &str, own_path: &str) -> Option<DriveFullFilePath> {
+        if !self.case_insensitive_paths {
+            return None;
+        }
+        [&self.full_folder_path_to_uuid, &self.full_file_path_to_uuid]
+            .into_iter()
+            .filter_map(|paths| paths.stored_path(path))
+            .find(|stored| stored != own_path)
+    }
+
+    fn set_case_insensitive_paths(&mut self, enabled: bool) -> Result<(), DriveError> {
+        if enabled {
+            let conflict = self
+                .full_folder_path_to_uuid
+                .enable_case_folding()
+                .and(self.full_file_path_to_uuid.enable_case_folding());
+            if let Err((first, second)) = conflict {
+                self.full_folder_path_to_uuid.disable_case_folding();
+                self.full_file_path_to_uuid.disable_case_folding();
+                return Err(DriveError::InvalidInput {
+                    field: "enabled".to_string(),
+                    reason: format!("'{}' and '{}' differ only in case; rename one first", first, second),
+                });
+            }
+        } else {
+            self.full_folder_path_to_uuid.disable_case_folding();
+            self.full_file_path_to_uuid.disable_case_folding();
+        }
+        self.case_insensitive_paths = enabled;
+        let owner = self.owner.to_text();
+        self.record_audit(AuditAction::SetCaseInsensitivePaths, &owner, None, None);
+        Ok(())
+    }
+}
+
+/// Turns case-insensitive paths on or off. Turning them on fails while two
+/// existing paths differ only in case, deleted items included.
+#[ic_cdk::update]
+fn set_case_insensitive_paths(enabled: bool) -> Result<(), DriveError> {
+    let _profile = profile("set_case_insensitive_paths");
+    check_writable()?;
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if !state.is_drive_admin(caller) {
+            return Err(DriveError::unauthorized("Only a drive admin can change the path case policy"));
+        }
+        state.set_case_insensitive_paths(enabled)
+    })
+}
+
+#[ic_cdk::query]
+fn get_case_insensitive_paths() -> Result<bool, DriveError> {
+    check_read_access()?;
+    STATE.with(|state| Ok(state.borrow().case_insensitive_paths))
+}
@@ -0,0 +1,157 @@
+// path_limits.rs
+//
+// Limits on the shape of the tree, enforced when folders and files are
+// created or renamed. Existing items are left alone when limits tighten.
+
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::audit::AuditAction;
+use crate::errors::DriveError;
+use crate::members::check_read_access;
+use crate::profiling::profile;
+use crate::read_only::check_writable;
+use crate::system_api;
+use crate::{State, STATE};
+
+const MAX_BANNED_CHARACTERS: usize = 64;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PathLimits {
+    // Folders and file below the storage root, e.g. 3 for `Loc::a/b/c.txt`
+    pub max_depth: u32,
+    // In characters, per folder or file name
+    pub max_name_length: u32,
+    // Direct children of one folder, deleted folders included
+    pub max_children_per_folder: u32,
+    pub banned_characters: String,
+}
+
+impl Default for PathLimits {
+    fn default() -> Self {
+        Self { max_depth: 64, max_name_length: 255, max_children_per_folder: 100_000, banned_characters: String::new() }
+    }
+}
+
+/// The limit a rejected path broke, carried by `DriveError::PathLimitExceeded`.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum PathRule {
+    MaxDepth(u32),
+    MaxNameLength(u32),
+    MaxChildrenPerFolder(u32),
+    BannedCharacter(String),
+}
+
+impl fmt::Display for PathRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathRule::MaxDepth(max) => write!(f, "deeper than {} levels", max),
+            PathRule::MaxNameLength(max) => write!(f, "a name is longer than {} characters", max),
+            PathRule::MaxChildrenPerFolder(max) => write!(f, "the folder already holds {} items", max),
+            PathRule::BannedCharacter(character) => write!(f, "contains the banned character '{}'", character),
+        }
+    }
+}
+
+fn exceeded(path: &str, rule: PathRule) -> DriveError {
+    DriveError::PathLimitExceeded { path: path.to_string(), rule }
+}
+
+impl State {
+    /// Checks a single new name, e.g. the target of a rename.
+    pub(crate) fn check_name_limits(&self, path: &str, name: &str) -> Result<(), DriveError> {
+        let limits = &self.path_limits;
+        if name.chars().count() > limits.max_name_length as usize {
+            return Err(exceeded(path, PathRule::MaxNameLength(limits.max_name_length)));
+        }
+        if let Some(banned) = name.chars().find(|c| limits.banned_characters.contains(*c)) {
+            return Err(exceeded(path, PathRule::BannedCharacter(banned.to_string())));
+        }
+        Ok(())
+    }
+
+    /// Checks the folders and file that creating sanitized `full_path` would
+    /// add. Parts of the path that already exist are not checked again.
+    pub(crate) fn check_path_limits(&self, full_path: &str) -> Result<(), DriveError> {
+        // Malformed paths are rejected by the storage location checks
+        let Some((storage, rest)) = full_path.split_once("::") else {
+            return Ok(());
+        };
+        let names: Vec<&str> = rest.split('/').filter(|name| !name.is_empty()).collect();
+        let limits = &self.path_limits;
+        if names.len() > limits.max_depth as usize {
+            return Err(exceeded(full_path, PathRule::MaxDepth(limits.max_depth)));
+        }
+
+        let mut parent_path = format!("{}::", storage);
+        let mut first_new = true;
+        for (index, name) in names.iter().enumerate() {
+            let folder_path = format!("{}{}/", parent_path, name);
+            let is_last = index + 1 == names.len();
+            let exists = self.full_folder_path_to_uuid.contains_key(&folder_path)
+                || (is_last && self.full_file_path_to_uuid.contains_key(&format!("{}{}", parent_path, name)));
+            if !exists {
+                self.check_name_limits(full_path, name)?;
+                // Everything after the first new item goes into new folders
+                if first_new {
+                    first_new = false;
+                    let max = limits.max_children_per_folder as usize;
+                    let full = self
+                        .full_folder_path_to_uuid
+                        .get(&parent_path)
+                        .is_some_and(|parent_id| self.children_of(&parent_id).take(max).count() >= max);
+                    if full {
+                        return Err(exceeded(full_path, PathRule::MaxChildrenPerFolder(limits.max_children_per_folder)));
+                    }
+                }
+            }
+            parent_path = folder_path;
+        }
+        Ok(())
+    }
+
+    fn set_path_limits(&mut self, limits: PathLimits) -> Result<(), DriveError> {
+        if limits.max_depth == 0 || limits.max_name_length == 0 || limits.max_children_per_folder == 0 {
+            return Err(DriveError::InvalidInput {
+                field: "limits".to_string(),
+                reason: "Limits must be above zero".to_string(),
+            });
+        }
+        if limits.banned_characters.chars().count() > MAX_BANNED_CHARACTERS
+            || limits.banned_characters.contains(['/', ':'])
+        {
+            return Err(DriveError::InvalidInput {
+                field: "banned_characters".to_string(),
+                reason: format!("At most {} characters, excluding the path separators '/' and ':'", MAX_BANNED_CHARACTERS),
+            });
+        }
+        self.path_limits = limits;
+        let owner = self.owner.to_text();
+        self.record_audit(AuditAction::SetPathLimits, &owner, None, None);
+        Ok(())
+    }
+}
+
+/// Sets the limits new folders and files must meet. They apply from the
+/// next call on and do not affect items that already exist.
+#[ic_cdk::update]
+fn set_path_limits(limits: PathLimits) -> Result<(), DriveError> {
+    let _profile = profile("set_path_limits");
+    check_writable()?;
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if !state.is_drive_admin(caller) {
+            return Err(DriveError::unauthorized("Only a drive admin can change path limits"));
+        }
+        state.set_path_limits(limits)
+    })
+}
+
+/// For clients to validate names before sending them.
+#[ic_cdk::query]
+fn get_path_limits() -> Result<PathLimits, DriveError> {
+    check_read_access()?;
+    STATE.with(|state| Ok(state.borrow().path_limits.clone()))
+}
@@ -0,0 +1,49 @@
+// path_validation.rs
+//
+// Paths and names that are rejected outright rather than sanitized, since
+// no rewrite of them is what the caller meant. Configurable rules are in
+// `path_limits.rs`.
+
+use crate::errors::DriveError;
+
+const RESERVED_NAMES: &[&str] = &[".", ".."];
+
+fn check_characters(path: &str, text: &str) -> Result<(), DriveError> {
+    if text.chars().any(char::is_control) {
+        return Err(DriveError::invalid_path(path, "Contains a control character"));
+    }
+    Ok(())
+}
+
+/// Checks a path as sent by a client, before `sanitize_file_path` rewrites
+/// it. A `::` after the storage prefix would read as a second location, so
+/// it is refused instead of being turned into `;;` like a single `:`.
+pub(crate) fn check_raw_path(path: &str) -> Result<(), DriveError> {
+    check_characters(path, path)?;
+    let Some((storage, rest)) = path.split_once("::") else {
+        return Ok(());
+    };
+    if storage.is_empty() || storage.contains('/') {
+        return Err(DriveError::invalid_path(path, "The storage location prefix is missing or malformed"));
+    }
+    if rest.contains("::") {
+        return Err(DriveError::invalid_path(path, "Only the storage location may be followed by '::'"));
+    }
+    if let Some(reserved) = rest.split('/').find(|name| RESERVED_NAMES.contains(name)) {
+        return Err(DriveError::invalid_path(path, &format!("'{}' is not a valid name", reserved)));
+    }
+    Ok(())
+}
+
+/// Checks a single folder or file name, e.g. the target of a rename, whose
+/// full path would be `path`.
+pub(crate) fn check_name(path: &str, name: &str) -> Result<(), DriveError> {
+    check_characters(path, name)?;
+    if name.is_empty() || RESERVED_NAMES.contains(&name) {
+        return Err(DriveError::invalid_path(path, &format!("'{}' is not a valid name", name)));
+    }
+    if name.contains(['/', ':']) {
+        return Err(DriveError::invalid_path(path, "A name cannot contain '/' or ':'"));
+    }
+    Ok(())
+}
@@ -0,0 +1,322 @@
+// paywalls.rs
+//
+// A file can be put behind a paywall priced in an ICRC-2 token. Viewers who
+// cannot edit the file must pay before the drive issues them a signed or
+// presigned download URL, or serves it from a published folder; until then
+// the file's `raw_url` is left out wherever it is listed. Payment is
+// an ICRC-2 `transfer_from` the drive makes from the payer to the drive
+// owner, so the payer first approves the drive for the amount plus the
+// ledger fee; the block index the ledger returns is the proof of payment.
+//
+// A payment unlocks the version paid for and every later version. The
+// paywall only guards links the drive hands out: keep paywalled content in
+// storage that does not serve `raw_url` without a signature.
+
+use candid::{CandidType, Nat, Principal};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::audit::AuditAction;
+use crate::errors::DriveError;
+use crate::metrics::count_call;
+use crate::profiling::profile;
+use crate::rate_limit::charge_rate_limit;
+use crate::read_only::check_writable;
+use crate::system_api;
+use crate::token_gates::IcrcAccount;
+use crate::{FetchFilesResult, FileMetadata, FileUUID, State, STATE};
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Paywall {
+    pub(crate) token_ledger: Principal,
+    // In the ledger's smallest unit, e.g. e8s
+    pub(crate) amount: u64,
+}
+
+impl Paywall {
+    /// The price, for consent messages.
+    pub(crate) fn describe(&self) -> String {
+        format!("{} units of the token on ledger {}", self.amount, self.token_ledger)
+    }
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct PaywallPayment {
+    file_id: FileUUID,
+    token_ledger: Principal,
+    amount: u64,
+    block_index: u64,
+    paid_at_ms: u64,
+}
+
+// What the caller must pay for a file, or their earlier payment
+enum PaymentDue {
+    Pay(Paywall),
+    Paid(PaywallPayment),
+}
+
+// ICRC-2 types, as ledgers declare them
+
+#[derive(CandidType, Deserialize)]
+struct TransferFromArgs {
+    spender_subaccount: Option<Vec<u8>>,
+    from: IcrcAccount,
+    to: IcrcAccount,
+    amount: Nat,
+    fee: Option<Nat>,
+    memo: Option<Vec<u8>>,
+    created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+enum TransferFromError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    InsufficientAllowance { allowance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    TemporarilyUnavailable,
+    GenericError { error_code: Nat, message: String },
+}
+
+#[derive(CandidType, Deserialize)]
+enum TransferFromResult {
+    Ok(Nat),
+    Err(TransferFromError),
+}
+
+impl State {
+    fn set_paywall(
+        &mut self,
+        caller: Principal,
+        file_id: &FileUUID,
+        paywall: Option<Paywall>,
+        now_ms: u64,
+    ) -> Result<(), DriveError> {
+        if !self.is_drive_admin(caller) {
+            return Err(DriveError::unauthorized("Only a drive admin can set paywalls"));
+        }
+        let file = self
+            .file_uuid_to_metadata
+            .get(file_id)
+            .filter(|file| !file.deleted)
+            .ok_or_else(|| DriveError::file_not_found(file_id))?;
+        if file.next_version.is_some() {
+            return Err(DriveError::InvalidInput {
+                field: "file_id".to_string(),
+                reason: "Set the paywall on the newest version of the file".to_string(),
+            });
+        }
+        if paywall.as_ref().is_some_and(|paywall| paywall.amount == 0) {
+            return Err(DriveError::InvalidInput {
+                field: "amount".to_string(),
+                reason: "Must be positive".to_string(),
+            });
+        }
+        let path = file.full_file_path.clone();
+        for mut version in self.version_chain(file) {
+            version.paywall = paywall.clone();
+            version.last_changed_unix_ms = now_ms;
+            version.revision += 1;
+            self.file_uuid_to_metadata.insert(version.id.clone(), version);
+        }
+        self.record_audit(AuditAction::SetPaywall, file_id, Some(path), None);
+        Ok(())
+    }
+
+    /// The caller's payment for `file` or an earlier version of it.
+    fn paywall_payment(&self, caller: Principal, file: &FileMetadata) -> Option<&PaywallPayment> {
+        let mut current = Some(file.id.clone());
+        while let Some(file_id) = current {
+            if let Some(payment) = self.paywall_payments.get(&(file_id.clone(), caller)) {
+                return Some(payment);
+            }
+            current = self.file_uuid_to_metadata.get(&file_id).and_then(|file| file.prior_version);
+        }
+        None
+    }
+
+    /// Fails unless the file has no paywall, the caller may edit it, or the
+    /// caller has paid for it. A shortcut is checked as the file it stands
+    /// for, since it hands out that file's content.
+    pub(crate) fn check_paywall(&self, caller: Principal, file: &FileMetadata) -> Result<(), DriveError> {
+        let target = file.shortcut_target.as_ref().and_then(|target_id| self.latest_version_of(target_id));
+        let file = target.as_ref().unwrap_or(file);
+        let Some(paywall) = &file.paywall else {
+            return Ok(());
+        };
+        if self.can_edit_file(caller, file) || self.paywall_payment(caller, file).is_some() {
+            return Ok(());
+        }
+        Err(DriveError::PaymentRequired {
+            file_id: file.id.clone(),
+            token_ledger: paywall.token_ledger.to_text(),
+            amount: paywall.amount,
+        })
+    }
+
+    /// `file` as `caller` may see it: without its `raw_url` or pinned
+    /// content ID while the paywall stands between them.
+    pub(crate) fn redact_unpaid(&self, caller: Principal, mut file: FileMetadata) -> FileMetadata {
+        if self.check_paywall(caller, &file).is_err() {
+            file.raw_url.clear();
+            file.permanent_id = None;
+        }
+        file
+    }
+
+    /// `redact_unpaid` for every file of a folder listing.
+    pub(crate) fn redact_unpaid_page(&self, caller: Principal, mut page: FetchFilesResult) -> FetchFilesResult {
+        page.files = page.files.into_iter().map(|file| self.redact_unpaid(caller, file)).collect();
+        page
+    }
+
+    /// Moves payments for a removed version to the version after it, so
+    /// pruning old versions does not make payers pay again.
+    pub(crate) fn carry_paywall_payments(&mut self, file_id: &FileUUID, next_version: Option<&FileUUID>) {
+        let payers: Vec<Principal> = self
+            .paywall_payments
+            .keys()
+            .filter(|(paid_file_id, _)| paid_file_id == file_id)
+            .map(|(_, payer)| *payer)
+            .collect();
+        for payer in payers {
+            if let Some(payment) = self.paywall_payments.remove(&(file_id.clone(), payer)) {
+                if let Some(next_version) = next_version {
+                    self.paywall_payments.entry((next_version.clone(), payer)).or_insert(payment);
+                }
+            }
+        }
+    }
+
+    fn payment_due(&self, caller: Principal, file_id: &FileUUID) -> Result<PaymentDue, DriveError> {
+        let file = self
+            .file_uuid_to_metadata
+            .get(file_id)
+            .filter(|file| !file.deleted)
+            .ok_or_else(|| DriveError::file_not_found(file_id))?;
+        if !self.can_view_file(caller, &file) && !self.is_published(&file.folder_uuid) {
+            return Err(DriveError::unauthorized("Caller cannot view this file"));
+        }
+        let paywall = file.paywall.clone().ok_or_else(|| DriveError::InvalidState {
+            reason: "The file has no paywall".to_string(),
+        })?;
+        Ok(match self.paywall_payment(caller, &file) {
+            Some(payment) => PaymentDue::Paid(payment.clone()),
+            None => PaymentDue::Pay(paywall),
+        })
+    }
+}
+
+async fn transfer_from(
+    payer: Principal,
+    recipient: Principal,
+    file_id: &FileUUID,
+    paywall: &Paywall,
+) -> Result<u64, DriveError> {
+    let args = TransferFromArgs {
+        spender_subaccount: None,
+        from: IcrcAccount { owner: payer, subaccount: None },
+        to: IcrcAccount { owner: recipient, subaccount: None },
+        amount: Nat::from(paywall.amount),
+        fee: None,
+        // ICRC-1 memos are at most 32 bytes
+        memo: Some(Sha256::digest(file_id.as_bytes()).to_vec()),
+        created_at_time: Some(system_api::time()),
+    };
+    let (result,): (TransferFromResult,) = ic_cdk::call(paywall.token_ledger, "icrc2_transfer_from", (args,))
+        .await
+        .map_err(|(code, message)| DriveError::Internal {
+            message: format!("Failed to call ledger {}: {:?} {}", paywall.token_ledger, code, message),
+        })?;
+    match result {
+        TransferFromResult::Ok(block_index) => u64::try_from(&block_index.0).map_err(|_| DriveError::Internal {
+            message: format!("Ledger returned an out of range block index {}", block_index),
+        }),
+        TransferFromResult::Err(e) => {
+            Err(DriveError::InvalidState { reason: format!("The ledger refused the transfer: {:?}", e) })
+        }
+    }
+}
+
+/// Puts the file, with all its versions, behind a paywall: viewers must pay
+/// `amount` of `token_ledger`'s token to the drive owner before getting a
+/// download link.
+#[ic_cdk::update]
+fn set_paywall(file_id: FileUUID, token_ledger: Principal, amount: u64) -> Result<(), DriveError> {
+    let _profile = profile("set_paywall");
+    check_writable()?;
+    let caller = system_api::caller();
+    let paywall = Paywall { token_ledger, amount };
+    let now_ms = system_api::time() / 1_000_000;
+    STATE.with(|state| state.borrow_mut().set_paywall(caller, &file_id, Some(paywall), now_ms))
+}
+
+/// Takes the paywall off the file. Earlier payments are kept.
+#[ic_cdk::update]
+fn remove_paywall(file_id: FileUUID) -> Result<(), DriveError> {
+    let _profile = profile("remove_paywall");
+    check_writable()?;
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow_mut().set_paywall(caller, &file_id, None, system_api::time() / 1_000_000))
+}
+
+/// Pays the file's paywall with an ICRC-2 `transfer_from` from the caller to
+/// the drive owner. Callers who already paid get their earlier payment back
+/// and are not charged again.
+#[ic_cdk::update]
+async fn pay_for_file(file_id: FileUUID) -> Result<PaywallPayment, DriveError> {
+    let _profile = profile("pay_for_file");
+    let caller = system_api::caller();
+    count_call("pay_for_file");
+    check_writable()?;
+    // Payers need not be members of the drive
+    charge_rate_limit(caller, "pay_for_file", 1)?;
+    if caller == Principal::anonymous() {
+        return Err(DriveError::unauthorized("Anonymous callers cannot pay"));
+    }
+    let (due, owner) = STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        let due = state.payment_due(caller, &file_id)?;
+        if matches!(due, PaymentDue::Pay(_)) && !state.paywall_payments_in_flight.insert((file_id.clone(), caller)) {
+            return Err(DriveError::InvalidState { reason: "A payment for this file is already underway".to_string() });
+        }
+        Ok((due, state.owner))
+    })?;
+    let paywall = match due {
+        PaymentDue::Pay(paywall) => paywall,
+        PaymentDue::Paid(payment) => return Ok(payment),
+    };
+
+    let result = transfer_from(caller, owner, &file_id, &paywall).await;
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.paywall_payments_in_flight.remove(&(file_id.clone(), caller));
+        let payment = PaywallPayment {
+            file_id: file_id.clone(),
+            token_ledger: paywall.token_ledger,
+            amount: paywall.amount,
+            block_index: result?,
+            paid_at_ms: system_api::time() / 1_000_000,
+        };
+        state.paywall_payments.insert((file_id.clone(), caller), payment.clone());
+        state.record_audit(AuditAction::PayForFile, &file_id, None, None);
+        Ok(payment)
+    })
+}
+
+/// The caller's payment for the file, if they made one.
+#[ic_cdk::query]
+fn get_paywall_payment(file_id: FileUUID) -> Result<Option<PaywallPayment>, DriveError> {
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let state = state.borrow();
+        let file = state
+            .file_uuid_to_metadata
+            .get(&file_id)
+            .ok_or_else(|| DriveError::file_not_found(&file_id))?;
+        Ok(state.paywall_payment(caller, &file).cloned())
+    })
+}
@@ -0,0 +1,225 @@
+// pinning.rs
+//
+// Files in an `Ipfs` or `Arweave` storage location can be pinned to make
+// their content permanent. The drive POSTs the file's `raw_url` to the
+// pinning service named by the location's `pin_endpoint` config entry; the
+// service fetches the content itself and answers with the IPFS CID (`cid`)
+// or Arweave transaction (`transaction_id`). If the owner stored a
+// `PinningService` token for the location (see `credential_vault.rs`), it
+// is sent as a bearer token. The location's optional `gateway` entry is
+// listed first among the permanent links.
+
+use candid::{CandidType, Principal};
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod, HttpResponse, TransformArgs,
+    TransformContext,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::audit::AuditAction;
+use crate::errors::DriveError;
+use crate::profiling::profile;
+use crate::rate_limit::check_update_allowed;
+use crate::storage::{StorageKind, StorageLocation};
+use crate::system_api;
+use crate::upload_status::UploadStatus;
+use crate::{FileMetadata, FileUUID, State, STATE};
+
+const PIN_OUTCALL_CYCLES: u128 = 2_000_000_000;
+const PIN_MAX_RESPONSE_BYTES: u64 = 16_384;
+const MAX_PERMANENT_ID_LENGTH: usize = 128;
+const IPFS_GATEWAYS: [&str; 2] = ["https://ipfs.io/ipfs/", "https://dweb.link/ipfs/"];
+const ARWEAVE_GATEWAYS: [&str; 1] = ["https://arweave.net/"];
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum PermanentId {
+    Ipfs { cid: String },
+    Arweave { transaction_id: String },
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct PermanentLink {
+    permanent_id: PermanentId,
+    // The location's own gateway first, then public ones
+    gateway_urls: Vec<String>,
+}
+
+struct PinRequest {
+    pin_endpoint: String,
+    api_token: Option<String>,
+    body: String,
+    ipfs: bool,
+}
+
+/// The content ID in a pinning service response, under any of the names
+/// services use for it.
+fn permanent_id_in_body(body: &[u8]) -> Option<String> {
+    let response: serde_json::Value = serde_json::from_slice(body).ok()?;
+    let id = ["cid", "transaction_id", "id"]
+        .iter()
+        .find_map(|name| response.get(name).and_then(|value| value.as_str()))?;
+    let valid_chars = id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    (!id.is_empty() && id.len() <= MAX_PERMANENT_ID_LENGTH && valid_chars).then(|| id.to_string())
+}
+
+fn gateway_urls(location: &StorageLocation, permanent_id: &PermanentId) -> Vec<String> {
+    let (id, public_gateways) = match permanent_id {
+        PermanentId::Ipfs { cid } => (cid, IPFS_GATEWAYS.as_slice()),
+        PermanentId::Arweave { transaction_id } => (transaction_id, ARWEAVE_GATEWAYS.as_slice()),
+    };
+    let configured = location
+        .config_value("gateway")
+        .filter(|gateway| gateway.starts_with("https://"))
+        .map(|gateway| format!("{}/", gateway.trim_end_matches('/')));
+    configured
+        .into_iter()
+        .chain(public_gateways.iter().map(|gateway| gateway.to_string()))
+        .map(|gateway| format!("{}{}", gateway, id))
+        .collect()
+}
+
+impl State {
+    fn pinning_location(&self, file: &FileMetadata) -> Result<&StorageLocation, DriveError> {
+        self.storage_locations
+            .get(&file.storage_location)
+            .filter(|location| matches!(location.kind, StorageKind::Ipfs | StorageKind::Arweave))
+            .ok_or_else(|| DriveError::InvalidState {
+                reason: "Only files in an Ipfs or Arweave storage location can be pinned".to_string(),
+            })
+    }
+
+    fn pin_request(&self, file: &FileMetadata) -> Result<PinRequest, DriveError> {
+        if file.deleted || file.upload_status != UploadStatus::Uploaded || !file.raw_url.starts_with("https://") {
+            return Err(DriveError::InvalidState {
+                reason: "Only uploaded files with an https raw_url can be pinned".to_string(),
+            });
+        }
+        let location = self.pinning_location(file)?;
+        let pin_endpoint = location
+            .config_value("pin_endpoint")
+            .filter(|endpoint| endpoint.starts_with("https://"))
+            .ok_or_else(|| DriveError::InvalidState {
+                reason: format!("Storage location {} has no https pin_endpoint configured", file.storage_location),
+            })?;
+        let api_token = match self.storage_credentials.get(&file.storage_location) {
+            Some(credentials) if !credentials.can_presign() => Some(self.unseal_secret(credentials)?),
+            _ => None,
+        };
+        let body = serde_json::json!({
+            "source_url": file.raw_url,
+            "name": file.original_file_name,
+            "content_type": file.mime_type,
+            "sha256": file.sha256_checksum,
+        });
+        Ok(PinRequest {
+            pin_endpoint: pin_endpoint.to_string(),
+            api_token,
+            body: body.to_string(),
+            ipfs: location.kind == StorageKind::Ipfs,
+        })
+    }
+
+    fn record_pin(&mut self, file_id: &FileUUID, permanent_id: PermanentId, now_ms: u64) -> Result<(), DriveError> {
+        let mut file = self
+            .file_uuid_to_metadata
+            .get(file_id)
+            .ok_or_else(|| DriveError::file_not_found(file_id))?;
+        file.permanent_id = Some(permanent_id);
+        file.last_changed_unix_ms = now_ms;
+        file.revision += 1;
+        let path = file.full_file_path.clone();
+        self.replace_file_metadata(file);
+        self.record_audit(AuditAction::PinFile, file_id, Some(path), None);
+        Ok(())
+    }
+
+    pub(crate) fn permanent_link(&self, caller: Principal, file_id: &FileUUID) -> Result<PermanentLink, DriveError> {
+        let file = self
+            .file_uuid_to_metadata
+            .get(file_id)
+            .filter(|file| !file.deleted)
+            .ok_or_else(|| DriveError::file_not_found(file_id))?;
+        if !self.can_view_file(caller, &file) {
+            return Err(DriveError::unauthorized("Caller cannot view this file"));
+        }
+        // The gateways serve the content to anyone holding the link
+        self.check_paywall(caller, &file)?;
+        let permanent_id = file.permanent_id.clone().ok_or_else(|| DriveError::InvalidState {
+            reason: "The file has not been pinned".to_string(),
+        })?;
+        let location = self.pinning_location(&file)?;
+        Ok(PermanentLink { gateway_urls: gateway_urls(location, &permanent_id), permanent_id })
+    }
+}
+
+async fn send_pin_request(file_id: &FileUUID, pin: PinRequest) -> Result<PermanentId, DriveError> {
+    let mut headers = vec![
+        HttpHeader { name: "Content-Type".to_string(), value: "application/json".to_string() },
+        // Every replica sends the request, so services should dedupe on this
+        HttpHeader { name: "Idempotency-Key".to_string(), value: file_id.clone() },
+    ];
+    if let Some(api_token) = pin.api_token {
+        headers.push(HttpHeader { name: "Authorization".to_string(), value: format!("Bearer {}", api_token) });
+    }
+    let request = CanisterHttpRequestArgument {
+        url: pin.pin_endpoint,
+        method: HttpMethod::POST,
+        body: Some(pin.body.into_bytes()),
+        max_response_bytes: Some(PIN_MAX_RESPONSE_BYTES),
+        transform: Some(TransformContext::from_name("transform_pin_response".to_string(), vec![])),
+        headers,
+    };
+    let (response,) = http_request(request, PIN_OUTCALL_CYCLES).await.map_err(|(code, message)| {
+        DriveError::Internal { message: format!("Pinning request failed: {:?} {}", code, message) }
+    })?;
+    let status: u64 = response.status.0.clone().try_into().unwrap_or(0);
+    if !(200..300).contains(&status) || response.body.is_empty() {
+        return Err(DriveError::Internal {
+            message: format!("Pinning service answered with status {} and no content ID", status),
+        });
+    }
+    // The transform left only the content ID in the body
+    let id = String::from_utf8_lossy(&response.body).to_string();
+    Ok(if pin.ipfs { PermanentId::Ipfs { cid: id } } else { PermanentId::Arweave { transaction_id: id } })
+}
+
+/// Pins the file's content with the pinning service of its storage location
+/// and records the resulting CID or transaction ID. Files already pinned
+/// return their existing link.
+#[ic_cdk::update]
+async fn pin_file(file_id: FileUUID) -> Result<PermanentLink, DriveError> {
+    let _profile = profile("pin_file");
+    check_update_allowed("pin_file")?;
+    let caller = system_api::caller();
+    let pin = STATE.with(|state| {
+        let state = state.borrow();
+        let file = state.editable_file(caller, &file_id)?;
+        if file.permanent_id.is_some() {
+            return Ok(None);
+        }
+        state.pin_request(&file).map(Some)
+    })?;
+    if let Some(pin) = pin {
+        let permanent_id = send_pin_request(&file_id, pin).await?;
+        STATE.with(|state| state.borrow_mut().record_pin(&file_id, permanent_id, system_api::time() / 1_000_000))?;
+    }
+    STATE.with(|state| state.borrow().permanent_link(caller, &file_id))
+}
+
+/// Gateway URLs for the pinned content of the file.
+#[ic_cdk::query]
+fn get_permanent_link(file_id: FileUUID) -> Result<PermanentLink, DriveError> {
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow().permanent_link(caller, &file_id))
+}
+
+/// Reduces the response to its status and the content ID, dropping headers
+/// and anything else the service returns, so replicas agree on it.
+#[ic_cdk::query]
+fn transform_pin_response(args: TransformArgs) -> HttpResponse {
+    HttpResponse {
+        status: args.response.status,
+        headers: Vec::new(),
+        body: permanent_id_in_body(&args.response.body).unwrap_or_default().into_bytes(),
+    }
+}
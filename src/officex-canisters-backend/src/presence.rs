@@ -0,0 +1,119 @@
+// presence.rs
+
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+use crate::errors::DriveError;
+use crate::members::check_read_access;
+use crate::profiling::profile;
+use crate::rate_limit::check_update_allowed;
+use crate::system_api;
+use crate::{generate_unique_id, FileUUID, State, STATE};
+
+// Editors heartbeat well within this; a closed tab drops out once it passes
+const SESSION_TTL_MS: u64 = 60 * 1000;
+const MAX_EDITING_SESSIONS: usize = 1_000;
+
+/// Someone with a document open. Sessions live on the heap only, so an
+/// upgrade simply makes every editor rejoin on its next heartbeat.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct EditingSession {
+    session_id: String,
+    file_id: FileUUID,
+    user: Principal,
+    joined_at_ms: u64,
+    last_seen_ms: u64,
+}
+
+fn session_not_found(session_id: &str) -> DriveError {
+    DriveError::NotFound { resource: "editing session".to_string(), id: session_id.to_string() }
+}
+
+impl State {
+    fn join_editing_session(&mut self, caller: Principal, file_id: FileUUID, now_ms: u64) -> Result<EditingSession, DriveError> {
+        let file = self
+            .file_uuid_to_metadata
+            .get(&file_id)
+            .filter(|file| !file.deleted)
+            .ok_or_else(|| DriveError::file_not_found(&file_id))?;
+        if !self.can_view_file(caller, &file) {
+            return Err(DriveError::unauthorized("Caller cannot open this file"));
+        }
+        self.editing_sessions.retain(|_, session| session.last_seen_ms + SESSION_TTL_MS > now_ms);
+        if self.editing_sessions.len() >= MAX_EDITING_SESSIONS {
+            return Err(DriveError::InvalidState { reason: "Too many open editing sessions".to_string() });
+        }
+
+        let session = EditingSession {
+            session_id: generate_unique_id(),
+            file_id,
+            user: caller,
+            joined_at_ms: now_ms,
+            last_seen_ms: now_ms,
+        };
+        self.editing_sessions.insert(session.session_id.clone(), session.clone());
+        Ok(session)
+    }
+
+    fn heartbeat_session(&mut self, caller: Principal, session_id: &str, now_ms: u64) -> Result<(), DriveError> {
+        let session = self
+            .editing_sessions
+            .get_mut(session_id)
+            .filter(|session| session.user == caller && session.last_seen_ms + SESSION_TTL_MS > now_ms)
+            .ok_or_else(|| session_not_found(session_id))?;
+        session.last_seen_ms = now_ms;
+        Ok(())
+    }
+
+    fn leave_editing_session(&mut self, caller: Principal, session_id: &str) -> Result<(), DriveError> {
+        match self.editing_sessions.get(session_id) {
+            Some(session) if session.user == caller => {
+                self.editing_sessions.remove(session_id);
+                Ok(())
+            }
+            _ => Err(session_not_found(session_id)),
+        }
+    }
+
+    fn list_active_editors(&self, file_id: &FileUUID, now_ms: u64) -> Vec<EditingSession> {
+        self.editing_sessions
+            .values()
+            .filter(|session| &session.file_id == file_id && session.last_seen_ms + SESSION_TTL_MS > now_ms)
+            .cloned()
+            .collect()
+    }
+}
+
+/// Announces that the caller opened a file. Keep the session alive with
+/// `heartbeat_session` at least every 60 seconds.
+#[ic_cdk::update]
+fn join_editing_session(file_id: FileUUID) -> Result<EditingSession, DriveError> {
+    let _profile = profile("join_editing_session");
+    check_update_allowed("join_editing_session")?;
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow_mut().join_editing_session(caller, file_id, system_api::time() / 1_000_000))
+}
+
+/// Fails with `NotFound` once the session has expired; join again then.
+#[ic_cdk::update]
+fn heartbeat_session(session_id: String) -> Result<(), DriveError> {
+    let _profile = profile("heartbeat_session");
+    check_update_allowed("heartbeat_session")?;
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow_mut().heartbeat_session(caller, &session_id, system_api::time() / 1_000_000))
+}
+
+#[ic_cdk::update]
+fn leave_editing_session(session_id: String) -> Result<(), DriveError> {
+    let _profile = profile("leave_editing_session");
+    check_update_allowed("leave_editing_session")?;
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow_mut().leave_editing_session(caller, &session_id))
+}
+
+/// Everyone who currently has the file open, one entry per session.
+#[ic_cdk::query]
+fn list_active_editors(file_id: FileUUID) -> Result<Vec<EditingSession>, DriveError> {
+    check_read_access()?;
+    STATE.with(|state| Ok(state.borrow().list_active_editors(&file_id, system_api::time() / 1_000_000)))
+}
@@ -0,0 +1,192 @@
+// presigning.rs
+//
+// Presigned S3 URLs, signed locally with AWS Signature Version 4 from the
+// credentials in the vault (see `credential_vault.rs`). Objects are stored
+// path-style at `<endpoint>/<bucket>/<object key>`, where the key is the
+// file path without its storage prefix. Clients upload to the presigned URL
+// and then record the plain `object_url` as the file's `raw_url`, which is
+// what `presign_download` later signs.
+
+use candid::{CandidType, Principal};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::credential_vault::SealedCredentials;
+use crate::errors::DriveError;
+use crate::profiling::profile;
+use crate::rate_limit::check_update_allowed;
+use crate::system_api;
+use crate::webdav::percent_decode;
+use crate::{FileUUID, State, STATE};
+
+const DEFAULT_PRESIGN_TTL_SECONDS: u64 = 15 * 60;
+// The longest expiry SigV4 allows
+const MAX_PRESIGN_TTL_SECONDS: u64 = 7 * 24 * 60 * 60;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct PresignedUrl {
+    url: String,
+    // `PUT` for uploads, `GET` for downloads
+    method: String,
+    object_key: String,
+    // The object's unsigned URL, to record as the file's `raw_url`
+    object_url: String,
+    expires_at_ms: u64,
+}
+
+fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key length");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Percent-encodes everything but unreserved characters, and `/` when
+/// encoding a path, as SigV4 requires.
+fn uri_encode(value: &str, keep_slash: bool) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+            b'/' if keep_slash => "/".to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+fn check_ttl(ttl_seconds: Option<u64>) -> Result<u64, DriveError> {
+    let ttl_seconds = ttl_seconds.unwrap_or(DEFAULT_PRESIGN_TTL_SECONDS);
+    if !(1..=MAX_PRESIGN_TTL_SECONDS).contains(&ttl_seconds) {
+        return Err(DriveError::InvalidInput {
+            field: "ttl_seconds".to_string(),
+            reason: format!("Must be between 1 and {}", MAX_PRESIGN_TTL_SECONDS),
+        });
+    }
+    Ok(ttl_seconds)
+}
+
+/// Builds a SigV4 query-string presigned URL for `method` on `object_key`.
+fn presign(
+    credentials: &SealedCredentials,
+    secret: &str,
+    method: &str,
+    object_key: &str,
+    ttl_seconds: u64,
+    now_ms: u64,
+) -> PresignedUrl {
+    let host = credentials.endpoint.trim_start_matches("https://");
+    let canonical_uri = format!("/{}/{}", uri_encode(&credentials.bucket, false), uri_encode(object_key, true));
+    let timestamp = chrono::DateTime::from_timestamp_millis(now_ms as i64).unwrap_or_default();
+    let amz_date = timestamp.format("%Y%m%dT%H%M%SZ").to_string();
+    let date = timestamp.format("%Y%m%d").to_string();
+    let scope = format!("{}/{}/s3/aws4_request", date, credentials.region);
+
+    // Already in the sorted order SigV4 expects
+    let canonical_query = [
+        ("X-Amz-Algorithm", "AWS4-HMAC-SHA256".to_string()),
+        ("X-Amz-Credential", format!("{}/{}", credentials.access_key_id, scope)),
+        ("X-Amz-Date", amz_date.clone()),
+        ("X-Amz-Expires", ttl_seconds.to_string()),
+        ("X-Amz-SignedHeaders", "host".to_string()),
+    ]
+    .iter()
+    .map(|(name, value)| format!("{}={}", name, uri_encode(value, false)))
+    .collect::<Vec<_>>()
+    .join("&");
+    let canonical_request =
+        format!("{}\n{}\n{}\nhost:{}\n\nhost\nUNSIGNED-PAYLOAD", method, canonical_uri, canonical_query, host);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        to_hex(&Sha256::digest(canonical_request.as_bytes()))
+    );
+
+    let signing_key = [date.as_str(), credentials.region.as_str(), "s3", "aws4_request"]
+        .iter()
+        .fold(format!("AWS4{}", secret).into_bytes(), |key, part| hmac_sha256(&key, part));
+    let signature = to_hex(&hmac_sha256(&signing_key, &string_to_sign));
+
+    PresignedUrl {
+        url: format!("{}{}?{}&X-Amz-Signature={}", credentials.endpoint, canonical_uri, canonical_query, signature),
+        method: method.to_string(),
+        object_key: object_key.to_string(),
+        object_url: format!("{}{}", credentials.endpoint, canonical_uri),
+        expires_at_ms: now_ms + ttl_seconds * 1000,
+    }
+}
+
+impl State {
+    fn credentials_for(&self, storage_location: &str) -> Result<&SealedCredentials, DriveError> {
+        self.storage_credentials
+            .get(storage_location)
+            .filter(|credentials| credentials.can_presign())
+            .ok_or_else(|| DriveError::InvalidState {
+                reason: format!("No S3 credentials are set for {}", storage_location),
+            })
+    }
+
+    fn presign_upload(&self, file_path: &str, ttl_seconds: u64, now_ms: u64) -> Result<PresignedUrl, DriveError> {
+        let file_path = Self::sanitize_file_path(file_path)?;
+        let (storage_location, object_key) = file_path.split_once("::").unwrap_or_default();
+        self.check_storage_location(&storage_location.to_string())?;
+        let credentials = self.credentials_for(storage_location)?;
+        let secret = self.unseal_secret(credentials)?;
+        Ok(presign(credentials, &secret, "PUT", object_key, ttl_seconds, now_ms))
+    }
+
+    fn presign_download(
+        &self,
+        caller: Principal,
+        file_id: &FileUUID,
+        ttl_seconds: u64,
+        now_ms: u64,
+    ) -> Result<PresignedUrl, DriveError> {
+        let file = self
+            .file_uuid_to_metadata
+            .get(file_id)
+            .filter(|file| !file.deleted)
+            .ok_or_else(|| DriveError::file_not_found(file_id))?;
+        if !self.can_view_file(caller, &file) {
+            return Err(DriveError::unauthorized("Caller cannot view this file"));
+        }
+        self.check_paywall(caller, &file)?;
+        let credentials = self.credentials_for(&file.storage_location)?;
+        let bucket_url = format!("{}/{}/", credentials.endpoint, uri_encode(&credentials.bucket, false));
+        let object_key = file.raw_url.strip_prefix(&bucket_url).ok_or_else(|| DriveError::InvalidState {
+            reason: "The file's raw_url is not an object in the configured bucket".to_string(),
+        })?;
+        // `raw_url` holds the key percent-encoded, as `object_url` returns it
+        let object_key = percent_decode(object_key);
+        let secret = self.unseal_secret(credentials)?;
+        Ok(presign(credentials, &secret, "GET", &object_key, ttl_seconds, now_ms))
+    }
+}
+
+/// A presigned `PUT` URL for uploading the object behind `file_path`, valid
+/// for `ttl_seconds` (15 minutes by default, at most 7 days).
+#[ic_cdk::update]
+fn presign_upload(file_path: String, ttl_seconds: Option<u64>) -> Result<PresignedUrl, DriveError> {
+    let _profile = profile("presign_upload");
+    check_update_allowed("presign_upload")?;
+    let ttl_seconds = check_ttl(ttl_seconds)?;
+    STATE.with(|state| state.borrow().presign_upload(&file_path, ttl_seconds, system_api::time() / 1_000_000))
+}
+
+/// A presigned `GET` URL for the file's content.
+#[ic_cdk::update]
+fn presign_download(file_id: FileUUID, ttl_seconds: Option<u64>) -> Result<PresignedUrl, DriveError> {
+    let _profile = profile("presign_download");
+    check_update_allowed("presign_download")?;
+    let ttl_seconds = check_ttl(ttl_seconds)?;
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        state
+            .borrow()
+            .presign_download(caller, &file_id, ttl_seconds, system_api::time() / 1_000_000)
+    })
+}
@@ -0,0 +1,99 @@
+// profiling.rs
+//
+// Instruction and latency figures per update endpoint, to spot operations
+// that get close to the per-message instruction limit as a drive grows.
+// Queries are not covered, as the state they write is thrown away.
+
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::DriveError;
+use crate::system_api;
+use crate::{State, STATE};
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default)]
+pub struct EndpointProfile {
+    calls: u64,
+    total_instructions: u128,
+    max_instructions: u64,
+    total_latency_ms: u64,
+    max_latency_ms: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct EndpointPerformance {
+    method: String,
+    calls: u64,
+    avg_instructions: u64,
+    max_instructions: u64,
+    // Only above zero for endpoints that await other canisters
+    avg_latency_ms: u64,
+    max_latency_ms: u64,
+}
+
+/// Measures the endpoint it is created in until it goes out of scope. Calls
+/// that trap are not recorded.
+pub(crate) struct CallProfile {
+    method: &'static str,
+    started_at_ns: u64,
+}
+
+/// Starts profiling the current update call; bind the result to `_profile`
+/// so it lives until the endpoint returns.
+pub(crate) fn profile(method: &'static str) -> CallProfile {
+    CallProfile { method, started_at_ns: system_api::time() }
+}
+
+impl Drop for CallProfile {
+    fn drop(&mut self) {
+        // Counts every message of the call, including those after an await
+        let instructions = ic_cdk::api::call_context_instruction_counter();
+        let latency_ms = system_api::time().saturating_sub(self.started_at_ns) / 1_000_000;
+        STATE.with(|state| {
+            if let Ok(mut state) = state.try_borrow_mut() {
+                state.record_call_profile(self.method, instructions, latency_ms);
+            }
+        });
+    }
+}
+
+impl State {
+    fn record_call_profile(&mut self, method: &str, instructions: u64, latency_ms: u64) {
+        let profile = self.endpoint_profiles.entry(method.to_string()).or_default();
+        profile.calls += 1;
+        profile.total_instructions += instructions as u128;
+        profile.max_instructions = profile.max_instructions.max(instructions);
+        profile.total_latency_ms = profile.total_latency_ms.saturating_add(latency_ms);
+        profile.max_latency_ms = profile.max_latency_ms.max(latency_ms);
+    }
+
+    fn performance_stats(&self) -> Vec<EndpointPerformance> {
+        let mut stats: Vec<EndpointPerformance> = self
+            .endpoint_profiles
+            .iter()
+            .map(|(method, profile)| EndpointPerformance {
+                method: method.clone(),
+                calls: profile.calls,
+                avg_instructions: (profile.total_instructions / profile.calls as u128) as u64,
+                max_instructions: profile.max_instructions,
+                avg_latency_ms: profile.total_latency_ms / profile.calls,
+                max_latency_ms: profile.max_latency_ms,
+            })
+            .collect();
+        stats.sort_by_key(|stat| std::cmp::Reverse(stat.max_instructions));
+        stats
+    }
+}
+
+/// Per endpoint costs since the last upgrade, heaviest first.
+#[ic_cdk::query]
+fn get_performance_stats() -> Result<Vec<EndpointPerformance>, DriveError> {
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let state = state.borrow();
+        if !state.is_drive_admin(caller) && !ic_cdk::api::is_controller(&caller) {
+            return Err(DriveError::unauthorized("Only a drive admin or a controller can read performance stats"));
+        }
+        Ok(state.performance_stats())
+    })
+}
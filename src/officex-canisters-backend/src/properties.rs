@@ -0,0 +1,172 @@
+// properties.rs
+
+use candid::Principal;
+
+use crate::audit::AuditAction;
+use crate::errors::DriveError;
+use crate::members::check_read_access;
+use crate::profiling::profile;
+use crate::rate_limit::check_update_allowed;
+use crate::sharing::ShareRole;
+use crate::system_api;
+use crate::{FileMetadata, FolderMetadata, State, STATE};
+
+const MAX_PROPERTIES: usize = 64;
+const MAX_PROPERTY_KEY_LENGTH: usize = 128;
+const MAX_PROPERTY_VALUE_LENGTH: usize = 4_096;
+
+/// Folder or file a property edit applies to, with its current metadata.
+enum PropertyTarget {
+    Folder(Box<FolderMetadata>),
+    File(Box<FileMetadata>),
+}
+
+impl PropertyTarget {
+    fn properties_mut(&mut self) -> &mut Vec<(String, String)> {
+        match self {
+            PropertyTarget::Folder(folder) => &mut folder.properties,
+            PropertyTarget::File(file) => &mut file.properties,
+        }
+    }
+
+    fn into_properties(self) -> Vec<(String, String)> {
+        match self {
+            PropertyTarget::Folder(folder) => folder.properties,
+            PropertyTarget::File(file) => file.properties,
+        }
+    }
+}
+
+fn validate_property(key: &str, value: &str) -> Result<(), DriveError> {
+    if key.is_empty() || key.chars().count() > MAX_PROPERTY_KEY_LENGTH {
+        return Err(DriveError::InvalidInput {
+            field: "key".to_string(),
+            reason: format!("Property keys must be between 1 and {} characters", MAX_PROPERTY_KEY_LENGTH),
+        });
+    }
+    if value.chars().count() > MAX_PROPERTY_VALUE_LENGTH {
+        return Err(DriveError::InvalidInput {
+            field: "value".to_string(),
+            reason: format!("Property values are limited to {} characters", MAX_PROPERTY_VALUE_LENGTH),
+        });
+    }
+    Ok(())
+}
+
+impl State {
+    fn property_target(&self, resource_id: &String) -> Result<PropertyTarget, DriveError> {
+        if let Some(folder) = self.folder_uuid_to_metadata.get(resource_id) {
+            return Ok(PropertyTarget::Folder(Box::new(folder)));
+        }
+        self.file_uuid_to_metadata
+            .get(resource_id)
+            .map(|file| PropertyTarget::File(Box::new(file)))
+            .ok_or_else(|| DriveError::NotFound { resource: "item".to_string(), id: resource_id.clone() })
+    }
+
+    fn editable_property_target(&self, caller: Principal, resource_id: &String) -> Result<PropertyTarget, DriveError> {
+        let target = self.property_target(resource_id)?;
+        let allowed = match &target {
+            PropertyTarget::Folder(folder) => {
+                self.can_edit_drive(caller)
+                    || caller == folder.owner
+                    || self.has_folder_share_role(caller, &folder.id, ShareRole::Editor)
+            }
+            PropertyTarget::File(file) => self.can_edit_file(caller, file),
+        };
+        if !allowed {
+            return Err(DriveError::unauthorized("Caller cannot edit this item"));
+        }
+        Ok(target)
+    }
+
+    fn save_property_target(&mut self, target: PropertyTarget) {
+        let now_ms = system_api::time() / 1_000_000;
+        match target {
+            PropertyTarget::Folder(mut folder) => {
+                folder.last_changed_unix_ms = now_ms;
+                folder.revision += 1;
+                let path = folder.full_folder_path.clone();
+                let id = folder.id.clone();
+                self.folder_uuid_to_metadata.insert(id.clone(), *folder);
+                self.record_audit(AuditAction::SetProperty, &id, Some(path), None);
+            }
+            PropertyTarget::File(mut file) => {
+                file.last_changed_unix_ms = now_ms;
+                file.revision += 1;
+                let path = file.full_file_path.clone();
+                let id = file.id.clone();
+                self.replace_file_metadata(*file);
+                self.record_audit(AuditAction::SetProperty, &id, Some(path), None);
+            }
+        }
+    }
+
+    fn set_property(&mut self, caller: Principal, resource_id: String, key: String, value: String) -> Result<(), DriveError> {
+        validate_property(&key, &value)?;
+        let mut target = self.editable_property_target(caller, &resource_id)?;
+        let properties = target.properties_mut();
+        match properties.iter_mut().find(|(existing, _)| *existing == key) {
+            Some((_, existing_value)) => *existing_value = value,
+            None => {
+                if properties.len() >= MAX_PROPERTIES {
+                    return Err(DriveError::InvalidState {
+                        reason: format!("An item can have at most {} properties", MAX_PROPERTIES),
+                    });
+                }
+                properties.push((key, value));
+            }
+        }
+        self.save_property_target(target);
+        Ok(())
+    }
+
+    fn delete_property(&mut self, caller: Principal, resource_id: String, key: String) -> Result<(), DriveError> {
+        let mut target = self.editable_property_target(caller, &resource_id)?;
+        let properties = target.properties_mut();
+        let before = properties.len();
+        properties.retain(|(existing, _)| *existing != key);
+        if properties.len() == before {
+            return Err(DriveError::NotFound { resource: "property".to_string(), id: key });
+        }
+        self.save_property_target(target);
+        Ok(())
+    }
+}
+
+/// Sets an app-defined key-value property on a folder or file, replacing
+/// any existing value for the key.
+#[ic_cdk::update]
+fn set_property(
+    resource_id: String,
+    key: String,
+    value: String,
+    expected_revision: Option<u64>,
+) -> Result<(), DriveError> {
+    let _profile = profile("set_property");
+    check_update_allowed("set_property")?;
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.check_item_revision(&resource_id, expected_revision)?;
+        state.set_property(caller, resource_id, key, value)
+    })
+}
+
+#[ic_cdk::update]
+fn delete_property(resource_id: String, key: String, expected_revision: Option<u64>) -> Result<(), DriveError> {
+    let _profile = profile("delete_property");
+    check_update_allowed("delete_property")?;
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.check_item_revision(&resource_id, expected_revision)?;
+        state.delete_property(caller, resource_id, key)
+    })
+}
+
+#[ic_cdk::query]
+fn get_properties(resource_id: String) -> Result<Vec<(String, String)>, DriveError> {
+    check_read_access()?;
+    STATE.with(|state| state.borrow().property_target(&resource_id).map(PropertyTarget::into_properties))
+}
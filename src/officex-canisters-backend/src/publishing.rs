@@ -0,0 +1,208 @@
+// publishing.rs
+//
+// Published folders are readable by anyone, including anonymous callers and
+// plain HTTP clients, together with everything below them. The rest of the
+// drive stays as private as it was. Through the HTTP gateway:
+//
+//   GET /public/folders/:id?limit=&after=
+//   GET /public/files/:id
+
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+use crate::audit::AuditAction;
+use crate::directory_profile::sync_directory_listing;
+use crate::errors::DriveError;
+use crate::profiling::profile;
+use crate::read_only::check_writable;
+use crate::rest_api::{error_response, json_response, GatewayRequest, GatewayResponse};
+use crate::system_api;
+use crate::{FetchFilesResult, FileMetadata, FileUUID, FolderUUID, State, STATE};
+
+const DEFAULT_PAGE_SIZE: u32 = 100;
+const MAX_PAGE_SIZE: u32 = 1_000;
+const MAX_PUBLISHED_FOLDERS: usize = 1_000;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct PublishedFolder {
+    pub folder_id: FolderUUID,
+    pub published_by: Principal,
+    pub published_at_ms: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct PublishedFolderInfo {
+    pub folder: PublishedFolder,
+    pub full_folder_path: String,
+}
+
+impl State {
+    /// Whether `folder_id` or one of its ancestors is published.
+    pub(crate) fn is_published(&self, folder_id: &FolderUUID) -> bool {
+        self.get_ancestors(folder_id)
+            .is_ok_and(|chain| chain.iter().any(|folder| self.published_folders.contains_key(&folder.id)))
+    }
+
+    pub(crate) fn public_folder_page(
+        &self,
+        caller: Principal,
+        folder_id: &FolderUUID,
+        limit: u32,
+        after: u32,
+    ) -> Result<FetchFilesResult, DriveError> {
+        self.folder_uuid_to_metadata
+            .get(folder_id)
+            .filter(|folder| !folder.deleted && self.is_published(folder_id))
+            .ok_or_else(|| DriveError::folder_not_found(folder_id))?;
+        // Deleted folders keep their place in the child index, so they are
+        // dropped before paging to keep pages full and offsets stable
+        let children = || {
+            self.listed_children(folder_id, true, true)
+                .filter(|(folder, _)| !folder.as_ref().is_some_and(|folder| folder.deleted))
+        };
+        let total = children().count();
+        let mut folders = Vec::new();
+        let mut files = Vec::new();
+        for (folder, file) in children().skip(after as usize).take(limit.min(MAX_PAGE_SIZE) as usize) {
+            folders.extend(folder);
+            files.extend(file.map(|file| self.redact_unpaid(caller, file)));
+        }
+        let has_more = (after as usize + folders.len() + files.len()) < total;
+        Ok(FetchFilesResult { folders, files, total: total as u32, has_more })
+    }
+
+    fn public_file(&self, caller: Principal, file_id: &FileUUID) -> Result<FileMetadata, DriveError> {
+        let file = self
+            .file_uuid_to_metadata
+            .get(file_id)
+            .filter(|file| !file.deleted && self.is_published(&file.folder_uuid))
+            .map(|file| self.resolve_shortcut(file))
+            .ok_or_else(|| DriveError::file_not_found(file_id))?;
+        self.check_paywall(caller, &file)?;
+        Ok(file)
+    }
+
+    pub(crate) fn publish_folder(&mut self, caller: Principal, folder_id: &FolderUUID) -> Result<(), DriveError> {
+        let folder = self
+            .folder_uuid_to_metadata
+            .get(folder_id)
+            .filter(|folder| !folder.deleted)
+            .ok_or_else(|| DriveError::folder_not_found(folder_id))?;
+        if self.published_folders.contains_key(folder_id) {
+            return Ok(());
+        }
+        if self.published_folders.len() >= MAX_PUBLISHED_FOLDERS {
+            return Err(DriveError::InvalidState {
+                reason: format!("At most {} folders can be published", MAX_PUBLISHED_FOLDERS),
+            });
+        }
+        let published = PublishedFolder {
+            folder_id: folder_id.clone(),
+            published_by: caller,
+            published_at_ms: system_api::time() / 1_000_000,
+        };
+        self.published_folders.insert(folder_id.clone(), published);
+        self.record_audit(AuditAction::PublishFolder, folder_id, Some(folder.full_folder_path), None);
+        Ok(())
+    }
+
+    fn unpublish_folder(&mut self, folder_id: &FolderUUID) -> Result<(), DriveError> {
+        if self.published_folders.remove(folder_id).is_none() {
+            return Err(DriveError::NotFound {
+                resource: "published folder".to_string(),
+                id: folder_id.clone(),
+            });
+        }
+        let path = self.folder_uuid_to_metadata.get(folder_id).map(|folder| folder.full_folder_path);
+        self.record_audit(AuditAction::UnpublishFolder, folder_id, path, None);
+        Ok(())
+    }
+
+    /// Serves `/public/...` gateway requests. No credentials are needed, so
+    /// this runs as a plain query.
+    pub(crate) fn handle_public_request(&self, request: &GatewayRequest) -> Option<GatewayResponse> {
+        let segments = request.path_segments();
+        let result = match (request.method.as_str(), segments.as_slice()) {
+            ("GET", ["public", "folders", id]) => {
+                let limit = request.query_param("limit").and_then(|limit| limit.parse().ok());
+                let after = request.query_param("after").and_then(|after| after.parse().ok());
+                self.public_folder_page(Principal::anonymous(), &id.to_string(), limit.unwrap_or(DEFAULT_PAGE_SIZE), after.unwrap_or(0))
+                    .map(|page| json_response(200, &page))
+            }
+            ("GET", ["public", "files", id]) => self
+                .public_file(Principal::anonymous(), &id.to_string())
+                .map(|file| json_response(200, &file)),
+            _ => return None,
+        };
+        Some(result.unwrap_or_else(|e| error_response(&e)))
+    }
+}
+
+/// Makes the folder and everything below it readable by anyone. Publishing
+/// a folder inside a published one is allowed and outlives unpublishing the
+/// outer one.
+#[ic_cdk::update]
+fn publish_folder(folder_id: FolderUUID) -> Result<(), DriveError> {
+    let _profile = profile("publish_folder");
+    check_writable()?;
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if !state.is_drive_admin(caller) {
+            return Err(DriveError::unauthorized("Only a drive admin can publish folders"));
+        }
+        state.publish_folder(caller, &folder_id)
+    })?;
+    sync_directory_listing();
+    Ok(())
+}
+
+#[ic_cdk::update]
+fn unpublish_folder(folder_id: FolderUUID) -> Result<(), DriveError> {
+    let _profile = profile("unpublish_folder");
+    check_writable()?;
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if !state.is_drive_admin(caller) {
+            return Err(DriveError::unauthorized("Only a drive admin can unpublish folders"));
+        }
+        state.unpublish_folder(&folder_id)
+    })?;
+    sync_directory_listing();
+    Ok(())
+}
+
+/// Open to anyone, since the folders listed are public.
+#[ic_cdk::query]
+fn list_published() -> Vec<PublishedFolderInfo> {
+    STATE.with(|state| {
+        let state = state.borrow();
+        state
+            .published_folders
+            .values()
+            .filter_map(|published| {
+                let folder = state.folder_uuid_to_metadata.get(&published.folder_id).filter(|folder| !folder.deleted)?;
+                Some(PublishedFolderInfo { folder: published.clone(), full_folder_path: folder.full_folder_path })
+            })
+            .collect()
+    })
+}
+
+/// Lists a folder inside a published subtree, for anyone.
+#[ic_cdk::query]
+fn list_public_folder(folder_id: FolderUUID, limit: Option<u32>, after: Option<u32>) -> Result<FetchFilesResult, DriveError> {
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        state
+            .borrow()
+            .public_folder_page(caller, &folder_id, limit.unwrap_or(DEFAULT_PAGE_SIZE), after.unwrap_or(0))
+    })
+}
+
+/// Gets a file inside a published subtree, for anyone.
+#[ic_cdk::query]
+fn get_public_file(file_id: FileUUID) -> Result<FileMetadata, DriveError> {
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow().public_file(caller, &file_id))
+}
@@ -0,0 +1,181 @@
+// quota.rs
+
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::audit::AuditAction;
+use crate::errors::DriveError;
+use crate::members::check_read_access;
+use crate::profiling::profile;
+use crate::read_only::check_writable;
+use crate::rollups::RollupDelta;
+use crate::system_api;
+use crate::{FileMetadata, FolderUUID, State, StorageLocationID, STATE};
+
+/// Running byte totals for every file version held by the drive, kept up to
+/// date as files are added, removed, renamed or moved.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Default)]
+pub struct StorageUsage {
+    total_bytes: u64,
+    by_storage_location: HashMap<StorageLocationID, u64>,
+    by_extension: HashMap<String, u64>,
+    by_top_level_folder: HashMap<FolderUUID, u64>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct StorageUsageReport {
+    total_bytes: u64,
+    quota_bytes: Option<u64>,
+    by_storage_location: Vec<(StorageLocationID, u64)>,
+    by_extension: Vec<(String, u64)>,
+    by_top_level_folder: Vec<(FolderUUID, u64)>,
+}
+
+impl StorageUsage {
+    pub(crate) fn by_extension_totals(&self) -> Vec<(String, u64)> {
+        self.by_extension.clone().into_iter().collect()
+    }
+}
+
+fn add_bytes<K: std::hash::Hash + Eq>(totals: &mut HashMap<K, u64>, key: K, bytes: u64) {
+    if bytes == 0 {
+        return;
+    }
+    *totals.entry(key).or_insert(0) += bytes;
+}
+
+fn remove_bytes<K: std::hash::Hash + Eq>(totals: &mut HashMap<K, u64>, key: K, bytes: u64) {
+    if let Some(total) = totals.get_mut(&key) {
+        *total = total.saturating_sub(bytes);
+        if *total == 0 {
+            totals.remove(&key);
+        }
+    }
+}
+
+impl State {
+    /// The folder directly below the storage root that contains `folder_uuid`
+    /// (the root itself for files stored at the top of a location).
+    fn top_level_folder_of(&self, folder_uuid: &FolderUUID) -> FolderUUID {
+        let mut current = folder_uuid.clone();
+        while let Some(folder) = self.folder_uuid_to_metadata.get(&current) {
+            match &folder.parent_folder_uuid {
+                Some(parent) if self
+                    .folder_uuid_to_metadata
+                    .get(parent)
+                    .is_some_and(|p| p.parent_folder_uuid.is_some()) =>
+                {
+                    current = parent.clone();
+                }
+                _ => break,
+            }
+        }
+        current
+    }
+
+    pub(crate) fn account_file_added(&mut self, file: &FileMetadata) {
+        let top_level = self.top_level_folder_of(&file.folder_uuid);
+        let usage = &mut self.storage_usage;
+        usage.total_bytes += file.file_size;
+        add_bytes(&mut usage.by_storage_location, file.storage_location.clone(), file.file_size);
+        add_bytes(&mut usage.by_extension, file.extension.clone(), file.file_size);
+        add_bytes(&mut usage.by_top_level_folder, top_level, file.file_size);
+        let delta = RollupDelta { bytes: file.file_size, files: 1, folders: 0 };
+        self.adjust_rollups(&file.folder_uuid, delta, true);
+        self.index_file_checksum(file);
+        self.index_file_size(file);
+        self.index_recent_file(file);
+    }
+
+    pub(crate) fn account_file_removed(&mut self, file: &FileMetadata) {
+        let top_level = self.top_level_folder_of(&file.folder_uuid);
+        let usage = &mut self.storage_usage;
+        usage.total_bytes = usage.total_bytes.saturating_sub(file.file_size);
+        remove_bytes(&mut usage.by_storage_location, file.storage_location.clone(), file.file_size);
+        remove_bytes(&mut usage.by_extension, file.extension.clone(), file.file_size);
+        remove_bytes(&mut usage.by_top_level_folder, top_level, file.file_size);
+        let delta = RollupDelta { bytes: file.file_size, files: 1, folders: 0 };
+        self.adjust_rollups(&file.folder_uuid, delta, false);
+        self.unindex_file_checksum(file);
+        self.unindex_file_size(file);
+        self.unindex_recent_file(file);
+    }
+
+    /// Rejects a write that would grow the drive by `additional_bytes` past its quota.
+    pub(crate) fn check_quota(&self, additional_bytes: u64) -> Result<(), DriveError> {
+        match self.quota_bytes {
+            Some(limit) if self.storage_usage.total_bytes.saturating_add(additional_bytes) > limit => {
+                Err(DriveError::QuotaExceeded {
+                    limit_bytes: limit,
+                    requested_bytes: self.storage_usage.total_bytes.saturating_add(additional_bytes),
+                })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn recompute_storage_usage(&mut self) {
+        self.storage_usage = StorageUsage::default();
+        self.checksum_to_file_uuids.clear_new();
+        self.files_by_size.clear_new();
+        let files: Vec<FileMetadata> = self.file_uuid_to_metadata.values().collect();
+        for file in &files {
+            self.account_file_added(file);
+        }
+        // account_file_added also bumped the folder rollups, so rebuild those from scratch
+        self.recompute_folder_rollups();
+    }
+
+    fn storage_usage_report(&self) -> StorageUsageReport {
+        let usage = &self.storage_usage;
+        StorageUsageReport {
+            total_bytes: usage.total_bytes,
+            quota_bytes: self.quota_bytes,
+            by_storage_location: usage.by_storage_location.clone().into_iter().collect(),
+            by_extension: usage.by_extension.clone().into_iter().collect(),
+            by_top_level_folder: usage.by_top_level_folder.clone().into_iter().collect(),
+        }
+    }
+}
+
+#[ic_cdk::query]
+fn get_storage_usage() -> Result<StorageUsageReport, DriveError> {
+    check_read_access()?;
+    STATE.with(|state| Ok(state.borrow().storage_usage_report()))
+}
+
+/// Sets or clears (`None`) the maximum number of bytes the drive may hold.
+#[ic_cdk::update]
+fn set_storage_quota(quota_bytes: Option<u64>) -> Result<(), DriveError> {
+    let _profile = profile("set_storage_quota");
+    check_writable()?;
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if !state.is_drive_admin(caller) {
+            return Err(DriveError::unauthorized("Only a drive admin can set the storage quota"));
+        }
+        state.quota_bytes = quota_bytes;
+        let owner = state.owner.to_text();
+        state.record_audit(AuditAction::SetQuota, &owner, None, None);
+        Ok(())
+    })
+}
+
+/// Rebuilds the usage counters, folder rollups, checksum and size indexes from the file table, for use
+/// after manual repairs.
+#[ic_cdk::update]
+fn recompute_storage_usage() -> Result<(), DriveError> {
+    let _profile = profile("recompute_storage_usage");
+    check_writable()?;
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if !state.is_drive_admin(caller) {
+            return Err(DriveError::unauthorized("Only a drive admin can recompute storage usage"));
+        }
+        state.recompute_storage_usage();
+        Ok(())
+    })
+}
@@ -0,0 +1,210 @@
+// rate_limit.rs
+
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+use crate::errors::DriveError;
+use crate::members::{check_member_access, check_read_access};
+use crate::metrics::count_call;
+use crate::profiling::profile;
+use crate::read_only::check_writable;
+use crate::system_api;
+use crate::{State, STATE};
+
+// Past this many buckets, those that have refilled are forgotten, then the
+// longest idle ones until `PRUNED_BUCKETS` remain, so pruning stays rare
+const MAX_TRACKED_BUCKETS: usize = 10_000;
+const PRUNED_BUCKETS: usize = MAX_TRACKED_BUCKETS * 9 / 10;
+// Tokens are tracked in thousandths so slow refill rates still accrue
+const MILLI: u64 = 1000;
+
+/// Token bucket budget: a caller may burst up to `capacity` calls, then
+/// `refill_per_minute` more calls per minute.
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct RateLimitBudget {
+    pub capacity: u32,
+    pub refill_per_minute: u32,
+}
+
+impl Default for RateLimitBudget {
+    fn default() -> Self {
+        Self { capacity: 200, refill_per_minute: 600 }
+    }
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct RateLimitConfig {
+    default_budget: RateLimitBudget,
+    method_budgets: Vec<(String, RateLimitBudget)>,
+}
+
+#[derive(Clone, Debug)]
+pub struct TokenBucket {
+    milli_tokens: u64,
+    updated_at_ms: u64,
+}
+
+impl TokenBucket {
+    fn full(budget: &RateLimitBudget, now_ms: u64) -> Self {
+        Self { milli_tokens: budget.capacity as u64 * MILLI, updated_at_ms: now_ms }
+    }
+
+    fn refill(&mut self, budget: &RateLimitBudget, now_ms: u64) {
+        let elapsed_ms = now_ms.saturating_sub(self.updated_at_ms);
+        // refill_per_minute tokens per 60_000ms is exactly that many milli-tokens per 60ms
+        let earned = elapsed_ms.saturating_mul(budget.refill_per_minute as u64) / 60;
+        self.milli_tokens = (self.milli_tokens.saturating_add(earned)).min(budget.capacity as u64 * MILLI);
+        self.updated_at_ms = now_ms;
+    }
+
+    fn is_full(&self, budget: &RateLimitBudget, now_ms: u64) -> bool {
+        let mut bucket = self.clone();
+        bucket.refill(budget, now_ms);
+        bucket.milli_tokens >= budget.capacity as u64 * MILLI
+    }
+}
+
+fn validate_budget(budget: &RateLimitBudget) -> Result<(), DriveError> {
+    if budget.capacity == 0 || budget.refill_per_minute == 0 {
+        return Err(DriveError::InvalidInput {
+            field: "budget".to_string(),
+            reason: "Capacity and refill rate must both be at least 1".to_string(),
+        });
+    }
+    Ok(())
+}
+
+impl State {
+    fn rate_limit_budget(&self, method: &str) -> RateLimitBudget {
+        self.rate_limit_budgets.get(method).copied().unwrap_or(self.default_rate_limit)
+    }
+
+    /// Takes `cost` tokens from the caller's bucket for `method`, or fails with
+    /// `RateLimited` and leaves the bucket untouched.
+    pub(crate) fn consume_rate_limit(
+        &mut self,
+        caller: Principal,
+        method: &str,
+        cost: u32,
+        now_ms: u64,
+    ) -> Result<(), DriveError> {
+        let budget = self.rate_limit_budget(method);
+        if self.rate_buckets.len() >= MAX_TRACKED_BUCKETS {
+            let budgets = &self.rate_limit_budgets;
+            let default_budget = self.default_rate_limit;
+            self.rate_buckets.retain(|(_, bucket_method), bucket| {
+                let budget = budgets.get(bucket_method).copied().unwrap_or(default_budget);
+                !bucket.is_full(&budget, now_ms)
+            });
+            if self.rate_buckets.len() > PRUNED_BUCKETS {
+                let mut idle: Vec<_> = self
+                    .rate_buckets
+                    .iter()
+                    .map(|(key, bucket)| (bucket.updated_at_ms, key.clone()))
+                    .collect();
+                idle.sort_unstable_by_key(|(updated_at_ms, _)| *updated_at_ms);
+                let excess = idle.len() - PRUNED_BUCKETS;
+                for (_, key) in idle.into_iter().take(excess) {
+                    self.rate_buckets.remove(&key);
+                }
+            }
+        }
+
+        let bucket = self
+            .rate_buckets
+            .entry((caller, method.to_string()))
+            .or_insert_with(|| TokenBucket::full(&budget, now_ms));
+        bucket.refill(&budget, now_ms);
+
+        // A batch bigger than the whole bucket may still run once it is full
+        let needed = cost.min(budget.capacity) as u64 * MILLI;
+        if bucket.milli_tokens < needed {
+            let missing = needed - bucket.milli_tokens;
+            return Err(DriveError::RateLimited {
+                method: method.to_string(),
+                retry_after_ms: (missing * 60).div_ceil(budget.refill_per_minute as u64),
+            });
+        }
+        bucket.milli_tokens -= needed;
+        Ok(())
+    }
+
+    fn set_rate_limit(
+        &mut self,
+        caller: Principal,
+        method: Option<String>,
+        budget: Option<RateLimitBudget>,
+    ) -> Result<(), DriveError> {
+        if !self.is_drive_admin(caller) {
+            return Err(DriveError::unauthorized("Only a drive admin can change rate limits"));
+        }
+        if let Some(budget) = &budget {
+            validate_budget(budget)?;
+        }
+        match (method, budget) {
+            (Some(method), Some(budget)) => {
+                self.rate_limit_budgets.insert(method, budget);
+            }
+            (Some(method), None) => {
+                self.rate_limit_budgets.remove(&method);
+            }
+            (None, Some(budget)) => self.default_rate_limit = budget,
+            (None, None) => self.default_rate_limit = RateLimitBudget::default(),
+        }
+        // Existing buckets may hold more tokens than a lowered capacity allows
+        self.rate_buckets.clear();
+        Ok(())
+    }
+
+    fn rate_limit_config(&self) -> RateLimitConfig {
+        RateLimitConfig {
+            default_budget: self.default_rate_limit,
+            method_budgets: self
+                .rate_limit_budgets
+                .iter()
+                .map(|(method, budget)| (method.clone(), *budget))
+                .collect(),
+        }
+    }
+}
+
+/// The gate update endpoints pass first, so a call that may not go ahead is
+/// turned away before doing any work. It counts the call for `get_metrics`,
+/// then fails while the drive is read-only, for callers without a suitable
+/// member role, and once the caller's budget for `method` is spent.
+pub(crate) fn check_update_allowed(method: &str) -> Result<(), DriveError> {
+    check_batch_allowed(method, 1)
+}
+
+/// Like `check_update_allowed`, for calls that do `cost` units of work at once.
+pub(crate) fn check_batch_allowed(method: &str, cost: u32) -> Result<(), DriveError> {
+    let caller = system_api::caller();
+    count_call(method);
+    check_writable()?;
+    check_member_access(caller, method)?;
+    charge_rate_limit(caller, method, cost)
+}
+
+/// Charges `cost` calls to `method` against the caller's budget, and nothing
+/// else. Endpoints open to non-members use this after their own checks.
+pub(crate) fn charge_rate_limit(caller: Principal, method: &str, cost: u32) -> Result<(), DriveError> {
+    let now_ms = system_api::time() / 1_000_000;
+    STATE.with(|state| state.borrow_mut().consume_rate_limit(caller, method, cost, now_ms))
+}
+
+/// Sets the budget for one method, or the default for every method without
+/// its own budget when `method` is omitted. A missing `budget` removes the
+/// method's override, or restores the built-in default.
+#[ic_cdk::update]
+fn set_rate_limit(method: Option<String>, budget: Option<RateLimitBudget>) -> Result<(), DriveError> {
+    let _profile = profile("set_rate_limit");
+    check_writable()?;
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow_mut().set_rate_limit(caller, method, budget))
+}
+
+#[ic_cdk::query]
+fn get_rate_limits() -> Result<RateLimitConfig, DriveError> {
+    check_read_access()?;
+    STATE.with(|state| Ok(state.borrow().rate_limit_config()))
+}
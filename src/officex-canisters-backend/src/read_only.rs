@@ -0,0 +1,75 @@
+// read_only.rs
+
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::errors::DriveError;
+use crate::profiling::profile;
+use crate::system_api;
+use crate::{State, STATE};
+
+/// Below this balance the drive stops accepting writes, leaving headroom for
+/// queries and a top-up before the canister freezes.
+pub const DEFAULT_READ_ONLY_THRESHOLD_CYCLES: u64 = 200_000_000_000;
+/// How long background jobs wait before checking the balance again.
+pub const READ_ONLY_RETRY_DELAY: Duration = Duration::from_secs(10 * 60);
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct ReadOnlyStatus {
+    read_only: bool,
+    balance_cycles: u64,
+    threshold_cycles: u64,
+}
+
+impl State {
+    fn read_only_status(&self) -> ReadOnlyStatus {
+        let balance_cycles = ic_cdk::api::canister_balance();
+        ReadOnlyStatus {
+            read_only: balance_cycles < self.read_only_threshold_cycles,
+            balance_cycles,
+            threshold_cycles: self.read_only_threshold_cycles,
+        }
+    }
+
+    pub(crate) fn is_read_only(&self) -> bool {
+        self.read_only_status().read_only
+    }
+
+    fn set_read_only_threshold(&mut self, caller: Principal, threshold_cycles: u64) -> Result<(), DriveError> {
+        if !self.is_drive_admin(caller) {
+            return Err(DriveError::unauthorized("Only a drive admin can change the read-only threshold"));
+        }
+        self.read_only_threshold_cycles = threshold_cycles;
+        Ok(())
+    }
+}
+
+/// Fails with `ReadOnlyMode` while the cycle balance is under the threshold.
+/// Every endpoint that changes state calls this before doing any work.
+pub(crate) fn check_writable() -> Result<(), DriveError> {
+    STATE.with(|state| {
+        let status = state.borrow().read_only_status();
+        if status.read_only {
+            return Err(DriveError::ReadOnlyMode {
+                balance_cycles: status.balance_cycles,
+                threshold_cycles: status.threshold_cycles,
+            });
+        }
+        Ok(())
+    })
+}
+
+/// Sets the balance below which the drive turns read-only; 0 disables the
+/// safety mode. Stays callable in read-only mode, as do cycle deposits.
+#[ic_cdk::update]
+fn set_read_only_threshold(threshold_cycles: u64) -> Result<(), DriveError> {
+    let _profile = profile("set_read_only_threshold");
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow_mut().set_read_only_threshold(caller, threshold_cycles))
+}
+
+#[ic_cdk::query]
+fn get_read_only_status() -> ReadOnlyStatus {
+    STATE.with(|state| state.borrow().read_only_status())
+}
@@ -0,0 +1,76 @@
+// recent.rs
+
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::Storable;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+use crate::members::require_read_access;
+use crate::system_api;
+use crate::{FileMetadata, FileUUID, State, STATE};
+
+const MAX_RECENT_FILES: u32 = 200;
+
+/// Entry in the time-ordered file index, oldest change first.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct RecentFileKey {
+    last_changed_ms: u64,
+    file_id: FileUUID,
+}
+
+impl Storable for RecentFileKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl RecentFileKey {
+    fn for_file(file: &FileMetadata) -> Self {
+        RecentFileKey {
+            last_changed_ms: file.last_changed_unix_ms,
+            file_id: file.id.clone(),
+        }
+    }
+}
+
+impl State {
+    pub(crate) fn index_recent_file(&mut self, file: &FileMetadata) {
+        self.recent_files.insert(RecentFileKey::for_file(file), ());
+    }
+
+    pub(crate) fn unindex_recent_file(&mut self, file: &FileMetadata) {
+        self.recent_files.remove(&RecentFileKey::for_file(file));
+    }
+
+    fn list_recent_files(&self, caller: Principal, limit: u32, before_ms: Option<u64>) -> Vec<FileMetadata> {
+        let upper = RecentFileKey {
+            last_changed_ms: before_ms.unwrap_or(u64::MAX),
+            file_id: String::new(),
+        };
+        self.recent_files
+            .range(..upper)
+            .rev()
+            .filter_map(|(key, _)| self.file_uuid_to_metadata.get(&key.file_id))
+            // Older versions are superseded by the file that replaced them
+            .filter(|file| file.next_version.is_none())
+            .take(limit.min(MAX_RECENT_FILES) as usize)
+            .map(|file| self.redact_unpaid(caller, file))
+            .collect()
+    }
+}
+
+/// Files ordered by `last_changed_unix_ms`, newest first. Pass the oldest
+/// timestamp of a page as `before_ms` to fetch the files changed before it.
+#[ic_cdk::query]
+fn list_recent_files(limit: u32, before_ms: Option<u64>) -> Vec<FileMetadata> {
+    require_read_access();
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow().list_recent_files(caller, limit, before_ms))
+}
@@ -0,0 +1,279 @@
+// rest_api.rs
+//
+// A JSON API served through the HTTP gateway, for tools without an IC agent.
+// Every request carries an API key as `Authorization: Bearer <secret>`; GET
+// needs the `Read` scope, everything else `Write`. Routes:
+//
+//   GET    /api/folders/:id
+//   GET    /api/folders/:id/children?limit=&after=
+//   POST   /api/folders             {"path", "storage_location"}
+//   DELETE /api/folders/:id
+//   GET    /api/files/:id
+//   POST   /api/files               {"path", "storage_location", "sha256_checksum"?}
+//   PATCH  /api/files/:id           {"file_size"?, "raw_url"?, "tags"?}
+//   DELETE /api/files/:id
+//
+// PATCH and DELETE honour `If-Match: <revision>`, see `revisions.rs`. The
+// same gateway serves WebDAV under `/dav/`, see `webdav.rs`, and published
+// folders under `/public/` without a key, see `publishing.rs`.
+
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+use crate::api_keys::{check_update_allowed_with_api_key, ApiScope};
+use crate::errors::DriveError;
+use crate::file_updates::UpdateFileMetadataRequest;
+use crate::profiling::profile;
+use crate::webdav::{dav_options, is_dav_read};
+use crate::{DriveFullFilePath, FetchFilesAtFolderPathConfig, State, StorageLocationID, STATE};
+
+const DEFAULT_PAGE_SIZE: u32 = 100;
+const MAX_PAGE_SIZE: u32 = 1_000;
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub(crate) struct GatewayRequest {
+    pub(crate) method: String,
+    pub(crate) url: String,
+    headers: Vec<(String, String)>,
+    pub(crate) body: Vec<u8>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct GatewayResponse {
+    pub(crate) status_code: u16,
+    pub(crate) headers: Vec<(String, String)>,
+    pub(crate) body: Vec<u8>,
+    // Asks the gateway to repeat the request as an update call
+    pub(crate) upgrade: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct CreateRequest {
+    path: DriveFullFilePath,
+    storage_location: StorageLocationID,
+    sha256_checksum: Option<String>,
+}
+
+impl GatewayRequest {
+    pub(crate) fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub(crate) fn query_param(&self, name: &str) -> Option<&str> {
+        let (_, query) = self.url.split_once('?')?;
+        query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| *key == name)
+            .map(|(_, value)| value)
+    }
+
+    pub(crate) fn path_segments(&self) -> Vec<&str> {
+        let path = self.url.split('?').next().unwrap_or("");
+        path.split('/').filter(|segment| !segment.is_empty()).collect()
+    }
+
+    fn json_body<T: for<'a> Deserialize<'a>>(&self) -> Result<T, DriveError> {
+        serde_json::from_slice(&self.body).map_err(|e| DriveError::InvalidInput {
+            field: "body".to_string(),
+            reason: e.to_string(),
+        })
+    }
+
+    /// The API key from a bearer token, or from the password of Basic auth
+    /// as WebDAV clients send it.
+    fn api_key(&self) -> Option<String> {
+        let authorization = self.header("authorization")?;
+        if let Some(secret) = authorization.strip_prefix("Bearer ") {
+            return Some(secret.trim().to_string());
+        }
+        let credentials = decode_base64(authorization.strip_prefix("Basic ")?.trim())?;
+        let credentials = String::from_utf8(credentials).ok()?;
+        let (_, secret) = credentials.split_once(':')?;
+        Some(secret.to_string())
+    }
+
+    fn expected_revision(&self) -> Result<Option<u64>, DriveError> {
+        self.header("if-match")
+            .map(|value| {
+                value.trim_matches('"').parse().map_err(|_| DriveError::InvalidInput {
+                    field: "If-Match".to_string(),
+                    reason: "Must be a revision number".to_string(),
+                })
+            })
+            .transpose()
+    }
+}
+
+fn decode_base64(input: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(input.len() * 3 / 4);
+    let (mut buffer, mut bits) = (0u32, 0);
+    for c in input.trim_end_matches('=').bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        buffer = (buffer << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Some(bytes)
+}
+
+pub(crate) fn json_response<T: Serialize>(status_code: u16, value: &T) -> GatewayResponse {
+    GatewayResponse {
+        status_code,
+        headers: vec![("Content-Type".to_string(), "application/json".to_string())],
+        body: serde_json::to_vec(value).unwrap_or_default(),
+        upgrade: None,
+    }
+}
+
+pub(crate) fn error_response(error: &DriveError) -> GatewayResponse {
+    let status_code = match error {
+        DriveError::NotFound { .. } => 404,
+        DriveError::AlreadyExists { .. }
+        | DriveError::InvalidState { .. }
+        | DriveError::RevisionMismatch { .. } => 409,
+        DriveError::Unauthorized { .. } => 403,
+        DriveError::InvalidPath { .. }
+        | DriveError::StorageMismatch { .. }
+        | DriveError::InvalidInput { .. }
+        | DriveError::PathLimitExceeded { .. } => 400,
+        DriveError::PaymentRequired { .. } => 402,
+        DriveError::QuotaExceeded { .. } => 507,
+        DriveError::RateLimited { .. } => 429,
+        DriveError::ReadOnlyMode { .. } => 503,
+        DriveError::Internal { .. } => 500,
+    };
+    json_response(status_code, &serde_json::json!({ "error": error.to_string(), "details": error }))
+}
+
+fn not_found() -> GatewayResponse {
+    json_response(404, &serde_json::json!({ "error": "No such route" }))
+}
+
+impl State {
+    /// Handles a request already authorized as `user`. `None` means no route matched.
+    fn handle_rest_request(&mut self, request: &GatewayRequest, user: Principal) -> Option<Result<GatewayResponse, DriveError>> {
+        let segments = request.path_segments();
+        let result = match (request.method.as_str(), segments.as_slice()) {
+            ("GET", ["api", "folders", id]) => self
+                .folder_uuid_to_metadata
+                .get(&id.to_string())
+                .ok_or_else(|| DriveError::folder_not_found(id))
+                .map(|folder| json_response(200, &folder)),
+            ("GET", ["api", "folders", id, "children"]) => self
+                .folder_uuid_to_metadata
+                .get(&id.to_string())
+                .ok_or_else(|| DriveError::folder_not_found(id))
+                .map(|folder| {
+                    let limit = request.query_param("limit").and_then(|limit| limit.parse().ok());
+                    let after = request.query_param("after").and_then(|after| after.parse().ok());
+                    let page = self.fetch_files_at_folder_path(FetchFilesAtFolderPathConfig {
+                        full_folder_path: folder.full_folder_path,
+                        limit: limit.unwrap_or(DEFAULT_PAGE_SIZE).min(MAX_PAGE_SIZE),
+                        after: after.unwrap_or(0),
+                        exclude_pending: None,
+                        pinned_first: None,
+                    });
+                    json_response(200, &self.redact_unpaid_page(user, page))
+                }),
+            ("POST", ["api", "folders"]) => request
+                .json_body::<CreateRequest>()
+                .and_then(|body| self.create_folder(body.path, body.storage_location, user))
+                .map(|folder| json_response(201, &folder)),
+            ("DELETE", ["api", "folders", id]) => request
+                .expected_revision()
+                .and_then(|expected| self.check_folder_revision(&id.to_string(), expected))
+                .and_then(|_| self.delete_folder(&id.to_string()))
+                .map(|_| json_response(200, &serde_json::json!({ "id": id }))),
+            ("GET", ["api", "files", id]) => self
+                .file_uuid_to_metadata
+                .get(&id.to_string())
+                .ok_or_else(|| DriveError::file_not_found(id))
+                .map(|file| json_response(200, &self.redact_unpaid(user, file))),
+            ("POST", ["api", "files"]) => request
+                .json_body::<CreateRequest>()
+                .and_then(|body| {
+                    self.upsert_file_to_hash_tables(body.path, body.storage_location, user, body.sha256_checksum)
+                })
+                .map(|id| json_response(201, &serde_json::json!({ "id": id }))),
+            ("PATCH", ["api", "files", id]) => request
+                .expected_revision()
+                .and_then(|expected| self.check_file_revision(&id.to_string(), expected))
+                .and_then(|_| request.json_body::<UpdateFileMetadataRequest>())
+                .and_then(|body| self.update_file_metadata(user, &id.to_string(), body))
+                .map(|file| json_response(200, &file)),
+            ("DELETE", ["api", "files", id]) => request
+                .expected_revision()
+                .and_then(|expected| self.check_file_revision(&id.to_string(), expected))
+                .and_then(|_| self.delete_file(&id.to_string()))
+                .map(|_| json_response(200, &serde_json::json!({ "id": id }))),
+            _ => return None,
+        };
+        Some(result)
+    }
+}
+
+/// Answers gateway queries. API and WebDAV requests are upgraded, since
+/// checking the API key and the rate limit both record state.
+#[ic_cdk::query]
+fn http_request(request: GatewayRequest) -> GatewayResponse {
+    match request.path_segments().first() {
+        Some(&"dav") if request.method == "OPTIONS" => dav_options(),
+        Some(&"public") => STATE
+            .with(|state| state.borrow().handle_public_request(&request))
+            .unwrap_or_else(not_found),
+        Some(&"api") | Some(&"dav") => {
+            GatewayResponse { status_code: 200, headers: Vec::new(), body: Vec::new(), upgrade: Some(true) }
+        }
+        _ => not_found(),
+    }
+}
+
+#[ic_cdk::update]
+fn http_request_update(request: GatewayRequest) -> GatewayResponse {
+    let _profile = profile("http_request_update");
+    let dav = request.path_segments().first() == Some(&"dav");
+    if dav && request.method == "OPTIONS" {
+        return dav_options();
+    }
+    let Some(secret) = request.api_key() else {
+        // Prompts WebDAV clients for credentials; the API key is the password
+        let mut response = error_response(&DriveError::unauthorized("Missing API key"));
+        response.status_code = 401;
+        response.headers.push(("WWW-Authenticate".to_string(), "Basic realm=\"OfficeX\"".to_string()));
+        return response;
+    };
+    let read = if dav { is_dav_read(&request.method) } else { request.method == "GET" };
+    let scope = if read { ApiScope::Read } else { ApiScope::Write };
+    let user = match check_update_allowed_with_api_key("http_request_update", Some(secret), scope) {
+        Ok(user) => user,
+        Err(e) => return error_response(&e),
+    };
+    let handled = STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if dav {
+            Some(state.handle_dav_request(&request, user))
+        } else {
+            state.handle_rest_request(&request, user)
+        }
+    });
+    match handled {
+        Some(Ok(response)) => response,
+        Some(Err(e)) => error_response(&e),
+        None => not_found(),
+    }
+}
@@ -0,0 +1,378 @@
+// restore.rs
+
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+use crate::audit::AuditAction;
+use crate::errors::DriveError;
+use crate::profiling::profile;
+use crate::read_only::check_writable;
+use crate::mime;
+use crate::system_api;
+use crate::upload_status::UploadStatus;
+use crate::{
+    DriveFullFilePath, FileMetadata, FileUUID, FolderMetadata, FolderUUID, State, StateSnapshot, StorageLocationID,
+    Tag, UserID, STATE,
+};
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ImportMode {
+    // Add records from the snapshot, leaving existing ones untouched
+    Merge,
+    // Drop every folder and file first, so the drive matches the snapshot
+    Replace,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, Default)]
+pub(crate) struct ImportReport {
+    folders_imported: u64,
+    files_imported: u64,
+    // Records whose UUID or path is already taken on this drive, whose path
+    // is invalid here, or whose parent folder was skipped
+    folders_skipped: u64,
+    files_skipped: u64,
+}
+
+/// Storage locations before the registry in `storage.rs`. Their names are
+/// the IDs of the built-in locations.
+#[derive(CandidType, Deserialize, Debug)]
+enum StorageLocationV0 {
+    BrowserCache,
+    HardDrive,
+    Web3Storj,
+}
+
+impl StorageLocationV0 {
+    fn id(&self) -> StorageLocationID {
+        format!("{:?}", self)
+    }
+}
+
+/// A folder as drives kept it on the heap before the stable maps. Its child
+/// lists are rebuilt from parent links, so they are not decoded.
+#[derive(CandidType, Deserialize)]
+pub(crate) struct FolderMetadataV0 {
+    id: FolderUUID,
+    original_folder_name: String,
+    parent_folder_uuid: Option<FolderUUID>,
+    full_folder_path: DriveFullFilePath,
+    tags: Vec<Tag>,
+    owner: UserID,
+    created_date: u64,
+    storage_location: StorageLocationV0,
+    last_changed_unix_ms: u64,
+    deleted: bool,
+}
+
+/// A file as drives kept it on the heap before the stable maps.
+#[derive(CandidType, Deserialize)]
+pub(crate) struct FileMetadataV0 {
+    id: FileUUID,
+    original_file_name: String,
+    folder_uuid: FolderUUID,
+    file_version: u32,
+    prior_version: Option<FileUUID>,
+    next_version: Option<FileUUID>,
+    extension: String,
+    full_file_path: DriveFullFilePath,
+    tags: Vec<Tag>,
+    owner: UserID,
+    created_date: u64,
+    storage_location: StorageLocationV0,
+    file_size: u64,
+    raw_url: String,
+    last_changed_unix_ms: u64,
+    deleted: bool,
+}
+
+// Subtree totals start at zero; importing recomputes them
+impl From<FolderMetadataV0> for FolderMetadata {
+    fn from(folder: FolderMetadataV0) -> Self {
+        FolderMetadata {
+            id: folder.id,
+            original_folder_name: folder.original_folder_name,
+            parent_folder_uuid: folder.parent_folder_uuid,
+            full_folder_path: folder.full_folder_path,
+            tags: folder.tags,
+            properties: Vec::new(),
+            owner: folder.owner,
+            created_date: folder.created_date,
+            storage_location: folder.storage_location.id(),
+            last_changed_unix_ms: folder.last_changed_unix_ms,
+            deleted: folder.deleted,
+            subtree_bytes: 0,
+            subtree_file_count: 0,
+            subtree_folder_count: 0,
+            revision: 0,
+            settings: None,
+            color: None,
+            pinned_rank: None,
+        }
+    }
+}
+
+// Uploads were not tracked, so every file was complete
+impl From<FileMetadataV0> for FileMetadata {
+    fn from(file: FileMetadataV0) -> Self {
+        FileMetadata {
+            id: file.id,
+            original_file_name: file.original_file_name,
+            folder_uuid: file.folder_uuid,
+            file_version: file.file_version,
+            prior_version: file.prior_version,
+            next_version: file.next_version,
+            mime_type: mime::mime_type_for_extension(&file.extension),
+            extension: file.extension,
+            full_file_path: file.full_file_path,
+            tags: file.tags,
+            properties: Vec::new(),
+            owner: file.owner,
+            created_date: file.created_date,
+            storage_location: file.storage_location.id(),
+            file_size: file.file_size,
+            raw_url: file.raw_url,
+            last_changed_unix_ms: file.last_changed_unix_ms,
+            deleted: file.deleted,
+            upload_status: UploadStatus::Uploaded,
+            sha256_checksum: None,
+            last_accessed_ms: None,
+            access_count: 0,
+            encrypted: false,
+            encryption_scheme: None,
+            wrapped_dek: None,
+            nonce: None,
+            conflicted: false,
+            thumbnail_url: None,
+            thumbnail_checksum: None,
+            thumbnail_dimensions: None,
+            revision: 0,
+            shortcut_target: None,
+            expires_at_ms: None,
+            permanent_id: None,
+            paywall: None,
+        }
+    }
+}
+
+/// A `snapshot_hashtables` export from a drive that predates the stable maps.
+#[derive(CandidType, Deserialize)]
+pub struct LegacyStateSnapshot {
+    folder_uuid_to_metadata: HashMap<FolderUUID, FolderMetadataV0>,
+    file_uuid_to_metadata: HashMap<FileUUID, FileMetadataV0>,
+    full_folder_path_to_uuid: HashMap<DriveFullFilePath, FolderUUID>,
+    full_file_path_to_uuid: HashMap<DriveFullFilePath, FileUUID>,
+    owner: Principal,
+    username: String,
+}
+
+impl From<LegacyStateSnapshot> for StateSnapshot {
+    fn from(snapshot: LegacyStateSnapshot) -> Self {
+        StateSnapshot {
+            folder_uuid_to_metadata: snapshot
+                .folder_uuid_to_metadata
+                .into_iter()
+                .map(|(id, folder)| (id, folder.into()))
+                .collect(),
+            file_uuid_to_metadata: snapshot
+                .file_uuid_to_metadata
+                .into_iter()
+                .map(|(id, file)| (id, file.into()))
+                .collect(),
+            full_folder_path_to_uuid: snapshot.full_folder_path_to_uuid,
+            full_file_path_to_uuid: snapshot.full_file_path_to_uuid,
+            owner: snapshot.owner,
+            username: snapshot.username,
+        }
+    }
+}
+
+impl State {
+    /// Empties the folder and file tables and everything derived from them.
+    /// The audit log, settings and members are kept.
+    fn clear_drive_contents(&mut self) {
+        self.folder_uuid_to_metadata.clear_new();
+        self.file_uuid_to_metadata.clear_new();
+        self.full_folder_path_to_uuid.clear_new();
+        self.full_file_path_to_uuid.clear_new();
+        self.folder_children.clear_new();
+        self.starred.clear_new();
+        self.recent_files.clear_new();
+        self.comments.clear_new();
+        self.document_deltas.clear_new();
+        self.document_snapshots.clear_new();
+        self.document_heads.clear();
+        self.share_grants.clear();
+        self.mount_points.clear();
+        self.sync_conflicts.clear();
+        self.published_folders.clear();
+        self.token_gates.clear();
+        self.gate_passes.clear();
+        self.paywall_payments.clear();
+        self.legal_holds.clear();
+        self.link_checks.clear();
+    }
+
+    /// The path `path` would be stored under here, if it is a valid path in
+    /// `storage_location` within the drive's limits.
+    fn imported_path(&self, path: &str, storage_location: &StorageLocationID, folder: bool) -> Option<DriveFullFilePath> {
+        let mut sanitized = State::sanitize_file_path(path).ok()?;
+        if folder && !sanitized.ends_with("::") {
+            sanitized.push('/');
+        }
+        let sanitized = self.canonical_path(&sanitized);
+        if sanitized.split("::").next() != Some(storage_location.as_str()) {
+            return None;
+        }
+        self.check_path_limits(&sanitized).ok()?;
+        Some(sanitized)
+    }
+
+    /// Imports the snapshot's records. In merge mode a record is skipped
+    /// when its UUID is taken, its path is invalid or taken by a live item,
+    /// or its parent was skipped; the children of a folder whose path is
+    /// taken join the existing folder instead. Only records the snapshot
+    /// indexed by path get a path entry, so deleted folders and older file
+    /// versions stay unlisted.
+    pub(crate) fn import_state_snapshot(&mut self, snapshot: StateSnapshot, mode: ImportMode) -> ImportReport {
+        if mode == ImportMode::Replace {
+            self.clear_drive_contents();
+        }
+        let mut report = ImportReport::default();
+        let indexed_folders: HashSet<FolderUUID> = snapshot.full_folder_path_to_uuid.into_values().collect();
+        let indexed_files: HashSet<FileUUID> = snapshot.full_file_path_to_uuid.into_values().collect();
+
+        // Parents before children, so whether the parent was imported is known
+        let mut folders: Vec<FolderMetadata> = snapshot.folder_uuid_to_metadata.into_values().collect();
+        folders.sort_by_cached_key(|folder| folder.full_folder_path.matches('/').count());
+        let mut merged_folders: HashMap<FolderUUID, FolderUUID> = HashMap::new();
+        for mut folder in folders {
+            if let Some(parent_id) = &folder.parent_folder_uuid {
+                if let Some(existing) = merged_folders.get(parent_id) {
+                    folder.parent_folder_uuid = Some(existing.clone());
+                }
+            }
+            let parent_present = folder
+                .parent_folder_uuid
+                .as_ref()
+                .is_none_or(|parent_id| self.folder_uuid_to_metadata.contains_key(parent_id));
+            let path = self.imported_path(&folder.full_folder_path, &folder.storage_location, true);
+            let path = match path {
+                Some(path) if parent_present && !self.folder_uuid_to_metadata.contains_key(&folder.id) => path,
+                _ => {
+                    report.folders_skipped += 1;
+                    continue;
+                }
+            };
+            let indexed = indexed_folders.contains(&folder.id);
+            if indexed {
+                if let Some(existing) = self.full_folder_path_to_uuid.stored_path(&path) {
+                    if let Some(existing_id) = self.full_folder_path_to_uuid.get(&existing) {
+                        merged_folders.insert(folder.id.clone(), existing_id);
+                    }
+                    report.folders_skipped += 1;
+                    continue;
+                }
+                self.full_folder_path_to_uuid.insert(path.clone(), folder.id.clone());
+            }
+            folder.full_folder_path = path;
+            // Deleted folders keep their listing entry, as they do when deleted here
+            self.index_child_folder(&folder);
+            self.folder_uuid_to_metadata.insert(folder.id.clone(), folder);
+            report.folders_imported += 1;
+        }
+
+        // Older versions follow the newest one, so settle the newest first
+        let mut files = snapshot.file_uuid_to_metadata;
+        let mut skipped_files: HashSet<FileUUID> = HashSet::new();
+        for (id, file) in &files {
+            if file.next_version.is_some() {
+                continue;
+            }
+            let folder_uuid = merged_folders.get(&file.folder_uuid).unwrap_or(&file.folder_uuid);
+            let importable = !self.file_uuid_to_metadata.contains_key(id)
+                && self.folder_uuid_to_metadata.contains_key(folder_uuid)
+                && self
+                    .imported_path(&file.full_file_path, &file.storage_location, false)
+                    .is_some_and(|path| !indexed_files.contains(id) || self.full_file_path_to_uuid.stored_path(&path).is_none());
+            if !importable {
+                let mut version = Some(id.clone());
+                while let Some(version_id) = version {
+                    version = files.get(&version_id).and_then(|file| file.prior_version.clone());
+                    skipped_files.insert(version_id);
+                }
+            }
+        }
+
+        for (id, mut file) in files.drain() {
+            if let Some(existing) = merged_folders.get(&file.folder_uuid) {
+                file.folder_uuid = existing.clone();
+            }
+            let path = self.imported_path(&file.full_file_path, &file.storage_location, false);
+            let path = match path {
+                Some(path) if !skipped_files.contains(&id) && !self.file_uuid_to_metadata.contains_key(&id) => path,
+                _ => {
+                    report.files_skipped += 1;
+                    continue;
+                }
+            };
+            if indexed_files.contains(&id) {
+                // Two snapshot paths may sanitize to the same one
+                if self.full_file_path_to_uuid.stored_path(&path).is_some() {
+                    report.files_skipped += 1;
+                    continue;
+                }
+                self.full_file_path_to_uuid.insert(path.clone(), id.clone());
+            }
+            file.full_file_path = path;
+            // Only the newest version is listed in the parent folder
+            if file.next_version.is_none() {
+                self.index_child_file(&file);
+            }
+            self.file_uuid_to_metadata.insert(id, file);
+            report.files_imported += 1;
+        }
+
+        // Usage, rollups and the checksum index are derived, so rebuild them
+        self.recompute_storage_usage();
+        report
+    }
+}
+
+/// Restores folders and files from a `snapshot_hashtables` export, e.g. after
+/// data loss or to move a drive from a dev canister to production. The
+/// snapshot's owner and username are ignored. Run `verify_state_integrity`
+/// afterwards when merging snapshots from different drives.
+#[ic_cdk::update]
+fn import_state_snapshot(snapshot: StateSnapshot, mode: ImportMode) -> Result<ImportReport, DriveError> {
+    let _profile = profile("import_state_snapshot");
+    import_snapshot(snapshot, mode)
+}
+
+/// Brings back the folders and files of a drive that predates the stable
+/// maps. Such drives kept everything on the heap and had no `pre_upgrade`,
+/// so their contents do not survive an upgrade: export them with
+/// `snapshot_hashtables` before upgrading and import the export here after.
+#[ic_cdk::update]
+fn import_legacy_snapshot(snapshot: LegacyStateSnapshot, mode: ImportMode) -> Result<ImportReport, DriveError> {
+    let _profile = profile("import_legacy_snapshot");
+    import_snapshot(snapshot.into(), mode)
+}
+
+fn import_snapshot(snapshot: StateSnapshot, mode: ImportMode) -> Result<ImportReport, DriveError> {
+    check_writable()?;
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if caller != state.owner {
+            return Err(DriveError::unauthorized("Only the owner can import a snapshot"));
+        }
+        if mode == ImportMode::Replace {
+            state.check_no_legal_holds()?;
+        }
+        let report = state.import_state_snapshot(snapshot, mode);
+        let owner = state.owner.to_text();
+        state.record_audit(AuditAction::ImportSnapshot, &owner, None, None);
+        Ok(report)
+    })
+}
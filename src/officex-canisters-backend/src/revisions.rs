@@ -0,0 +1,54 @@
+// revisions.rs
+//
+// Every folder and file record carries a `revision` that goes up by one each
+// time the record changes. Mutating endpoints take the revision the client
+// last saw and refuse to act on a newer record, so two devices editing the
+// same item cannot silently overwrite each other. Passing `None` skips the
+// check, for clients that do not track revisions.
+
+use crate::errors::DriveError;
+use crate::{FileUUID, FolderUUID, State};
+
+fn check_revision(id: &str, expected: Option<u64>, actual: u64) -> Result<(), DriveError> {
+    match expected {
+        Some(expected) if expected != actual => Err(DriveError::RevisionMismatch {
+            id: id.to_string(),
+            expected,
+            actual,
+        }),
+        _ => Ok(()),
+    }
+}
+
+impl State {
+    pub(crate) fn check_folder_revision(&self, folder_id: &FolderUUID, expected: Option<u64>) -> Result<(), DriveError> {
+        if expected.is_none() {
+            return Ok(());
+        }
+        let folder = self
+            .folder_uuid_to_metadata
+            .get(folder_id)
+            .ok_or_else(|| DriveError::folder_not_found(folder_id))?;
+        check_revision(folder_id, expected, folder.revision)
+    }
+
+    pub(crate) fn check_file_revision(&self, file_id: &FileUUID, expected: Option<u64>) -> Result<(), DriveError> {
+        if expected.is_none() {
+            return Ok(());
+        }
+        let file = self
+            .file_uuid_to_metadata
+            .get(file_id)
+            .ok_or_else(|| DriveError::file_not_found(file_id))?;
+        check_revision(file_id, expected, file.revision)
+    }
+
+    /// For endpoints that take either a folder or a file UUID.
+    pub(crate) fn check_item_revision(&self, id: &String, expected: Option<u64>) -> Result<(), DriveError> {
+        if self.folder_uuid_to_metadata.contains_key(id) {
+            self.check_folder_revision(id, expected)
+        } else {
+            self.check_file_revision(id, expected)
+        }
+    }
+}
@@ -0,0 +1,121 @@
+// rollups.rs
+
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::DriveError;
+use crate::members::check_read_access;
+use crate::{FolderMetadata, FolderUUID, State, STATE};
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub(crate) struct FolderSize {
+    folder_id: FolderUUID,
+    total_bytes: u64,
+    file_count: u64,
+    folder_count: u64,
+}
+
+/// Change applied to the cached subtree counters of a folder and all of its ancestors.
+#[derive(Clone, Copy, Default)]
+pub struct RollupDelta {
+    pub bytes: u64,
+    pub files: u64,
+    pub folders: u64,
+}
+
+impl State {
+    /// Adds (`grow == true`) or subtracts `delta` on `folder_uuid` and every
+    /// ancestor up to the storage root.
+    pub(crate) fn adjust_rollups(&mut self, folder_uuid: &FolderUUID, delta: RollupDelta, grow: bool) {
+        let mut current = Some(folder_uuid.clone());
+        while let Some(uuid) = current {
+            let Some(mut folder) = self.folder_uuid_to_metadata.get(&uuid) else {
+                break;
+            };
+            if grow {
+                folder.subtree_bytes += delta.bytes;
+                folder.subtree_file_count += delta.files;
+                folder.subtree_folder_count += delta.folders;
+            } else {
+                folder.subtree_bytes = folder.subtree_bytes.saturating_sub(delta.bytes);
+                folder.subtree_file_count = folder.subtree_file_count.saturating_sub(delta.files);
+                folder.subtree_folder_count = folder.subtree_folder_count.saturating_sub(delta.folders);
+            }
+            current = folder.parent_folder_uuid.clone();
+            self.folder_uuid_to_metadata.insert(uuid, folder);
+        }
+    }
+
+    /// Registers a newly created (or undeleted) folder with its ancestors' counters.
+    pub(crate) fn rollup_folder_added(&mut self, folder: &FolderMetadata) {
+        if let Some(parent) = &folder.parent_folder_uuid {
+            self.adjust_rollups(parent, RollupDelta { folders: 1, ..Default::default() }, true);
+        }
+    }
+
+    pub(crate) fn rollup_folder_removed(&mut self, folder: &FolderMetadata) {
+        if let Some(parent) = &folder.parent_folder_uuid {
+            self.adjust_rollups(parent, RollupDelta { folders: 1, ..Default::default() }, false);
+        }
+    }
+
+    /// Moves a folder's own subtree totals into (or out of) its parent chain,
+    /// used when a sync re-parents or restores a folder wholesale.
+    pub(crate) fn adjust_rollups_for_subtree(&mut self, folder: &FolderMetadata, grow: bool) {
+        if let Some(parent) = &folder.parent_folder_uuid {
+            let delta = RollupDelta {
+                bytes: folder.subtree_bytes,
+                files: folder.subtree_file_count,
+                folders: folder.subtree_folder_count,
+            };
+            self.adjust_rollups(parent, delta, grow);
+        }
+    }
+
+    pub(crate) fn recompute_folder_rollups(&mut self) {
+        let folders: Vec<FolderMetadata> = self.folder_uuid_to_metadata.values().collect();
+        for mut folder in folders {
+            folder.subtree_bytes = 0;
+            folder.subtree_file_count = 0;
+            folder.subtree_folder_count = 0;
+            self.folder_uuid_to_metadata.insert(folder.id.clone(), folder);
+        }
+        let live_folders: Vec<FolderMetadata> = self
+            .folder_uuid_to_metadata
+            .values()
+            .filter(|folder| !folder.deleted)
+            .collect();
+        for folder in &live_folders {
+            self.rollup_folder_added(folder);
+        }
+        let files: Vec<(FolderUUID, u64)> = self
+            .file_uuid_to_metadata
+            .values()
+            .map(|file| (file.folder_uuid.clone(), file.file_size))
+            .collect();
+        for (folder_uuid, bytes) in files {
+            self.adjust_rollups(&folder_uuid, RollupDelta { bytes, files: 1, folders: 0 }, true);
+        }
+    }
+
+    fn get_folder_size(&self, folder_id: &FolderUUID) -> Result<FolderSize, DriveError> {
+        let folder = self
+            .folder_uuid_to_metadata
+            .get(folder_id)
+            .ok_or_else(|| DriveError::folder_not_found(folder_id))?;
+        Ok(FolderSize {
+            folder_id: folder.id.clone(),
+            total_bytes: folder.subtree_bytes,
+            file_count: folder.subtree_file_count,
+            folder_count: folder.subtree_folder_count,
+        })
+    }
+}
+
+/// Total bytes, file versions and subfolders below a folder, served from the
+/// counters cached on each FolderMetadata.
+#[ic_cdk::query]
+fn get_folder_size(folder_id: FolderUUID) -> Result<FolderSize, DriveError> {
+    check_read_access()?;
+    STATE.with(|state| state.borrow().get_folder_size(&folder_id))
+}
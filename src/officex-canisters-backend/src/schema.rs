@@ -0,0 +1,78 @@
+// schema.rs
+//
+// Versioning for the heap part of State that is carried across upgrades.
+// It is stored as CBOR, so it is upgraded as a generic CBOR value before
+// being decoded into the State of the new code:
+//
+// 1. Migrations run in order from the stored version up to SCHEMA_VERSION,
+//    for changes a default cannot cover: renamed fields, changed types, or
+//    values derived from existing data.
+// 2. Fields still missing are filled in from a fresh State, so adding a
+//    field needs no migration as long as its `State::new` value suits
+//    existing drives too.
+//
+// Folder and file records in the stable maps are Candid, which decodes
+// missing `opt` fields as null, so new record fields should be `Option`s.
+// Records also carry a version (see `memory.rs`); a change Candid cannot
+// decode needs a new record version and a decoder for the old one.
+
+use candid::Principal;
+use serde_cbor::Value;
+use std::collections::BTreeMap;
+
+use crate::State;
+
+/// Bump when adding a migration below.
+pub(crate) const SCHEMA_VERSION: u32 = 1;
+
+/// Saved state always carries `schema_version`; state without one is read
+/// as the first version.
+const FIRST_VERSION: u32 = 1;
+
+type StateFields = BTreeMap<Value, Value>;
+type Migration = fn(&mut StateFields);
+
+/// Each entry upgrades state from the version it is listed with to the next.
+const MIGRATIONS: &[(u32, Migration)] = &[];
+
+fn key(name: &str) -> Value {
+    Value::Text(name.to_string())
+}
+
+fn stored_version(fields: &StateFields) -> u32 {
+    match fields.get(&key("schema_version")) {
+        Some(Value::Integer(version)) => u32::try_from(*version).unwrap_or(FIRST_VERSION),
+        _ => FIRST_VERSION,
+    }
+}
+
+/// Brings stored state up to the current schema. Traps on state written by
+/// newer code, as decoding it could silently drop data.
+pub(crate) fn migrate(value: Value) -> Value {
+    let Value::Map(mut fields) = value else {
+        ic_cdk::trap("Stored drive state is not a map");
+    };
+    let version = stored_version(&fields);
+    if version > SCHEMA_VERSION {
+        ic_cdk::trap(&format!(
+            "Stored drive state has schema version {}, newer than this build's {}",
+            version, SCHEMA_VERSION
+        ));
+    }
+
+    for (from, migration) in MIGRATIONS {
+        if *from >= version {
+            migration(&mut fields);
+        }
+    }
+
+    let fresh = serde_cbor::value::to_value(State::new(Principal::anonymous(), String::new()))
+        .expect("Failed to serialize default drive state");
+    if let Value::Map(defaults) = fresh {
+        for (name, default) in defaults {
+            fields.entry(name).or_insert(default);
+        }
+    }
+    fields.insert(key("schema_version"), Value::Integer(SCHEMA_VERSION.into()));
+    Value::Map(fields)
+}
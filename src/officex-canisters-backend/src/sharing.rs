@@ -0,0 +1,249 @@
+// sharing.rs
+
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+use crate::audit::AuditAction;
+use crate::errors::DriveError;
+use crate::notifications::NotificationKind;
+use crate::profiling::profile;
+use crate::read_only::check_writable;
+use crate::system_api;
+use crate::{FileMetadata, FolderMetadata, FolderUUID, State, STATE};
+
+const MAX_SHARE_PAGE_SIZE: u32 = 200;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum ShareRole {
+    Viewer,
+    // May edit shared files, and every file below a shared folder
+    Editor,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ShareGrant {
+    resource_id: String,
+    grantee: Principal,
+    role: ShareRole,
+    granted_by: Principal,
+    granted_at_ms: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+enum SharedResource {
+    Folder(Box<FolderMetadata>),
+    File(Box<FileMetadata>),
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+struct SharedItem {
+    grant: ShareGrant,
+    item: SharedResource,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct SharedWithMePage {
+    items: Vec<SharedItem>,
+    total: u32,
+    has_more: bool,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct ShareGrantPage {
+    grants: Vec<ShareGrant>,
+    total: u32,
+    has_more: bool,
+}
+
+impl State {
+    fn resolve_shared(&self, id: &String) -> Option<SharedResource> {
+        if let Some(folder) = self.folder_uuid_to_metadata.get(id) {
+            return (!folder.deleted).then_some(SharedResource::Folder(Box::new(folder)));
+        }
+        let file = self.file_uuid_to_metadata.get(id)?;
+        (!file.deleted).then_some(SharedResource::File(Box::new(file)))
+    }
+
+    fn share_item(
+        &mut self,
+        caller: Principal,
+        resource_id: String,
+        grantee: Principal,
+        role: ShareRole,
+    ) -> Result<ShareGrant, DriveError> {
+        if !self.is_drive_admin(caller) {
+            return Err(DriveError::unauthorized("Only a drive admin can share items"));
+        }
+        if grantee == Principal::anonymous() || grantee == self.owner {
+            return Err(DriveError::InvalidInput {
+                field: "grantee".to_string(),
+                reason: "Cannot share with the anonymous principal or the owner".to_string(),
+            });
+        }
+        if self.resolve_shared(&resource_id).is_none() {
+            return Err(DriveError::NotFound { resource: "item".to_string(), id: resource_id });
+        }
+
+        let grant = ShareGrant {
+            resource_id: resource_id.clone(),
+            grantee,
+            role,
+            granted_by: caller,
+            granted_at_ms: system_api::time() / 1_000_000,
+        };
+        // Sharing again with the same principal replaces the earlier role
+        let grants = self.share_grants.entry(resource_id.clone()).or_default();
+        grants.retain(|existing| existing.grantee != grantee);
+        grants.push(grant.clone());
+        self.touch_contact(grantee);
+        self.notify(grantee, caller, NotificationKind::Shared { role }, &resource_id);
+        self.record_audit(AuditAction::ShareItem, &resource_id, None, None);
+        Ok(grant)
+    }
+
+    fn unshare_item(&mut self, caller: Principal, resource_id: &str, grantee: Principal) -> Result<(), DriveError> {
+        if !self.is_drive_admin(caller) {
+            return Err(DriveError::unauthorized("Only a drive admin can unshare items"));
+        }
+        let grants = self.share_grants.get_mut(resource_id);
+        let removed = grants.is_some_and(|grants| {
+            let before = grants.len();
+            grants.retain(|existing| existing.grantee != grantee);
+            grants.len() < before
+        });
+        if !removed {
+            return Err(DriveError::NotFound {
+                resource: "share".to_string(),
+                id: format!("{}:{}", resource_id, grantee),
+            });
+        }
+        if self.share_grants.get(resource_id).is_some_and(|grants| grants.is_empty()) {
+            self.share_grants.remove(resource_id);
+        }
+        self.record_audit(AuditAction::UnshareItem, resource_id, None, None);
+        Ok(())
+    }
+
+    fn share_role(&self, principal: Principal, resource_id: &str) -> Option<ShareRole> {
+        self.share_grants
+            .get(resource_id)?
+            .iter()
+            .find(|grant| grant.grantee == principal)
+            .map(|grant| grant.role)
+    }
+
+    /// Whether `principal` holds at least `role` on the folder or any folder
+    /// above it, since a folder's grants cover everything inside. A pass
+    /// through a folder's token gate counts as a grant.
+    pub(crate) fn has_folder_share_role(&self, principal: Principal, folder_id: &FolderUUID, role: ShareRole) -> bool {
+        let mut current = Some(folder_id.clone());
+        while let Some(folder_id) = current {
+            let granted = self.share_role(principal, &folder_id).max(self.gate_pass_role(principal, &folder_id));
+            if granted.is_some_and(|granted| granted >= role) {
+                return true;
+            }
+            current = self
+                .folder_uuid_to_metadata
+                .get(&folder_id)
+                .and_then(|folder| folder.parent_folder_uuid);
+        }
+        false
+    }
+
+    pub(crate) fn has_file_share_role(&self, principal: Principal, file: &FileMetadata, role: ShareRole) -> bool {
+        self.share_role(principal, &file.id).is_some_and(|granted| granted >= role)
+            || self.has_folder_share_role(principal, &file.folder_uuid, role)
+    }
+
+    /// Items shared with `caller`, most recently shared first. Deleted items
+    /// are left out.
+    fn list_shared_with_me(&self, caller: Principal, limit: u32, after: u32) -> SharedWithMePage {
+        let mut grants: Vec<&ShareGrant> = self
+            .share_grants
+            .values()
+            .flatten()
+            .filter(|grant| grant.grantee == caller && self.resolve_shared(&grant.resource_id).is_some())
+            .collect();
+        grants.sort_by(|a, b| {
+            b.granted_at_ms
+                .cmp(&a.granted_at_ms)
+                .then_with(|| a.resource_id.cmp(&b.resource_id))
+        });
+
+        let total = grants.len() as u32;
+        let items: Vec<SharedItem> = grants
+            .into_iter()
+            .skip(after as usize)
+            .take(limit.min(MAX_SHARE_PAGE_SIZE) as usize)
+            .filter_map(|grant| {
+                let item = match self.resolve_shared(&grant.resource_id)? {
+                    SharedResource::File(file) => SharedResource::File(Box::new(self.redact_unpaid(caller, *file))),
+                    folder => folder,
+                };
+                Some(SharedItem { grant: grant.clone(), item })
+            })
+            .collect();
+        let has_more = (after as usize + items.len()) < total as usize;
+        SharedWithMePage { items, total, has_more }
+    }
+
+    /// Every grant on the drive, most recently issued first.
+    fn list_my_shares(&self, limit: u32, after: u32) -> ShareGrantPage {
+        let mut grants: Vec<&ShareGrant> = self.share_grants.values().flatten().collect();
+        grants.sort_by(|a, b| {
+            b.granted_at_ms
+                .cmp(&a.granted_at_ms)
+                .then_with(|| a.resource_id.cmp(&b.resource_id))
+                .then_with(|| a.grantee.cmp(&b.grantee))
+        });
+
+        let total = grants.len() as u32;
+        let grants: Vec<ShareGrant> = grants
+            .into_iter()
+            .skip(after as usize)
+            .take(limit.min(MAX_SHARE_PAGE_SIZE) as usize)
+            .cloned()
+            .collect();
+        let has_more = (after as usize + grants.len()) < total as usize;
+        ShareGrantPage { grants, total, has_more }
+    }
+}
+
+/// Grants `grantee` access to a folder or file. Editors of a folder may edit
+/// every file below it.
+#[ic_cdk::update]
+fn share_item(resource_id: String, grantee: Principal, role: ShareRole) -> Result<ShareGrant, DriveError> {
+    let _profile = profile("share_item");
+    check_writable()?;
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow_mut().share_item(caller, resource_id, grantee, role))
+}
+
+#[ic_cdk::update]
+fn unshare_item(resource_id: String, grantee: Principal) -> Result<(), DriveError> {
+    let _profile = profile("unshare_item");
+    check_writable()?;
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow_mut().unshare_item(caller, &resource_id, grantee))
+}
+
+/// Folders and files the caller has been granted access to, for the
+/// "Shared with me" view.
+#[ic_cdk::query]
+fn list_shared_with_me(limit: u32, after: u32) -> SharedWithMePage {
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow().list_shared_with_me(caller, limit, after))
+}
+
+/// Grants the owner has issued, for the "Shared by me" view.
+#[ic_cdk::query]
+fn list_my_shares(limit: u32, after: u32) -> Result<ShareGrantPage, DriveError> {
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let state = state.borrow();
+        if !state.is_drive_admin(caller) {
+            return Err(DriveError::unauthorized("Only a drive admin can list shares"));
+        }
+        Ok(state.list_my_shares(limit, after))
+    })
+}
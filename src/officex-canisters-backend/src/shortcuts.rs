@@ -0,0 +1,151 @@
+// shortcuts.rs
+//
+// A shortcut is a file entry that stands for another file, so one document
+// can appear in several folders without copies. It has its own name, path,
+// tags and properties, but no content: listings fill in the content fields
+// from the newest version of the target. A shortcut whose target is deleted
+// is listed as it is, without content.
+
+use crate::audit::AuditAction;
+use crate::errors::DriveError;
+use crate::path_validation;
+use crate::profiling::profile;
+use crate::rate_limit::check_update_allowed;
+use crate::system_api;
+use crate::{
+    generate_unique_id, DriveFullFilePath, FileMetadata, FileUUID, State, StorageLocationID,
+    UploadStatus, UserID, STATE,
+};
+
+// Far longer than any real version chain; stops a corrupted link from looping
+const MAX_VERSION_CHAIN: usize = 10_000;
+
+impl State {
+    pub(crate) fn latest_version_of(&self, file_id: &FileUUID) -> Option<FileMetadata> {
+        let mut file = self.file_uuid_to_metadata.get(file_id)?;
+        for _ in 0..MAX_VERSION_CHAIN {
+            match file.next_version.as_ref().and_then(|next| self.file_uuid_to_metadata.get(next)) {
+                Some(next) => file = next,
+                None => break,
+            }
+        }
+        Some(file)
+    }
+
+    /// Fills a shortcut's content fields from its target. Other files are
+    /// returned unchanged.
+    pub(crate) fn resolve_shortcut(&self, mut file: FileMetadata) -> FileMetadata {
+        let Some(target) = file
+            .shortcut_target
+            .as_ref()
+            .and_then(|target_id| self.latest_version_of(target_id))
+            .filter(|target| !target.deleted)
+        else {
+            return file;
+        };
+        file.mime_type = target.mime_type;
+        file.file_size = target.file_size;
+        file.raw_url = target.raw_url;
+        file.upload_status = target.upload_status;
+        file.sha256_checksum = target.sha256_checksum;
+        file.encrypted = target.encrypted;
+        file.encryption_scheme = target.encryption_scheme;
+        file.wrapped_dek = target.wrapped_dek;
+        file.nonce = target.nonce;
+        file.thumbnail_url = target.thumbnail_url;
+        file.thumbnail_checksum = target.thumbnail_checksum;
+        file.thumbnail_dimensions = target.thumbnail_dimensions;
+        // Shown so clients know the price; `check_paywall` goes by the target
+        file.paywall = target.paywall;
+        file
+    }
+
+    fn create_shortcut(
+        &mut self,
+        target_id: &FileUUID,
+        path: &DriveFullFilePath,
+        storage_location: StorageLocationID,
+        user_id: UserID,
+    ) -> Result<FileMetadata, DriveError> {
+        let target = self
+            .file_uuid_to_metadata
+            .get(target_id)
+            .filter(|target| !target.deleted)
+            .ok_or_else(|| DriveError::file_not_found(target_id))?;
+        // A shortcut to a shortcut points at the file behind it
+        let target_id = target.shortcut_target.clone().unwrap_or(target.id.clone());
+
+        let full_file_path = self.canonical_path(&Self::sanitize_file_path(path)?);
+        self.check_path_storage_location(&full_file_path, &storage_location)?;
+        self.check_path_limits(&full_file_path)?;
+        self.check_path_legal_hold(&full_file_path)?;
+        let (folder_path, file_name) = self.split_path(&full_file_path);
+        path_validation::check_name(&full_file_path, &file_name)?;
+        if self.full_file_path_to_uuid.contains_key(&full_file_path) {
+            return Err(DriveError::AlreadyExists { path: full_file_path });
+        }
+        let folder_uuid = self.ensure_folder_structure(&folder_path, storage_location.clone(), user_id);
+
+        let now_ns = system_api::time();
+        let shortcut = FileMetadata {
+            id: generate_unique_id(),
+            extension: file_name.rsplit('.').next().unwrap_or("").to_string(),
+            original_file_name: file_name,
+            folder_uuid: folder_uuid.clone(),
+            file_version: 1,
+            prior_version: None,
+            next_version: None,
+            full_file_path: full_file_path.clone(),
+            mime_type: target.mime_type,
+            tags: self.default_tags_for(&folder_uuid),
+            properties: Vec::new(),
+            owner: user_id,
+            created_date: now_ns,
+            storage_location,
+            // Content stays with the target and is counted there
+            file_size: 0,
+            raw_url: String::new(),
+            last_changed_unix_ms: now_ns / 1_000_000,
+            deleted: false,
+            upload_status: UploadStatus::Uploaded,
+            sha256_checksum: None,
+            last_accessed_ms: None,
+            access_count: 0,
+            encrypted: false,
+            encryption_scheme: None,
+            wrapped_dek: None,
+            nonce: None,
+            conflicted: false,
+            thumbnail_url: None,
+            thumbnail_checksum: None,
+            thumbnail_dimensions: None,
+            revision: 0,
+            shortcut_target: Some(target_id.clone()),
+            expires_at_ms: None,
+            permanent_id: None,
+            paywall: None,
+        };
+
+        self.account_file_added(&shortcut);
+        self.index_child_file(&shortcut);
+        self.file_uuid_to_metadata.insert(shortcut.id.clone(), shortcut.clone());
+        self.full_file_path_to_uuid.insert(full_file_path.clone(), shortcut.id.clone());
+        self.record_audit(AuditAction::CreateShortcut, &shortcut.id, None, Some(full_file_path));
+        Ok(self.resolve_shortcut(shortcut))
+    }
+}
+
+/// Creates a shortcut to `target_id` at `path`, creating missing folders.
+/// The shortcut is renamed, moved, tagged and deleted like any file;
+/// deleting it leaves the target alone.
+#[ic_cdk::update]
+fn create_shortcut(
+    target_id: FileUUID,
+    path: DriveFullFilePath,
+    storage_location: StorageLocationID,
+) -> Result<FileMetadata, DriveError> {
+    let _profile = profile("create_shortcut");
+    check_update_allowed("create_shortcut")?;
+    let user_id = system_api::caller();
+    STATE.with(|state| state.borrow_mut().create_shortcut(&target_id, &path, storage_location, user_id))
+}
@@ -0,0 +1,159 @@
+// signed_urls.rs
+
+use candid::{CandidType, Principal};
+use ic_cdk::api::management_canister::ecdsa::{
+    ecdsa_public_key, sign_with_ecdsa, EcdsaCurve, EcdsaKeyId, EcdsaPublicKeyArgument, SignWithEcdsaArgument,
+};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::api_keys::{check_update_allowed_with_api_key, ApiScope};
+use crate::errors::DriveError;
+use crate::profiling::profile;
+use crate::read_only::check_writable;
+use crate::system_api;
+use crate::{FileUUID, State, STATE};
+
+const MIN_SIGNED_URL_TTL_SECONDS: u64 = 1;
+const MAX_SIGNED_URL_TTL_SECONDS: u64 = 7 * 24 * 60 * 60;
+const DOWNLOAD_SCOPE: &str = "download";
+// Tokens are signed under their own derived key, separate from any other use
+const DOWNLOAD_TOKEN_DERIVATION_PATH: &[u8] = b"officex-download-tokens";
+// Threshold key available on local replicas; mainnet drives switch to "key_1"
+pub const DEFAULT_ECDSA_KEY_NAME: &str = "dfx_test_key";
+
+#[derive(Serialize)]
+struct DownloadTokenPayload<'a> {
+    drive_id: String,
+    file_id: &'a str,
+    scope: &'a str,
+    expires_at_ms: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct SignedDownloadUrl {
+    url: String,
+    // JSON payload that was signed, hex encoded as in the URL
+    token: String,
+    // 64-byte (r, s) secp256k1 signature over SHA-256 of the JSON payload
+    signature: String,
+    expires_at_ms: u64,
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn ecdsa_key_id(name: String) -> EcdsaKeyId {
+    EcdsaKeyId { curve: EcdsaCurve::Secp256k1, name }
+}
+
+impl State {
+    fn set_ecdsa_key_name(&mut self, caller: Principal, key_name: String) -> Result<(), DriveError> {
+        if !self.is_drive_admin(caller) {
+            return Err(DriveError::unauthorized("Only a drive admin can change the signing key"));
+        }
+        if key_name.is_empty() {
+            return Err(DriveError::InvalidInput {
+                field: "key_name".to_string(),
+                reason: "Key name cannot be empty".to_string(),
+            });
+        }
+        self.ecdsa_key_name = key_name;
+        Ok(())
+    }
+}
+
+/// Issues a time-limited download URL for a file. Gateways verify the
+/// signature against `get_download_signing_public_key` and reject tokens
+/// past `expires_at_ms`. An `api_key` with the `Read` scope may link any file.
+#[ic_cdk::update]
+async fn issue_signed_url(
+    file_id: FileUUID,
+    ttl_seconds: u64,
+    api_key: Option<String>,
+) -> Result<SignedDownloadUrl, DriveError> {
+    let _profile = profile("issue_signed_url");
+    let caller = check_update_allowed_with_api_key("issue_signed_url", api_key, ApiScope::Read)?;
+    if !(MIN_SIGNED_URL_TTL_SECONDS..=MAX_SIGNED_URL_TTL_SECONDS).contains(&ttl_seconds) {
+        return Err(DriveError::InvalidInput {
+            field: "ttl_seconds".to_string(),
+            reason: format!(
+                "TTL must be between {} and {} seconds",
+                MIN_SIGNED_URL_TTL_SECONDS, MAX_SIGNED_URL_TTL_SECONDS
+            ),
+        });
+    }
+
+    let (raw_url, key_name) = STATE.with(|state| {
+        let state = state.borrow();
+        let file = state
+            .file_uuid_to_metadata
+            .get(&file_id)
+            .ok_or_else(|| DriveError::file_not_found(&file_id))?;
+        if !state.can_view_file(caller, &file) {
+            return Err(DriveError::unauthorized("Caller cannot issue download links for this file"));
+        }
+        state.check_paywall(caller, &file)?;
+        Ok((file.raw_url, state.ecdsa_key_name.clone()))
+    })?;
+
+    let expires_at_ms = system_api::time() / 1_000_000 + ttl_seconds * 1000;
+    let payload = DownloadTokenPayload {
+        drive_id: system_api::canister_id().to_text(),
+        file_id: &file_id,
+        scope: DOWNLOAD_SCOPE,
+        expires_at_ms,
+    };
+    let payload = serde_json::to_string(&payload).map_err(|e| DriveError::Internal {
+        message: format!("Failed to encode download token: {}", e),
+    })?;
+
+    let (response,) = sign_with_ecdsa(SignWithEcdsaArgument {
+        message_hash: Sha256::digest(payload.as_bytes()).to_vec(),
+        derivation_path: vec![DOWNLOAD_TOKEN_DERIVATION_PATH.to_vec()],
+        key_id: ecdsa_key_id(key_name),
+    })
+    .await
+    .map_err(|(code, message)| DriveError::Internal {
+        message: format!("Failed to sign download token: {:?} {}", code, message),
+    })?;
+
+    let token = to_hex(payload.as_bytes());
+    let signature = to_hex(&response.signature);
+    let separator = if raw_url.contains('?') { '&' } else { '?' };
+    Ok(SignedDownloadUrl {
+        url: format!("{}{}officex_token={}&officex_signature={}", raw_url, separator, token, signature),
+        token,
+        signature,
+        expires_at_ms,
+    })
+}
+
+/// SEC1-compressed secp256k1 public key, hex encoded, that verifies download
+/// token signatures from this drive.
+#[ic_cdk::update]
+async fn get_download_signing_public_key() -> Result<String, DriveError> {
+    let _profile = profile("get_download_signing_public_key");
+    let key_name = STATE.with(|state| state.borrow().ecdsa_key_name.clone());
+    let (response,) = ecdsa_public_key(EcdsaPublicKeyArgument {
+        canister_id: None,
+        derivation_path: vec![DOWNLOAD_TOKEN_DERIVATION_PATH.to_vec()],
+        key_id: ecdsa_key_id(key_name),
+    })
+    .await
+    .map_err(|(code, message)| DriveError::Internal {
+        message: format!("Failed to fetch signing key: {:?} {}", code, message),
+    })?;
+    Ok(to_hex(&response.public_key))
+}
+
+/// Selects the threshold ECDSA key used for download tokens, e.g. "key_1"
+/// on mainnet.
+#[ic_cdk::update]
+fn set_ecdsa_key_name(key_name: String) -> Result<(), DriveError> {
+    let _profile = profile("set_ecdsa_key_name");
+    check_writable()?;
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow_mut().set_ecdsa_key_name(caller, key_name))
+}
@@ -0,0 +1,109 @@
+// starred.rs
+
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+use crate::errors::DriveError;
+use crate::members::require_read_access;
+use crate::profiling::profile;
+use crate::rate_limit::check_update_allowed;
+use crate::system_api;
+use crate::{FileMetadata, FolderMetadata, State, STATE};
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+enum StarredResource {
+    Folder(Box<FolderMetadata>),
+    File(Box<FileMetadata>),
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+struct StarredItem {
+    starred_at_ms: u64,
+    item: StarredResource,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct StarredPage {
+    items: Vec<StarredItem>,
+    total: u32,
+    has_more: bool,
+}
+
+impl State {
+    fn star_item(&mut self, id: String) -> Result<(), DriveError> {
+        let exists = self.folder_uuid_to_metadata.get(&id).is_some_and(|folder| !folder.deleted)
+            || self.file_uuid_to_metadata.contains_key(&id);
+        if !exists {
+            return Err(DriveError::NotFound { resource: "item".to_string(), id });
+        }
+        // Starring twice keeps the original time
+        if !self.starred.contains_key(&id) {
+            self.starred.insert(id, system_api::time() / 1_000_000);
+        }
+        Ok(())
+    }
+
+    pub(crate) fn unstar_item(&mut self, id: &str) -> bool {
+        self.starred.remove(&id.to_string()).is_some()
+    }
+
+    fn resolve_starred(&self, caller: Principal, id: &String) -> Option<StarredResource> {
+        if let Some(folder) = self.folder_uuid_to_metadata.get(id) {
+            return (!folder.deleted).then_some(StarredResource::Folder(Box::new(folder)));
+        }
+        self.file_uuid_to_metadata
+            .get(id)
+            .map(|file| StarredResource::File(Box::new(self.redact_unpaid(caller, file))))
+    }
+
+    /// Most recently starred first.
+    fn list_starred(&self, caller: Principal, limit: u32, after: u32) -> StarredPage {
+        let mut starred: Vec<(String, u64)> = self
+            .starred
+            .iter()
+            .filter(|(id, _)| self.resolve_starred(caller, id).is_some())
+            .collect();
+        starred.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+        let total = starred.len() as u32;
+        let items: Vec<StarredItem> = starred
+            .into_iter()
+            .skip(after as usize)
+            .take(limit as usize)
+            .filter_map(|(id, starred_at_ms)| {
+                self.resolve_starred(caller, &id).map(|item| StarredItem { starred_at_ms, item })
+            })
+            .collect();
+        let has_more = (after as usize + items.len()) < total as usize;
+        StarredPage { items, total, has_more }
+    }
+}
+
+#[ic_cdk::update]
+fn star_item(id: String) -> Result<(), DriveError> {
+    let _profile = profile("star_item");
+    check_update_allowed("star_item")?;
+    STATE.with(|state| state.borrow_mut().star_item(id))
+}
+
+#[ic_cdk::update]
+fn unstar_item(id: String) -> Result<(), DriveError> {
+    let _profile = profile("unstar_item");
+    check_update_allowed("unstar_item")?;
+    STATE.with(|state| {
+        if state.borrow_mut().unstar_item(&id) {
+            Ok(())
+        } else {
+            Err(DriveError::NotFound { resource: "starred item".to_string(), id })
+        }
+    })
+}
+
+/// Starred folders and files, most recently starred first. Deleted items
+/// are left out.
+#[ic_cdk::query]
+fn list_starred(limit: u32, after: u32) -> StarredPage {
+    require_read_access();
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow().list_starred(caller, limit, after))
+}
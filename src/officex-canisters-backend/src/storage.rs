@@ -0,0 +1,227 @@
+// storage.rs
+
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::errors::DriveError;
+use crate::members::check_read_access;
+use crate::profiling::profile;
+use crate::rate_limit::check_update_allowed;
+use crate::read_only::check_writable;
+use crate::system_api;
+use crate::{FolderMetadata, FolderUUID, State, STATE};
+
+/// Name of a registered storage location. It is also the prefix of every
+/// path stored there, as in `BrowserCache::docs/report.pdf`.
+pub type StorageLocationID = String;
+
+const MAX_STORAGE_LOCATIONS: usize = 32;
+const MAX_STORAGE_LOCATION_NAME_LENGTH: usize = 64;
+const MAX_STORAGE_CONFIG_ENTRIES: usize = 32;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum StorageKind {
+    BrowserCache,
+    HardDrive,
+    Web3Storj,
+    Ipfs,
+    Arweave,
+    S3Compatible,
+    Other(String),
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct StorageLocation {
+    id: StorageLocationID,
+    pub(crate) kind: StorageKind,
+    // Backend-specific settings such as a bucket or gateway URL; no secrets
+    config: Vec<(String, String)>,
+    created_at_ms: u64,
+}
+
+impl StorageLocation {
+    pub(crate) fn config_value(&self, key: &str) -> Option<&str> {
+        self.config.iter().find(|(name, _)| name == key).map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct LocationStats {
+    storage_location: StorageLocationID,
+    file_count: u64,
+    folder_count: u64,
+    total_bytes: u64,
+}
+
+/// Locations every drive starts with, matching the backends OfficeX clients
+/// have always supported.
+pub fn builtin_storage_locations() -> BTreeMap<StorageLocationID, StorageLocation> {
+    [
+        ("BrowserCache", StorageKind::BrowserCache),
+        ("HardDrive", StorageKind::HardDrive),
+        ("Web3Storj", StorageKind::Web3Storj),
+    ]
+    .into_iter()
+    .map(|(id, kind)| {
+        let location = StorageLocation {
+            id: id.to_string(),
+            kind,
+            config: Vec::new(),
+            created_at_ms: 0,
+        };
+        (id.to_string(), location)
+    })
+    .collect()
+}
+
+fn validate_storage_location_name(name: &str) -> Result<(), DriveError> {
+    let valid_chars = name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if name.is_empty() || name.len() > MAX_STORAGE_LOCATION_NAME_LENGTH || !valid_chars {
+        return Err(DriveError::InvalidInput {
+            field: "name".to_string(),
+            reason: format!(
+                "Storage location names must be 1 to {} letters, digits, '_' or '-'",
+                MAX_STORAGE_LOCATION_NAME_LENGTH
+            ),
+        });
+    }
+    Ok(())
+}
+
+impl State {
+    /// Rejects locations that are not in the drive's registry.
+    pub(crate) fn check_storage_location(&self, storage_location: &StorageLocationID) -> Result<(), DriveError> {
+        if self.storage_locations.contains_key(storage_location) {
+            Ok(())
+        } else {
+            Err(DriveError::NotFound {
+                resource: "storage location".to_string(),
+                id: storage_location.clone(),
+            })
+        }
+    }
+
+    /// Checks that `path` is prefixed with a registered `storage_location`.
+    pub(crate) fn check_path_storage_location(
+        &self,
+        path: &str,
+        storage_location: &StorageLocationID,
+    ) -> Result<(), DriveError> {
+        self.check_storage_location(storage_location)?;
+        let storage_part = path.split("::").next().unwrap_or("");
+        if storage_part != storage_location {
+            return Err(DriveError::StorageMismatch {
+                expected: storage_location.clone(),
+                found: storage_part.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    fn register_storage_location(
+        &mut self,
+        caller: Principal,
+        name: String,
+        kind: StorageKind,
+        config: Vec<(String, String)>,
+    ) -> Result<StorageLocation, DriveError> {
+        if !self.is_drive_admin(caller) {
+            return Err(DriveError::unauthorized("Only a drive admin can register storage locations"));
+        }
+        validate_storage_location_name(&name)?;
+        if self.storage_locations.contains_key(&name) {
+            return Err(DriveError::AlreadyExists { path: format!("{}::", name) });
+        }
+        if self.storage_locations.len() >= MAX_STORAGE_LOCATIONS {
+            return Err(DriveError::InvalidState {
+                reason: format!("A drive can have at most {} storage locations", MAX_STORAGE_LOCATIONS),
+            });
+        }
+        if config.len() > MAX_STORAGE_CONFIG_ENTRIES {
+            return Err(DriveError::InvalidInput {
+                field: "config".to_string(),
+                reason: format!("At most {} config entries are allowed", MAX_STORAGE_CONFIG_ENTRIES),
+            });
+        }
+
+        let location = StorageLocation {
+            id: name.clone(),
+            kind,
+            config,
+            created_at_ms: system_api::time() / 1_000_000,
+        };
+        self.storage_locations.insert(name.clone(), location.clone());
+        let owner = self.owner;
+        self.ensure_root_folder(&name, &owner);
+        Ok(location)
+    }
+
+    /// Creates the root folder of every storage location that lacks one, so
+    /// clients can list an empty location right away. Returns all roots.
+    pub(crate) fn ensure_roots(&mut self) -> Vec<FolderMetadata> {
+        let owner = self.owner;
+        let locations: Vec<StorageLocationID> = self.storage_locations.keys().cloned().collect();
+        let root_ids: Vec<FolderUUID> = locations
+            .iter()
+            .map(|location| self.ensure_root_folder(location, &owner))
+            .collect();
+        root_ids.iter().filter_map(|id| self.folder_uuid_to_metadata.get(id)).collect()
+    }
+
+    /// Read off the subtree counters of each location's root folder, which
+    /// the rollups keep current.
+    fn location_stats(&self) -> Vec<LocationStats> {
+        self.storage_locations
+            .keys()
+            .map(|location| {
+                let root = self
+                    .full_folder_path_to_uuid
+                    .get(&format!("{}::", location))
+                    .and_then(|root_id| self.folder_uuid_to_metadata.get(&root_id));
+                LocationStats {
+                    storage_location: location.clone(),
+                    file_count: root.as_ref().map_or(0, |root| root.subtree_file_count),
+                    folder_count: root.as_ref().map_or(0, |root| root.subtree_folder_count),
+                    total_bytes: root.as_ref().map_or(0, |root| root.subtree_bytes),
+                }
+            })
+            .collect()
+    }
+}
+
+/// Adds a storage backend to the drive. `name` becomes the location ID and
+/// the path prefix for folders and files stored there.
+#[ic_cdk::update]
+fn register_storage_location(
+    name: String,
+    kind: StorageKind,
+    config: Vec<(String, String)>,
+) -> Result<StorageLocation, DriveError> {
+    let _profile = profile("register_storage_location");
+    check_writable()?;
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow_mut().register_storage_location(caller, name, kind, config))
+}
+
+/// Makes sure every storage location has its root folder. New drives get
+/// them at install; this covers drives created before that.
+#[ic_cdk::update]
+fn ensure_roots() -> Result<Vec<FolderMetadata>, DriveError> {
+    let _profile = profile("ensure_roots");
+    check_update_allowed("ensure_roots")?;
+    STATE.with(|state| Ok(state.borrow_mut().ensure_roots()))
+}
+
+#[ic_cdk::query]
+fn list_storage_locations() -> Vec<StorageLocation> {
+    STATE.with(|state| state.borrow().storage_locations.values().cloned().collect())
+}
+
+/// Files, folders and bytes in each storage location, counting every file
+/// version. Roots themselves are not counted as folders.
+#[ic_cdk::query]
+fn get_location_stats() -> Result<Vec<LocationStats>, DriveError> {
+    check_read_access()?;
+    STATE.with(|state| Ok(state.borrow().location_stats()))
+}
@@ -0,0 +1,143 @@
+// storage_report.rs
+
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::Storable;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+
+use crate::dedup::DuplicateGroup;
+use crate::errors::DriveError;
+use crate::members::check_read_access;
+use crate::system_api;
+use crate::{FileMetadata, FileUUID, State, STATE};
+
+const MAX_REPORT_ENTRIES: usize = 20;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct NameSizeDuplicateGroup {
+    file_name: String,
+    file_size: u64,
+    file_ids: Vec<FileUUID>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct StorageReport {
+    largest_files: Vec<FileMetadata>,
+    // Identical content, most wasted bytes first
+    duplicates_by_checksum: Vec<DuplicateGroup>,
+    // Likely copies without a checksum: same name and size in different places
+    duplicates_by_name_and_size: Vec<NameSizeDuplicateGroup>,
+    // Largest first
+    bytes_by_extension: Vec<(String, u64)>,
+    // Bytes freed by keeping one file of every checksum group
+    reclaimable_bytes: u64,
+}
+
+/// Entry in the size index, smallest file first.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct FileSizeKey {
+    file_size: u64,
+    file_name: String,
+    file_id: FileUUID,
+}
+
+impl Storable for FileSizeKey {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).unwrap())
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).unwrap()
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+impl FileSizeKey {
+    pub(crate) fn for_file(file: &FileMetadata) -> Self {
+        FileSizeKey {
+            file_size: file.file_size,
+            file_name: file.original_file_name.clone(),
+            file_id: file.id.clone(),
+        }
+    }
+}
+
+impl State {
+    pub(crate) fn index_file_size(&mut self, file: &FileMetadata) {
+        self.files_by_size.insert(FileSizeKey::for_file(file), ());
+    }
+
+    pub(crate) fn unindex_file_size(&mut self, file: &FileMetadata) {
+        self.files_by_size.remove(&FileSizeKey::for_file(file));
+    }
+
+    fn live_files<'a>(&'a self, ids: impl Iterator<Item = FileUUID> + 'a) -> impl Iterator<Item = FileMetadata> + 'a {
+        ids.filter_map(|id| self.file_uuid_to_metadata.get(&id)).filter(|file| !file.deleted)
+    }
+
+    /// Walks the size index from the largest file down, so only the entries
+    /// that end up in the report are looked up.
+    fn duplicates_by_name_and_size(&self) -> Vec<NameSizeDuplicateGroup> {
+        let mut groups = Vec::new();
+        let mut entries = self.files_by_size.iter().rev().map(|(key, _)| key).filter(|key| key.file_size > 0).peekable();
+        while let Some(first) = entries.next() {
+            let mut ids = vec![first.file_id];
+            while let Some(key) = entries.next_if(|key| key.file_size == first.file_size && key.file_name == first.file_name) {
+                ids.push(key.file_id);
+            }
+            if ids.len() < 2 {
+                continue;
+            }
+            // Versions of one file share its path, so count each path once
+            let paths: BTreeSet<String> = self.live_files(ids.iter().cloned()).map(|file| file.full_file_path).collect();
+            if paths.len() < 2 {
+                continue;
+            }
+            groups.push(NameSizeDuplicateGroup {
+                file_name: first.file_name,
+                file_size: first.file_size,
+                file_ids: ids,
+            });
+            if groups.len() == MAX_REPORT_ENTRIES {
+                break;
+            }
+        }
+        groups
+    }
+
+    fn storage_report(&self, caller: Principal) -> StorageReport {
+        let largest_files = self
+            .live_files(self.files_by_size.iter().rev().map(|(key, _)| key.file_id))
+            .take(MAX_REPORT_ENTRIES)
+            .map(|file| self.redact_unpaid(caller, file))
+            .collect();
+
+        let mut checksum_groups = self.find_duplicates();
+        let reclaimable_bytes = checksum_groups.iter().map(DuplicateGroup::wasted_bytes).sum();
+        checksum_groups.sort_by_key(|group| std::cmp::Reverse(group.wasted_bytes()));
+        checksum_groups.truncate(MAX_REPORT_ENTRIES);
+
+        let mut bytes_by_extension: Vec<(String, u64)> = self.storage_usage.by_extension_totals();
+        bytes_by_extension.sort_by_key(|(_, bytes)| std::cmp::Reverse(*bytes));
+
+        StorageReport {
+            largest_files,
+            duplicates_by_checksum: checksum_groups,
+            duplicates_by_name_and_size: self.duplicates_by_name_and_size(),
+            bytes_by_extension,
+            reclaimable_bytes,
+        }
+    }
+}
+
+/// Where the drive's space goes and what could be cleaned up, built from
+/// the size, checksum and usage indexes kept as files change.
+#[ic_cdk::query]
+fn get_storage_report() -> Result<StorageReport, DriveError> {
+    check_read_access()?;
+    let caller = system_api::caller();
+    STATE.with(|state| Ok(state.borrow().storage_report(caller)))
+}
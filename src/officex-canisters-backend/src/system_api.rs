@@ -0,0 +1,40 @@
+// system_api.rs
+//
+// The system calls State reaches for directly. Outside a canister, as in the
+// unit tests, there is no system API to call, so fixed values stand in.
+
+use candid::Principal;
+
+/// Arbitrary time the unit tests run at: 2023-11-14T22:13:20Z.
+#[cfg(not(target_arch = "wasm32"))]
+const NATIVE_TIME_NS: u64 = 1_700_000_000_000_000_000;
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn time() -> u64 {
+    ic_cdk::api::time()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn time() -> u64 {
+    NATIVE_TIME_NS
+}
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn caller() -> Principal {
+    ic_cdk::caller()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn caller() -> Principal {
+    Principal::anonymous()
+}
+
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn canister_id() -> Principal {
+    ic_cdk::api::id()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn canister_id() -> Principal {
+    Principal::management_canister()
+}
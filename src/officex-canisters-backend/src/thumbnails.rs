@@ -0,0 +1,79 @@
+// thumbnails.rs
+
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+
+use crate::api_keys::{check_update_allowed_with_api_key, ApiScope};
+use crate::audit::AuditAction;
+use crate::errors::DriveError;
+use crate::integrity::normalize_checksum;
+use crate::profiling::profile;
+use crate::{FileMetadata, FileUUID, State, UserID, STATE};
+
+const MAX_THUMBNAIL_URL_LEN: usize = 2_048;
+const MAX_THUMBNAIL_EDGE_PX: u32 = 4_096;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ThumbnailDimensions {
+    width: u32,
+    height: u32,
+}
+
+impl State {
+    fn set_thumbnail(
+        &mut self,
+        user_id: UserID,
+        file_id: &FileUUID,
+        url: String,
+        dimensions: ThumbnailDimensions,
+        checksum: Option<String>,
+    ) -> Result<FileMetadata, DriveError> {
+        let mut file = self.editable_file(user_id, file_id)?;
+        if url.len() > MAX_THUMBNAIL_URL_LEN {
+            return Err(DriveError::InvalidInput {
+                field: "url".to_string(),
+                reason: format!("Must be at most {} bytes", MAX_THUMBNAIL_URL_LEN),
+            });
+        }
+        let checksum = normalize_checksum(checksum)?;
+
+        if url.is_empty() {
+            file.thumbnail_url = None;
+            file.thumbnail_checksum = None;
+            file.thumbnail_dimensions = None;
+        } else {
+            let edge = 1..=MAX_THUMBNAIL_EDGE_PX;
+            if !edge.contains(&dimensions.width) || !edge.contains(&dimensions.height) {
+                return Err(DriveError::InvalidInput {
+                    field: "dimensions".to_string(),
+                    reason: format!("Width and height must be 1 to {} pixels", MAX_THUMBNAIL_EDGE_PX),
+                });
+            }
+            file.thumbnail_url = Some(url);
+            file.thumbnail_checksum = checksum;
+            file.thumbnail_dimensions = Some(dimensions);
+        }
+        // A new preview is not a change to the file itself, so it keeps its place in recents
+        file.revision += 1;
+
+        self.file_uuid_to_metadata.insert(file_id.clone(), file.clone());
+        self.record_audit(AuditAction::SetThumbnail, file_id, Some(file.full_file_path.clone()), None);
+        Ok(file)
+    }
+}
+
+/// Registers a preview image for a file, so listings can show it without
+/// another lookup. An empty `url` removes the thumbnail. Thumbnail workers
+/// may pass an `api_key` with the `Write` scope.
+#[ic_cdk::update]
+fn set_thumbnail(
+    file_id: FileUUID,
+    url: String,
+    dimensions: ThumbnailDimensions,
+    checksum: Option<String>,
+    api_key: Option<String>,
+) -> Result<FileMetadata, DriveError> {
+    let _profile = profile("set_thumbnail");
+    let user_id = check_update_allowed_with_api_key("set_thumbnail", api_key, ApiScope::Write)?;
+    STATE.with(|state| state.borrow_mut().set_thumbnail(user_id, &file_id, url, dimensions, checksum))
+}
@@ -0,0 +1,218 @@
+// token_gates.rs
+//
+// A folder can be gated on an NFT collection: anyone holding a token from
+// the collection gets the gate's role on the folder, with no principal list
+// to keep up to date. Holding is checked with a call to the NFT canister
+// when the caller asks for access, which grants a pass for a few minutes;
+// access checks themselves stay synchronous and only look at the pass. Once
+// it lapses the holder verifies again, so sold tokens stop granting access.
+
+use candid::{CandidType, Nat, Principal};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha224};
+
+use crate::audit::AuditAction;
+use crate::errors::DriveError;
+use crate::metrics::count_call;
+use crate::profiling::profile;
+use crate::rate_limit::charge_rate_limit;
+use crate::read_only::check_writable;
+use crate::sharing::ShareRole;
+use crate::system_api;
+use crate::{FolderUUID, State, STATE};
+
+const GATE_PASS_TTL_MS: u64 = 10 * 60 * 1000;
+// Expired passes are swept once the table grows past this
+const MAX_GATE_PASSES: usize = 10_000;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum NftStandard {
+    Icrc7,
+    Ext,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct TokenGate {
+    nft_canister: Principal,
+    standard: NftStandard,
+    role: ShareRole,
+    created_by: Principal,
+    created_at_ms: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct GatePass {
+    folder_id: FolderUUID,
+    role: ShareRole,
+    valid_until_ms: u64,
+}
+
+// ICRC and EXT types, as the NFT canisters declare them
+
+#[derive(CandidType, Deserialize)]
+pub(crate) struct IcrcAccount {
+    pub(crate) owner: Principal,
+    pub(crate) subaccount: Option<Vec<u8>>,
+}
+
+#[derive(CandidType, Deserialize)]
+enum ExtCommonError {
+    InvalidToken(String),
+    Other(String),
+}
+
+#[derive(CandidType, Deserialize)]
+enum ExtTokensResult {
+    #[serde(rename = "ok")]
+    Ok(Vec<u32>),
+    #[serde(rename = "err")]
+    Err(ExtCommonError),
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// The hex ledger account identifier of `principal`'s default subaccount,
+/// which is how EXT canisters name holders.
+fn ext_account_id(principal: Principal) -> String {
+    let mut hasher = Sha224::new();
+    hasher.update(b"\x0Aaccount-id");
+    hasher.update(principal.as_slice());
+    hasher.update([0u8; 32]);
+    let hash = hasher.finalize();
+    crc32(&hash)
+        .to_be_bytes()
+        .iter()
+        .chain(hash.iter())
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+async fn holds_token(gate: &TokenGate, holder: Principal) -> Result<bool, DriveError> {
+    let call_failed = |(code, message): (ic_cdk::api::call::RejectionCode, String)| DriveError::Internal {
+        message: format!("Failed to query NFT canister {}: {:?} {}", gate.nft_canister, code, message),
+    };
+    match gate.standard {
+        NftStandard::Icrc7 => {
+            let accounts = vec![IcrcAccount { owner: holder, subaccount: None }];
+            let (balances,): (Vec<Nat>,) = ic_cdk::call(gate.nft_canister, "icrc7_balance_of", (accounts,))
+                .await
+                .map_err(call_failed)?;
+            Ok(balances.first().is_some_and(|balance| balance.0.bits() > 0))
+        }
+        NftStandard::Ext => {
+            let (result,): (ExtTokensResult,) = ic_cdk::call(gate.nft_canister, "tokens", (ext_account_id(holder),))
+                .await
+                .map_err(call_failed)?;
+            // EXT canisters answer with an error when the account holds nothing
+            Ok(matches!(result, ExtTokensResult::Ok(tokens) if !tokens.is_empty()))
+        }
+    }
+}
+
+impl State {
+    fn set_token_gate(
+        &mut self,
+        caller: Principal,
+        folder_id: FolderUUID,
+        gate: Option<(Principal, NftStandard, ShareRole)>,
+    ) -> Result<(), DriveError> {
+        if !self.is_drive_admin(caller) {
+            return Err(DriveError::unauthorized("Only a drive admin can gate folders"));
+        }
+        let folder = self
+            .folder_uuid_to_metadata
+            .get(&folder_id)
+            .filter(|folder| !folder.deleted)
+            .ok_or_else(|| DriveError::NotFound { resource: "folder".to_string(), id: folder_id.clone() })?;
+        match gate {
+            Some((nft_canister, standard, role)) => {
+                let gate = TokenGate {
+                    nft_canister,
+                    standard,
+                    role,
+                    created_by: caller,
+                    created_at_ms: system_api::time() / 1_000_000,
+                };
+                self.token_gates.insert(folder_id.clone(), gate);
+            }
+            None => {
+                self.token_gates.remove(&folder_id);
+                self.gate_passes.retain(|(gated_folder, _), _| *gated_folder != folder_id);
+            }
+        }
+        self.record_audit(AuditAction::SetTokenGate, &folder_id, Some(folder.full_folder_path), None);
+        Ok(())
+    }
+
+    fn grant_gate_pass(&mut self, folder_id: &FolderUUID, holder: Principal, now_ms: u64) -> Option<GatePass> {
+        let role = self.token_gates.get(folder_id)?.role;
+        if self.gate_passes.len() >= MAX_GATE_PASSES {
+            self.gate_passes.retain(|_, valid_until_ms| *valid_until_ms > now_ms);
+        }
+        let valid_until_ms = now_ms + GATE_PASS_TTL_MS;
+        self.gate_passes.insert((folder_id.clone(), holder), valid_until_ms);
+        Some(GatePass { folder_id: folder_id.clone(), role, valid_until_ms })
+    }
+
+    /// The role a current gate pass gives `principal` on the folder itself.
+    pub(crate) fn gate_pass_role(&self, principal: Principal, folder_id: &FolderUUID) -> Option<ShareRole> {
+        let valid_until_ms = self.gate_passes.get(&(folder_id.clone(), principal))?;
+        if *valid_until_ms <= system_api::time() / 1_000_000 {
+            return None;
+        }
+        self.token_gates.get(folder_id).map(|gate| gate.role)
+    }
+}
+
+/// Gates the folder on holding a token from `nft_canister`: holders get
+/// `role` on it and everything inside. `None` removes the gate and every
+/// pass issued for it.
+#[ic_cdk::update]
+fn set_token_gate(
+    folder_id: FolderUUID,
+    gate: Option<(Principal, NftStandard, ShareRole)>,
+) -> Result<(), DriveError> {
+    let _profile = profile("set_token_gate");
+    check_writable()?;
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow_mut().set_token_gate(caller, folder_id, gate))
+}
+
+/// Checks that the caller holds a token from the folder's gate collection
+/// and, if so, grants them the gate's role for the next few minutes.
+#[ic_cdk::update]
+async fn verify_token_gate(folder_id: FolderUUID) -> Result<GatePass, DriveError> {
+    let _profile = profile("verify_token_gate");
+    let caller = system_api::caller();
+    count_call("verify_token_gate");
+    check_writable()?;
+    // Holders need not be members of the drive
+    charge_rate_limit(caller, "verify_token_gate", 1)?;
+    if caller == Principal::anonymous() {
+        return Err(DriveError::unauthorized("Anonymous callers cannot pass a token gate"));
+    }
+    let gate = STATE.with(|state| state.borrow().token_gates.get(&folder_id).cloned()).ok_or_else(|| {
+        DriveError::NotFound { resource: "token gate".to_string(), id: folder_id.clone() }
+    })?;
+    if !holds_token(&gate, caller).await? {
+        return Err(DriveError::unauthorized("Caller holds no token from the gate's collection"));
+    }
+    // The gate may have been removed while the NFT canister answered
+    STATE.with(|state| state.borrow_mut().grant_gate_pass(&folder_id, caller, system_api::time() / 1_000_000))
+        .ok_or_else(|| DriveError::NotFound { resource: "token gate".to_string(), id: folder_id })
+}
+
+/// The folder's gate, so clients can tell holders how to get in.
+#[ic_cdk::query]
+fn get_token_gate(folder_id: FolderUUID) -> Option<TokenGate> {
+    STATE.with(|state| state.borrow().token_gates.get(&folder_id).cloned())
+}
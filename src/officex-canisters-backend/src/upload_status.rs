@@ -0,0 +1,125 @@
+// upload_status.rs
+
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+use crate::audit::AuditAction;
+use crate::errors::DriveError;
+use crate::integrity::normalize_checksum;
+use crate::logs::LogLevel;
+use crate::profiling::profile;
+use crate::rate_limit::check_update_allowed;
+use crate::system_api;
+use crate::{FileMetadata, FileUUID, State, STATE};
+
+/// Pending entries older than this are assumed abandoned and removed.
+const PENDING_UPLOAD_TTL_MS: u64 = 24 * 60 * 60 * 1000;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum UploadStatus {
+    Pending,
+    Uploaded,
+    Failed { reason: String },
+}
+
+impl State {
+    pub(crate) fn editable_file(&self, caller: Principal, file_id: &FileUUID) -> Result<FileMetadata, DriveError> {
+        let file = self
+            .file_uuid_to_metadata
+            .get(file_id)
+            .ok_or_else(|| DriveError::file_not_found(file_id))?;
+        if !self.can_edit_file(caller, &file) {
+            return Err(DriveError::unauthorized("Caller cannot edit this file"));
+        }
+        Ok(file)
+    }
+
+    fn mark_upload_complete(
+        &mut self,
+        caller: Principal,
+        file_id: &FileUUID,
+        file_size: u64,
+        raw_url: String,
+        checksum: Option<String>,
+    ) -> Result<FileMetadata, DriveError> {
+        let mut file = self.editable_file(caller, file_id)?;
+        if file.upload_status == UploadStatus::Uploaded {
+            return Err(DriveError::InvalidState {
+                reason: "Upload is already complete".to_string(),
+            });
+        }
+        if file_size > file.file_size {
+            self.check_quota(file_size - file.file_size)?;
+        }
+
+        file.file_size = file_size;
+        file.raw_url = raw_url;
+        file.sha256_checksum = normalize_checksum(checksum)?;
+        file.upload_status = UploadStatus::Uploaded;
+        file.last_changed_unix_ms = system_api::time() / 1_000_000;
+        file.revision += 1;
+
+        self.replace_file_metadata(file.clone());
+        self.record_audit(AuditAction::UploadComplete, file_id, Some(file.full_file_path.clone()), None);
+        Ok(file)
+    }
+
+    fn mark_upload_failed(&mut self, caller: Principal, file_id: &FileUUID, reason: String) -> Result<FileMetadata, DriveError> {
+        let mut file = self.editable_file(caller, file_id)?;
+        if file.upload_status != UploadStatus::Pending {
+            return Err(DriveError::InvalidState {
+                reason: "Only pending uploads can be marked as failed".to_string(),
+            });
+        }
+
+        file.upload_status = UploadStatus::Failed { reason };
+        file.last_changed_unix_ms = system_api::time() / 1_000_000;
+        file.revision += 1;
+
+        self.replace_file_metadata(file.clone());
+        self.record_audit(AuditAction::UploadFailed, file_id, Some(file.full_file_path.clone()), None);
+        Ok(file)
+    }
+
+    /// Deletes uploads left pending for longer than a day. Returns how many.
+    pub(crate) fn purge_stale_pending_uploads(&mut self, now_ms: u64) -> usize {
+        let cutoff = now_ms.saturating_sub(PENDING_UPLOAD_TTL_MS);
+        let stale: Vec<FileUUID> = self
+            .file_uuid_to_metadata
+            .values()
+            .filter(|file| file.upload_status == UploadStatus::Pending && file.last_changed_unix_ms < cutoff)
+            .map(|file| file.id.clone())
+            .collect();
+        for file_id in &stale {
+            if let Err(e) = self.delete_file(file_id) {
+                self.log(LogLevel::Error, format!("Failed to purge stale upload {}: {}", file_id, e));
+            }
+        }
+        stale.len()
+    }
+}
+
+#[ic_cdk::update]
+fn mark_upload_complete(
+    file_id: FileUUID,
+    file_size: u64,
+    raw_url: String,
+    checksum: Option<String>,
+) -> Result<FileMetadata, DriveError> {
+    let _profile = profile("mark_upload_complete");
+    check_update_allowed("mark_upload_complete")?;
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        state
+            .borrow_mut()
+            .mark_upload_complete(caller, &file_id, file_size, raw_url, checksum)
+    })
+}
+
+#[ic_cdk::update]
+fn mark_upload_failed(file_id: FileUUID, reason: String) -> Result<FileMetadata, DriveError> {
+    let _profile = profile("mark_upload_failed");
+    check_update_allowed("mark_upload_failed")?;
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow_mut().mark_upload_failed(caller, &file_id, reason))
+}
@@ -0,0 +1,83 @@
+// version_pruning.rs
+//
+// Drives keep every version of a file unless told otherwise. With a version
+// limit set, the `VersionPruning` job removes the oldest versions beyond it.
+
+use crate::audit::AuditAction;
+use crate::errors::DriveError;
+use crate::members::check_read_access;
+use crate::profiling::profile;
+use crate::read_only::check_writable;
+use crate::system_api;
+use crate::{FileUUID, State, STATE};
+
+// Keeps one run well inside the instruction limit; the next run continues
+const MAX_VERSIONS_PRUNED_PER_RUN: usize = 1_000;
+
+impl State {
+    /// Removes versions older than the newest `max_file_versions`. Returns
+    /// how many were removed.
+    pub(crate) fn prune_file_versions(&mut self) -> usize {
+        let Some(keep) = self.max_file_versions else {
+            return 0;
+        };
+        let mut excess: Vec<FileUUID> = Vec::new();
+        for file in self.file_uuid_to_metadata.values() {
+            if file.next_version.is_some() || file.prior_version.is_none() {
+                continue;
+            }
+            let mut kept = 1;
+            let mut prior = file.prior_version;
+            while let Some(prior_id) = prior {
+                let Some(version) = self.file_uuid_to_metadata.get(&prior_id) else {
+                    break;
+                };
+                if kept < keep {
+                    kept += 1;
+                } else {
+                    excess.push(prior_id);
+                }
+                prior = version.prior_version;
+            }
+            if excess.len() >= MAX_VERSIONS_PRUNED_PER_RUN {
+                break;
+            }
+        }
+        excess.truncate(MAX_VERSIONS_PRUNED_PER_RUN);
+        excess
+            .iter()
+            .filter(|file_id| self.remove_file_record(file_id).is_ok())
+            .count()
+    }
+}
+
+/// Keeps at most `max_versions` versions of each file, counting the newest.
+/// `None` keeps every version.
+#[ic_cdk::update]
+fn set_max_file_versions(max_versions: Option<u32>) -> Result<(), DriveError> {
+    let _profile = profile("set_max_file_versions");
+    check_writable()?;
+    if max_versions == Some(0) {
+        return Err(DriveError::InvalidInput {
+            field: "max_versions".to_string(),
+            reason: "Must be at least 1".to_string(),
+        });
+    }
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if !state.is_drive_admin(caller) {
+            return Err(DriveError::unauthorized("Only a drive admin can change the version limit"));
+        }
+        state.max_file_versions = max_versions;
+        let owner = state.owner.to_text();
+        state.record_audit(AuditAction::SetMaxFileVersions, &owner, None, None);
+        Ok(())
+    })
+}
+
+#[ic_cdk::query]
+fn get_max_file_versions() -> Result<Option<u32>, DriveError> {
+    check_read_access()?;
+    STATE.with(|state| Ok(state.borrow().max_file_versions))
+}
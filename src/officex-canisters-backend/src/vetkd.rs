@@ -0,0 +1,146 @@
+// vetkd.rs
+
+use candid::{CandidType, Principal};
+use ic_cdk::api::call::call_with_payment128;
+use serde::Deserialize;
+
+use crate::errors::DriveError;
+use crate::profiling::profile;
+use crate::rate_limit::check_update_allowed;
+use crate::read_only::check_writable;
+use crate::system_api;
+use crate::{FileUUID, State, STATE};
+
+// Per-file keys are derived in their own context, apart from any other use
+const FILE_KEY_CONTEXT: &[u8] = b"officex-file-keys";
+// Key available on local replicas; mainnet drives switch to "key_1"
+pub const DEFAULT_VETKD_KEY_NAME: &str = "dfx_test_key";
+// Upper bound attached to key derivation; unused cycles are refunded
+const VETKD_DERIVE_KEY_CYCLES: u128 = 30_000_000_000;
+
+// Management canister types, which ic-cdk does not wrap yet
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+enum VetKDCurve {
+    #[serde(rename = "bls12_381_g2")]
+    Bls12381G2,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct VetKDKeyId {
+    curve: VetKDCurve,
+    name: String,
+}
+
+#[derive(CandidType, Deserialize)]
+struct VetKDPublicKeyArgs {
+    canister_id: Option<Principal>,
+    context: Vec<u8>,
+    key_id: VetKDKeyId,
+}
+
+#[derive(CandidType, Deserialize)]
+struct VetKDPublicKeyResult {
+    public_key: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize)]
+struct VetKDDeriveKeyArgs {
+    input: Vec<u8>,
+    context: Vec<u8>,
+    transport_public_key: Vec<u8>,
+    key_id: VetKDKeyId,
+}
+
+#[derive(CandidType, Deserialize)]
+struct VetKDDeriveKeyResult {
+    encrypted_key: Vec<u8>,
+}
+
+fn vetkd_key_id(name: String) -> VetKDKeyId {
+    VetKDKeyId { curve: VetKDCurve::Bls12381G2, name }
+}
+
+impl State {
+    fn set_vetkd_key_name(&mut self, caller: Principal, key_name: String) -> Result<(), DriveError> {
+        if !self.is_drive_admin(caller) {
+            return Err(DriveError::unauthorized("Only a drive admin can change the encryption key"));
+        }
+        if key_name.is_empty() {
+            return Err(DriveError::InvalidInput {
+                field: "key_name".to_string(),
+                reason: "Key name cannot be empty".to_string(),
+            });
+        }
+        self.vetkd_key_name = key_name;
+        Ok(())
+    }
+}
+
+/// Master public key that clients use to verify the per-file keys they
+/// receive from `get_encrypted_file_key`.
+#[ic_cdk::update]
+async fn get_encryption_public_key() -> Result<Vec<u8>, DriveError> {
+    let _profile = profile("get_encryption_public_key");
+    let key_name = STATE.with(|state| state.borrow().vetkd_key_name.clone());
+    let args = VetKDPublicKeyArgs {
+        canister_id: None,
+        context: FILE_KEY_CONTEXT.to_vec(),
+        key_id: vetkd_key_id(key_name),
+    };
+    let (result,): (VetKDPublicKeyResult,) =
+        ic_cdk::call(Principal::management_canister(), "vetkd_public_key", (args,))
+            .await
+            .map_err(|(code, message)| DriveError::Internal {
+                message: format!("Failed to fetch encryption public key: {:?} {}", code, message),
+            })?;
+    Ok(result.public_key)
+}
+
+/// Derives the symmetric key for a file, encrypted to the caller's
+/// `transport_public_key` so only the caller can decrypt it. Only principals
+/// allowed to edit the file may obtain its key.
+#[ic_cdk::update]
+async fn get_encrypted_file_key(file_id: FileUUID, transport_public_key: Vec<u8>) -> Result<Vec<u8>, DriveError> {
+    let _profile = profile("get_encrypted_file_key");
+    check_update_allowed("get_encrypted_file_key")?;
+    let caller = system_api::caller();
+    let key_name = STATE.with(|state| {
+        let state = state.borrow();
+        let file = state
+            .file_uuid_to_metadata
+            .get(&file_id)
+            .ok_or_else(|| DriveError::file_not_found(&file_id))?;
+        if !state.can_view_file(caller, &file) {
+            return Err(DriveError::unauthorized("Caller cannot access this file's key"));
+        }
+        Ok(state.vetkd_key_name.clone())
+    })?;
+
+    let args = VetKDDeriveKeyArgs {
+        input: file_id.into_bytes(),
+        context: FILE_KEY_CONTEXT.to_vec(),
+        transport_public_key,
+        key_id: vetkd_key_id(key_name),
+    };
+    let (result,): (VetKDDeriveKeyResult,) = call_with_payment128(
+        Principal::management_canister(),
+        "vetkd_derive_key",
+        (args,),
+        VETKD_DERIVE_KEY_CYCLES,
+    )
+    .await
+    .map_err(|(code, message)| DriveError::Internal {
+        message: format!("Failed to derive file key: {:?} {}", code, message),
+    })?;
+    Ok(result.encrypted_key)
+}
+
+/// Selects the vetKD master key used for file keys, e.g. "key_1" on mainnet.
+#[ic_cdk::update]
+fn set_vetkd_key_name(key_name: String) -> Result<(), DriveError> {
+    let _profile = profile("set_vetkd_key_name");
+    check_writable()?;
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow_mut().set_vetkd_key_name(caller, key_name))
+}
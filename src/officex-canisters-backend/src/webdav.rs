@@ -0,0 +1,279 @@
+// webdav.rs
+//
+// A WebDAV class 1 subset under `/dav/<storage location>/<path>`, enough for
+// Finder and Explorer to mount a drive. Resources resolve through the
+// `full_*_path_to_uuid` indexes. The drive only holds metadata, so GET
+// redirects to the file's `raw_url` and PUT records a new pending version
+// whose content is uploaded to storage as usual.
+
+use candid::Principal;
+
+use crate::children::ChildKind;
+use crate::errors::DriveError;
+use crate::rest_api::{GatewayRequest, GatewayResponse};
+use crate::upload_status::UploadStatus;
+use crate::{DriveFullFilePath, FileMetadata, FolderMetadata, State};
+
+const DAV_PREFIX: &str = "/dav/";
+const ALLOWED_METHODS: &str = "OPTIONS, PROPFIND, GET, HEAD, PUT, MKCOL, MOVE, DELETE";
+// Larger folders are truncated in listings; clients can still open items by path
+const MAX_PROPFIND_CHILDREN: usize = 1_000;
+
+enum DavResource {
+    Folder(Box<FolderMetadata>),
+    File(Box<FileMetadata>),
+}
+
+pub(crate) fn is_dav_read(method: &str) -> bool {
+    matches!(method, "GET" | "HEAD" | "PROPFIND")
+}
+
+pub(crate) fn dav_options() -> GatewayResponse {
+    GatewayResponse {
+        status_code: 200,
+        headers: vec![
+            ("DAV".to_string(), "1".to_string()),
+            ("Allow".to_string(), ALLOWED_METHODS.to_string()),
+        ],
+        body: Vec::new(),
+        upgrade: None,
+    }
+}
+
+fn empty_response(status_code: u16) -> GatewayResponse {
+    GatewayResponse { status_code, headers: Vec::new(), body: Vec::new(), upgrade: None }
+}
+
+pub(crate) fn percent_decode(segment: &str) -> String {
+    let bytes = segment.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|hex| std::str::from_utf8(hex).ok());
+        match (bytes[i], hex.and_then(|hex| u8::from_str_radix(hex, 16).ok())) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn percent_encode(path: &str) -> String {
+    path.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn http_date(unix_ms: u64) -> String {
+    chrono::DateTime::from_timestamp_millis(unix_ms as i64)
+        .map(|date| date.format("%a, %d %b %Y %H:%M:%S GMT").to_string())
+        .unwrap_or_default()
+}
+
+/// Maps `/dav/<storage>/<a>/<b>` to the drive path `<storage>::a/b`, along
+/// with the storage location. Accepts absolute URLs, as in `Destination`.
+fn drive_path(url: &str) -> Option<(String, DriveFullFilePath)> {
+    let path = url.split('?').next()?;
+    let (_, rest) = path.split_once(DAV_PREFIX)?;
+    let mut segments = rest.split('/').filter(|segment| !segment.is_empty()).map(percent_decode);
+    let storage = segments.next()?;
+    let relative: Vec<String> = segments.collect();
+    Some((storage.clone(), format!("{}::{}", storage, relative.join("/"))))
+}
+
+/// The `/dav/` URL of a drive path; folders keep their trailing slash.
+fn dav_href(full_path: &str) -> String {
+    let (storage, relative) = full_path.split_once("::").unwrap_or(("", full_path));
+    let href = format!("{}{}/{}", DAV_PREFIX, storage, relative);
+    percent_encode(&href.replace("//", "/"))
+}
+
+fn propfind_entry(resource: &DavResource) -> String {
+    let (href, name, modified, revision, content) = match resource {
+        DavResource::Folder(folder) => (
+            dav_href(&folder.full_folder_path),
+            &folder.original_folder_name,
+            folder.last_changed_unix_ms,
+            folder.revision,
+            None,
+        ),
+        DavResource::File(file) => (
+            dav_href(&file.full_file_path),
+            &file.original_file_name,
+            file.last_changed_unix_ms,
+            file.revision,
+            Some((file.file_size, file.mime_type.as_str())),
+        ),
+    };
+    let kind = match content {
+        Some((size, mime_type)) => format!(
+            "<d:resourcetype/><d:getcontentlength>{}</d:getcontentlength><d:getcontenttype>{}</d:getcontenttype>",
+            size,
+            xml_escape(mime_type)
+        ),
+        None => "<d:resourcetype><d:collection/></d:resourcetype>".to_string(),
+    };
+    format!(
+        "<d:response><d:href>{}</d:href><d:propstat><d:prop><d:displayname>{}</d:displayname>{}<d:getlastmodified>{}</d:getlastmodified><d:getetag>\"{}\"</d:getetag></d:prop><d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>",
+        xml_escape(&href),
+        xml_escape(name),
+        kind,
+        http_date(modified),
+        revision
+    )
+}
+
+impl State {
+    fn dav_resource(&self, path: &DriveFullFilePath) -> Option<DavResource> {
+        let file = self
+            .full_file_path_to_uuid
+            .get(path)
+            .and_then(|id| self.file_uuid_to_metadata.get(&id))
+            .filter(|file| !file.deleted);
+        if let Some(file) = file {
+            return Some(DavResource::File(Box::new(file)));
+        }
+        let folder_path = if path.ends_with("::") { path.clone() } else { format!("{}/", path) };
+        self.full_folder_path_to_uuid
+            .get(&folder_path)
+            .and_then(|id| self.folder_uuid_to_metadata.get(&id))
+            .filter(|folder| !folder.deleted)
+            .map(|folder| DavResource::Folder(Box::new(folder)))
+    }
+
+    fn dav_propfind(&self, request: &GatewayRequest, resource: DavResource) -> GatewayResponse {
+        let mut body = String::from("<?xml version=\"1.0\" encoding=\"utf-8\"?><d:multistatus xmlns:d=\"DAV:\">");
+        body.push_str(&propfind_entry(&resource));
+        // Depth: infinity is treated as 1, which clients accept
+        let list_children = request.header("depth") != Some("0");
+        if let (DavResource::Folder(folder), true) = (&resource, list_children) {
+            let children = self
+                .children_of(&folder.id)
+                .filter_map(|child| match child.kind {
+                    ChildKind::Folder => self
+                        .folder_uuid_to_metadata
+                        .get(&child.child)
+                        .filter(|folder| !folder.deleted)
+                        .map(|folder| DavResource::Folder(Box::new(folder))),
+                    ChildKind::File => self
+                        .file_uuid_to_metadata
+                        .get(&child.child)
+                        .filter(|file| !file.deleted)
+                        .map(|file| DavResource::File(Box::new(file))),
+                })
+                .take(MAX_PROPFIND_CHILDREN);
+            for child in children {
+                body.push_str(&propfind_entry(&child));
+            }
+        }
+        body.push_str("</d:multistatus>");
+        GatewayResponse {
+            status_code: 207,
+            headers: vec![("Content-Type".to_string(), "application/xml; charset=utf-8".to_string())],
+            body: body.into_bytes(),
+            upgrade: None,
+        }
+    }
+
+    fn dav_move(&mut self, request: &GatewayRequest, resource: DavResource) -> Result<GatewayResponse, DriveError> {
+        let (_, destination) = request
+            .header("destination")
+            .and_then(drive_path)
+            .ok_or_else(|| DriveError::InvalidInput {
+                field: "Destination".to_string(),
+                reason: "Must be a URL under /dav/".to_string(),
+            })?;
+        let (destination_folder, new_name) = self.split_path(&destination);
+        match resource {
+            DavResource::File(file) => {
+                let destination_folder = if destination_folder.ends_with("::") {
+                    destination_folder
+                } else {
+                    format!("{}/", destination_folder)
+                };
+                self.move_file(&file.id, &destination_folder)?;
+                if new_name != file.original_file_name {
+                    self.rename_file(file.id, new_name)?;
+                }
+            }
+            DavResource::Folder(folder) => {
+                let (current_parent, _) = self.split_path(folder.full_folder_path.trim_end_matches('/'));
+                if current_parent != destination_folder {
+                    return Err(DriveError::InvalidInput {
+                        field: "Destination".to_string(),
+                        reason: "Folders can only be renamed in place".to_string(),
+                    });
+                }
+                self.rename_folder(folder.id, new_name)?;
+            }
+        }
+        Ok(empty_response(201))
+    }
+
+    fn dav_put(&mut self, request: &GatewayRequest, storage: String, path: DriveFullFilePath, user: Principal) -> Result<GatewayResponse, DriveError> {
+        let existed = self.full_file_path_to_uuid.contains_key(&path);
+        let file_size = request.body.len() as u64;
+        self.check_quota(file_size)?;
+        let id = self.upsert_file_to_hash_tables(path, storage, user, None)?;
+        if let Some(mut file) = self.file_uuid_to_metadata.get(&id) {
+            file.file_size = file_size;
+            self.replace_file_metadata(file);
+        }
+        Ok(empty_response(if existed { 204 } else { 201 }))
+    }
+
+    /// Handles a WebDAV request already authorized as `user`.
+    pub(crate) fn handle_dav_request(&mut self, request: &GatewayRequest, user: Principal) -> Result<GatewayResponse, DriveError> {
+        let (storage, path) = drive_path(&request.url)
+            .ok_or_else(|| DriveError::invalid_path(&request.url, "Expected /dav/<storage location>/<path>"))?;
+        let resource = self.dav_resource(&path);
+
+        match (request.method.as_str(), resource) {
+            ("PROPFIND", Some(resource)) => Ok(self.dav_propfind(request, resource)),
+            ("GET" | "HEAD", Some(DavResource::File(file))) => match file.upload_status {
+                UploadStatus::Uploaded => self.check_paywall(user, &file).map(|_| GatewayResponse {
+                    status_code: 302,
+                    headers: vec![("Location".to_string(), file.raw_url)],
+                    body: Vec::new(),
+                    upgrade: None,
+                }),
+                _ => Err(DriveError::InvalidState { reason: "File content has not been uploaded".to_string() }),
+            },
+            ("PUT", Some(DavResource::Folder(_))) | ("MKCOL", Some(_)) => Ok(empty_response(405)),
+            ("PUT", _) => self.dav_put(request, storage, path, user),
+            ("MKCOL", None) => {
+                if !request.body.is_empty() {
+                    return Ok(empty_response(415));
+                }
+                self.create_folder(path, storage, user).map(|_| empty_response(201))
+            }
+            ("MOVE", Some(resource)) => self.dav_move(request, resource),
+            ("DELETE", Some(DavResource::Folder(folder))) => {
+                self.delete_folder(&folder.id).map(|_| empty_response(204))
+            }
+            ("DELETE", Some(DavResource::File(file))) => self.delete_file(&file.id).map(|_| empty_response(204)),
+            (_, None) => Err(DriveError::NotFound { resource: "path".to_string(), id: path }),
+            _ => {
+                let mut response = empty_response(405);
+                response.headers.push(("Allow".to_string(), ALLOWED_METHODS.to_string()));
+                Ok(response)
+            }
+        }
+    }
+}
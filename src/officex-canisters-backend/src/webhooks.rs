@@ -0,0 +1,349 @@
+// webhooks.rs
+
+use candid::{CandidType, Principal};
+use hmac::{Hmac, Mac};
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod, HttpResponse, TransformArgs,
+    TransformContext,
+};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::audit::{AuditAction, AuditEntry};
+use crate::errors::DriveError;
+use crate::logs::{log, LogLevel};
+use crate::profiling::profile;
+use crate::read_only::check_writable;
+use crate::system_api;
+use crate::{generate_unique_id, State, STATE};
+
+const MAX_WEBHOOKS: usize = 20;
+const MAX_DELIVERY_ATTEMPTS: u32 = 5;
+const RETRY_BASE_DELAY_MS: u64 = 30_000;
+// Undelivered events beyond this are dropped, oldest first
+const MAX_QUEUED_DELIVERIES: usize = 1_000;
+const WEBHOOK_MAX_RESPONSE_BYTES: u64 = 2_048;
+// Upper bound attached to each outcall; unused cycles are refunded
+const WEBHOOK_OUTCALL_CYCLES: u128 = 2_000_000_000;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WebhookEvent {
+    FileCreated,
+    FileUpdated,
+    FileDeleted,
+    FolderCreated,
+    FolderUpdated,
+    FolderDeleted,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Webhook {
+    id: String,
+    url: String,
+    event_types: Vec<WebhookEvent>,
+    // Key for the HMAC-SHA256 signature sent with every delivery
+    secret: String,
+    created_at_ms: u64,
+}
+
+/// Webhook as returned to callers, without its signing secret.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub(crate) struct WebhookInfo {
+    id: String,
+    url: String,
+    event_types: Vec<WebhookEvent>,
+    created_at_ms: u64,
+}
+
+impl From<&Webhook> for WebhookInfo {
+    fn from(webhook: &Webhook) -> Self {
+        WebhookInfo {
+            id: webhook.id.clone(),
+            url: webhook.url.clone(),
+            event_types: webhook.event_types.clone(),
+            created_at_ms: webhook.created_at_ms,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PendingDelivery {
+    delivery_id: String,
+    webhook_id: String,
+    event: WebhookEvent,
+    body: String,
+    attempts: u32,
+    next_attempt_ms: u64,
+}
+
+#[derive(Serialize)]
+struct WebhookPayload<'a> {
+    delivery_id: &'a str,
+    drive_id: String,
+    event: WebhookEvent,
+    resource_id: &'a str,
+    old_path: &'a Option<String>,
+    new_path: &'a Option<String>,
+    caller: String,
+    timestamp_ms: u64,
+}
+
+fn sign_body(secret: &str, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(body.as_bytes());
+    format!("{:x}", mac.finalize().into_bytes())
+}
+
+impl State {
+    fn webhook_event_for(&self, entry: &AuditEntry) -> Option<WebhookEvent> {
+        let is_folder = self.folder_uuid_to_metadata.contains_key(&entry.resource_id);
+        match entry.action {
+            AuditAction::CreateFolder => Some(WebhookEvent::FolderCreated),
+            AuditAction::UpsertFile => Some(WebhookEvent::FileCreated),
+            AuditAction::DeleteFolder => Some(WebhookEvent::FolderDeleted),
+            AuditAction::DeleteFile => Some(WebhookEvent::FileDeleted),
+            AuditAction::RenameFolder | AuditAction::SyncFolder => Some(WebhookEvent::FolderUpdated),
+            AuditAction::SetTags | AuditAction::SetProperty if is_folder => Some(WebhookEvent::FolderUpdated),
+            AuditAction::RenameFile
+            | AuditAction::MoveFile
+            | AuditAction::SyncFile
+            | AuditAction::UpdateFileMetadata
+            | AuditAction::SetTags
+            | AuditAction::SetProperty
+            | AuditAction::SetEncryption
+            | AuditAction::UploadComplete
+            | AuditAction::UploadFailed => Some(WebhookEvent::FileUpdated),
+            _ => None,
+        }
+    }
+
+    /// Queues a delivery to every webhook subscribed to the event behind `entry`.
+    pub(crate) fn enqueue_webhooks(&mut self, entry: &AuditEntry) {
+        let Some(event) = self.webhook_event_for(entry) else {
+            return;
+        };
+        let subscribed: Vec<String> = self
+            .webhooks
+            .values()
+            .filter(|webhook| webhook.event_types.contains(&event))
+            .map(|webhook| webhook.id.clone())
+            .collect();
+        if subscribed.is_empty() {
+            return;
+        }
+
+        for webhook_id in subscribed {
+            let delivery_id = generate_unique_id();
+            let payload = WebhookPayload {
+                delivery_id: &delivery_id,
+                drive_id: system_api::canister_id().to_text(),
+                event,
+                resource_id: &entry.resource_id,
+                old_path: &entry.old_path,
+                new_path: &entry.new_path,
+                caller: entry.caller.to_text(),
+                timestamp_ms: entry.timestamp_ms,
+            };
+            let body = serde_json::to_string(&payload).unwrap_or_default();
+            self.webhook_queue.push_back(PendingDelivery {
+                delivery_id,
+                webhook_id,
+                event,
+                body,
+                attempts: 0,
+                next_attempt_ms: entry.timestamp_ms,
+            });
+        }
+        while self.webhook_queue.len() > MAX_QUEUED_DELIVERIES {
+            self.webhook_queue.pop_front();
+        }
+        schedule_webhook_delivery();
+    }
+
+    fn register_webhook(
+        &mut self,
+        caller: Principal,
+        url: String,
+        event_types: Vec<WebhookEvent>,
+        secret: String,
+    ) -> Result<WebhookInfo, DriveError> {
+        if !self.is_drive_admin(caller) {
+            return Err(DriveError::unauthorized("Only a drive admin can register webhooks"));
+        }
+        if !url.starts_with("https://") {
+            return Err(DriveError::InvalidInput {
+                field: "url".to_string(),
+                reason: "Webhook URLs must use https".to_string(),
+            });
+        }
+        if event_types.is_empty() {
+            return Err(DriveError::InvalidInput {
+                field: "event_types".to_string(),
+                reason: "Subscribe to at least one event type".to_string(),
+            });
+        }
+        if secret.is_empty() {
+            return Err(DriveError::InvalidInput {
+                field: "secret".to_string(),
+                reason: "A signing secret is required".to_string(),
+            });
+        }
+        if self.webhooks.len() >= MAX_WEBHOOKS {
+            return Err(DriveError::InvalidState {
+                reason: format!("A drive can have at most {} webhooks", MAX_WEBHOOKS),
+            });
+        }
+
+        let webhook = Webhook {
+            id: generate_unique_id(),
+            url,
+            event_types,
+            secret,
+            created_at_ms: system_api::time() / 1_000_000,
+        };
+        let info = WebhookInfo::from(&webhook);
+        self.webhooks.insert(webhook.id.clone(), webhook);
+        Ok(info)
+    }
+
+    /// Removes and returns the deliveries whose next attempt is due.
+    fn take_due_deliveries(&mut self, now_ms: u64) -> Vec<(PendingDelivery, Webhook)> {
+        let mut due = Vec::new();
+        let mut waiting = VecDeque::new();
+        while let Some(delivery) = self.webhook_queue.pop_front() {
+            match self.webhooks.get(&delivery.webhook_id) {
+                // Webhook was unregistered; drop its deliveries
+                None => {}
+                Some(webhook) if delivery.next_attempt_ms <= now_ms => due.push((delivery, webhook.clone())),
+                Some(_) => waiting.push_back(delivery),
+            }
+        }
+        self.webhook_queue = waiting;
+        due
+    }
+
+    fn requeue_failed_delivery(&mut self, mut delivery: PendingDelivery, now_ms: u64) {
+        delivery.attempts += 1;
+        if delivery.attempts >= MAX_DELIVERY_ATTEMPTS {
+            self.log(
+                LogLevel::Warn,
+                format!("Giving up on webhook delivery {} after {} attempts", delivery.delivery_id, delivery.attempts),
+            );
+            return;
+        }
+        delivery.next_attempt_ms = now_ms + RETRY_BASE_DELAY_MS * (1 << (delivery.attempts - 1));
+        self.webhook_queue.push_back(delivery);
+    }
+}
+
+fn schedule_webhook_delivery() {
+    ic_cdk_timers::set_timer(Duration::ZERO, || {
+        ic_cdk::spawn(async {
+            deliver_due_webhooks().await;
+        })
+    });
+}
+
+async fn send_webhook(webhook: &Webhook, delivery: &PendingDelivery) -> bool {
+    let signature = sign_body(&webhook.secret, &delivery.body);
+    let request = CanisterHttpRequestArgument {
+        url: webhook.url.clone(),
+        method: HttpMethod::POST,
+        body: Some(delivery.body.clone().into_bytes()),
+        max_response_bytes: Some(WEBHOOK_MAX_RESPONSE_BYTES),
+        transform: Some(TransformContext::from_name("transform_webhook_response".to_string(), vec![])),
+        headers: vec![
+            HttpHeader { name: "Content-Type".to_string(), value: "application/json".to_string() },
+            HttpHeader { name: "X-OfficeX-Event".to_string(), value: format!("{:?}", delivery.event) },
+            // Every replica sends the request, so receivers should dedupe on this
+            HttpHeader { name: "X-OfficeX-Delivery".to_string(), value: delivery.delivery_id.clone() },
+            HttpHeader { name: "X-OfficeX-Signature".to_string(), value: format!("sha256={}", signature) },
+        ],
+    };
+    match http_request(request, WEBHOOK_OUTCALL_CYCLES).await {
+        Ok((response,)) => {
+            let status: u64 = response.status.0.try_into().unwrap_or(0);
+            (200..300).contains(&status)
+        }
+        Err((code, message)) => {
+            log(LogLevel::Warn, format!("Webhook delivery {} failed: {:?} {}", delivery.delivery_id, code, message));
+            false
+        }
+    }
+}
+
+/// Sends every delivery that is due. New deliveries are sent right away;
+/// failed ones are retried by the `WebhookRetries` job. Returns how many
+/// were sent and how many failed.
+pub(crate) async fn deliver_due_webhooks() -> (u32, u32) {
+    // Outcalls are costly; hold deliveries until the drive is topped up
+    if STATE.with(|state| state.borrow().is_read_only()) {
+        return (0, 0);
+    }
+    let now_ms = system_api::time() / 1_000_000;
+    let due = STATE.with(|state| state.borrow_mut().take_due_deliveries(now_ms));
+    let (mut sent, mut failed) = (0, 0);
+    for (delivery, webhook) in due {
+        if send_webhook(&webhook, &delivery).await {
+            sent += 1;
+        } else {
+            failed += 1;
+            let now_ms = system_api::time() / 1_000_000;
+            STATE.with(|state| state.borrow_mut().requeue_failed_delivery(delivery, now_ms));
+        }
+    }
+    (sent, failed)
+}
+
+/// Registers an HTTPS endpoint that receives a signed JSON POST whenever one
+/// of `event_types` happens. The `X-OfficeX-Signature` header carries the
+/// hex HMAC-SHA256 of the body keyed with `secret`.
+#[ic_cdk::update]
+fn register_webhook(url: String, event_types: Vec<WebhookEvent>, secret: String) -> Result<WebhookInfo, DriveError> {
+    let _profile = profile("register_webhook");
+    check_writable()?;
+    let caller = system_api::caller();
+    STATE.with(|state| state.borrow_mut().register_webhook(caller, url, event_types, secret))
+}
+
+#[ic_cdk::update]
+fn unregister_webhook(webhook_id: String) -> Result<(), DriveError> {
+    let _profile = profile("unregister_webhook");
+    check_writable()?;
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if !state.is_drive_admin(caller) {
+            return Err(DriveError::unauthorized("Only a drive admin can remove webhooks"));
+        }
+        state
+            .webhooks
+            .remove(&webhook_id)
+            .map(|_| ())
+            .ok_or_else(|| DriveError::NotFound { resource: "webhook".to_string(), id: webhook_id })
+    })
+}
+
+#[ic_cdk::query]
+fn list_webhooks() -> Result<Vec<WebhookInfo>, DriveError> {
+    let caller = system_api::caller();
+    STATE.with(|state| {
+        let state = state.borrow();
+        if !state.is_drive_admin(caller) {
+            return Err(DriveError::unauthorized("Only a drive admin can list webhooks"));
+        }
+        Ok(state.webhooks.values().map(WebhookInfo::from).collect())
+    })
+}
+
+/// Strips everything but the status code so replicas agree on the response.
+#[ic_cdk::query]
+fn transform_webhook_response(args: TransformArgs) -> HttpResponse {
+    HttpResponse {
+        status: args.response.status,
+        headers: Vec::new(),
+        body: Vec::new(),
+    }
+}